@@ -0,0 +1,197 @@
+//! Compares building the list responses served to the `GetQuestions` and `GetAnswers`
+//! scenarios of `bin/get.rs` by decoding the database's JSON into a `serde_json::Value` and
+//! serializing it again, against splicing the database's JSON text into the envelope.
+//!
+//! Run with `cargo bench --bench serialization`. Besides criterion's timings, the number of
+//! heap allocations and bytes allocated per response is printed for both approaches.
+
+use criterion::{black_box, criterion_group, BenchmarkId, Criterion, Throughput};
+use qa_rs::utils::{answers_envelope, questions_envelope};
+use serde_json::{json, Value};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// The `results` text Postgres returns for a product with `questions` questions, each with
+/// `answers` answers carrying two photos.
+fn questions_results(questions: i64, answers: i64) -> String {
+    let results: Vec<Value> = (1..=questions)
+        .map(|q| {
+            let answers: serde_json::Map<String, Value> = (1..=answers)
+                .map(|a| {
+                    let id = q * 100 + a;
+                    (id.to_string(), json!({
+                        "id": id,
+                        "body": "Runs true to size, I wear a medium in most brands and the medium fits well.",
+                        "date": "2021-03-01T17:21:39.518",
+                        "answerer_name": "sillyguy",
+                        "helpfulness": 4,
+                        "photos": photos(id),
+                    }))
+                })
+                .collect();
+
+            json!({
+                "question_id": q,
+                "question_body": "How does the sizing compare to other brands?",
+                "question_date": "2021-02-27T08:15:02.112",
+                "asker_name": "jbilas",
+                "question_helpfulness": 7,
+                "reported": false,
+                "answers": answers,
+            })
+        })
+        .collect();
+
+    Value::Array(results).to_string()
+}
+
+/// The `results` text Postgres returns for a page of `answers` answers to one question.
+fn answers_results(answers: i64) -> String {
+    let results: Vec<Value> = (1..=answers)
+        .map(|id| {
+            json!({
+                "answer_id": id,
+                "body": "Runs true to size, I wear a medium in most brands and the medium fits well.",
+                "date": "2021-03-01T17:21:39.518",
+                "answerer_name": "sillyguy",
+                "helpfulness": 4,
+                "photos": photos(id),
+            })
+        })
+        .collect();
+
+    Value::Array(results).to_string()
+}
+
+fn photos(answer_id: i64) -> Value {
+    json!([
+        { "id": answer_id * 10 + 1, "url": "https://images.unsplash.com/photo-1530519729491-aea5b51d1ee1?w=1651&q=80" },
+        { "id": answer_id * 10 + 2, "url": "https://images.unsplash.com/photo-1511127088257-53ccfcc769fa?w=1650&q=80" },
+    ])
+}
+
+/// What the handlers did before: decode into a `Value`, wrap it and serialize everything again.
+fn reserialize_questions(product_id: i32, results: &str) -> String {
+    let results: Value = serde_json::from_str(results).unwrap();
+
+    let mut response = serde_json::Map::new();
+    response.insert("product_id".to_string(), Value::from(product_id));
+    response.insert("results".to_string(), results);
+    Value::Object(response).to_string()
+}
+
+fn reserialize_answers(question_id: i32, page: i32, count: i32, results: &str) -> String {
+    let results: Value = serde_json::from_str(results).unwrap();
+
+    let mut response = serde_json::Map::new();
+    response.insert("question_id".to_string(), Value::from(question_id));
+    response.insert("page".to_string(), Value::from(page));
+    response.insert("count".to_string(), Value::from(count));
+    response.insert("results".to_string(), results);
+    Value::Object(response).to_string()
+}
+
+/// Returns the allocations and bytes allocated by one call of `f`.
+fn measure_allocations<T>(f: impl FnOnce() -> T) -> (usize, usize) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    black_box(f());
+    (
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes,
+    )
+}
+
+fn report_allocations() {
+    // The scenarios request the default page of 5; the larger pages show how the gap grows.
+    for (questions, answers) in [(5, 2), (5, 5), (20, 5)] {
+        let results = questions_results(questions, answers);
+        let (old_allocations, old_bytes) = measure_allocations(|| reserialize_questions(1, &results));
+        let (new_allocations, new_bytes) = measure_allocations(|| questions_envelope(1, &results));
+        println!(
+            "GetQuestions {} questions x {} answers ({} bytes): reserialize {} allocations / {} bytes, splice {} allocations / {} bytes",
+            questions, answers, results.len(), old_allocations, old_bytes, new_allocations, new_bytes
+        );
+    }
+
+    for answers in [5, 20] {
+        let results = answers_results(answers);
+        let (old_allocations, old_bytes) = measure_allocations(|| reserialize_answers(1, 1, answers as i32, &results));
+        let (new_allocations, new_bytes) = measure_allocations(|| answers_envelope(1, 1, answers as i32, &results));
+        println!(
+            "GetAnswers {} answers ({} bytes): reserialize {} allocations / {} bytes, splice {} allocations / {} bytes",
+            answers, results.len(), old_allocations, old_bytes, new_allocations, new_bytes
+        );
+    }
+}
+
+fn get_questions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("GetQuestions");
+
+    for (questions, answers) in [(5, 2), (5, 5), (20, 5)] {
+        let results = questions_results(questions, answers);
+        let label = format!("{}x{}", questions, answers);
+        group.throughput(Throughput::Bytes(results.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("reserialize", &label), &results, |b, results| {
+            b.iter(|| reserialize_questions(black_box(1), results))
+        });
+        group.bench_with_input(BenchmarkId::new("splice", &label), &results, |b, results| {
+            b.iter(|| questions_envelope(black_box(1), results))
+        });
+    }
+
+    group.finish();
+}
+
+fn get_answers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("GetAnswers");
+
+    for answers in [5, 20] {
+        let results = answers_results(answers);
+        group.throughput(Throughput::Bytes(results.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("reserialize", answers), &results, |b, results| {
+            b.iter(|| reserialize_answers(black_box(1), 1, answers as i32, results))
+        });
+        group.bench_with_input(BenchmarkId::new("splice", answers), &results, |b, results| {
+            b.iter(|| answers_envelope(black_box(1), 1, answers as i32, results))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, get_questions, get_answers);
+
+fn main() {
+    report_allocations();
+    benches();
+    criterion::Criterion::default().configure_from_args().final_summary();
+}
@@ -0,0 +1,197 @@
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use qa_rs::{auth, db::Database, emails::{self, EmailProtection, EmailProtector}, loadtest, webhooks};
+
+/// Administers the Q&A service's database selected by `DATABASE_URL`.
+#[derive(Parser)]
+#[command(name = "admin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manages API keys.
+    #[command(subcommand)]
+    Keys(KeysCommand),
+    /// Manages stored asker and answerer emails.
+    #[command(subcommand)]
+    Emails(EmailsCommand),
+    /// Manages data written by the load test.
+    #[command(subcommand)]
+    Loadtest(LoadtestCommand),
+    /// Manages webhooks and their failed deliveries.
+    #[command(subcommand)]
+    Webhooks(WebhooksCommand),
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Creates a key and prints it. The key cannot be shown again.
+    Create {
+        /// Who or what the key is for, e.g. `storefront`.
+        #[arg(long)]
+        name: String,
+        /// Comma-separated scopes: read, write, admin.
+        #[arg(long, default_value = "read")]
+        scopes: String,
+    },
+    /// Lists all keys, including revoked ones.
+    List,
+    /// Revokes a key by id.
+    Revoke { id: i32 },
+}
+
+#[derive(Subcommand)]
+enum EmailsCommand {
+    /// Seals every stored email the way EMAIL_PROTECTION and EMAIL_KEY seal new ones:
+    /// plaintext emails from before protection was turned on, and emails sealed with one of
+    /// EMAIL_OLD_KEYS. Safe to interrupt and run again.
+    Reencrypt {
+        /// Rows read per query.
+        #[arg(long, default_value_t = 500)]
+        batch_size: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum LoadtestCommand {
+    /// Deletes the questions and answers a load test posted, with their photos, votes and
+    /// reports.
+    Purge {
+        /// The run to purge, as printed when the load test started. Every run when left out.
+        #[arg(long)]
+        run: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum WebhooksCommand {
+    /// Registers a webhook and prints the secret its deliveries are signed with. The secret
+    /// cannot be shown again.
+    Create {
+        #[arg(long)]
+        url: String,
+        /// Comma-separated event types, e.g. `answer.created,answer.reported`. Every event
+        /// when left out.
+        #[arg(long, default_value = "")]
+        events: String,
+    },
+    /// Lists all webhooks.
+    List,
+    /// Deletes a webhook by id, with its pending and failed deliveries.
+    Delete { id: i32 },
+    /// Lists deliveries that ran out of attempts.
+    DeadLetters,
+    /// Queues a failed delivery, by dead letter id, to be sent again.
+    Redeliver { id: i64 },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+
+    let cli = Cli::parse();
+    let db = Database::connect(&std::env::var("DATABASE_URL")?).await?;
+
+    match cli.command {
+        Command::Keys(KeysCommand::Create { name, scopes }) => {
+            let scopes = auth::parse_scopes(&scopes)?;
+            if scopes.is_empty() {
+                return Err("A key needs at least one scope".into());
+            }
+
+            let (id, key) = auth::create_api_key(&db, &name, &scopes).await?;
+            println!("Created key {} ({}) with scopes {}", id, name, auth::format_scopes(&scopes));
+            println!("{}", key);
+        }
+        Command::Keys(KeysCommand::List) => {
+            println!("{:<6} {:<24} {:<12} {:<18} {:<24} REVOKED", "ID", "NAME", "PREFIX", "SCOPES", "CREATED");
+            for key in auth::list_api_keys(&db).await? {
+                println!(
+                    "{:<6} {:<24} {:<12} {:<18} {:<24} {}",
+                    key.id,
+                    key.name,
+                    key.key_prefix,
+                    key.scopes,
+                    key.created_at,
+                    key.revoked_at.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        Command::Keys(KeysCommand::Revoke { id }) => {
+            if !auth::revoke_api_key(&db, id).await? {
+                return Err(format!("No active key with id {}", id).into());
+            }
+            println!("Revoked key {}", id);
+        }
+        Command::Emails(EmailsCommand::Reencrypt { batch_size }) => {
+            let protector = EmailProtector::from_env()?;
+            if protector.protection() == EmailProtection::Plaintext {
+                return Err("Set EMAIL_PROTECTION to encrypt or hash, and EMAIL_KEY, first".into());
+            }
+
+            let report = emails::reencrypt_emails(&db, &protector, batch_size).await?;
+            println!("Re-encrypted {} emails", report.reencrypted);
+            if report.skipped > 0 {
+                println!("Skipped {} emails that are hashed or sealed with a key no longer configured", report.skipped);
+            }
+        }
+        Command::Loadtest(LoadtestCommand::Purge { run }) => {
+            if run.as_deref().is_some_and(|run| !loadtest::is_valid_run(run)) {
+                return Err("A run is made of letters, digits, - and _".into());
+            }
+
+            let report = loadtest::purge_load_test_data(&db, run.as_deref()).await?;
+            println!("Deleted {} questions and {} answers", report.questions, report.answers);
+        }
+        Command::Webhooks(WebhooksCommand::Create { url, events }) => {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err("A webhook URL starts with http:// or https://".into());
+            }
+            let events: Vec<String> = events.split(',').map(str::trim).filter(|event| !event.is_empty()).map(String::from).collect();
+            let events = webhooks::parse_events(&events)?;
+
+            let (id, secret) = webhooks::create_webhook(&db, &url, &events).await?;
+            println!("Created webhook {} for {}", id, if events.is_empty() { "every event" } else { &events });
+            println!("{}", secret);
+        }
+        Command::Webhooks(WebhooksCommand::List) => {
+            println!("{:<6} {:<48} {:<40} CREATED", "ID", "URL", "EVENTS");
+            for webhook in webhooks::list_webhooks(&db).await? {
+                let events = if webhook.events.is_empty() { "*" } else { &webhook.events };
+                println!("{:<6} {:<48} {:<40} {}", webhook.id, webhook.url, events, webhook.created_at);
+            }
+        }
+        Command::Webhooks(WebhooksCommand::Delete { id }) => {
+            if !webhooks::delete_webhook(&db, id).await? {
+                return Err(format!("No webhook with id {}", id).into());
+            }
+            println!("Deleted webhook {}", id);
+        }
+        Command::Webhooks(WebhooksCommand::DeadLetters) => {
+            println!("{:<8} {:<8} {:<20} {:<8} {:<8} {:<24} ERROR", "ID", "EVENT", "TYPE", "WEBHOOK", "TRIES", "FAILED");
+            for letter in webhooks::list_dead_letters(&db).await? {
+                println!(
+                    "{:<8} {:<8} {:<20} {:<8} {:<8} {:<24} {}",
+                    letter.id,
+                    letter.event_id,
+                    letter.event_type,
+                    letter.webhook_id,
+                    letter.attempts,
+                    letter.failed_at,
+                    letter.last_error.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        Command::Webhooks(WebhooksCommand::Redeliver { id }) => {
+            if !webhooks::redeliver(&db, id).await? {
+                return Err(format!("No dead letter with id {}", id).into());
+            }
+            println!("Queued dead letter {} for redelivery", id);
+        }
+    }
+
+    Ok(())
+}
@@ -1,19 +1,343 @@
-use rand::Rng;
+use dotenv::dotenv;
+use goose::config::GooseConfiguration;
+use goose::goose::GooseResponse;
 use goose::prelude::*;
-use std::{time::Duration, thread::sleep};
+use gumdrop::Options as _;
+use qa_rs::capture::{parse_capture, route_name, CapturedRequest};
+use qa_rs::db::Database;
+use qa_rs::loadtest::{is_valid_run, load_ids, load_test_tag, parse_ids, IdKind, IdSampler, IdSource, Popularity, RequestStats, Slo, LOAD_TEST_NAME};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Read scenarios and their share of the read users.
+const READ_SCENARIOS: [(&str, usize); 2] = [("GetQuestions", 1), ("GetAnswers", 1)];
+
+/// Write scenarios and their share of the write users: posts and votes are common, reports
+/// are rare.
+const WRITE_SCENARIOS: [(&str, usize); 6] = [
+    ("AddQuestion", 2),
+    ("AddAnswer", 3),
+    ("QuestionHelpful", 3),
+    ("AnswerHelpful", 3),
+    ("ReportQuestion", 1),
+    ("ReportAnswer", 1),
+];
+
+const QUESTIONS: [&str; 5] = [
+    "Does this run true to size or should I order one up?",
+    "Is the material waterproof or just water resistant?",
+    "How does it hold up after a few months of daily use?",
+    "Can it be machine washed, and does it shrink?",
+    "Is there a warranty if the zipper breaks?",
+];
+
+const ANSWERS: [&str; 5] = [
+    "Fits true to size for me, I usually wear a medium.",
+    "It kept me dry through a full day of rain.",
+    "Still looks new after six months, no complaints.",
+    "I wash it cold and hang dry, no shrinking so far.",
+    "Mine came with a one year warranty card in the box.",
+];
+
+const REPORT_REASONS: [&str; 5] = ["spam", "offensive", "off_topic", "inaccurate", "other"];
+
+/// The flags the load test takes on top of Goose's, and the variables standing in for them.
+const FLAGS: [(&str, &str, &str); 13] = [
+    ("--scenario-file", "LOADTEST_SCENARIO_FILE", "Reads these options, and Goose's under \"goose\", from a JSON file"),
+    ("--write-percent", "LOADTEST_WRITE_PERCENT", "Sets the share of users running write scenarios (default: 10)"),
+    ("--run", "LOADTEST_RUN", "Tags rows written by this run (default: the start time)"),
+    ("--product-ids", "LOADTEST_PRODUCT_IDS", "Takes product ids from database, file:<path> or range:<min>-<max> (default: database)"),
+    ("--question-ids", "LOADTEST_QUESTION_IDS", "Takes question ids from database, file:<path> or range:<min>-<max> (default: database)"),
+    ("--popularity", "LOADTEST_POPULARITY", "Spreads requests uniform, zipf:<exponent> or hot:<share of ids>:<share of requests> (default: zipf:1)"),
+    ("--seed", "LOADTEST_SEED", "Seeds which ids are popular, the same every run by default (default: 0)"),
+    ("--startup-delay", "LOADTEST_STARTUP_DELAY", "Waits this many seconds before starting, e.g. for the service to come up (default: 0)"),
+    ("--slo-p95", "LOADTEST_SLO_P95", "Fails the run if any request's 95th percentile is slower, in ms"),
+    ("--slo-p99", "LOADTEST_SLO_P99", "Fails the run if any request's 99th percentile is slower, in ms"),
+    ("--slo-error-rate", "LOADTEST_SLO_ERROR_RATE", "Fails the run if more of any request fail, in percent (default: 1)"),
+    ("--replay", "LOADTEST_REPLAY", "Replays a file captured with CAPTURE_FILE against --host instead of running scenarios"),
+    ("--replay-speed", "LOADTEST_REPLAY_SPEED", "Replays this many times as fast as captured, or as fast as possible at 0 (default: 1)"),
+];
+
+/// The host load tested when `--host` is not given.
+const DEFAULT_HOST: &str = "http://localhost:3000";
+
+/// Load-test options. Each is read from the scenario file, then its `LOADTEST_*` variable,
+/// then its flag, the later overriding the earlier. A scenario file looks like:
+///
+/// ```json
+/// { "popularity": "hot:0.2:0.8", "product_ids": "file:hot-products.txt", "goose": ["--users", "200"] }
+/// ```
+///
+/// Goose's own options come from `goose` in the file followed by the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Options {
+    write_percent: Option<usize>,
+    run: Option<String>,
+    product_ids: Option<String>,
+    question_ids: Option<String>,
+    popularity: Option<String>,
+    seed: Option<u64>,
+    startup_delay: Option<u64>,
+    slo_p95: Option<usize>,
+    slo_p99: Option<usize>,
+    slo_error_rate: Option<f64>,
+    replay: Option<String>,
+    replay_speed: Option<f64>,
+    goose: Vec<String>,
+}
+
+impl Options {
+    /// Reads the options from the scenario file, the environment and the flags in `args`,
+    /// and returns them with Goose's arguments.
+    fn read(args: Vec<String>) -> Result<(Options, Vec<String>), String> {
+        let (flags, goose_args) = split_flags(args)?;
+        let flag_or_var = |flag: &str, var: &str| {
+            flags.iter().rev().find(|(name, _)| name == flag).map(|(_, value)| value.clone()).or_else(|| std::env::var(var).ok())
+        };
+
+        let mut options = match flag_or_var(FLAGS[0].0, FLAGS[0].1) {
+            Some(path) => {
+                let file = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read scenario file {}: {}", path, e))?;
+                serde_json::from_str(&file).map_err(|e| format!("Invalid scenario file {}: {}", path, e))?
+            }
+            None => Options::default(),
+        };
+
+        for (flag, var, _) in &FLAGS[1..] {
+            let Some(value) = flag_or_var(flag, var) else {
+                continue;
+            };
+            match *flag {
+                "--write-percent" => options.write_percent = Some(parse(flag, &value)?),
+                "--run" => options.run = Some(value),
+                "--product-ids" => options.product_ids = Some(value),
+                "--question-ids" => options.question_ids = Some(value),
+                "--popularity" => options.popularity = Some(value),
+                "--seed" => options.seed = Some(parse(flag, &value)?),
+                "--startup-delay" => options.startup_delay = Some(parse(flag, &value)?),
+                "--slo-p95" => options.slo_p95 = Some(parse(flag, &value)?),
+                "--slo-p99" => options.slo_p99 = Some(parse(flag, &value)?),
+                "--slo-error-rate" => options.slo_error_rate = Some(parse(flag, &value)?),
+                "--replay" => options.replay = Some(value),
+                "--replay-speed" => options.replay_speed = Some(parse(flag, &value)?),
+                _ => unreachable!("unknown flag {}", flag),
+            }
+        }
+
+        let mut goose = std::mem::take(&mut options.goose);
+        goose.extend(goose_args);
+        Ok((options, goose))
+    }
+
+    fn slo(&self) -> Result<Slo, String> {
+        let error_rate = self.slo_error_rate.unwrap_or(1.0);
+        if !(0.0..=100.0).contains(&error_rate) {
+            return Err(format!("Invalid --slo-error-rate: {}", error_rate));
+        }
+        Ok(Slo { p95: self.slo_p95, p99: self.slo_p99, error_rate: Some(error_rate) })
+    }
+}
+
+/// A load-test flag given on the command line, and its value.
+type Flag = (String, String);
+
+/// Takes the load test's flags, as `--flag value` or `--flag=value`, out of `args` and
+/// leaves the rest to Goose.
+fn split_flags(args: Vec<String>) -> Result<(Vec<Flag>, Vec<String>), String> {
+    let (mut flags, mut rest) = (Vec::new(), Vec::new());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        if !FLAGS.iter().any(|(flag, _, _)| *flag == name) {
+            rest.push(arg);
+            continue;
+        }
+        let value = match value {
+            Some(value) => value,
+            None => args.next().ok_or_else(|| format!("Missing value for {}", name))?,
+        };
+        flags.push((name, value));
+    }
+    Ok((flags, rest))
+}
+
+fn parse<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid {}: {}", flag, value))
+}
+
+fn usage() -> String {
+    let mut usage = format!("{}\n\nLoad test options:\n", GooseConfiguration::usage());
+    for (flag, var, help) in FLAGS {
+        usage.push_str(&format!("  {:<28}{} [{}]\n", format!("{} VALUE", flag), help, var));
+    }
+    usage.push_str("\nWrites are sent with LOADTEST_API_KEY and LOADTEST_BEARER_TOKEN when set.");
+    usage
+}
+
+/// Load-test settings, resolved from [`Options`] once at startup. Rows written by the run
+/// are tagged with `run`; purge them afterwards with `admin loadtest purge --run <run>`.
+struct LoadTest {
+    write_percent: usize,
+    run: String,
+    tag: String,
+    api_key: Option<String>,
+    bearer_token: Option<String>,
+    products: IdSampler,
+    /// Whether every product has questions to list, as when its id came from the database.
+    products_have_questions: bool,
+    /// Only read when some users run read scenarios.
+    questions: Option<IdSampler>,
+    slo: Slo,
+}
+
+static LOAD_TEST: OnceLock<LoadTest> = OnceLock::new();
+
+impl LoadTest {
+    async fn resolve(options: Options) -> Result<LoadTest, String> {
+        let write_percent = options.write_percent.unwrap_or(10);
+        if write_percent > 100 {
+            return Err(format!("Invalid --write-percent: {}", write_percent));
+        }
+        let slo = options.slo()?;
+
+        let run = options
+            .run
+            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string());
+        if !is_valid_run(&run) {
+            return Err(format!("Invalid --run: {}", run));
+        }
+
+        let popularity: Popularity = options.popularity.as_deref().unwrap_or("zipf:1").parse()?;
+        let product_source: IdSource = options.product_ids.as_deref().unwrap_or("database").parse()?;
+        let question_source: IdSource = options.question_ids.as_deref().unwrap_or("database").parse()?;
+
+        let mut db = None;
+        let mut rng = StdRng::seed_from_u64(options.seed.unwrap_or(0));
+        let products = IdSampler::new(read_ids(&product_source, IdKind::Product, &mut db).await?, popularity, &mut rng)
+            .map_err(|e| format!("{} for product ids", e))?;
+        let questions = match write_percent {
+            100 => None,
+            _ => Some(
+                IdSampler::new(read_ids(&question_source, IdKind::Question, &mut db).await?, popularity, &mut rng)
+                    .map_err(|e| format!("{} for question ids", e))?,
+            ),
+        };
+
+        Ok(LoadTest {
+            write_percent,
+            tag: load_test_tag(&run),
+            run,
+            api_key: std::env::var("LOADTEST_API_KEY").ok(),
+            bearer_token: std::env::var("LOADTEST_BEARER_TOKEN").ok(),
+            products,
+            products_have_questions: product_source == IdSource::Database,
+            questions,
+            slo,
+        })
+    }
+}
+
+/// Reads ids from `source`, connecting to the database selected by `DATABASE_URL` the
+/// first time it is needed.
+async fn read_ids(source: &IdSource, kind: IdKind, db: &mut Option<Database>) -> Result<Vec<i32>, String> {
+    let ids = match source {
+        IdSource::Range(min, max) => Ok((*min..=*max).collect()),
+        IdSource::File(path) => {
+            let file = std::fs::read_to_string(path).map_err(|e| format!("Failed to read ids from {}: {}", path.display(), e))?;
+            parse_ids(&file).map_err(|e| format!("{} in {}", e, path.display()))
+        }
+        IdSource::Database => {
+            if db.is_none() {
+                let url = std::env::var("DATABASE_URL")
+                    .map_err(|_| "Reading ids from the database needs DATABASE_URL; give ids as file:<path> or range:<min>-<max> instead")?;
+                *db = Some(Database::connect(&url).await.map_err(|e| format!("Failed to connect to the database: {}", e))?);
+            }
+            let db = db.as_ref().unwrap();
+            load_ids(db, kind).await.map_err(|e| format!("Failed to read ids from the database: {}", e))
+        }
+    }?;
+
+    let kind = match kind {
+        IdKind::Product => "product",
+        IdKind::Question => "question",
+    };
+    println!("Drawing from {} {} ids", ids.len(), kind);
+    Ok(ids)
+}
+
+fn load_test() -> &'static LoadTest {
+    LOAD_TEST.get().expect("load test settings are read before the attack starts")
+}
+
+/// The questions and answers a write user posted on start, which its votes and reports go
+/// to so that everything the load test touches can be purged.
+struct WriteSession {
+    question_id: i64,
+    answer_id: i64,
+}
 
 #[tokio::main]
-async fn main() -> Result<(), GooseError> {
-    sleep(Duration::from_secs(5));
-
-    let _goose_metrics = GooseAttack::initialize()?
-        .register_scenario(scenario!("GetQuestions")
-            .register_transaction(transaction!(get_questions))
-        )
-        .register_scenario(scenario!("GetAnswers")
-            .register_transaction(transaction!(get_answers))
-        )
-        .set_default(GooseDefault::Host, "http://localhost:3000")?
+async fn main() -> Result<(), Box<dyn Error>> {
+    dotenv().ok();
+
+    let (options, goose_args) = Options::read(std::env::args().skip(1).collect())?;
+    let configuration = GooseConfiguration::parse_args_default(&goose_args).map_err(|e| e.to_string())?;
+    if configuration.help {
+        println!("{}", usage());
+        return Ok(());
+    }
+
+    if let Some(delay) = options.startup_delay.filter(|delay| *delay > 0) {
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+    }
+
+    if let Some(path) = &options.replay {
+        let host = if configuration.host.is_empty() { DEFAULT_HOST } else { configuration.host.as_str() };
+        let speed = options.replay_speed.unwrap_or(1.0);
+        if !speed.is_finite() || speed < 0.0 {
+            return Err(format!("Invalid --replay-speed: {}", speed).into());
+        }
+        return replay(path, speed, host, &options.slo()?).await;
+    }
+
+    let settings = LoadTest::resolve(options).await?;
+    if settings.write_percent > 0 {
+        println!("Tagging writes with {}; purge them with `admin loadtest purge --run {}`", settings.tag, settings.run);
+    }
+    let (read_percent, write_percent) = (100 - settings.write_percent, settings.write_percent);
+    LOAD_TEST.set(settings).ok();
+
+    // Scenario weights split users: reads get `read_percent` of them and writes the rest, each
+    // side divided by the shares above.
+    let read_total: usize = READ_SCENARIOS.iter().map(|(_, share)| share).sum();
+    let write_total: usize = WRITE_SCENARIOS.iter().map(|(_, share)| share).sum();
+
+    let mut attack = GooseAttack::initialize_with_config(configuration)?;
+    if read_percent > 0 {
+        for (name, share) in READ_SCENARIOS {
+            attack = attack.register_scenario(read_scenario(name).set_weight(share * read_percent * write_total)?);
+        }
+    }
+    if write_percent > 0 {
+        for (name, share) in WRITE_SCENARIOS {
+            attack = attack.register_scenario(write_scenario(name).set_weight(share * write_percent * read_total)?);
+        }
+    }
+
+    let metrics = attack
+        .set_default(GooseDefault::Host, DEFAULT_HOST)?
         .set_default(GooseDefault::Users, 1000)?
         .set_default(GooseDefault::HatchRate, "34")?
         .set_default(GooseDefault::ReportFile, "metrics.html")?
@@ -21,23 +345,384 @@ async fn main() -> Result<(), GooseError> {
         .execute()
         .await?;
 
-    Ok(())
+    let requests = metrics.requests.iter().map(|(name, request)| {
+        let stats = RequestStats {
+            times: request.raw_data.times.clone(),
+            successes: request.success_count,
+            failures: request.fail_count,
+        };
+        (name.clone(), stats)
+    });
+    check_slo(requests.collect(), &load_test().slo)
+}
+
+/// Checks every request, and all of them together, against `slo`.
+fn check_slo(requests: BTreeMap<String, RequestStats>, slo: &Slo) -> Result<(), Box<dyn Error>> {
+    let mut total = RequestStats::default();
+    let mut breaches = Vec::new();
+    for (name, stats) in &requests {
+        total.merge(stats);
+        breaches.extend(slo.breaches(name, stats));
+    }
+    breaches.extend(slo.breaches("Aggregated", &total));
+
+    if breaches.is_empty() {
+        return Ok(());
+    }
+    for breach in &breaches {
+        eprintln!("SLO breached: {}", breach);
+    }
+    Err(format!("{} SLO breaches", breaches.len()).into())
+}
+
+/// What became of a replayed request.
+struct Replayed {
+    route: String,
+    captured_status: u16,
+    /// The status and response time in milliseconds, or why no response came.
+    response: Result<(u16, usize), String>,
+}
+
+/// Replays the requests captured in `path` against `host`, `speed` times as fast as they
+/// arrived, and checks the responses against `slo`. Requests fail when no response comes or
+/// it is a server error; responses with another status than captured are counted apart.
+async fn replay(path: &str, speed: f64, host: &str, slo: &Slo) -> Result<(), Box<dyn Error>> {
+    let file = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let requests = parse_capture(&file)?;
+    let Some(first_ms) = requests.first().map(|request| request.at_ms) else {
+        return Err(format!("No requests to replay in {}", path).into());
+    };
+    println!("Replaying {} requests against {} at {}x", requests.len(), host, speed);
+
+    let client = reqwest::Client::new();
+    let started = tokio::time::Instant::now();
+    let mut tasks = Vec::with_capacity(requests.len());
+    for request in requests {
+        if speed > 0.0 {
+            let offset = Duration::from_secs_f64((request.at_ms - first_ms) as f64 / 1000.0 / speed);
+            tokio::time::sleep_until(started + offset).await;
+        }
+        tasks.push(tokio::spawn(send_captured(client.clone(), host.to_string(), request)));
+    }
+
+    let mut routes: BTreeMap<String, RequestStats> = BTreeMap::new();
+    let mut mismatched = 0;
+    for task in tasks {
+        let replayed = task.await?;
+        let stats = routes.entry(replayed.route).or_default();
+        match replayed.response {
+            Ok((status, ms)) => {
+                *stats.times.entry(ms).or_insert(0) += 1;
+                if status >= 500 { stats.failures += 1 } else { stats.successes += 1 }
+                if status != replayed.captured_status {
+                    mismatched += 1;
+                }
+            }
+            Err(e) => {
+                eprintln!("Replay failed: {}", e);
+                stats.failures += 1;
+            }
+        }
+    }
+
+    println!("{:<48} {:>8} {:>8} {:>8} {:>8}", "Route", "Requests", "Failed", "p95 ms", "p99 ms");
+    for (route, stats) in &routes {
+        let percentile = |percent| stats.percentile(percent).map_or("-".to_string(), |ms| ms.to_string());
+        println!("{:<48} {:>8} {:>8} {:>8} {:>8}", route, stats.successes + stats.failures, stats.failures, percentile(95.0), percentile(99.0));
+    }
+    println!("{} responses had another status than captured", mismatched);
+
+    check_slo(routes, slo)
+}
+
+/// Sends a captured request to `host` as the voter it was captured from, with the
+/// configured credentials.
+async fn send_captured(client: reqwest::Client, host: String, request: CapturedRequest) -> Replayed {
+    let route = route_name(&request.method, &request.path);
+    let url = match &request.query {
+        Some(query) => format!("{}{}?{}", host.trim_end_matches('/'), request.path, query),
+        None => format!("{}{}", host.trim_end_matches('/'), request.path),
+    };
+    let response = async {
+        let method = reqwest::Method::from_bytes(request.method.as_bytes()).map_err(|e| e.to_string())?;
+        let mut builder = client.request(method, &url).header("x-user-token", format!("replay-{}", request.client));
+        if let Ok(api_key) = std::env::var("LOADTEST_API_KEY") {
+            builder = builder.header("x-api-key", api_key);
+        }
+        if let Ok(token) = std::env::var("LOADTEST_BEARER_TOKEN") {
+            builder = builder.bearer_auth(token);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.header("content-type", "application/json").body(body.to_string());
+        }
+
+        let sent = Instant::now();
+        let response = builder.send().await.map_err(|e| e.to_string())?;
+        let status = response.status().as_u16();
+        response.bytes().await.map_err(|e| e.to_string())?;
+        Ok((status, sent.elapsed().as_millis() as usize))
+    };
+
+    Replayed { route, captured_status: request.status, response: response.await }
+}
+
+fn read_scenario(name: &str) -> Scenario {
+    match name {
+        "GetQuestions" => scenario!("GetQuestions").register_transaction(transaction!(get_questions)),
+        "GetAnswers" => scenario!("GetAnswers").register_transaction(transaction!(get_answers)),
+        _ => unreachable!("unknown read scenario {}", name),
+    }
+}
+
+fn write_scenario(name: &str) -> Scenario {
+    let scenario = scenario!(name);
+    let transaction = match name {
+        "AddQuestion" => return scenario.register_transaction(transaction!(add_question)),
+        "AddAnswer" => transaction!(add_answer),
+        "QuestionHelpful" => transaction!(question_helpful),
+        "AnswerHelpful" => transaction!(answer_helpful),
+        "ReportQuestion" => transaction!(report_question),
+        "ReportAnswer" => transaction!(report_answer),
+        _ => unreachable!("unknown write scenario {}", name),
+    };
+
+    scenario
+        .register_transaction(transaction!(start_write_session).set_on_start())
+        .register_transaction(transaction)
 }
 
 async fn get_questions(user: &mut GooseUser) -> TransactionResult {
-    let max_product_id = 1000011;
-    let random_product_id = rand::thread_rng().gen_range(1..max_product_id);
+    let settings = load_test();
+    let product_id = settings.products.sample(&mut rand::thread_rng());
+    let path = format!("/api/v1/questions?product_id={}", product_id);
+    let request = GooseRequest::builder()
+        .path(path.as_str())
+        .name("/api/v1/questions?product_id=:id")
+        .expect_status_code(200)
+        .build();
+
+    let goose = user.request(request).await?;
+    validate(user, goose, |body| {
+        let results = check_list(body, "product_id", product_id)?;
+        if results.is_empty() && settings.products_have_questions {
+            return Err("no questions listed".to_string());
+        }
+        results.iter().try_for_each(|question| {
+            check_fields(question, &[("question_id", Value::is_i64), ("question_body", Value::is_string), ("answers", Value::is_object)])
+        })
+    })
+    .await
+}
+
+async fn get_answers(user: &mut GooseUser) -> TransactionResult {
+    let questions = load_test().questions.as_ref().expect("question ids are read when users run read scenarios");
+    let question_id = questions.sample(&mut rand::thread_rng());
+    let path = format!("/api/v1/questions/{}/answers", question_id);
+    let request = GooseRequest::builder()
+        .path(path.as_str())
+        .name("/api/v1/questions/:id/answers")
+        .expect_status_code(200)
+        .build();
 
-    let _response = user.get(&format!("/api/v1/questions?product_id={}", random_product_id)).await?;
+    let goose = user.request(request).await?;
+    validate(user, goose, |body| {
+        check_list(body, "question_id", question_id)?.iter().try_for_each(|answer| {
+            check_fields(answer, &[("answer_id", Value::is_i64), ("body", Value::is_string), ("photos", Value::is_array)])
+        })
+    })
+    .await
+}
+
+/// Checks that `body` is the list of `field` `id`, and returns its results.
+fn check_list<'a>(body: &'a Value, field: &str, id: i32) -> Result<&'a Vec<Value>, String> {
+    if body[field].as_i64() != Some(id.into()) {
+        return Err(format!("{} is {}, not {}", field, body[field], id));
+    }
+    body["results"].as_array().ok_or_else(|| "results is not an array".to_string())
+}
+
+/// A field of a listed item, and whether a value is valid for it.
+type FieldCheck<'a> = (&'a str, fn(&Value) -> bool);
+
+fn check_fields(item: &Value, fields: &[FieldCheck]) -> Result<(), String> {
+    match fields.iter().find(|(field, is_valid)| !is_valid(&item[*field])) {
+        Some((field, _)) => Err(format!("invalid {} in {}", field, item)),
+        None => Ok(()),
+    }
+}
+
+/// Marks the request of `goose` failed in the metrics unless `check` accepts its JSON body.
+/// Requests that already failed, e.g. with an unexpected status, are left as they are.
+async fn validate(user: &mut GooseUser, mut goose: GooseResponse, check: impl FnOnce(&Value) -> Result<(), String>) -> TransactionResult {
+    let response = match goose.response {
+        Ok(response) if goose.request.success => response,
+        _ => return Ok(()),
+    };
+    let headers = response.headers().clone();
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(e) => return user.set_failure(&format!("failed to read body: {}", e), &mut goose.request, Some(&headers), None),
+    };
 
+    let checked = serde_json::from_str::<Value>(&body).map_err(|e| format!("invalid JSON: {}", e)).and_then(|body| check(&body));
+    match checked {
+        Ok(()) => Ok(()),
+        Err(detail) => user.set_failure(&detail, &mut goose.request, Some(&headers), Some(&body)),
+    }
+}
+
+/// Posts the question and answer the user's votes and reports go to.
+async fn start_write_session(user: &mut GooseUser) -> TransactionResult {
+    let Some(question_id) = post_question(user).await? else {
+        return Ok(());
+    };
+    let Some(answer_id) = post_answer(user, question_id).await? else {
+        return Ok(());
+    };
+
+    user.set_session_data(WriteSession { question_id, answer_id });
     Ok(())
 }
 
-async fn get_answers(user: &mut GooseUser) -> TransactionResult {
-    let max_product_id = 1000011;
-    let random_product_id = rand::thread_rng().gen_range(1..max_product_id);
+/// The user's question and answer ids, posting them again if that failed on start: a write
+/// user without them would otherwise loop without sending a request.
+async fn write_session(user: &mut GooseUser) -> Result<Option<(i64, i64)>, Box<TransactionError>> {
+    if user.get_session_data::<WriteSession>().is_none() {
+        start_write_session(user).await?;
+    }
+    Ok(user.get_session_data::<WriteSession>().map(|session| (session.question_id, session.answer_id)))
+}
 
-    let _response = user.get(&format!("/api/v1/questions/{}/answers", random_product_id)).await?;
+async fn add_question(user: &mut GooseUser) -> TransactionResult {
+    post_question(user).await?;
+    Ok(())
+}
 
+async fn add_answer(user: &mut GooseUser) -> TransactionResult {
+    if let Some((question_id, _)) = write_session(user).await? {
+        post_answer(user, question_id).await?;
+    }
     Ok(())
 }
+
+/// Marks the user's question helpful and takes the vote back again.
+async fn question_helpful(user: &mut GooseUser) -> TransactionResult {
+    if let Some((question_id, _)) = write_session(user).await? {
+        let path = format!("/api/v1/questions/{}/helpful", question_id);
+        send(user, GooseMethod::Put, &path, "/api/v1/questions/:id/helpful", None, 204).await?;
+        send(user, GooseMethod::Delete, &path, "/api/v1/questions/:id/helpful", None, 204).await?;
+    }
+    Ok(())
+}
+
+/// Marks the user's answer helpful and takes the vote back again.
+async fn answer_helpful(user: &mut GooseUser) -> TransactionResult {
+    if let Some((_, answer_id)) = write_session(user).await? {
+        let path = format!("/api/v1/answers/{}/helpful", answer_id);
+        send(user, GooseMethod::Put, &path, "/api/v1/answers/:id/helpful", None, 204).await?;
+        send(user, GooseMethod::Delete, &path, "/api/v1/answers/:id/helpful", None, 204).await?;
+    }
+    Ok(())
+}
+
+async fn report_question(user: &mut GooseUser) -> TransactionResult {
+    if let Some((question_id, _)) = write_session(user).await? {
+        let path = format!("/api/v1/questions/{}/report", question_id);
+        send(user, GooseMethod::Put, &path, "/api/v1/questions/:id/report", Some(report()), 204).await?;
+    }
+    Ok(())
+}
+
+async fn report_answer(user: &mut GooseUser) -> TransactionResult {
+    if let Some((_, answer_id)) = write_session(user).await? {
+        let path = format!("/api/v1/answers/{}/report", answer_id);
+        send(user, GooseMethod::Put, &path, "/api/v1/answers/:id/report", Some(report()), 204).await?;
+    }
+    Ok(())
+}
+
+/// Posts a tagged question to a random product and returns its id, if it was created.
+async fn post_question(user: &mut GooseUser) -> Result<Option<i64>, Box<TransactionError>> {
+    let (product_id, body) = {
+        let mut rng = rand::thread_rng();
+        (load_test().products.sample(&mut rng), *QUESTIONS.choose(&mut rng).unwrap())
+    };
+    let question = json!({
+        "body": format!("{} {}", load_test().tag, body),
+        "name": LOAD_TEST_NAME,
+        "email": author_email(user),
+        "product_id": product_id,
+    });
+
+    let goose = send(user, GooseMethod::Post, "/api/v1/questions", "/api/v1/questions", Some(question), 201).await?;
+    created_id(user, goose, "question_id").await
+}
+
+/// Posts a tagged answer with up to three photos and returns its id, if it was created.
+async fn post_answer(user: &mut GooseUser, question_id: i64) -> Result<Option<i64>, Box<TransactionError>> {
+    let (body, photos) = {
+        let mut rng = rand::thread_rng();
+        let photos: Vec<String> = (0..rng.gen_range(0..=3))
+            .map(|_| format!("https://images.example.com/loadtest/{}.jpg", rng.gen_range(1..10000)))
+            .collect();
+        (*ANSWERS.choose(&mut rng).unwrap(), photos)
+    };
+    let answer = json!({
+        "body": format!("{} {}", load_test().tag, body),
+        "name": LOAD_TEST_NAME,
+        "email": author_email(user),
+        "photos": photos,
+    });
+
+    let path = format!("/api/v1/questions/{}/answers", question_id);
+    let goose = send(user, GooseMethod::Post, &path, "/api/v1/questions/:id/answers", Some(answer), 201).await?;
+    created_id(user, goose, "answer_id").await
+}
+
+fn report() -> Value {
+    let reason = *REPORT_REASONS.choose(&mut rand::thread_rng()).unwrap();
+    json!({ "reason": reason, "note": format!("{} load test report", load_test().tag) })
+}
+
+fn author_email(user: &GooseUser) -> String {
+    format!("loadtest+{}@example.com", user.weighted_users_index)
+}
+
+/// Sends a write with the configured credentials and a user token of its own per user, so
+/// votes are counted once per simulated shopper. `name` groups requests in the metrics.
+async fn send(user: &mut GooseUser, method: GooseMethod, path: &str, name: &str, body: Option<Value>, expected_status: u16) -> Result<GooseResponse, Box<TransactionError>> {
+    let settings = load_test();
+    let mut builder = user
+        .get_request_builder(&method, path)?
+        .header("x-user-token", format!("{}-{}", settings.run, user.weighted_users_index));
+    if let Some(api_key) = &settings.api_key {
+        builder = builder.header("x-api-key", api_key);
+    }
+    if let Some(token) = &settings.bearer_token {
+        builder = builder.bearer_auth(token);
+    }
+    if let Some(body) = body {
+        builder = builder.json(&body);
+    }
+
+    let request = GooseRequest::builder()
+        .method(method)
+        .path(path)
+        .name(name)
+        .expect_status_code(expected_status)
+        .set_request_builder(builder)
+        .build();
+    user.request(request).await
+}
+
+/// Returns the id `field` of a created row, marking the request failed when a `201 Created`
+/// response is missing it.
+async fn created_id(user: &mut GooseUser, goose: GooseResponse, field: &str) -> Result<Option<i64>, Box<TransactionError>> {
+    let mut id = None;
+    validate(user, goose, |body| {
+        id = body[field].as_i64();
+        id.map(|_| ()).ok_or_else(|| format!("no {} in {}", field, body))
+    })
+    .await?;
+    Ok(id)
+}
@@ -0,0 +1,35 @@
+-- Tables as they stand after schema.sql has imported the CSV dataset.
+-- Everything is IF NOT EXISTS so databases bootstrapped by schema.sql pick up
+-- later migrations without conflicts.
+
+CREATE TABLE IF NOT EXISTS questions (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL,
+    body VARCHAR,
+    date_written TIMESTAMP WITHOUT TIME ZONE,
+    asker_name VARCHAR,
+    asker_email VARCHAR,
+    reported BOOLEAN,
+    helpful INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS answers (
+    id SERIAL PRIMARY KEY,
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    body VARCHAR,
+    date_written TIMESTAMP WITHOUT TIME ZONE,
+    answerer_name VARCHAR,
+    answerer_email VARCHAR,
+    reported BOOLEAN,
+    helpful INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS answer_photos (
+    id SERIAL PRIMARY KEY,
+    answer_id INTEGER NOT NULL REFERENCES answers(id),
+    url VARCHAR
+);
+
+CREATE INDEX IF NOT EXISTS questions_product_id_idx ON questions(product_id);
+CREATE INDEX IF NOT EXISTS answers_question_id_idx ON answers(question_id);
+CREATE INDEX IF NOT EXISTS answers_photos_id_idx ON answer_photos(answer_id);
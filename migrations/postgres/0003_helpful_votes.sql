@@ -0,0 +1,17 @@
+-- One row per voter and question or answer they marked helpful, so that repeated votes
+-- are not counted twice. `voter` is the keyed hash computed by votes::VoterHasher.
+-- Counts imported with the CSV dataset have no rows here.
+
+CREATE TABLE IF NOT EXISTS question_votes (
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    voter TEXT NOT NULL,
+    voted_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (question_id, voter)
+);
+
+CREATE TABLE IF NOT EXISTS answer_votes (
+    answer_id INTEGER NOT NULL REFERENCES answers(id),
+    voter TEXT NOT NULL,
+    voted_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (answer_id, voter)
+);
@@ -0,0 +1,43 @@
+-- Individual reports behind the `reported` flag, and the moderation of reported content.
+-- `removed_at` is set when a moderator removes content; it stays hidden like any reported
+-- row but no longer shows up in the moderation queue.
+
+ALTER TABLE questions ADD COLUMN IF NOT EXISTS removed_at TIMESTAMP WITHOUT TIME ZONE;
+ALTER TABLE answers ADD COLUMN IF NOT EXISTS removed_at TIMESTAMP WITHOUT TIME ZONE;
+
+CREATE TABLE IF NOT EXISTS question_reports (
+    id SERIAL PRIMARY KEY,
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    reason TEXT NOT NULL,
+    note TEXT,
+    reporter TEXT NOT NULL,
+    reported_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE TABLE IF NOT EXISTS answer_reports (
+    id SERIAL PRIMARY KEY,
+    answer_id INTEGER NOT NULL REFERENCES answers(id),
+    reason TEXT NOT NULL,
+    note TEXT,
+    reporter TEXT NOT NULL,
+    reported_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE TABLE IF NOT EXISTS moderation_actions (
+    id SERIAL PRIMARY KEY,
+    target_type TEXT NOT NULL,
+    target_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    moderator TEXT,
+    note TEXT,
+    reports_resolved INTEGER NOT NULL,
+    acted_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS question_reports_pending_idx ON question_reports(question_id) WHERE resolved_at IS NULL;
+CREATE INDEX IF NOT EXISTS answer_reports_pending_idx ON answer_reports(answer_id) WHERE resolved_at IS NULL;
+CREATE INDEX IF NOT EXISTS questions_moderation_queue_idx ON questions(id) WHERE reported = true AND removed_at IS NULL;
+CREATE INDEX IF NOT EXISTS answers_moderation_queue_idx ON answers(id) WHERE reported = true AND removed_at IS NULL;
+CREATE INDEX IF NOT EXISTS moderation_actions_target_idx ON moderation_actions(target_type, target_id);
@@ -0,0 +1,12 @@
+-- API keys for authenticating clients. Only a SHA-256 hash of each key is stored,
+-- plus a short prefix to tell keys apart. `scopes` is a comma-separated list.
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMP WITHOUT TIME ZONE
+);
@@ -0,0 +1,8 @@
+-- Keyed hashes of asker and answerer emails, for looking authors up once the emails
+-- themselves are encrypted or hashed. NULL for rows stored as plaintext.
+
+ALTER TABLE questions ADD COLUMN IF NOT EXISTS asker_email_hash TEXT;
+ALTER TABLE answers ADD COLUMN IF NOT EXISTS answerer_email_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS questions_asker_email_hash_idx ON questions(asker_email_hash);
+CREATE INDEX IF NOT EXISTS answers_answerer_email_hash_idx ON answers(answerer_email_hash);
@@ -0,0 +1,21 @@
+-- Audit log of data-subject requests: exports and erasures of everything tied to an
+-- email. The email itself is only kept as a fingerprint so the log survives erasure.
+
+CREATE TABLE IF NOT EXISTS data_requests (
+    id SERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    content_policy TEXT,
+    questions INTEGER NOT NULL,
+    answers INTEGER NOT NULL,
+    photos INTEGER NOT NULL,
+    requested_by TEXT,
+    reference TEXT,
+    requested_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS data_requests_subject_idx ON data_requests(subject);
+
+-- Rows stored before email protection was turned on are matched on their plaintext email.
+CREATE INDEX IF NOT EXISTS questions_asker_email_lower_idx ON questions(lower(asker_email));
+CREATE INDEX IF NOT EXISTS answers_answerer_email_lower_idx ON answers(lower(answerer_email));
@@ -0,0 +1,15 @@
+-- Full-text search over question and answer bodies. The vectors are generated from the
+-- bodies, so they follow every insert, edit and erasure without triggers.
+
+ALTER TABLE questions ADD COLUMN IF NOT EXISTS search_vector tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', COALESCE(body, ''))) STORED;
+ALTER TABLE answers ADD COLUMN IF NOT EXISTS search_vector tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', COALESCE(body, ''))) STORED;
+
+CREATE INDEX IF NOT EXISTS questions_search_idx ON questions USING GIN (search_vector);
+CREATE INDEX IF NOT EXISTS answers_search_idx ON answers USING GIN (search_vector);
+
+-- Bodies are plain text, so they are escaped before snippets mark matches up with HTML.
+CREATE OR REPLACE FUNCTION html_escape(text) RETURNS text
+    LANGUAGE sql IMMUTABLE STRICT
+    AS $$ SELECT replace(replace(replace($1, '&', '&amp;'), '<', '&lt;'), '>', '&gt;') $$;
@@ -0,0 +1,18 @@
+-- Thumbnails and WebP variants of answer photos, made by the background worker in
+-- src/thumbnails.rs. A photo is `pending` until the worker has made its variants
+-- (`ready`) or given up on it (`failed`); `variants_claimed_at` leases a photo to the
+-- worker processing it, so that several instances never process the same photo. Photos
+-- already here are marked `backfill` and only processed with THUMBNAIL_BACKFILL=on.
+
+ALTER TABLE answer_photos
+    ADD COLUMN IF NOT EXISTS thumbnail_url VARCHAR,
+    ADD COLUMN IF NOT EXISTS thumbnail_webp_url VARCHAR,
+    ADD COLUMN IF NOT EXISTS width INTEGER,
+    ADD COLUMN IF NOT EXISTS height INTEGER,
+    ADD COLUMN IF NOT EXISTS variants_status TEXT NOT NULL DEFAULT 'pending',
+    ADD COLUMN IF NOT EXISTS variants_attempts INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS variants_claimed_at TIMESTAMP WITHOUT TIME ZONE;
+
+UPDATE answer_photos SET variants_status = 'backfill' WHERE variants_status = 'pending';
+
+CREATE INDEX IF NOT EXISTS answer_photos_pending_variants_idx ON answer_photos(id) WHERE variants_status IN ('pending', 'backfill');
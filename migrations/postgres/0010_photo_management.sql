@@ -0,0 +1,37 @@
+-- Photos can be reported on their own, which hides them without hiding their answer, and
+-- moderated like questions and answers. An answer holds at most 10 photos that are not
+-- removed; the trigger locks the answer so that concurrent uploads cannot both slip under
+-- the limit.
+
+ALTER TABLE answer_photos
+    ADD COLUMN IF NOT EXISTS reported BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS removed_at TIMESTAMP WITHOUT TIME ZONE;
+
+CREATE TABLE IF NOT EXISTS photo_reports (
+    id SERIAL PRIMARY KEY,
+    photo_id INTEGER NOT NULL REFERENCES answer_photos(id),
+    reason TEXT NOT NULL,
+    note TEXT,
+    reporter TEXT NOT NULL,
+    reported_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS photo_reports_pending_idx ON photo_reports(photo_id) WHERE resolved_at IS NULL;
+CREATE INDEX IF NOT EXISTS answer_photos_moderation_queue_idx ON answer_photos(id) WHERE reported = true AND removed_at IS NULL;
+
+CREATE OR REPLACE FUNCTION enforce_answer_photo_limit() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+    PERFORM 1 FROM answers WHERE id = NEW.answer_id FOR UPDATE;
+    IF (SELECT COUNT(*) FROM answer_photos WHERE answer_id = NEW.answer_id AND removed_at IS NULL) >= 10 THEN
+        RAISE EXCEPTION 'answer_photo_limit' USING ERRCODE = 'check_violation';
+    END IF;
+    RETURN NEW;
+END
+$$;
+
+DROP TRIGGER IF EXISTS answer_photos_limit ON answer_photos;
+CREATE TRIGGER answer_photos_limit BEFORE INSERT ON answer_photos
+    FOR EACH ROW EXECUTE FUNCTION enforce_answer_photo_limit();
@@ -0,0 +1,18 @@
+-- Verification of photos registered by URL, done by the background job in
+-- src/verification.rs. A photo is `pending` until the job has fetched it and found an image
+-- (`ok`) or flagged it (`broken`), with the reason in `verify_error`. Broken photos are left
+-- out of responses. `verify_claimed_at` leases a photo to the job checking it, like
+-- `variants_claimed_at`. Uploaded photos are checked as they are uploaded, so they are
+-- inserted as `ok`. Photos already here were imported or added before verification
+-- existed and are taken as they are, so only photos added from now on are fetched.
+
+ALTER TABLE answer_photos
+    ADD COLUMN IF NOT EXISTS verify_status TEXT NOT NULL DEFAULT 'pending',
+    ADD COLUMN IF NOT EXISTS verify_attempts INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS verify_claimed_at TIMESTAMP WITHOUT TIME ZONE,
+    ADD COLUMN IF NOT EXISTS verify_error TEXT,
+    ADD COLUMN IF NOT EXISTS verified_at TIMESTAMP WITHOUT TIME ZONE;
+
+UPDATE answer_photos SET verify_status = 'ok' WHERE verify_status = 'pending';
+
+CREATE INDEX IF NOT EXISTS answer_photos_pending_verification_idx ON answer_photos(id) WHERE verify_status = 'pending';
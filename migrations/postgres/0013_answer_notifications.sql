@@ -0,0 +1,29 @@
+-- Emails telling askers their questions were answered, see src/notifications.rs. A row is
+-- queued with each answer while NOTIFY_ANSWERS is on, and stays `pending` until the
+-- notifier has `sent` it, `skipped` it (the asker opted out, has no address left, or the
+-- answer was reported) or given up on it as `failed`, with the reason in `last_error`.
+-- Answers for the same address are sent together, once the oldest of them has waited
+-- NOTIFY_DELAY_SECS. `claimed_at` leases a row to the notifier sending it, like
+-- `variants_claimed_at`.
+-- `email_opt_outs` holds the addresses that unsubscribed, as keyed hashes only.
+
+CREATE TABLE IF NOT EXISTS answer_notifications (
+    id BIGSERIAL PRIMARY KEY,
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    answer_id INTEGER NOT NULL REFERENCES answers(id),
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW(),
+    claimed_at TIMESTAMP WITHOUT TIME ZONE,
+    last_error TEXT,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW(),
+    sent_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS answer_notifications_pending_idx ON answer_notifications(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS answer_notifications_answer_id_idx ON answer_notifications(answer_id);
+
+CREATE TABLE IF NOT EXISTS email_opt_outs (
+    address_hash TEXT PRIMARY KEY,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT NOW()
+);
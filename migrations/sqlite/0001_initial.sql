@@ -0,0 +1,34 @@
+-- SQLite schema mirroring the Postgres tables created by schema.sql.
+-- Dates are stored as ISO-8601 text so they serialize like Postgres timestamps.
+
+CREATE TABLE IF NOT EXISTS questions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    product_id INTEGER NOT NULL,
+    body TEXT,
+    date_written TEXT,
+    asker_name TEXT,
+    asker_email TEXT,
+    reported BOOLEAN,
+    helpful INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS answers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    body TEXT,
+    date_written TEXT,
+    answerer_name TEXT,
+    answerer_email TEXT,
+    reported BOOLEAN,
+    helpful INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS answer_photos (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    answer_id INTEGER NOT NULL REFERENCES answers(id),
+    url TEXT
+);
+
+CREATE INDEX IF NOT EXISTS questions_product_id_idx ON questions(product_id);
+CREATE INDEX IF NOT EXISTS answers_question_id_idx ON answers(question_id);
+CREATE INDEX IF NOT EXISTS answers_photos_id_idx ON answer_photos(answer_id);
@@ -0,0 +1,5 @@
+-- Last time a question or answer changed after it was written (helpful votes, reports).
+-- NULL means the row is unchanged since date_written.
+
+ALTER TABLE questions ADD COLUMN updated_at TEXT;
+ALTER TABLE answers ADD COLUMN updated_at TEXT;
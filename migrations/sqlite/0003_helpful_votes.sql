@@ -0,0 +1,15 @@
+-- One row per voter and question or answer they marked helpful.
+
+CREATE TABLE IF NOT EXISTS question_votes (
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    voter TEXT NOT NULL,
+    voted_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now')),
+    PRIMARY KEY (question_id, voter)
+);
+
+CREATE TABLE IF NOT EXISTS answer_votes (
+    answer_id INTEGER NOT NULL REFERENCES answers(id),
+    voter TEXT NOT NULL,
+    voted_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now')),
+    PRIMARY KEY (answer_id, voter)
+);
@@ -0,0 +1,39 @@
+-- Individual reports behind the `reported` flag, and the moderation of reported content.
+
+ALTER TABLE questions ADD COLUMN removed_at TEXT;
+ALTER TABLE answers ADD COLUMN removed_at TEXT;
+
+CREATE TABLE IF NOT EXISTS question_reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    reason TEXT NOT NULL,
+    note TEXT,
+    reporter TEXT NOT NULL,
+    reported_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now')),
+    resolved_at TEXT
+);
+
+CREATE TABLE IF NOT EXISTS answer_reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    answer_id INTEGER NOT NULL REFERENCES answers(id),
+    reason TEXT NOT NULL,
+    note TEXT,
+    reporter TEXT NOT NULL,
+    reported_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now')),
+    resolved_at TEXT
+);
+
+CREATE TABLE IF NOT EXISTS moderation_actions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    target_type TEXT NOT NULL,
+    target_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    moderator TEXT,
+    note TEXT,
+    reports_resolved INTEGER NOT NULL,
+    acted_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS question_reports_pending_idx ON question_reports(question_id) WHERE resolved_at IS NULL;
+CREATE INDEX IF NOT EXISTS answer_reports_pending_idx ON answer_reports(answer_id) WHERE resolved_at IS NULL;
+CREATE INDEX IF NOT EXISTS moderation_actions_target_idx ON moderation_actions(target_type, target_id);
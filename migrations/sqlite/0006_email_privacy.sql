@@ -0,0 +1,8 @@
+-- Keyed hashes of asker and answerer emails, for looking authors up once the emails
+-- themselves are encrypted or hashed.
+
+ALTER TABLE questions ADD COLUMN asker_email_hash TEXT;
+ALTER TABLE answers ADD COLUMN answerer_email_hash TEXT;
+
+CREATE INDEX questions_asker_email_hash_idx ON questions(asker_email_hash);
+CREATE INDEX answers_answerer_email_hash_idx ON answers(answerer_email_hash);
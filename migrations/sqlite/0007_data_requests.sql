@@ -0,0 +1,18 @@
+-- Audit log of data-subject requests, with the email kept only as a fingerprint.
+
+CREATE TABLE IF NOT EXISTS data_requests (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    content_policy TEXT,
+    questions INTEGER NOT NULL,
+    answers INTEGER NOT NULL,
+    photos INTEGER NOT NULL,
+    requested_by TEXT,
+    reference TEXT,
+    requested_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now'))
+);
+
+CREATE INDEX data_requests_subject_idx ON data_requests(subject);
+CREATE INDEX questions_asker_email_lower_idx ON questions(lower(asker_email));
+CREATE INDEX answers_answerer_email_lower_idx ON answers(lower(answerer_email));
@@ -0,0 +1,41 @@
+-- Full-text search over question and answer bodies. The indexes hold the bodies HTML
+-- escaped, so the snippets cut from them can mark matches up safely, and are kept in
+-- step with the tables by triggers.
+
+CREATE VIRTUAL TABLE questions_fts USING fts5(body, tokenize = 'porter unicode61');
+CREATE VIRTUAL TABLE answers_fts USING fts5(body, tokenize = 'porter unicode61');
+
+INSERT INTO questions_fts (rowid, body)
+SELECT id, replace(replace(replace(body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;') FROM questions;
+INSERT INTO answers_fts (rowid, body)
+SELECT id, replace(replace(replace(body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;') FROM answers;
+
+CREATE TRIGGER questions_fts_insert AFTER INSERT ON questions BEGIN
+    INSERT INTO questions_fts (rowid, body)
+    VALUES (new.id, replace(replace(replace(new.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'));
+END;
+
+CREATE TRIGGER questions_fts_update AFTER UPDATE OF body ON questions BEGIN
+    DELETE FROM questions_fts WHERE rowid = old.id;
+    INSERT INTO questions_fts (rowid, body)
+    VALUES (new.id, replace(replace(replace(new.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'));
+END;
+
+CREATE TRIGGER questions_fts_delete AFTER DELETE ON questions BEGIN
+    DELETE FROM questions_fts WHERE rowid = old.id;
+END;
+
+CREATE TRIGGER answers_fts_insert AFTER INSERT ON answers BEGIN
+    INSERT INTO answers_fts (rowid, body)
+    VALUES (new.id, replace(replace(replace(new.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'));
+END;
+
+CREATE TRIGGER answers_fts_update AFTER UPDATE OF body ON answers BEGIN
+    DELETE FROM answers_fts WHERE rowid = old.id;
+    INSERT INTO answers_fts (rowid, body)
+    VALUES (new.id, replace(replace(replace(new.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'));
+END;
+
+CREATE TRIGGER answers_fts_delete AFTER DELETE ON answers BEGIN
+    DELETE FROM answers_fts WHERE rowid = old.id;
+END;
@@ -0,0 +1,14 @@
+-- Thumbnails and WebP variants of answer photos, made by the background worker. Photos
+-- already here are marked `backfill` and only processed with THUMBNAIL_BACKFILL=on.
+
+ALTER TABLE answer_photos ADD COLUMN thumbnail_url TEXT;
+ALTER TABLE answer_photos ADD COLUMN thumbnail_webp_url TEXT;
+ALTER TABLE answer_photos ADD COLUMN width INTEGER;
+ALTER TABLE answer_photos ADD COLUMN height INTEGER;
+ALTER TABLE answer_photos ADD COLUMN variants_status TEXT NOT NULL DEFAULT 'pending';
+ALTER TABLE answer_photos ADD COLUMN variants_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE answer_photos ADD COLUMN variants_claimed_at TEXT;
+
+UPDATE answer_photos SET variants_status = 'backfill';
+
+CREATE INDEX answer_photos_pending_variants_idx ON answer_photos(id) WHERE variants_status IN ('pending', 'backfill');
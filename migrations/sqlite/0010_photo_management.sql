@@ -0,0 +1,23 @@
+-- Photos reported on their own, and the limit of 10 photos an answer holds.
+
+ALTER TABLE answer_photos ADD COLUMN reported BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE answer_photos ADD COLUMN removed_at TEXT;
+
+CREATE TABLE photo_reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    photo_id INTEGER NOT NULL REFERENCES answer_photos(id),
+    reason TEXT NOT NULL,
+    note TEXT,
+    reporter TEXT NOT NULL,
+    reported_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now')),
+    resolved_at TEXT
+);
+
+CREATE INDEX photo_reports_pending_idx ON photo_reports(photo_id) WHERE resolved_at IS NULL;
+CREATE INDEX answer_photos_moderation_queue_idx ON answer_photos(id) WHERE reported = true AND removed_at IS NULL;
+
+CREATE TRIGGER answer_photos_limit BEFORE INSERT ON answer_photos
+WHEN (SELECT COUNT(*) FROM answer_photos WHERE answer_id = NEW.answer_id AND removed_at IS NULL) >= 10
+BEGIN
+    SELECT RAISE(ABORT, 'answer_photo_limit');
+END;
@@ -0,0 +1,12 @@
+-- Verification of photos registered by URL, done by the background job. Photos already
+-- here predate it and are taken as they are.
+
+ALTER TABLE answer_photos ADD COLUMN verify_status TEXT NOT NULL DEFAULT 'pending';
+ALTER TABLE answer_photos ADD COLUMN verify_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE answer_photos ADD COLUMN verify_claimed_at TEXT;
+ALTER TABLE answer_photos ADD COLUMN verify_error TEXT;
+ALTER TABLE answer_photos ADD COLUMN verified_at TEXT;
+
+UPDATE answer_photos SET verify_status = 'ok';
+
+CREATE INDEX answer_photos_pending_verification_idx ON answer_photos(id) WHERE verify_status = 'pending';
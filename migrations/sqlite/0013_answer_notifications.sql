@@ -0,0 +1,22 @@
+-- Emails telling askers their questions were answered, and the addresses that opted out.
+
+CREATE TABLE IF NOT EXISTS answer_notifications (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    question_id INTEGER NOT NULL REFERENCES questions(id),
+    answer_id INTEGER NOT NULL REFERENCES answers(id),
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now')),
+    claimed_at TEXT,
+    last_error TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now')),
+    sent_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS answer_notifications_pending_idx ON answer_notifications(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS answer_notifications_answer_id_idx ON answer_notifications(answer_id);
+
+CREATE TABLE IF NOT EXISTS email_opt_outs (
+    address_hash TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f', 'now'))
+);
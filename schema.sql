@@ -1,4 +1,7 @@
 -- psql -U bootdme -d postgres -f schema.sql
+--
+-- Bootstraps the database from the CSV dataset. Later schema changes live in
+-- migrations/postgres and are applied by the server when it starts.
 
 DROP DATABASE IF EXISTS qa;
 CREATE DATABASE qa;
@@ -0,0 +1,271 @@
+use crate::db::Database;
+use crate::jwt::{JwtVerifier, TokenError, User};
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::utils::hex;
+use hyper::header::{HeaderMap, AUTHORIZATION};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Header through which clients pass their API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Permissions an API key can be granted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// Reading questions and answers.
+    Read,
+    /// Asking, answering, voting and reporting.
+    Write,
+    /// The moderation endpoints. Implies `read` and `write`.
+    Admin,
+}
+
+impl Scope {
+    pub fn parse(value: &str) -> Option<Scope> {
+        match value {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// Parses a comma-separated scope list such as `read,write`. An empty string is no scopes.
+pub fn parse_scopes(value: &str) -> Result<Vec<Scope>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|scope| !scope.is_empty())
+        .map(|scope| Scope::parse(scope).ok_or_else(|| format!("Unknown scope: {}", scope)))
+        .collect()
+}
+
+pub fn format_scopes(scopes: &[Scope]) -> String {
+    scopes.iter().map(Scope::as_str).collect::<Vec<_>>().join(",")
+}
+
+/// Who a request was made by, as established by [`authenticate`].
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// The API key the request was made with, or `None` for anonymous requests.
+    pub key_name: Option<String>,
+    pub scopes: Vec<Scope>,
+    /// The signed-in shopper named by the request's bearer token, if it has one.
+    pub user: Option<User>,
+}
+
+impl Principal {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+}
+
+/// How requests without an API key are treated, and how bearer tokens are verified.
+pub struct AuthConfig {
+    pub anonymous_scopes: Vec<Scope>,
+    /// Verifies shoppers' bearer tokens. Without one, `Authorization` headers are ignored.
+    pub jwt: Option<JwtVerifier>,
+}
+
+impl AuthConfig {
+    /// Reads the scopes granted to requests without a key from `ANONYMOUS_SCOPES`, a
+    /// comma-separated list defaulting to `read`. Set it to an empty string to require a
+    /// key for every request. Bearer tokens are configured as described in
+    /// [`JwtVerifier::from_env`].
+    pub fn from_env() -> Result<AuthConfig, String> {
+        let anonymous_scopes = match std::env::var("ANONYMOUS_SCOPES") {
+            Ok(scopes) => parse_scopes(&scopes)?,
+            Err(_) => vec![Scope::Read],
+        };
+
+        Ok(AuthConfig { anonymous_scopes, jwt: JwtVerifier::from_env()? })
+    }
+}
+
+/// Why a request could not be authenticated.
+#[derive(Debug)]
+pub enum AuthError {
+    /// The API key header is malformed, unknown or revoked.
+    InvalidKey,
+    /// The bearer token is malformed or fails verification.
+    InvalidToken,
+    /// The bearer token has expired.
+    ExpiredToken,
+    Database(sqlx::Error),
+}
+
+/// Resolves the API key sent with a request, falling back to the anonymous scopes when
+/// there is none, and the shopper named by its bearer token.
+///
+/// A valid bearer token adds the `read` and `write` scopes, so signed-in shoppers can ask,
+/// answer and vote even where anonymous requests may only read. It never grants `admin`.
+pub async fn authenticate(db: &Database, config: &AuthConfig, headers: &HeaderMap) -> Result<Principal, AuthError> {
+    // Tokens are checked first as that needs no database round trip.
+    let user = bearer_user(config, headers)?;
+
+    let mut principal = match headers.get(API_KEY_HEADER) {
+        Some(key) => key_principal(db, key.to_str().map_err(|_| AuthError::InvalidKey)?).await?,
+        None => Principal { key_name: None, scopes: config.anonymous_scopes.clone(), user: None },
+    };
+
+    if let Some(user) = user {
+        for scope in [Scope::Read, Scope::Write] {
+            if !principal.scopes.contains(&scope) {
+                principal.scopes.push(scope);
+            }
+        }
+        principal.user = Some(user);
+    }
+
+    Ok(principal)
+}
+
+/// Verifies the `Authorization: Bearer` token of a request, if it has one.
+fn bearer_user(config: &AuthConfig, headers: &HeaderMap) -> Result<Option<User>, AuthError> {
+    let (Some(verifier), Some(value)) = (&config.jwt, headers.get(AUTHORIZATION)) else {
+        return Ok(None);
+    };
+
+    let token = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(AuthError::InvalidToken)?;
+
+    match verifier.verify(token.trim()) {
+        Ok(user) => Ok(Some(user)),
+        Err(TokenError::Expired) => Err(AuthError::ExpiredToken),
+        Err(TokenError::Invalid) => Err(AuthError::InvalidToken),
+    }
+}
+
+async fn key_principal(db: &Database, key: &str) -> Result<Principal, AuthError> {
+    let key_hash = hash_key(key);
+    let row = match db {
+        Database::Postgres(pool) => {
+            sqlx::query!(
+                "SELECT name, scopes FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL;",
+                key_hash
+            )
+            .fetch_optional(pool)
+            .await
+            .map(|row| row.map(|row| (row.name, row.scopes)))
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::find_api_key(pool, &key_hash).await,
+    };
+
+    match row {
+        Ok(Some((name, scopes))) => Ok(Principal {
+            key_name: Some(name),
+            // Scopes are validated when keys are created, so unknown ones can only come from
+            // manual edits and are ignored.
+            scopes: scopes.split(',').filter_map(Scope::parse).collect(),
+            user: None,
+        }),
+        Ok(None) => Err(AuthError::InvalidKey),
+        Err(e) => Err(AuthError::Database(e)),
+    }
+}
+
+/// A stored API key, as listed by the admin CLI. The key itself is never stored.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ApiKeyRecord {
+    pub id: i32,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: String,
+    pub created_at: String,
+    pub revoked_at: Option<String>,
+}
+
+/// Length of the key prefix kept in clear text so keys can be told apart in listings.
+const KEY_PREFIX_LEN: usize = 11;
+
+/// Creates an API key and returns its id and the key. Only a hash of the key is stored, so
+/// this is the one time it can be shown.
+pub async fn create_api_key(db: &Database, name: &str, scopes: &[Scope]) -> Result<(i32, String), sqlx::Error> {
+    let mut secret = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let key = format!("qa_{}", hex(&secret));
+
+    let key_prefix = &key[..KEY_PREFIX_LEN];
+    let key_hash = hash_key(&key);
+    let scopes = format_scopes(scopes);
+
+    let id = match db {
+        Database::Postgres(pool) => {
+            sqlx::query_scalar!(
+                "INSERT INTO api_keys (name, key_prefix, key_hash, scopes) VALUES ($1, $2, $3, $4) RETURNING id;",
+                name,
+                key_prefix,
+                key_hash,
+                scopes
+            )
+            .fetch_one(pool)
+            .await?
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::insert_api_key(pool, name, key_prefix, &key_hash, &scopes).await?,
+    };
+
+    Ok((id, key))
+}
+
+pub async fn list_api_keys(db: &Database) -> Result<Vec<ApiKeyRecord>, sqlx::Error> {
+    match db {
+        Database::Postgres(pool) => {
+            sqlx::query_as!(
+                ApiKeyRecord,
+                r#"
+                SELECT
+                    id,
+                    name,
+                    key_prefix,
+                    scopes,
+                    to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "created_at!",
+                    to_char(revoked_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS revoked_at
+                FROM api_keys
+                ORDER BY id;
+                "#
+            )
+            .fetch_all(pool)
+            .await
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::list_api_keys(pool).await,
+    }
+}
+
+/// Revokes a key. Returns `false` if there is no such key or it was already revoked.
+pub async fn revoke_api_key(db: &Database, id: i32) -> Result<bool, sqlx::Error> {
+    let result = match db {
+        Database::Postgres(pool) => {
+            sqlx::query!("UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL;", id)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::revoke_api_key(pool, id).await?,
+    };
+
+    Ok(result > 0)
+}
+
+/// Keys are long random strings, so a plain SHA-256 is enough to make the stored hashes
+/// useless to someone reading the table.
+fn hash_key(key: &str) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
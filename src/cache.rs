@@ -0,0 +1,159 @@
+use crate::conditional::Representation;
+use crate::models::QuestionSort;
+use lru::LruCache;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Identifies one page of `GET /api/v1/questions` for a product.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct QuestionsPage {
+    pub page: i32,
+    pub count: i32,
+    pub sort: QuestionSort,
+}
+
+/// Hit/miss counters of a [`QuestionsCache`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+    pub products: usize,
+    pub pages: usize,
+}
+
+/// Most pages kept per product; the oldest is evicted to make room for another.
+pub const MAX_PAGES_PER_PRODUCT: usize = 32;
+
+type ProductPages = HashMap<QuestionsPage, (Instant, Representation)>;
+
+/// In-process cache of serialized `GET /api/v1/questions` responses and their validators.
+///
+/// Pages are grouped per product so that a write can drop every cached page of the product
+/// it touched in one step; once `capacity` products are cached the least recently used one
+/// is evicted, and each product keeps at most [`MAX_PAGES_PER_PRODUCT`] pages. Every replica keeps its own cache, so writes served by another replica only
+/// show up here once the TTL has expired.
+pub struct QuestionsCache {
+    products: Option<Mutex<LruCache<i32, ProductPages>>>,
+    ttl: Duration,
+    epoch: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl QuestionsCache {
+    /// Creates a cache holding pages for up to `capacity` products for `ttl` each.
+    /// A zero capacity or TTL disables caching.
+    pub fn new(capacity: usize, ttl: Duration) -> QuestionsCache {
+        let products = NonZeroUsize::new(capacity)
+            .filter(|_| !ttl.is_zero())
+            .map(|capacity| Mutex::new(LruCache::new(capacity)));
+
+        QuestionsCache {
+            products,
+            ttl,
+            epoch: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates a cache configured by `QUESTIONS_CACHE_CAPACITY` (products, default 10000)
+    /// and `QUESTIONS_CACHE_TTL_SECS` (default 30).
+    pub fn from_env() -> QuestionsCache {
+        let capacity = std::env::var("QUESTIONS_CACHE_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10_000);
+        let ttl = std::env::var("QUESTIONS_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+
+        QuestionsCache::new(capacity, Duration::from_secs(ttl))
+    }
+
+    /// A cache that never stores anything.
+    pub fn disabled() -> QuestionsCache {
+        QuestionsCache::new(0, Duration::ZERO)
+    }
+
+    /// Returns the cached response for `page`, if it is present and still fresh.
+    pub fn get(&self, product_id: i32, page: &QuestionsPage) -> Option<Representation> {
+        let products = self.products.as_ref()?;
+        let mut products = products.lock().unwrap();
+
+        let representation = products
+            .get(&product_id)
+            .and_then(|pages| pages.get(page))
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, representation)| representation.clone());
+
+        match representation {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        representation
+    }
+
+    /// Returns a marker to pass to [`QuestionsCache::insert`]; take it before querying the
+    /// database so that results racing with a write are not cached.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Stores the response for `page`, unless any write invalidated the cache since `epoch`.
+    pub fn insert(&self, product_id: i32, page: QuestionsPage, epoch: u64, representation: Representation) {
+        let Some(products) = self.products.as_ref() else {
+            return;
+        };
+        let mut products = products.lock().unwrap();
+
+        if self.epoch.load(Ordering::Acquire) != epoch {
+            return;
+        }
+
+        let pages = products.get_or_insert_mut(product_id, HashMap::new);
+        pages.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+        if pages.len() >= MAX_PAGES_PER_PRODUCT && !pages.contains_key(&page) {
+            if let Some(oldest) = pages.iter().min_by_key(|(_, (inserted, _))| *inserted).map(|(page, _)| *page) {
+                pages.remove(&oldest);
+            }
+        }
+        pages.insert(page, (Instant::now(), representation));
+    }
+
+    /// Drops every cached page of `product_id`.
+    pub fn invalidate(&self, product_id: i32) {
+        let Some(products) = self.products.as_ref() else {
+            return;
+        };
+        let mut products = products.lock().unwrap();
+
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+        if products.pop(&product_id).is_some() {
+            self.invalidations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (products, pages) = self.products.as_ref().map_or((0, 0), |products| {
+            let products = products.lock().unwrap();
+            (products.len(), products.iter().map(|(_, pages)| pages.len()).sum())
+        });
+
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            products,
+            pages,
+        }
+    }
+}
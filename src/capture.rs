@@ -0,0 +1,177 @@
+use crate::utils::{log_error, redact_emails};
+use hyper::body::{Bytes, HttpBody};
+use hyper::http::request::Parts;
+use hyper::Body;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The address every `email` in a captured body is replaced with. It is still a valid
+/// address, so that replayed posts are accepted.
+pub const REDACTED_EMAIL: &str = "redacted@example.com";
+
+/// Length of the [`CapturedRequest::client`] pseudonym, in hex digits.
+const CLIENT_LEN: usize = 16;
+
+/// Largest body captured; larger bodies are passed on without being read in whole.
+pub const MAX_CAPTURED_BODY: usize = 64 * 1024;
+
+/// A request as captured to a line of the capture file, without its headers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedRequest {
+    /// When the request arrived, in milliseconds since the Unix epoch.
+    pub at_ms: u64,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    /// The JSON body with emails redacted, or `None` for empty, oversized and other bodies.
+    pub body: Option<Value>,
+    /// A pseudonym of the voter the request came from, the same for all of its requests
+    /// so that replayed votes are cast by as many voters as the captured ones.
+    pub client: String,
+    pub status: u16,
+    pub duration_ms: u64,
+}
+
+impl CapturedRequest {
+    /// Captures the request of `parts` and `body` from `voter`, a voter identity as derived
+    /// by [`VoterHasher`](crate::votes::VoterHasher), before it is handled.
+    pub fn new(parts: &Parts, body: &[u8], voter: &str, at: SystemTime) -> CapturedRequest {
+        CapturedRequest {
+            at_ms: at.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64),
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+            query: parts.uri.query().map(redact_emails),
+            body: sanitize_body(body),
+            client: voter.chars().take(CLIENT_LEN).collect(),
+            status: 0,
+            duration_ms: 0,
+        }
+    }
+}
+
+/// Reads `body` to capture it, up to `limit` bytes. Returns the body read and one to
+/// handle the request with, or, once it exceeds `limit`, `None` and a body yielding what was
+/// read followed by the rest, which is no longer buffered.
+pub async fn read_body(mut body: Body, limit: usize) -> Result<(Option<Bytes>, Body), hyper::Error> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if data.len() + chunk.len() > limit {
+            let (mut sender, rest) = Body::channel();
+            tokio::spawn(async move {
+                for chunk in [Bytes::from(data), chunk] {
+                    if sender.send_data(chunk).await.is_err() {
+                        return;
+                    }
+                }
+                while let Some(chunk) = body.data().await {
+                    let Ok(chunk) = chunk else {
+                        return sender.abort();
+                    };
+                    if sender.send_data(chunk).await.is_err() {
+                        return;
+                    }
+                }
+            });
+            return Ok((None, rest));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    let data = Bytes::from(data);
+    Ok((Some(data.clone()), Body::from(data)))
+}
+
+/// Parses a JSON body and redacts the emails in it: `email` fields are replaced with
+/// [`REDACTED_EMAIL`] and addresses in any other string with `[email]`.
+pub fn sanitize_body(body: &[u8]) -> Option<Value> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return None;
+    }
+    let mut body = serde_json::from_slice(body).ok()?;
+    sanitize(&mut body, false);
+    Some(body)
+}
+
+fn sanitize(value: &mut Value, is_email: bool) {
+    match value {
+        Value::String(text) if is_email => *text = REDACTED_EMAIL.to_string(),
+        Value::String(text) => *text = redact_emails(text),
+        Value::Array(items) => items.iter_mut().for_each(|item| sanitize(item, is_email)),
+        Value::Object(fields) => fields.iter_mut().for_each(|(name, field)| sanitize(field, name == "email")),
+        _ => {}
+    }
+}
+
+/// Writes handled requests to a JSONL file, one [`CapturedRequest`] a line, to replay
+/// them later with `get --replay`.
+///
+/// Admin routes are never captured: their bodies are about people rather than content,
+/// and replaying them would moderate or erase content.
+pub struct RequestCapture {
+    file: Option<Mutex<LineWriter<File>>>,
+}
+
+impl RequestCapture {
+    /// Appends captured requests to `path`, creating it if needed.
+    pub fn to_file(path: impl AsRef<Path>) -> std::io::Result<RequestCapture> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RequestCapture { file: Some(Mutex::new(LineWriter::new(file))) })
+    }
+
+    /// Captures to the file named by `CAPTURE_FILE`, or nothing when it is unset.
+    pub fn from_env() -> Result<RequestCapture, String> {
+        match std::env::var("CAPTURE_FILE") {
+            Ok(path) => RequestCapture::to_file(&path).map_err(|e| format!("Failed to open CAPTURE_FILE {}: {}", path, e)),
+            Err(_) => Ok(RequestCapture::disabled()),
+        }
+    }
+
+    /// A capture that records nothing.
+    pub fn disabled() -> RequestCapture {
+        RequestCapture { file: None }
+    }
+
+    /// Whether requests to `path` are captured.
+    pub fn captures(&self, path: &str) -> bool {
+        self.file.is_some() && !path.starts_with("/api/v1/admin/")
+    }
+
+    /// Appends `request` to the file. Failing to is logged, never failing the request.
+    pub fn record(&self, request: &CapturedRequest) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut line = serde_json::to_vec(request).expect("captured requests serialize");
+        line.push(b'\n');
+        if let Err(e) = file.lock().unwrap().write_all(&line) {
+            log_error("Failed to capture request", &e);
+        }
+    }
+}
+
+/// Reads a capture file, in order of arrival.
+pub fn parse_capture(text: &str) -> Result<Vec<CapturedRequest>, String> {
+    let mut requests = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| serde_json::from_str(line).map_err(|e| format!("Invalid captured request on line {}: {}", index + 1, e)))
+        .collect::<Result<Vec<CapturedRequest>, String>>()?;
+    requests.sort_by_key(|request| request.at_ms);
+    Ok(requests)
+}
+
+/// Names the route of a request `path` by replacing its numeric segments with `:id`, so
+/// that replayed requests are reported per route.
+pub fn route_name(method: &str, path: &str) -> String {
+    let path: Vec<&str> = path
+        .split('/')
+        .map(|segment| if !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()) { ":id" } else { segment })
+        .collect();
+    format!("{} {}", method, path.join("/"))
+}
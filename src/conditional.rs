@@ -0,0 +1,116 @@
+use crate::utils::hex;
+use hyper::{
+    body::Bytes,
+    header::{self, HeaderMap, HeaderValue},
+    Body, Response, StatusCode,
+};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A serialized JSON response body together with its validators.
+#[derive(Clone, Debug)]
+pub struct Representation {
+    pub body: Bytes,
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Representation {
+    /// Wraps `body`, deriving a strong ETag from its bytes. `last_modified` is a Unix
+    /// timestamp in seconds, as produced by the database queries.
+    pub fn new(body: Bytes, last_modified: Option<i64>) -> Representation {
+        let digest = Sha256::digest(&body);
+        let etag = format!(
+            "\"{}\"",
+            hex(&digest[..16])
+        );
+        let last_modified = last_modified
+            .and_then(|secs| u64::try_from(secs).ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+        Representation { body, etag, last_modified }
+    }
+}
+
+/// `Cache-Control` values sent with each cacheable route.
+pub struct CacheControl {
+    pub questions: HeaderValue,
+    pub answers: HeaderValue,
+}
+
+impl CacheControl {
+    /// Reads `QUESTIONS_CACHE_CONTROL` and `ANSWERS_CACHE_CONTROL`, defaulting to
+    /// `public, no-cache` so that shared caches may store responses but must revalidate
+    /// them with the ETag before reuse.
+    pub fn from_env() -> CacheControl {
+        let header = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| HeaderValue::from_str(&v).ok())
+                .unwrap_or_else(|| HeaderValue::from_static("public, no-cache"))
+        };
+
+        CacheControl {
+            questions: header("QUESTIONS_CACHE_CONTROL"),
+            answers: header("ANSWERS_CACHE_CONTROL"),
+        }
+    }
+}
+
+impl Default for CacheControl {
+    fn default() -> CacheControl {
+        CacheControl {
+            questions: HeaderValue::from_static("public, no-cache"),
+            answers: HeaderValue::from_static("public, no-cache"),
+        }
+    }
+}
+
+/// Answers a GET with `representation`, or with `304 Not Modified` when the request's
+/// `If-None-Match` or `If-Modified-Since` precondition shows the client already has it.
+///
+/// As in RFC 9110, `If-Modified-Since` is only consulted when `If-None-Match` is absent.
+pub fn respond(request_headers: &HeaderMap, representation: &Representation, cache_control: &HeaderValue) -> Response<Body> {
+    let mut builder = Response::builder()
+        .header(header::ETAG, &representation.etag)
+        .header(header::CACHE_CONTROL, cache_control);
+
+    if let Some(last_modified) = representation.last_modified {
+        builder = builder.header(header::LAST_MODIFIED, httpdate::fmt_http_date(last_modified));
+    }
+
+    if is_not_modified(request_headers, representation) {
+        return builder.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap();
+    }
+
+    builder
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(representation.body.clone()))
+        .unwrap()
+}
+
+fn is_not_modified(request_headers: &HeaderMap, representation: &Representation) -> bool {
+    if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+        let Ok(if_none_match) = if_none_match.to_str() else {
+            return false;
+        };
+
+        // If-None-Match uses the weak comparison, so `W/` prefixes are ignored.
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == representation.etag);
+    }
+
+    let (Some(if_modified_since), Some(last_modified)) =
+        (request_headers.get(header::IF_MODIFIED_SINCE), representation.last_modified)
+    else {
+        return false;
+    };
+
+    match if_modified_since.to_str().ok().and_then(|v| httpdate::parse_http_date(v).ok()) {
+        Some(since) => last_modified <= since,
+        None => false,
+    }
+}
@@ -0,0 +1,45 @@
+use sqlx::postgres::{PgPool, PgPoolOptions};
+#[cfg(feature = "sqlite")]
+use sqlx::sqlite::SqlitePool;
+
+/// The storage backend the handlers run against.
+///
+/// Postgres is the production backend. When the crate is built with the `sqlite` feature,
+/// a SQLite database can be used instead for edge deployments and local demos.
+pub enum Database {
+    Postgres(PgPool),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqlitePool),
+}
+
+impl Database {
+    /// Connects to the backend selected by the scheme of `database_url`.
+    ///
+    /// URLs starting with `sqlite:` select the SQLite backend, anything else is handed
+    /// to Postgres. Pending migrations for the selected backend are applied before the
+    /// handle is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection cannot be established, or if a `sqlite:` URL
+    /// is given to a binary built without the `sqlite` feature.
+    pub async fn connect(database_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if database_url.starts_with("sqlite:") {
+            #[cfg(feature = "sqlite")]
+            return Ok(Database::Sqlite(crate::sqlite::connect(database_url).await?));
+
+            #[cfg(not(feature = "sqlite"))]
+            return Err("SQLite DATABASE_URL given, but qa-rs was built without the `sqlite` feature".into());
+        }
+
+        let pool = PgPoolOptions::new()
+            .max_connections(1000)
+            .idle_timeout(std::time::Duration::from_secs(30))
+            .connect(database_url)
+            .await?;
+
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+
+        Ok(Database::Postgres(pool))
+    }
+}
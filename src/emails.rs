@@ -0,0 +1,329 @@
+use crate::db::Database;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::utils::hex;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// How asker and answerer emails are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailProtection {
+    /// As sent. This is the default, matching databases that predate the setting.
+    Plaintext,
+    /// Encrypted with AES-256-GCM, so they can still be read back to contact the author.
+    Encrypt,
+    /// Replaced by a keyed hash. Authors can still be looked up by email, but the address
+    /// itself is gone.
+    Hash,
+}
+
+impl EmailProtection {
+    pub fn parse(value: &str) -> Option<EmailProtection> {
+        match value {
+            "plaintext" => Some(EmailProtection::Plaintext),
+            "encrypt" => Some(EmailProtection::Encrypt),
+            "hash" => Some(EmailProtection::Hash),
+            _ => None,
+        }
+    }
+}
+
+/// A 256-bit email key, identified in stored values by a short fingerprint.
+pub struct EmailKey {
+    id: String,
+    cipher: Aes256Gcm,
+    index_key: Vec<u8>,
+}
+
+impl EmailKey {
+    pub fn new(secret: &[u8; 32]) -> EmailKey {
+        let id = hex(&Sha256::digest(secret)[..4]);
+
+        // The lookup hashes get a key of their own, so they reveal nothing about the one
+        // used for encryption.
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+        mac.update(b"qa-rs email index");
+        let index_key = mac.finalize().into_bytes().to_vec();
+
+        EmailKey { id, cipher: Aes256Gcm::new(secret.into()), index_key }
+    }
+
+    /// Parses a base64-encoded 32-byte key, as generated by `openssl rand -base64 32`.
+    pub fn parse(value: &str) -> Result<EmailKey, String> {
+        let secret: [u8; 32] = BASE64
+            .decode(value.trim())
+            .ok()
+            .and_then(|secret| secret.try_into().ok())
+            .ok_or_else(|| "Email keys must be 32 bytes, base64-encoded".to_string())?;
+
+        Ok(EmailKey::new(&secret))
+    }
+
+    fn index(&self, email: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.index_key).expect("HMAC accepts any key length");
+        mac.update(normalize(email).as_bytes());
+        hex(&mac.finalize().into_bytes())
+    }
+}
+
+/// An email as it is written to the database: the `*_email` column and its `*_email_hash`
+/// lookup column.
+#[derive(Clone, Debug)]
+pub struct SealedEmail {
+    pub stored: String,
+    pub hash: Option<String>,
+}
+
+/// Encrypts or hashes asker and answerer emails before they are stored.
+///
+/// Stored values name the key they were sealed with, as `enc:v1:<key id>:<base64>` for
+/// encrypted emails and `hmac:v1:<key id>:<hex>` for hashed ones, so keys can be rotated:
+/// the current key seals new emails and old keys keep opening existing ones until
+/// `admin emails reencrypt` has moved them over. Emails stored before protection was turned
+/// on carry no prefix and are read as they are.
+pub struct EmailProtector {
+    protection: EmailProtection,
+    key: Option<EmailKey>,
+    old_keys: Vec<EmailKey>,
+}
+
+impl EmailProtector {
+    /// Creates a protector sealing with `key`, which `Encrypt` and `Hash` require.
+    pub fn new(protection: EmailProtection, key: Option<EmailKey>, old_keys: Vec<EmailKey>) -> Result<EmailProtector, String> {
+        if protection != EmailProtection::Plaintext && key.is_none() {
+            return Err("Encrypting or hashing emails requires EMAIL_KEY".into());
+        }
+
+        Ok(EmailProtector { protection, key, old_keys })
+    }
+
+    /// A protector that stores emails as sent.
+    pub fn plaintext() -> EmailProtector {
+        EmailProtector { protection: EmailProtection::Plaintext, key: None, old_keys: Vec::new() }
+    }
+
+    /// Reads `EMAIL_PROTECTION` (`plaintext`, `encrypt` or `hash`, defaulting to
+    /// `plaintext`), the current key from `EMAIL_KEY` and retired keys still needed to read
+    /// existing rows from the comma-separated `EMAIL_OLD_KEYS`.
+    pub fn from_env() -> Result<EmailProtector, String> {
+        let protection = match std::env::var("EMAIL_PROTECTION") {
+            Ok(value) => EmailProtection::parse(&value).ok_or_else(|| format!("Unknown EMAIL_PROTECTION: {}", value))?,
+            Err(_) => EmailProtection::Plaintext,
+        };
+        let key = match std::env::var("EMAIL_KEY") {
+            Ok(value) if !value.is_empty() => Some(EmailKey::parse(&value)?),
+            _ => None,
+        };
+        let old_keys = std::env::var("EMAIL_OLD_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|value| !value.trim().is_empty())
+            .map(EmailKey::parse)
+            .collect::<Result<_, _>>()?;
+
+        EmailProtector::new(protection, key, old_keys)
+    }
+
+    pub fn protection(&self) -> EmailProtection {
+        self.protection
+    }
+
+    /// Seals `email` for storage.
+    pub fn seal(&self, email: &str) -> SealedEmail {
+        let Some(key) = self.key.as_ref().filter(|_| self.protection != EmailProtection::Plaintext) else {
+            return SealedEmail { stored: email.to_string(), hash: None };
+        };
+
+        let hash = key.index(email);
+        let stored = match self.protection {
+            EmailProtection::Encrypt => {
+                let mut nonce = [0u8; 12];
+                rand::thread_rng().fill_bytes(&mut nonce);
+                let ciphertext = key
+                    .cipher
+                    .encrypt(Nonce::from_slice(&nonce), email.trim().as_bytes())
+                    .expect("AES-GCM encryption of a short message cannot fail");
+
+                let mut sealed = nonce.to_vec();
+                sealed.extend(ciphertext);
+                format!("enc:v1:{}:{}", key.id, BASE64.encode(sealed))
+            }
+            _ => format!("hmac:v1:{}:{}", key.id, hash),
+        };
+
+        SealedEmail { stored, hash: Some(hash) }
+    }
+
+    /// Reads back a stored email. Returns `None` for hashed emails and for ones sealed with a
+    /// key that is no longer configured.
+    pub fn open(&self, stored: &str) -> Option<String> {
+        if stored.starts_with("hmac:v1:") {
+            return None;
+        }
+        let Some(sealed) = stored.strip_prefix("enc:v1:") else {
+            return Some(stored.to_string());
+        };
+
+        let (key_id, sealed) = sealed.split_once(':')?;
+        let key = self.key.iter().chain(&self.old_keys).find(|key| key.id == key_id)?;
+        let sealed = BASE64.decode(sealed).ok()?;
+        if sealed.len() < 12 {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let email = key.cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+
+        String::from_utf8(email).ok()
+    }
+
+    /// The lookup hashes `email` may be stored under: one per configured key, so rows not yet
+    /// moved to the current key are found too.
+    pub fn lookup_hashes(&self, email: &str) -> Vec<String> {
+        self.key.iter().chain(&self.old_keys).map(|key| key.index(email)).collect()
+    }
+
+    /// A pseudonym for `email` to record in audit logs in its place: the lookup hash under
+    /// the current key, or a plain SHA-256 of the normalised address without one.
+    pub fn fingerprint(&self, email: &str) -> String {
+        match &self.key {
+            Some(key) => key.index(email),
+            None => hex(&Sha256::digest(normalize(email).as_bytes())),
+        }
+    }
+
+    /// The prefix of values sealed the way new emails are, or `None` for plaintext.
+    fn current_prefix(&self) -> Option<String> {
+        let key = self.key.as_ref()?;
+        match self.protection {
+            EmailProtection::Plaintext => None,
+            EmailProtection::Encrypt => Some(format!("enc:v1:{}:", key.id)),
+            EmailProtection::Hash => Some(format!("hmac:v1:{}:", key.id)),
+        }
+    }
+}
+
+/// Emails are matched case-insensitively, as mail providers do in practice.
+pub fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// The email columns, for code that treats questions and answers alike.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailColumn {
+    /// `questions.asker_email`.
+    Asker,
+    /// `answers.answerer_email`.
+    Answerer,
+}
+
+/// Outcome of [`reencrypt_emails`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReencryptReport {
+    pub reencrypted: u64,
+    /// Rows that could not be read back: hashed under an old key, or sealed with a key that
+    /// is no longer configured.
+    pub skipped: u64,
+}
+
+/// Seals every stored email that is not yet sealed the way new ones are: plaintext rows
+/// from before protection was turned on, and rows sealed with a retired key.
+///
+/// Rows are read `batch_size` at a time and rewritten one by one, so the tool can be
+/// interrupted and run again; it picks up whatever is left.
+pub async fn reencrypt_emails(db: &Database, emails: &EmailProtector, batch_size: i64) -> Result<ReencryptReport, sqlx::Error> {
+    let Some(prefix) = emails.current_prefix() else {
+        return Ok(ReencryptReport::default());
+    };
+    let pattern = format!("{}%", prefix);
+
+    let mut report = ReencryptReport::default();
+    for column in [EmailColumn::Asker, EmailColumn::Answerer] {
+        let mut after = 0;
+        loop {
+            let rows = email_batch(db, column, &pattern, after, batch_size).await?;
+            let Some(&(last_id, _)) = rows.last() else {
+                break;
+            };
+
+            for (id, stored) in rows {
+                match emails.open(&stored) {
+                    Some(email) => {
+                        update_email(db, column, id, &emails.seal(&email)).await?;
+                        report.reencrypted += 1;
+                    }
+                    None => report.skipped += 1,
+                }
+            }
+            after = last_id;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Up to `limit` rows after id `after` whose email does not match `pattern`.
+async fn email_batch(db: &Database, column: EmailColumn, pattern: &str, after: i32, limit: i64) -> Result<Vec<(i32, String)>, sqlx::Error> {
+    match db {
+        Database::Postgres(pool) => match column {
+            EmailColumn::Asker => {
+                sqlx::query!(
+                    r#"
+                    SELECT id, asker_email AS "email!" FROM questions
+                    WHERE id > $1 AND asker_email IS NOT NULL AND asker_email NOT LIKE $2
+                    ORDER BY id LIMIT $3;
+                    "#,
+                    after,
+                    pattern,
+                    limit
+                )
+                .fetch_all(pool)
+                .await
+                .map(|rows| rows.into_iter().map(|row| (row.id, row.email)).collect())
+            }
+            EmailColumn::Answerer => {
+                sqlx::query!(
+                    r#"
+                    SELECT id, answerer_email AS "email!" FROM answers
+                    WHERE id > $1 AND answerer_email IS NOT NULL AND answerer_email NOT LIKE $2
+                    ORDER BY id LIMIT $3;
+                    "#,
+                    after,
+                    pattern,
+                    limit
+                )
+                .fetch_all(pool)
+                .await
+                .map(|rows| rows.into_iter().map(|row| (row.id, row.email)).collect())
+            }
+        },
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::email_batch(pool, column, pattern, after, limit).await,
+    }
+}
+
+async fn update_email(db: &Database, column: EmailColumn, id: i32, email: &SealedEmail) -> Result<(), sqlx::Error> {
+    match db {
+        Database::Postgres(pool) => {
+            match column {
+                EmailColumn::Asker => {
+                    sqlx::query!("UPDATE questions SET asker_email = $2, asker_email_hash = $3 WHERE id = $1;", id, email.stored, email.hash)
+                        .execute(pool)
+                        .await?
+                }
+                EmailColumn::Answerer => {
+                    sqlx::query!("UPDATE answers SET answerer_email = $2, answerer_email_hash = $3 WHERE id = $1;", id, email.stored, email.hash)
+                        .execute(pool)
+                        .await?
+                }
+            };
+            Ok(())
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::update_email(pool, column, id, email).await,
+    }
+}
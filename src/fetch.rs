@@ -0,0 +1,220 @@
+use crate::proxy::in_network;
+use hyper::body::Bytes;
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Redirects followed before a fetch is given up on.
+const MAX_REDIRECTS: usize = 3;
+
+/// Networks photo URLs may not point into: this host, private and shared address space,
+/// link-local addresses (cloud metadata services among them) and other reserved ranges, and
+/// the IPv6 ranges that carry an IPv4 address (IPv4-compatible, NAT64, 6to4 and Teredo).
+const BLOCKED_NETWORKS: [(IpAddr, u8); 19] = [
+    (IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8),
+    (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8),
+    (IpAddr::V4(Ipv4Addr::new(100, 64, 0, 0)), 10),
+    (IpAddr::V4(Ipv4Addr::new(127, 0, 0, 0)), 8),
+    (IpAddr::V4(Ipv4Addr::new(169, 254, 0, 0)), 16),
+    (IpAddr::V4(Ipv4Addr::new(172, 16, 0, 0)), 12),
+    (IpAddr::V4(Ipv4Addr::new(192, 0, 0, 0)), 24),
+    (IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0)), 16),
+    (IpAddr::V4(Ipv4Addr::new(198, 18, 0, 0)), 15),
+    (IpAddr::V4(Ipv4Addr::new(224, 0, 0, 0)), 4),
+    (IpAddr::V4(Ipv4Addr::new(240, 0, 0, 0)), 4),
+    // IPv4-compatible addresses, `::` and `::1` among them.
+    (IpAddr::V6(Ipv6Addr::UNSPECIFIED), 96),
+    (IpAddr::V6(Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0)), 96),
+    (IpAddr::V6(Ipv6Addr::new(0x64, 0xff9b, 1, 0, 0, 0, 0, 0)), 48),
+    (IpAddr::V6(Ipv6Addr::new(0x2001, 0, 0, 0, 0, 0, 0, 0)), 32),
+    (IpAddr::V6(Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 0)), 16),
+    (IpAddr::V6(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0)), 7),
+    (IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0)), 10),
+    (IpAddr::V6(Ipv6Addr::new(0xff00, 0, 0, 0, 0, 0, 0, 0)), 8),
+];
+
+/// Whether `ip` is in one of the [`BLOCKED_NETWORKS`], or is an IPv6 address carrying an
+/// IPv4 address that is.
+pub fn is_blocked(ip: IpAddr) -> bool {
+    let blocked = |ip: IpAddr| BLOCKED_NETWORKS.iter().any(|&(network, prefix)| in_network(ip, network, prefix));
+    match ip {
+        IpAddr::V6(v6) => blocked(ip) || embedded_ipv4(v6).is_some_and(|v4| blocked(IpAddr::V4(v4))),
+        IpAddr::V4(_) => blocked(ip),
+    }
+}
+
+/// The IPv4 address an IPv6 address carries: in its last 32 bits when IPv4-mapped
+/// (`::ffff:0:0/96`), IPv4-compatible (`::/96`) or NAT64 (`64:ff9b::/96`), in bits 16 to 48
+/// for 6to4 (`2002::/16`), and inverted in its last 32 bits for a Teredo client
+/// (`2001::/32`).
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let [a, b, c, d, e, f, g, h] = ip.segments();
+    let last = ((g as u32) << 16) | h as u32;
+    match (a, b, c, d, e, f) {
+        (0, 0, 0, 0, 0, 0xffff) | (0, 0, 0, 0, 0, 0) | (0x64, 0xff9b, 0, 0, 0, 0) => Some(Ipv4Addr::from(last)),
+        (0x2002, ..) => Some(Ipv4Addr::from(((b as u32) << 16) | c as u32)),
+        (0x2001, 0, ..) => Some(Ipv4Addr::from(!last)),
+        _ => None,
+    }
+}
+
+/// A photo fetched over HTTP.
+#[derive(Debug)]
+pub struct Fetched {
+    /// The media type the server declared, without parameters.
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+/// Why a photo could not be fetched.
+#[derive(Debug)]
+pub enum FetchError {
+    /// The URL is not an `http` or `https` URL with a host.
+    InvalidUrl(String),
+    /// The URL, or a redirect, resolves to a blocked address.
+    Blocked(String),
+    /// The server answered with an error status.
+    Status(u16),
+    TooLarge(usize),
+    TooManyRedirects,
+    Timeout,
+    Http(reqwest::Error),
+}
+
+impl FetchError {
+    /// Whether fetching again could succeed: timeouts, connection errors and server errors
+    /// may pass, while a client error or a blocked URL will not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FetchError::Timeout | FetchError::Http(_) => true,
+            FetchError::Status(status) => *status >= 500,
+            FetchError::InvalidUrl(_) | FetchError::Blocked(_) | FetchError::TooLarge(_) | FetchError::TooManyRedirects => false,
+        }
+    }
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::InvalidUrl(url) => write!(f, "Unsupported photo URL: {}", url),
+            FetchError::Blocked(host) => write!(f, "{} resolves to a blocked address", host),
+            FetchError::Status(status) => write!(f, "Photo server answered {}", status),
+            FetchError::TooLarge(max_bytes) => write!(f, "Photo is over {} bytes", max_bytes),
+            FetchError::TooManyRedirects => write!(f, "Photo URL redirects more than {} times", MAX_REDIRECTS),
+            FetchError::Timeout => write!(f, "Fetching the photo timed out"),
+            FetchError::Http(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// Fetches photos from the URLs shoppers register with their answers.
+///
+/// Those URLs are untrusted, so a fetch never reaches a [blocked](is_blocked) address: the
+/// host is resolved here, every address it resolves to is checked, and the connection is
+/// pinned to a checked address so that the name cannot be rebound in between. Redirects
+/// are followed by hand for the same checks. Each fetch has `timeout` to finish in.
+#[derive(Clone, Debug)]
+pub struct PhotoFetcher {
+    pub timeout: Duration,
+    /// Lets URLs reach blocked addresses, for development and tests against local servers.
+    pub allow_private: bool,
+}
+
+impl PhotoFetcher {
+    pub fn new(timeout: Duration) -> PhotoFetcher {
+        PhotoFetcher { timeout, allow_private: false }
+    }
+
+    /// Reads `PHOTO_FETCH_TIMEOUT_SECS` (10 by default) and `PHOTO_FETCH_ALLOW_PRIVATE`
+    /// (`off` by default).
+    pub fn from_env() -> Result<PhotoFetcher, String> {
+        let timeout = match std::env::var("PHOTO_FETCH_TIMEOUT_SECS") {
+            Ok(value) => value.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| format!("Invalid PHOTO_FETCH_TIMEOUT_SECS: {}", value))?,
+            Err(_) => 10,
+        };
+        let allow_private = match std::env::var("PHOTO_FETCH_ALLOW_PRIVATE").as_deref() {
+            Ok("on") => true,
+            Ok("off") | Err(_) => false,
+            Ok(other) => return Err(format!("Invalid PHOTO_FETCH_ALLOW_PRIVATE: {}", other)),
+        };
+        Ok(PhotoFetcher { timeout: Duration::from_secs(timeout), allow_private })
+    }
+
+    /// Fetches `url`, reading at most `max_bytes` bytes of it.
+    pub async fn fetch(&self, url: &str, max_bytes: usize) -> Result<Fetched, FetchError> {
+        let url = Url::parse(url).map_err(|_| FetchError::InvalidUrl(url.to_string()))?;
+        tokio::time::timeout(self.timeout, self.follow(url, max_bytes)).await.map_err(|_| FetchError::Timeout)?
+    }
+
+    async fn follow(&self, mut url: Url, max_bytes: usize) -> Result<Fetched, FetchError> {
+        for _ in 0..=MAX_REDIRECTS {
+            let client = self.client_for(&url).await?;
+            let mut response = client.get(url.clone()).send().await.map_err(FetchError::Http)?;
+
+            let status = response.status();
+            if status.is_redirection() {
+                let location = response.headers().get(LOCATION).and_then(|value| value.to_str().ok());
+                url = location.and_then(|location| url.join(location).ok()).ok_or(FetchError::Status(status.as_u16()))?;
+                continue;
+            }
+            if !status.is_success() {
+                return Err(FetchError::Status(status.as_u16()));
+            }
+            if response.content_length().is_some_and(|length| length > max_bytes as u64) {
+                return Err(FetchError::TooLarge(max_bytes));
+            }
+
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.split(';').next().unwrap_or_default().trim().to_ascii_lowercase());
+            let mut data = Vec::new();
+            while let Some(chunk) = response.chunk().await.map_err(FetchError::Http)? {
+                if data.len() + chunk.len() > max_bytes {
+                    return Err(FetchError::TooLarge(max_bytes));
+                }
+                data.extend_from_slice(&chunk);
+            }
+            return Ok(Fetched { content_type, data: data.into() });
+        }
+        Err(FetchError::TooManyRedirects)
+    }
+
+    /// A client that connects to `url`'s host only at an address checked here.
+    async fn client_for(&self, url: &Url) -> Result<reqwest::Client, FetchError> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(FetchError::InvalidUrl(url.to_string()));
+        }
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            return Err(FetchError::InvalidUrl(url.to_string()));
+        };
+
+        let builder = reqwest::Client::builder().redirect(Policy::none()).no_proxy();
+        let builder = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(ip) => {
+                self.check(host, &[ip])?;
+                builder
+            }
+            Err(_) => {
+                let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await.map_err(|_| FetchError::InvalidUrl(url.to_string()))?.collect();
+                let ips: Vec<IpAddr> = addrs.iter().map(SocketAddr::ip).collect();
+                self.check(host, &ips)?;
+                builder.resolve_to_addrs(host, &addrs)
+            }
+        };
+        Ok(builder.build().expect("a client without custom TLS settings always builds"))
+    }
+
+    /// Refuses a host if any address it resolves to is blocked.
+    fn check(&self, host: &str, ips: &[IpAddr]) -> Result<(), FetchError> {
+        if !self.allow_private && ips.iter().any(|&ip| is_blocked(ip)) {
+            return Err(FetchError::Blocked(host.to_string()));
+        }
+        Ok(())
+    }
+}
@@ -1,30 +1,69 @@
-use crate::models::{NewAnswer, NewQuestion};
+use crate::cache::QuestionsPage;
+use crate::conditional::{self, Representation};
+use crate::db::Database;
+use crate::emails::SealedEmail;
+use crate::models::{NewAnswer, NewQuestion, NewReport, QuestionSort};
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::outbox::{self, Event};
+use crate::state::AppState;
 use crate::utils::{
-    create_error_response, create_success_response,
+    answers_envelope, batch_envelope, create_error_response, create_success_response, log_error, questions_envelope,
 };
-use hyper::{Body, Response, StatusCode};
+use hyper::{header::HeaderMap, Body, Response, StatusCode};
 use sqlx::PgPool;
 use std::sync::Arc;
 
+/// SQLSTATE raised when an insert references a row that does not exist.
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
 /// Retrieves questions and their related information for a specific product.
 ///
 /// This function queries the database for questions and their corresponding answers and photos
-/// based on the given product_id, page, and count.
+/// based on the given product_id, page, count and sort order. Reported questions and answers are
+/// left out. Serialized responses are kept in the questions cache until a write touches the
+/// product or the entry expires, and requests whose `If-None-Match`/`If-Modified-Since`
+/// preconditions match are answered with `304 Not Modified`.
 ///
 /// # Arguments
 ///
-/// * `pool` - An Arc-wrapped connection pool for database access.
+/// * `state` - The shared application state holding the database and the questions cache.
+/// * `request_headers` - The request headers, checked for conditional GET preconditions.
 /// * `product_id` - The product ID for which to retrieve questions.
 /// * `page` - The page number for pagination.
 /// * `count` - The number of questions to retrieve per page.
+/// * `sort` - The order in which questions are listed.
 ///
 /// # Returns
 ///
 /// Returns a `Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>>`:
 /// * `Ok(Response<Body>)` - A successful response with questions, answers, and photos in JSON format.
 /// * `Err(Box<dyn std::error::Error + Send + Sync>)` - An error if any issues occurred during the database query or response generation.
-pub async fn get_questions(pool: Arc<PgPool>, product_id: i32, page: i32, count: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    let limit = (page * count) as i64;
+pub async fn get_questions(state: Arc<AppState>, request_headers: &HeaderMap, product_id: i32, page: i32, count: i32, sort: QuestionSort) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let cache_page = QuestionsPage { page, count, sort };
+    if let Some(representation) = state.questions_cache.get(product_id, &cache_page) {
+        return Ok(conditional::respond(request_headers, &representation, &state.cache_control.questions));
+    }
+
+    let epoch = state.questions_cache.epoch();
+    let (results, last_modified) = match &state.db {
+        Database::Postgres(pool) => query_questions(pool, product_id, page, count, sort).await?,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_questions(pool, product_id, page, count, sort).await?,
+    };
+
+    // The database already serialized the results, so they are spliced into the envelope as is
+    let representation = Representation::new(questions_envelope(product_id, &results), last_modified);
+    state.questions_cache.insert(product_id, cache_page, epoch, representation.clone());
+
+    Ok(conditional::respond(request_headers, &representation, &state.cache_control.questions))
+}
+
+/// Runs the questions query, returning the `results` array as JSON text and the Unix time of
+/// the most recent write to any of the product's questions or answers, reported ones included.
+async fn query_questions(pool: &PgPool, product_id: i32, page: i32, count: i32, sort: QuestionSort) -> Result<(String, Option<i64>), sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
 
     let row = sqlx::query!(
         r#"
@@ -55,57 +94,408 @@ pub async fn get_questions(pool: Arc<PgPool>, product_id: i32, page: i32, count:
                                                 SELECT
                                                     Json_agg(
                                                         Json_build_object(
-                                                            'id',  ap.id,
-                                                            'url', ap.url
+                                                            'id',                 ap.id,
+                                                            'url',                ap.url,
+                                                            'thumbnail_url',      ap.thumbnail_url,
+                                                            'thumbnail_webp_url', ap.thumbnail_webp_url,
+                                                            'width',              ap.width,
+                                                            'height',             ap.height
                                                         )
                                                     ) AS p
                                                 FROM answer_photos AS ap
-                                                WHERE ap.answer_id = a.id
+                                                WHERE ap.answer_id = a.id AND ap.reported = false AND ap.verify_status <> 'broken'
                                             ) AS myPhotos
                                         )
                                     )
                                 ) AS a
                                 FROM answers a
-                                WHERE a.question_id = q.id
+                                WHERE a.question_id = q.id AND a.reported = false
                             ) AS myAnswers
                         )
                     )
                 ), '[]'::json
-            ) AS results
+            )::text AS "results!",
+            (
+                SELECT EXTRACT(EPOCH FROM MAX(GREATEST(
+                    COALESCE(mq.updated_at, mq.date_written),
+                    (
+                        SELECT MAX(COALESCE(ma.updated_at, ma.date_written))
+                        FROM answers ma
+                        WHERE ma.question_id = mq.id
+                    )
+                )))::bigint
+                FROM questions mq
+                WHERE mq.product_id = $1
+            ) AS last_modified
         FROM (
             SELECT *
             FROM questions
-            WHERE product_id = $1
-            LIMIT $2
+            WHERE product_id = $1 AND reported = false
+            ORDER BY
+                CASE WHEN $4 = 'helpful' THEN helpful END DESC NULLS LAST,
+                CASE WHEN $4 = 'newest' THEN date_written END DESC NULLS LAST,
+                CASE WHEN $4 = 'newest' THEN id END DESC,
+                id
+            LIMIT $2 OFFSET $3
         ) AS q;
         "#,
         product_id,
-        limit
+        limit,
+        offset,
+        sort.as_str()
     )
-    .fetch_optional(&*pool)
+    .fetch_optional(pool)
     .await
-    .map_err(|e| {
-        println!("Failed to fetch data from the database: {:?}", e);
-        e
-    })?;
-
-    // Deserialize the JSON result of the query
-    let results = if let Some(row) = row {
-        serde_json::from_value(row.results.into()).unwrap_or_else(|_| serde_json::Value::Array(vec![]))
-    } else {
-        serde_json::Value::Array(vec![])
+    .inspect_err(|e| log_error("Failed to fetch data from the database", e))?;
+
+    let results = match row {
+        Some(row) => (row.results, row.last_modified),
+        None => ("[]".to_string(), None),
+    };
+
+    Ok(results)
+}
+
+/// Retrieves the question count and top `top` questions of several products in one query.
+///
+/// Products come back in the order asked for, each with its `question_count` and its first
+/// `top` questions in `sort` order, shaped like the results of [`get_questions`]. Reported
+/// questions and answers are left out. With a `top` of 0 only the counts are returned.
+pub async fn get_questions_batch(state: Arc<AppState>, request_headers: &HeaderMap, product_ids: &[i32], top: i32, sort: QuestionSort) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let results = match &state.db {
+        Database::Postgres(pool) => query_questions_batch(pool, product_ids, top, sort).await?,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_questions_batch(pool, product_ids, top, sort).await?,
+    };
+
+    let representation = Representation::new(batch_envelope(top, &results), None);
+
+    Ok(conditional::respond(request_headers, &representation, &state.cache_control.questions))
+}
+
+/// Runs the batch query, returning the `results` array as JSON text.
+async fn query_questions_batch(pool: &PgPool, product_ids: &[i32], top: i32, sort: QuestionSort) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            COALESCE(
+                Json_agg(
+                    Json_build_object(
+                        'product_id', p.product_id,
+                        'question_count', (
+                            SELECT COUNT(*)
+                            FROM questions
+                            WHERE product_id = p.product_id AND reported = false
+                        ),
+                        'questions', (
+                            SELECT COALESCE(
+                                Json_agg(
+                                    Json_build_object(
+                                        'question_id',          q.id,
+                                        'question_body',        q.body,
+                                        'question_date',        q.date_written,
+                                        'asker_name',           q.asker_name,
+                                        'question_helpfulness', q.helpful,
+                                        'reported',             q.reported,
+                                        'answers', (
+                                            SELECT COALESCE(a, '{}'::json)
+                                            FROM (
+                                                SELECT Json_object_agg(
+                                                    a.id,
+                                                    Json_build_object(
+                                                        'id',            a.id,
+                                                        'body',          a.body,
+                                                        'date',          a.date_written,
+                                                        'answerer_name', a.answerer_name,
+                                                        'helpfulness',   a.helpful,
+                                                        'photos', (
+                                                            SELECT COALESCE(p, '[]'::json)
+                                                            FROM (
+                                                                SELECT
+                                                                    Json_agg(
+                                                                        Json_build_object(
+                                                                            'id',                 ap.id,
+                                                                            'url',                ap.url,
+                                                                            'thumbnail_url',      ap.thumbnail_url,
+                                                                            'thumbnail_webp_url', ap.thumbnail_webp_url,
+                                                                            'width',              ap.width,
+                                                                            'height',             ap.height
+                                                                        )
+                                                                    ) AS p
+                                                                FROM answer_photos AS ap
+                                                                WHERE ap.answer_id = a.id AND ap.reported = false AND ap.verify_status <> 'broken'
+                                                            ) AS myPhotos
+                                                        )
+                                                    )
+                                                ) AS a
+                                                FROM answers a
+                                                WHERE a.question_id = q.id AND a.reported = false
+                                            ) AS myAnswers
+                                        )
+                                    )
+                                ), '[]'::json
+                            )
+                            FROM (
+                                SELECT *
+                                FROM questions
+                                WHERE product_id = p.product_id AND reported = false
+                                ORDER BY
+                                    CASE WHEN $3 = 'helpful' THEN helpful END DESC NULLS LAST,
+                                    CASE WHEN $3 = 'newest' THEN date_written END DESC NULLS LAST,
+                                    CASE WHEN $3 = 'newest' THEN id END DESC,
+                                    id
+                                LIMIT $2
+                            ) AS q
+                        )
+                    )
+                    ORDER BY p.position
+                ), '[]'::json
+            )::text AS "results!"
+        FROM unnest($1::int[]) WITH ORDINALITY AS p(product_id, position);
+        "#,
+        product_ids,
+        top as i64,
+        sort.as_str()
+    )
+    .fetch_one(pool)
+    .await
+    .inspect_err(|e| log_error("Failed to fetch data from the database", e))
+}
+
+/// Options for the snippets cut from matching bodies: matches are wrapped in `<mark>` and up
+/// to two fragments are joined with an ellipsis.
+const SNIPPET_OPTIONS: &str = r#"StartSel=<mark>, StopSel=</mark>, MaxWords=30, MinWords=10, MaxFragments=2, FragmentDelimiter=" … ""#;
+
+/// Searches a product's questions and their answers for `query`.
+///
+/// Questions come back ranked by their best match, in the same shape as [`get_questions`].
+/// Questions and answers whose body matches carry a `question_snippet` or `snippet` with the
+/// matches marked up; the bodies are HTML escaped before marking, so snippets can be shown
+/// as HTML. Reported questions and answers are left out. Results are not cached, but carry
+/// an ETag for conditional requests.
+pub async fn search_questions(state: Arc<AppState>, request_headers: &HeaderMap, product_id: i32, query: &str, page: i32, count: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let results = match &state.db {
+        Database::Postgres(pool) => query_search(pool, product_id, query, page, count).await?,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_search(pool, product_id, query, page, count).await?,
     };
 
-    // Create a JSON response object with the product_id and results
-    let mut response = serde_json::Map::new();
-    response.insert("product_id".to_string(), serde_json::Value::from(product_id));
-    response.insert("results".to_string(), results);
+    let representation = Representation::new(questions_envelope(product_id, &results), None);
 
-    create_success_response(StatusCode::OK, serde_json::Value::Object(response))
+    Ok(conditional::respond(request_headers, &representation, &state.cache_control.questions))
 }
 
-pub async fn get_answers(pool: Arc<PgPool>, question_id: i32, page: i32, count: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    let limit = (page * count) as i64;
+/// Runs the search query, returning the `results` array as JSON text.
+///
+/// `query` is read as web search syntax: quoted phrases, `or` and `-` for exclusion.
+async fn query_search(pool: &PgPool, product_id: i32, query: &str, page: i32, count: i32) -> Result<String, sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    sqlx::query_scalar!(
+        r#"
+        WITH search AS (
+            SELECT websearch_to_tsquery('english', $2) AS query
+        ),
+        matches AS (
+            SELECT q.id AS question_id, ts_rank(q.search_vector, search.query) AS rank
+            FROM questions q
+            CROSS JOIN search
+            WHERE q.product_id = $1 AND q.reported = false AND q.search_vector @@ search.query
+            UNION ALL
+            SELECT a.question_id, ts_rank(a.search_vector, search.query)
+            FROM answers a
+            JOIN questions q ON q.id = a.question_id
+            CROSS JOIN search
+            WHERE q.product_id = $1 AND q.reported = false AND a.reported = false AND a.search_vector @@ search.query
+        ),
+        ranked AS (
+            SELECT question_id, MAX(rank) AS rank
+            FROM matches
+            GROUP BY question_id
+            ORDER BY rank DESC, question_id
+            LIMIT $3 OFFSET $4
+        )
+        SELECT
+            COALESCE(
+                Json_agg(
+                    Json_build_object(
+                        'question_id',          q.id,
+                        'question_body',        q.body,
+                        'question_date',        q.date_written,
+                        'asker_name',           q.asker_name,
+                        'question_helpfulness', q.helpful,
+                        'reported',             q.reported,
+                        'question_snippet',     CASE WHEN q.search_vector @@ search.query THEN ts_headline('english', html_escape(q.body), search.query, $5) END,
+                        'answers', (
+                            SELECT COALESCE(a, '{}'::json)
+                            FROM (
+                                SELECT Json_object_agg(
+                                    a.id,
+                                    Json_build_object(
+                                        'id',            a.id,
+                                        'body',          a.body,
+                                        'date',          a.date_written,
+                                        'answerer_name', a.answerer_name,
+                                        'helpfulness',   a.helpful,
+                                        'snippet',       CASE WHEN a.search_vector @@ search.query THEN ts_headline('english', html_escape(a.body), search.query, $5) END,
+                                        'photos', (
+                                            SELECT COALESCE(p, '[]'::json)
+                                            FROM (
+                                                SELECT
+                                                    Json_agg(
+                                                        Json_build_object(
+                                                            'id',                 ap.id,
+                                                            'url',                ap.url,
+                                                            'thumbnail_url',      ap.thumbnail_url,
+                                                            'thumbnail_webp_url', ap.thumbnail_webp_url,
+                                                            'width',              ap.width,
+                                                            'height',             ap.height
+                                                        )
+                                                    ) AS p
+                                                FROM answer_photos AS ap
+                                                WHERE ap.answer_id = a.id AND ap.reported = false AND ap.verify_status <> 'broken'
+                                            ) AS myPhotos
+                                        )
+                                    )
+                                ) AS a
+                                FROM answers a
+                                WHERE a.question_id = q.id AND a.reported = false
+                            ) AS myAnswers
+                        )
+                    )
+                    ORDER BY r.rank DESC, q.id
+                ), '[]'::json
+            )::text AS "results!"
+        FROM ranked r
+        JOIN questions q ON q.id = r.question_id
+        CROSS JOIN search;
+        "#,
+        product_id,
+        query,
+        limit,
+        offset,
+        SNIPPET_OPTIONS
+    )
+    .fetch_one(pool)
+    .await
+    .inspect_err(|e| log_error("Failed to search questions", e))
+}
+
+/// Summary of a product's Q&A, leaving out reported questions and answers.
+#[derive(sqlx::FromRow)]
+pub struct QaStats {
+    pub question_count: i64,
+    /// Questions with at least one answer.
+    pub answered_count: i64,
+    pub answer_count: i64,
+    pub photo_count: i64,
+    /// When the most recent question or answer was posted.
+    pub last_activity: Option<String>,
+    /// The question with the most helpful votes, the oldest of them on a tie. `None` when no
+    /// question has any.
+    pub top_question_id: Option<i32>,
+    pub top_question_body: Option<String>,
+    pub top_question_helpfulness: Option<i32>,
+}
+
+/// Retrieves the Q&A summary of a product, see [`QaStats`]. Unknown products have no
+/// questions rather than being an error, as with [`get_questions`].
+pub async fn get_qa_stats(state: Arc<AppState>, product_id: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => query_qa_stats(pool, product_id).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_qa_stats(pool, product_id).await,
+    };
+
+    match result {
+        Ok(stats) => {
+            let top_question = stats.top_question_id.map(|question_id| {
+                serde_json::json!({
+                    "question_id": question_id,
+                    "question_body": stats.top_question_body,
+                    "question_helpfulness": stats.top_question_helpfulness,
+                })
+            });
+            let response = serde_json::json!({
+                "product_id": product_id,
+                "question_count": stats.question_count,
+                "answered_count": stats.answered_count,
+                "unanswered_count": stats.question_count - stats.answered_count,
+                "answer_count": stats.answer_count,
+                "photo_count": stats.photo_count,
+                "last_activity": stats.last_activity,
+                "top_question": top_question,
+            });
+            create_success_response(StatusCode::OK, response)
+        }
+        Err(e) => {
+            log_error("Failed to fetch Q&A stats", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch Q&A stats".into())
+        }
+    }
+}
+
+async fn query_qa_stats(pool: &PgPool, product_id: i32) -> Result<QaStats, sqlx::Error> {
+    sqlx::query_as!(
+        QaStats,
+        r#"
+        WITH q AS (
+            SELECT id, body, helpful, date_written
+            FROM questions
+            WHERE product_id = $1 AND reported = false
+        ),
+        a AS (
+            SELECT answers.id, answers.question_id, answers.date_written
+            FROM answers
+            JOIN q ON q.id = answers.question_id
+            WHERE answers.reported = false
+        ),
+        top AS (
+            SELECT id, body, helpful
+            FROM q
+            WHERE helpful > 0
+            ORDER BY helpful DESC, id
+            LIMIT 1
+        )
+        SELECT
+            (SELECT COUNT(*) FROM q) AS "question_count!",
+            (SELECT COUNT(DISTINCT question_id) FROM a) AS "answered_count!",
+            (SELECT COUNT(*) FROM a) AS "answer_count!",
+            (SELECT COUNT(*) FROM answer_photos AS ap JOIN a ON a.id = ap.answer_id WHERE ap.reported = false AND ap.verify_status <> 'broken') AS "photo_count!",
+            to_char(
+                GREATEST((SELECT MAX(date_written) FROM q), (SELECT MAX(date_written) FROM a)),
+                'YYYY-MM-DD"T"HH24:MI:SS.MS'
+            ) AS last_activity,
+            (SELECT id FROM top) AS top_question_id,
+            (SELECT body FROM top) AS top_question_body,
+            (SELECT helpful FROM top) AS top_question_helpfulness;
+        "#,
+        product_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Retrieves a page of answers to a question, with the same conditional GET handling as
+/// [`get_questions`].
+pub async fn get_answers(state: Arc<AppState>, request_headers: &HeaderMap, question_id: i32, page: i32, count: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let (results, last_modified) = match &state.db {
+        Database::Postgres(pool) => query_answers(pool, question_id, page, count).await?,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_answers(pool, question_id, page, count).await?,
+    };
+
+    let representation = Representation::new(answers_envelope(question_id, page, count, &results), last_modified);
+
+    Ok(conditional::respond(request_headers, &representation, &state.cache_control.answers))
+}
+
+/// Runs the answers query, returning the `results` array as JSON text and the Unix time of
+/// the most recent write to any of the question's answers, reported ones included.
+async fn query_answers(pool: &PgPool, question_id: i32, page: i32, count: i32) -> Result<(String, Option<i64>), sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
 
     let row = sqlx::query!(
         r#"
@@ -123,192 +513,479 @@ pub async fn get_answers(pool: Arc<PgPool>, question_id: i32, page: i32, count:
                             FROM (
                                 SELECT
                                 ap.id,
-                                ap.url
+                                ap.url,
+                                ap.thumbnail_url,
+                                ap.thumbnail_webp_url,
+                                ap.width,
+                                ap.height
                                 FROM answer_photos ap
-                                WHERE ap.answer_id = a.id
+                                WHERE ap.answer_id = a.id AND ap.reported = false AND ap.verify_status <> 'broken'
                                 ) d
                             ) 
                         )
-                    ), '[]'::json 
-            ) AS results
-        FROM answers a
-        WHERE a.question_id = $1
-        LIMIT $2
+                    ), '[]'::json
+            )::text AS "results!",
+            (
+                SELECT EXTRACT(EPOCH FROM MAX(COALESCE(ma.updated_at, ma.date_written)))::bigint
+                FROM answers ma
+                WHERE ma.question_id = $1
+            ) AS last_modified
+        FROM (
+            SELECT *
+            FROM answers
+            WHERE question_id = $1 AND reported = false
+            ORDER BY id
+            LIMIT $2 OFFSET $3
+        ) AS a;
         "#,
         question_id,
-        limit
+        limit,
+        offset
     )
-    .fetch_optional(&*pool)
+    .fetch_optional(pool)
     .await
-    .map_err(|e| {
-        println!("Failed to fetch data from the database: {:?}", e);
-        e
-    })?;
+    .inspect_err(|e| log_error("Failed to fetch data from the database", e))?;
 
-    let results = if let Some(row) = row {
-        serde_json::from_value(row.results.into()).unwrap_or_else(|_| serde_json::Value::Array(vec![]))
-    } else {
-        serde_json::Value::Array(vec![])
+    let results = match row {
+        Some(row) => (row.results, row.last_modified),
+        None => ("[]".to_string(), None),
     };
 
-    let mut response = serde_json::Map::new();
-    response.insert("question_id".to_string(), serde_json::Value::from(question_id));
-    response.insert("page".to_string(), serde_json::Value::from(page));
-    response.insert("count".to_string(), serde_json::Value::from(count));
-    response.insert("results".to_string(), results);
-
-    create_success_response(StatusCode::OK, serde_json::Value::Object(response))
+    Ok(results)
 }
 
-pub async fn add_question(pool: Arc<PgPool>, question_data: NewQuestion) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    let result = sqlx::query!(
-        r#"
-        INSERT INTO questions (product_id, body, date_written, asker_name, asker_email, reported, helpful)
-        VALUES ($1, $2, NOW(), $3, $4, false, 0)
-        RETURNING id;
-        "#,
-        question_data.product_id,
-        question_data.body,
-        question_data.name,
-        question_data.email
-    )
-    .fetch_one(&*pool)
-    .await;
+pub async fn add_question(state: Arc<AppState>, question_data: NewQuestion) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let email = state.emails.seal(&question_data.email);
+
+    let pool = match &state.db {
+        Database::Postgres(pool) => pool,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => return sqlite::add_question(pool, &state.questions_cache, question_data, &email).await,
+    };
+
+    let result = insert_question(pool, &question_data, &email).await;
 
     match result {
-        Ok(row) => {
-            let response = serde_json::json!({ "question_id": row.id });
+        Ok(question_id) => {
+            state.questions_cache.invalidate(question_data.product_id);
+
+            let response = serde_json::json!({ "question_id": question_id });
             create_success_response(StatusCode::CREATED, response)
         }
         Err(e) => {
-            println!("Failed to add question: {:?}", e);
+            log_error("Failed to add question", &e);
             create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add question".into())
         }
     }
 }
 
-pub async fn add_answer(pool: Arc<PgPool>, question_id: i32, answer_data: NewAnswer) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    let result = sqlx::query!(
+/// Inserts a question and records its [`Event::QuestionCreated`].
+async fn insert_question(pool: &PgPool, question_data: &NewQuestion, email: &SealedEmail) -> Result<i32, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let question_id = sqlx::query_scalar!(
         r#"
-        INSERT INTO answers (question_id, body, date_written, answerer_name, answerer_email, reported, helpful)
-        VALUES ($1, $2, NOW(), $3, $4, false, 0)
+        INSERT INTO questions (product_id, body, date_written, asker_name, asker_email, asker_email_hash, reported, helpful)
+        VALUES ($1, $2, NOW(), $3, $4, $5, false, 0)
         RETURNING id;
         "#,
-        question_id,
-        answer_data.body,
-        answer_data.name,
-        answer_data.email
+        question_data.product_id,
+        question_data.body,
+        question_data.name,
+        email.stored,
+        email.hash
     )
-    .fetch_one(&*pool)
-    .await;
+    .fetch_one(&mut tx)
+    .await?;
+    outbox::record(&mut tx, Event::QuestionCreated { question_id, product_id: question_data.product_id }).await?;
+
+    tx.commit().await?;
+    Ok(question_id)
+}
+
+pub async fn add_answer(state: Arc<AppState>, question_id: i32, answer_data: NewAnswer) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let email = state.emails.seal(&answer_data.email);
+
+    let pool = match &state.db {
+        Database::Postgres(pool) => pool,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => return sqlite::add_answer(pool, &state.questions_cache, question_id, answer_data, &email, state.notifications.enabled()).await,
+    };
+
+    let result = insert_answer(pool, question_id, &answer_data, &email, state.notifications.enabled()).await;
 
     match result {
-        Ok(row) => {
-            let answer_id = row.id;
-
-            for url in answer_data.photos {
-                let _ = sqlx::query!(
-                    r#"
-                    INSERT INTO answer_photos (answer_id, url)
-                    VALUES ($1, $2);
-                    "#,
-                    answer_id,
-                    url
-                )
-                .execute(&*pool)
-                .await;
-            }
+        Ok((answer_id, product_id)) => {
+            state.questions_cache.invalidate(product_id);
 
             let response = serde_json::json!({ "answer_id": answer_id });
             create_success_response(StatusCode::CREATED, response)
         }
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+            create_error_response(StatusCode::NOT_FOUND, "Question not found".into())
+        }
         Err(e) => {
-            println!("Failed to add answer: {:?}", e);
+            log_error("Failed to add answer", &e);
             create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add answer".into())
         }
     }
 }
 
-pub async fn update_question_helpful(pool: Arc<PgPool>, question_id: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    let result = sqlx::query!(
+/// Inserts an answer with its photos and records its [`Event::AnswerCreated`], queueing a
+/// notification to the asker when `notify` is set. Returns the answer's id and the product of
+/// its question.
+async fn insert_answer(pool: &PgPool, question_id: i32, answer_data: &NewAnswer, email: &SealedEmail, notify: bool) -> Result<(i32, i32), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query!(
         r#"
-        UPDATE questions
-        SET helpful = helpful + 1
-        WHERE id = $1;
+        INSERT INTO answers (question_id, body, date_written, answerer_name, answerer_email, answerer_email_hash, reported, helpful)
+        VALUES ($1, $2, NOW(), $3, $4, $5, false, 0)
+        RETURNING id, (SELECT product_id FROM questions WHERE questions.id = answers.question_id) AS "product_id!";
         "#,
-        question_id
+        question_id,
+        answer_data.body,
+        answer_data.name,
+        email.stored,
+        email.hash
     )
-    .execute(&*pool)
-    .await;
+    .fetch_one(&mut tx)
+    .await?;
+
+    for url in &answer_data.photos {
+        sqlx::query!(
+            r#"
+            INSERT INTO answer_photos (answer_id, url)
+            VALUES ($1, $2);
+            "#,
+            row.id,
+            url
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    outbox::record(&mut tx, Event::AnswerCreated { answer_id: row.id, question_id, product_id: row.product_id }).await?;
+
+    if notify {
+        // Askers answering their own question are not told about it.
+        sqlx::query!(
+            r#"
+            INSERT INTO answer_notifications (question_id, answer_id)
+            SELECT id, $2
+            FROM questions
+            WHERE id = $1 AND NOT COALESCE(asker_email_hash = $3 OR ($3 IS NULL AND lower(asker_email) = lower($4)), false);
+            "#,
+            question_id,
+            row.id,
+            email.hash,
+            email.stored
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok((row.id, row.product_id))
+}
+
+/// Marks a question as helpful on behalf of `voter`. Votes are idempotent: a voter who
+/// already marked the question gets the same `204 No Content` without it being counted again.
+pub async fn update_question_helpful(state: Arc<AppState>, question_id: i32, voter: &str) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    set_question_helpful(state, question_id, voter, true).await
+}
+
+/// Withdraws `voter`'s helpful vote on a question. Withdrawing a vote that was never cast is
+/// a no-op.
+pub async fn remove_question_helpful(state: Arc<AppState>, question_id: i32, voter: &str) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    set_question_helpful(state, question_id, voter, false).await
+}
+
+async fn set_question_helpful(state: Arc<AppState>, question_id: i32, voter: &str, helpful: bool) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => set_question_vote(pool, question_id, voter, helpful).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::set_question_vote(pool, question_id, voter, helpful).await,
+    };
 
     match result {
-        Ok(_) => create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null),
+        Ok(Some((product_id, changed))) => {
+            if changed {
+                state.questions_cache.invalidate(product_id);
+            }
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Question not found".into()),
         Err(e) => {
-            println!("Failed to update question helpfulness: {:?}", e);
+            log_error("Failed to update question helpfulness", &e);
             create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update question helpfulness".into())
         }
     }
 }
 
-pub async fn update_question_report(pool: Arc<PgPool>, question_id: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    let result = sqlx::query!(
-        r#"
-        UPDATE questions
-        SET reported = true
-        WHERE id = $1;
-        "#,
-        question_id
-    )
-    .execute(&*pool)
-    .await;
+/// Records or withdraws `voter`'s vote on a question and moves its helpful count along with
+/// it. Returns the question's product id and whether the vote changed, or `None` if there
+/// is no such question.
+async fn set_question_vote(pool: &PgPool, question_id: i32, voter: &str, helpful: bool) -> Result<Option<(i32, bool)>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Locking the question serializes concurrent votes on it
+    let product_id = sqlx::query_scalar!("SELECT product_id FROM questions WHERE id = $1 FOR UPDATE;", question_id)
+        .fetch_optional(&mut tx)
+        .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    let result = if helpful {
+        sqlx::query!("INSERT INTO question_votes (question_id, voter) VALUES ($1, $2) ON CONFLICT DO NOTHING;", question_id, voter)
+            .execute(&mut tx)
+            .await?
+    } else {
+        sqlx::query!("DELETE FROM question_votes WHERE question_id = $1 AND voter = $2;", question_id, voter)
+            .execute(&mut tx)
+            .await?
+    };
+    let changed = result.rows_affected() > 0;
+
+    if changed {
+        sqlx::query!(
+            "UPDATE questions SET helpful = helpful + $2, updated_at = NOW() WHERE id = $1;",
+            question_id,
+            if helpful { 1 } else { -1 }
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    if changed && helpful {
+        outbox::record(&mut tx, Event::QuestionHelpful { question_id, product_id }).await?;
+    }
+
+    tx.commit().await?;
+    Ok(Some((product_id, changed)))
+}
+
+/// Reports a question, hiding it from the question lists until a moderator approves it.
+/// Every report is kept with its reason, note and the hashed identity of `reporter`.
+pub async fn update_question_report(state: Arc<AppState>, question_id: i32, reporter: &str, report: NewReport) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let pool = match &state.db {
+        Database::Postgres(pool) => pool,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => return sqlite::update_question_report(pool, &state.questions_cache, question_id, reporter, report).await,
+    };
+
+    let result = report_question(pool, question_id, reporter, &report).await;
 
     match result {
-        Ok(_) => create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null),
+        Ok(Some(product_id)) => {
+            state.questions_cache.invalidate(product_id);
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Question not found".into()),
         Err(e) => {
-            println!("Failed to update question report: {:?}", e);
+            log_error("Failed to update question report", &e);
             create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update question report".into())
         }
     }
 }
 
-pub async fn update_answer_helpful(pool: Arc<PgPool>, answer_id: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    let result = sqlx::query!(
+/// Reports a question and records its [`Event::QuestionReported`]. Returns the question's
+/// product id, or `None` if there is no such question.
+async fn report_question(pool: &PgPool, question_id: i32, reporter: &str, report: &NewReport) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let product_id = sqlx::query_scalar!(
         r#"
-        UPDATE answers
-        SET helpful = helpful + 1
-        WHERE id = $1;
+        WITH q AS (
+            UPDATE questions
+            SET reported = true, updated_at = NOW()
+            WHERE id = $1
+            RETURNING id, product_id
+        ), r AS (
+            INSERT INTO question_reports (question_id, reason, note, reporter)
+            SELECT id, $2, $3, $4 FROM q
+        )
+        SELECT product_id AS "product_id!" FROM q;
         "#,
-        answer_id
+        question_id,
+        report.reason.as_str(),
+        report.note,
+        reporter
     )
-    .execute(&*pool)
-    .await;
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+    outbox::record(&mut tx, Event::QuestionReported { question_id, product_id, reason: report.reason }).await?;
+
+    tx.commit().await?;
+    Ok(Some(product_id))
+}
+
+/// Marks an answer as helpful on behalf of `voter`, counting each voter once like
+/// [`update_question_helpful`].
+pub async fn update_answer_helpful(state: Arc<AppState>, answer_id: i32, voter: &str) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    set_answer_helpful(state, answer_id, voter, true).await
+}
+
+/// Withdraws `voter`'s helpful vote on an answer.
+pub async fn remove_answer_helpful(state: Arc<AppState>, answer_id: i32, voter: &str) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    set_answer_helpful(state, answer_id, voter, false).await
+}
+
+async fn set_answer_helpful(state: Arc<AppState>, answer_id: i32, voter: &str, helpful: bool) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => set_answer_vote(pool, answer_id, voter, helpful).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::set_answer_vote(pool, answer_id, voter, helpful).await,
+    };
 
     match result {
-        Ok(_) => create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null),
+        Ok(Some((product_id, changed))) => {
+            if changed {
+                state.questions_cache.invalidate(product_id);
+            }
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Answer not found".into()),
         Err(e) => {
-            println!("Failed to update answer helpfulness: {:?}", e);
+            log_error("Failed to update answer helpfulness", &e);
             create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update answer helpfulness".into())
         }
     }
 }
 
-pub async fn update_answer_report(pool: Arc<PgPool>, answer_id: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    let result = sqlx::query!(
+/// Answer counterpart of [`set_question_vote`]; the product id is that of the answer's question.
+async fn set_answer_vote(pool: &PgPool, answer_id: i32, voter: &str, helpful: bool) -> Result<Option<(i32, bool)>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let product_id = sqlx::query_scalar!(
         r#"
-        UPDATE answers
-        SET reported = true
-        WHERE id = $1;
+        SELECT q.product_id
+        FROM answers AS a
+        JOIN questions AS q ON q.id = a.question_id
+        WHERE a.id = $1
+        FOR UPDATE OF a;
         "#,
         answer_id
     )
-    .execute(&*pool)
-    .await;
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    let result = if helpful {
+        sqlx::query!("INSERT INTO answer_votes (answer_id, voter) VALUES ($1, $2) ON CONFLICT DO NOTHING;", answer_id, voter)
+            .execute(&mut tx)
+            .await?
+    } else {
+        sqlx::query!("DELETE FROM answer_votes WHERE answer_id = $1 AND voter = $2;", answer_id, voter)
+            .execute(&mut tx)
+            .await?
+    };
+    let changed = result.rows_affected() > 0;
+
+    if changed {
+        sqlx::query!(
+            "UPDATE answers SET helpful = helpful + $2, updated_at = NOW() WHERE id = $1;",
+            answer_id,
+            if helpful { 1 } else { -1 }
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    if changed && helpful {
+        outbox::record(&mut tx, Event::AnswerHelpful { answer_id, product_id }).await?;
+    }
+
+    tx.commit().await?;
+    Ok(Some((product_id, changed)))
+}
+
+/// Reports an answer, like [`update_question_report`].
+pub async fn update_answer_report(state: Arc<AppState>, answer_id: i32, reporter: &str, report: NewReport) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let pool = match &state.db {
+        Database::Postgres(pool) => pool,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => return sqlite::update_answer_report(pool, &state.questions_cache, answer_id, reporter, report).await,
+    };
+
+    let result = report_answer(pool, answer_id, reporter, &report).await;
 
     match result {
-        Ok(_) => create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null),
+        Ok(Some(product_id)) => {
+            state.questions_cache.invalidate(product_id);
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Answer not found".into()),
         Err(e) => {
-            println!("Failed to update answer report: {:?}", e);
+            log_error("Failed to update answer report", &e);
             create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update answer report".into())
         }
     }
 }
+
+/// Answer counterpart of [`report_question`].
+async fn report_answer(pool: &PgPool, answer_id: i32, reporter: &str, report: &NewReport) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let product_id = sqlx::query_scalar!(
+        r#"
+        WITH a AS (
+            UPDATE answers AS a
+            SET reported = true, updated_at = NOW()
+            FROM questions AS q
+            WHERE a.id = $1 AND q.id = a.question_id
+            RETURNING a.id, q.product_id
+        ), r AS (
+            INSERT INTO answer_reports (answer_id, reason, note, reporter)
+            SELECT id, $2, $3, $4 FROM a
+        )
+        SELECT product_id AS "product_id!" FROM a;
+        "#,
+        answer_id,
+        report.reason.as_str(),
+        report.note,
+        reporter
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+    outbox::record(&mut tx, Event::AnswerReported { answer_id, product_id, reason: report.reason }).await?;
+
+    tx.commit().await?;
+    Ok(Some(product_id))
+}
+
+/// Reports the questions cache counters in the Prometheus text exposition format.
+pub fn get_metrics(state: Arc<AppState>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let stats = state.questions_cache.stats();
+
+    let body = format!(
+        "# HELP qa_questions_cache_hits_total Question list requests served from the cache.\n\
+         # TYPE qa_questions_cache_hits_total counter\n\
+         qa_questions_cache_hits_total {}\n\
+         # HELP qa_questions_cache_misses_total Question list requests that had to query the database.\n\
+         # TYPE qa_questions_cache_misses_total counter\n\
+         qa_questions_cache_misses_total {}\n\
+         # HELP qa_questions_cache_invalidations_total Cached products dropped because of a write.\n\
+         # TYPE qa_questions_cache_invalidations_total counter\n\
+         qa_questions_cache_invalidations_total {}\n\
+         # HELP qa_questions_cache_products Products currently held in the cache.\n\
+         # TYPE qa_questions_cache_products gauge\n\
+         qa_questions_cache_products {}\n\
+         # HELP qa_questions_cache_pages Pages currently held in the cache.\n\
+         # TYPE qa_questions_cache_pages gauge\n\
+         qa_questions_cache_pages {}\n",
+        stats.hits, stats.misses, stats.invalidations, stats.products, stats.pages
+    );
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(Body::from(body))
+        .unwrap())
+}
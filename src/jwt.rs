@@ -0,0 +1,138 @@
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+
+/// A shopper signed in to the storefront, as named by the claims of their bearer token.
+#[derive(Clone, Debug)]
+pub struct User {
+    /// The token's `sub` claim, the storefront's stable id for the shopper.
+    pub subject: String,
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    name: String,
+    email: String,
+}
+
+/// Why a bearer token was not accepted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TokenError {
+    /// The token was valid but its `exp` claim has passed.
+    Expired,
+    /// The token is malformed, signed with an unknown key or algorithm, or fails the
+    /// issuer, audience or claim checks.
+    Invalid,
+}
+
+/// Verifies the JWTs the storefront issues to signed-in shoppers.
+///
+/// HS256 tokens are checked against a shared secret and RS256 tokens against the RSA keys
+/// of a JWKS document, picked by the token's `kid`. Tokens must carry `sub`, `name`, `email`
+/// and `exp` claims; `iss` and `aud` are checked when an issuer or audience is configured.
+pub struct JwtVerifier {
+    secret: Option<DecodingKey>,
+    rsa_keys: Vec<(Option<String>, DecodingKey)>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl JwtVerifier {
+    /// Creates a verifier from an HS256 secret, a JWKS document in JSON, or both.
+    pub fn new(secret: Option<&[u8]>, jwks: Option<&str>, issuer: Option<String>, audience: Option<String>) -> Result<JwtVerifier, String> {
+        let secret = secret.map(DecodingKey::from_secret);
+
+        let mut rsa_keys = Vec::new();
+        if let Some(jwks) = jwks {
+            let jwks: JwkSet = serde_json::from_str(jwks).map_err(|e| format!("Invalid JWKS document: {}", e))?;
+            for jwk in &jwks.keys {
+                if let AlgorithmParameters::RSA(_) = jwk.algorithm {
+                    let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("Invalid key in JWKS document: {}", e))?;
+                    rsa_keys.push((jwk.common.key_id.clone(), key));
+                }
+            }
+            if rsa_keys.is_empty() {
+                return Err("JWKS document has no RSA keys".into());
+            }
+        }
+
+        if secret.is_none() && rsa_keys.is_empty() {
+            return Err("A JWT secret or JWKS document is required".into());
+        }
+
+        Ok(JwtVerifier { secret, rsa_keys, issuer, audience })
+    }
+
+    /// Reads the verifier's configuration from `JWT_SECRET` (HS256) and `JWT_JWKS_FILE`
+    /// (RS256), with optional `JWT_ISSUER` and `JWT_AUDIENCE`. Returns `None` when neither
+    /// a secret nor a JWKS file is set, which leaves bearer tokens unused.
+    pub fn from_env() -> Result<Option<JwtVerifier>, String> {
+        let secret = std::env::var("JWT_SECRET").ok().filter(|secret| !secret.is_empty());
+        let jwks = match std::env::var("JWT_JWKS_FILE") {
+            Ok(path) if !path.is_empty() => {
+                Some(std::fs::read_to_string(&path).map_err(|e| format!("Failed to read JWT_JWKS_FILE {}: {}", path, e))?)
+            }
+            _ => None,
+        };
+
+        if secret.is_none() && jwks.is_none() {
+            return Ok(None);
+        }
+
+        JwtVerifier::new(
+            secret.as_deref().map(str::as_bytes),
+            jwks.as_deref(),
+            std::env::var("JWT_ISSUER").ok(),
+            std::env::var("JWT_AUDIENCE").ok(),
+        )
+        .map(Some)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<User, TokenError> {
+        let header = decode_header(token).map_err(|_| TokenError::Invalid)?;
+
+        // Each algorithm only ever sees keys of its own family, so an RS256 public key can't
+        // be passed off as an HS256 secret.
+        let key = match header.alg {
+            Algorithm::HS256 => self.secret.as_ref(),
+            Algorithm::RS256 => self.rsa_key(header.kid.as_deref()),
+            _ => None,
+        }
+        .ok_or(TokenError::Invalid)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.validate_nbf = true;
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+        }
+
+        let claims = decode::<Claims>(token, key, &validation)
+            .map_err(|e| match e.kind() {
+                ErrorKind::ExpiredSignature => TokenError::Expired,
+                _ => TokenError::Invalid,
+            })?
+            .claims;
+
+        if claims.sub.is_empty() || claims.name.trim().is_empty() || claims.email.trim().is_empty() {
+            return Err(TokenError::Invalid);
+        }
+
+        Ok(User { subject: claims.sub, name: claims.name, email: claims.email })
+    }
+
+    /// The RSA key named by `kid`, or the only key when the token names none.
+    fn rsa_key(&self, kid: Option<&str>) -> Option<&DecodingKey> {
+        match kid {
+            Some(kid) => self.rsa_keys.iter().find(|(id, _)| id.as_deref() == Some(kid)).map(|(_, key)| key),
+            None if self.rsa_keys.len() == 1 => Some(&self.rsa_keys[0].1),
+            None => None,
+        }
+    }
+}
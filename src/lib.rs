@@ -0,0 +1,34 @@
+// Without the `sqlite` feature `Database` has a single variant, so the backend matches
+// throughout the crate are infallible.
+#![cfg_attr(not(feature = "sqlite"), allow(clippy::infallible_destructuring_match))]
+
+pub mod auth;
+pub mod cache;
+pub mod capture;
+pub mod conditional;
+pub mod db;
+pub mod emails;
+pub mod fetch;
+pub mod handlers;
+pub mod jwt;
+pub mod loadtest;
+pub mod models;
+pub mod moderation;
+pub mod multipart;
+pub mod notifications;
+pub mod outbox;
+pub mod photos;
+pub mod privacy;
+pub mod proxy;
+pub mod ratelimit;
+pub mod routes;
+pub mod smtp;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod state;
+pub mod storage;
+pub mod thumbnails;
+pub mod utils;
+pub mod verification;
+pub mod votes;
+pub mod webhooks;
@@ -0,0 +1,335 @@
+use crate::db::Database;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The name the load test posts questions and answers under.
+pub const LOAD_TEST_NAME: &str = "loadtest";
+
+/// The prefix of every question and answer body posted by load-test run `run`, which is
+/// how its rows are found again by [`purge_load_test_data`].
+pub fn load_test_tag(run: &str) -> String {
+    format!("[loadtest:{}]", run)
+}
+
+/// Whether `run` can name a load-test run: up to 64 letters, digits, `-` and `_`.
+pub fn is_valid_run(run: &str) -> bool {
+    !run.is_empty() && run.len() <= 64 && run.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// What [`purge_load_test_data`] deleted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PurgeReport {
+    pub questions: u64,
+    pub answers: u64,
+}
+
+/// Deletes every question and answer posted by the load test, with their photos, votes,
+/// reports, notifications and moderation actions. Only rows posted under [`LOAD_TEST_NAME`]
+/// with a body tagged by [`load_test_tag`] are touched: those of run `run`, or of every run
+/// when `None`.
+///
+/// Answers posted to load-test questions go with them, whoever posted them.
+pub async fn purge_load_test_data(db: &Database, run: Option<&str>) -> Result<PurgeReport, sqlx::Error> {
+    // `_` is the one character a run may contain that LIKE gives a meaning to.
+    let pattern = match run {
+        Some(run) => format!("{}%", load_test_tag(run).replace('_', "\\_")),
+        None => "[loadtest:%".to_string(),
+    };
+
+    match db {
+        Database::Postgres(pool) => purge(pool, &pattern).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::purge_load_test_data(pool, &pattern).await,
+    }
+}
+
+async fn purge(pool: &PgPool, pattern: &str) -> Result<PurgeReport, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let question_ids = sqlx::query_scalar!(
+        r#"SELECT id FROM questions WHERE asker_name = $1 AND body LIKE $2 ESCAPE '\';"#,
+        LOAD_TEST_NAME,
+        pattern
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let answer_ids = sqlx::query_scalar!(
+        r#"SELECT id FROM answers WHERE question_id = ANY($1) OR (answerer_name = $2 AND body LIKE $3 ESCAPE '\');"#,
+        &question_ids,
+        LOAD_TEST_NAME,
+        pattern
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM answer_notifications WHERE answer_id = ANY($1);", &answer_ids).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM photo_reports WHERE photo_id IN (SELECT id FROM answer_photos WHERE answer_id = ANY($1));", &answer_ids)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM moderation_actions WHERE target_type = 'photo' AND target_id IN (SELECT id FROM answer_photos WHERE answer_id = ANY($1));",
+        &answer_ids
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!("DELETE FROM answer_photos WHERE answer_id = ANY($1);", &answer_ids).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM answer_votes WHERE answer_id = ANY($1);", &answer_ids).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM answer_reports WHERE answer_id = ANY($1);", &answer_ids).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM moderation_actions WHERE target_type = 'answer' AND target_id = ANY($1);", &answer_ids).execute(&mut tx).await?;
+    let answers = sqlx::query!("DELETE FROM answers WHERE id = ANY($1);", &answer_ids).execute(&mut tx).await?.rows_affected();
+
+    sqlx::query!("DELETE FROM question_votes WHERE question_id = ANY($1);", &question_ids).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM question_reports WHERE question_id = ANY($1);", &question_ids).execute(&mut tx).await?;
+    sqlx::query!("DELETE FROM moderation_actions WHERE target_type = 'question' AND target_id = ANY($1);", &question_ids).execute(&mut tx).await?;
+    let questions = sqlx::query!("DELETE FROM questions WHERE id = ANY($1);", &question_ids).execute(&mut tx).await?.rows_affected();
+
+    tx.commit().await?;
+    Ok(PurgeReport { questions, answers })
+}
+
+/// The ids a load test requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdKind {
+    Product,
+    Question,
+}
+
+/// Where a load test takes its ids from, written `database`, `file:<path>` or
+/// `range:<min>-<max>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdSource {
+    /// The products with questions, or the questions, visible in the database.
+    Database,
+    /// A file of ids separated by whitespace or commas. `#` starts a comment.
+    File(PathBuf),
+    /// Every id from the first to the second, both included.
+    Range(i32, i32),
+}
+
+impl FromStr for IdSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        if source == "database" {
+            return Ok(IdSource::Database);
+        }
+        if let Some(path) = source.strip_prefix("file:").filter(|path| !path.is_empty()) {
+            return Ok(IdSource::File(PathBuf::from(path)));
+        }
+        if let Some((min, max)) = source.strip_prefix("range:").and_then(|range| range.split_once('-')) {
+            if let (Ok(min), Ok(max)) = (min.trim().parse(), max.trim().parse()) {
+                if min <= max {
+                    return Ok(IdSource::Range(min, max));
+                }
+            }
+        }
+        Err(format!("Invalid id source {:?}, expected database, file:<path> or range:<min>-<max>", source))
+    }
+}
+
+/// Parses the contents of an [`IdSource::File`].
+pub fn parse_ids(text: &str) -> Result<Vec<i32>, String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse().map_err(|_| format!("Invalid id {:?}", id)))
+        .collect()
+}
+
+/// Reads the ids of `kind` visible in the database, in ascending order.
+pub async fn load_ids(db: &Database, kind: IdKind) -> Result<Vec<i32>, sqlx::Error> {
+    match db {
+        Database::Postgres(pool) => query_ids(pool, kind).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::load_test_ids(pool, kind).await,
+    }
+}
+
+async fn query_ids(pool: &PgPool, kind: IdKind) -> Result<Vec<i32>, sqlx::Error> {
+    match kind {
+        IdKind::Product => {
+            sqlx::query_scalar!("SELECT DISTINCT product_id FROM questions WHERE reported = false ORDER BY product_id;")
+                .fetch_all(pool)
+                .await
+        }
+        IdKind::Question => {
+            sqlx::query_scalar!("SELECT id FROM questions WHERE reported = false ORDER BY id;")
+                .fetch_all(pool)
+                .await
+        }
+    }
+}
+
+/// How requests spread over ids, written `uniform`, `zipf:<exponent>` or
+/// `hot:<share of ids>:<share of requests>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Popularity {
+    /// Every id is as likely as any other.
+    Uniform,
+    /// The id ranked `k` is requested in proportion to `1 / k^exponent`, the long tail of
+    /// real catalogues. Exponents around 1 are typical.
+    Zipf { exponent: f64 },
+    /// A hot set of `ids` of the ids takes `requests` of the requests, e.g. `hot:0.2:0.8`.
+    HotSet { ids: f64, requests: f64 },
+}
+
+impl FromStr for Popularity {
+    type Err = String;
+
+    fn from_str(popularity: &str) -> Result<Self, Self::Err> {
+        let share = |value: &str| value.parse::<f64>().ok().filter(|share| *share > 0.0 && *share <= 1.0);
+        let parsed = match popularity.split(':').collect::<Vec<_>>()[..] {
+            ["uniform"] => Some(Popularity::Uniform),
+            ["zipf", exponent] => exponent
+                .parse::<f64>()
+                .ok()
+                .filter(|exponent| exponent.is_finite() && *exponent > 0.0)
+                .map(|exponent| Popularity::Zipf { exponent }),
+            ["hot", ids, requests] => share(ids).zip(share(requests)).map(|(ids, requests)| Popularity::HotSet { ids, requests }),
+            _ => None,
+        };
+        parsed.ok_or_else(|| format!("Invalid popularity {:?}, expected uniform, zipf:<exponent> or hot:<share of ids>:<share of requests>", popularity))
+    }
+}
+
+/// Draws ids with a [`Popularity`].
+#[derive(Debug)]
+pub struct IdSampler {
+    ids: Vec<i32>,
+    pick: Pick,
+}
+
+#[derive(Debug)]
+enum Pick {
+    Uniform,
+    /// Cumulative weights of the ids in rank order.
+    Weighted(Vec<f64>),
+    /// The first `len` ids are hot and take `requests` of the draws.
+    Hot { len: usize, requests: f64 },
+}
+
+impl IdSampler {
+    /// Ranks `ids` by popularity. Which ids are popular is shuffled by `rng`, so that it
+    /// has nothing to do with id order, and a seeded `rng` makes the same ids popular every run.
+    pub fn new<R: Rng>(mut ids: Vec<i32>, popularity: Popularity, rng: &mut R) -> Result<IdSampler, String> {
+        if ids.is_empty() {
+            return Err("No ids to draw from".to_string());
+        }
+        ids.shuffle(rng);
+
+        let pick = match popularity {
+            Popularity::Uniform => Pick::Uniform,
+            Popularity::Zipf { exponent } => Pick::Weighted(
+                (1..=ids.len())
+                    .scan(0.0, |total, rank| {
+                        *total += (rank as f64).powf(-exponent);
+                        Some(*total)
+                    })
+                    .collect(),
+            ),
+            Popularity::HotSet { ids: share, requests } => {
+                let len = ((ids.len() as f64 * share).ceil() as usize).clamp(1, ids.len());
+                if len == ids.len() { Pick::Uniform } else { Pick::Hot { len, requests } }
+            }
+        };
+        Ok(IdSampler { ids, pick })
+    }
+
+    /// Draws an id.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> i32 {
+        let index = match &self.pick {
+            Pick::Uniform => rng.gen_range(0..self.ids.len()),
+            Pick::Weighted(cumulative) => {
+                let target = rng.gen::<f64>() * cumulative[cumulative.len() - 1];
+                cumulative.partition_point(|total| *total <= target).min(cumulative.len() - 1)
+            }
+            Pick::Hot { len, requests } => {
+                if rng.gen_bool(*requests) {
+                    rng.gen_range(0..*len)
+                } else {
+                    rng.gen_range(*len..self.ids.len())
+                }
+            }
+        };
+        self.ids[index]
+    }
+}
+
+/// Response times and outcomes of a load test's requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestStats {
+    /// How many responses took each number of milliseconds.
+    pub times: BTreeMap<usize, usize>,
+    pub successes: usize,
+    pub failures: usize,
+}
+
+impl RequestStats {
+    /// Adds `other` to these stats, e.g. to total every request.
+    pub fn merge(&mut self, other: &RequestStats) {
+        for (time, count) in &other.times {
+            *self.times.entry(*time).or_insert(0) += count;
+        }
+        self.successes += other.successes;
+        self.failures += other.failures;
+    }
+
+    /// The response time in milliseconds that `percent` of the responses were no slower
+    /// than, or `None` without responses.
+    pub fn percentile(&self, percent: f64) -> Option<usize> {
+        let total: usize = self.times.values().sum();
+        let rank = ((total as f64 * percent / 100.0).ceil() as usize).max(1);
+        let mut seen = 0;
+        self.times.iter().find_map(|(time, count)| {
+            seen += count;
+            (seen >= rank).then_some(*time)
+        })
+    }
+
+    /// The percentage of requests that failed.
+    pub fn error_rate(&self) -> f64 {
+        match self.successes + self.failures {
+            0 => 0.0,
+            total => self.failures as f64 * 100.0 / total as f64,
+        }
+    }
+}
+
+/// The service level objectives a load test is held to. Unset objectives are not checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Slo {
+    /// The slowest the 95th percentile response may be, in milliseconds.
+    pub p95: Option<usize>,
+    /// The slowest the 99th percentile response may be, in milliseconds.
+    pub p99: Option<usize>,
+    /// The highest percentage of requests that may fail.
+    pub error_rate: Option<f64>,
+}
+
+impl Slo {
+    /// Describes each objective that the requests `name` breached.
+    pub fn breaches(&self, name: &str, stats: &RequestStats) -> Vec<String> {
+        let mut breaches = Vec::new();
+        for (percent, limit) in [(95.0, self.p95), (99.0, self.p99)] {
+            if let (Some(limit), Some(time)) = (limit, stats.percentile(percent)) {
+                if time > limit {
+                    breaches.push(format!("{}: p{} of {} ms is over {} ms", name, percent, time, limit));
+                }
+            }
+        }
+        if let Some(limit) = self.error_rate {
+            let rate = stats.error_rate();
+            if rate > limit {
+                breaches.push(format!("{}: {:.2}% of requests failed, over {}%", name, rate, limit));
+            }
+        }
+        breaches
+    }
+}
@@ -1,8 +1,3 @@
-mod handlers;
-mod models;
-mod utils;
-mod routes;
-
 use dotenv::dotenv;
 use hyper::{ 
     Server,
@@ -10,20 +5,20 @@ use hyper::{
     server::conn::AddrStream,
 };
 
-use sqlx::postgres::PgPoolOptions;
+use qa_rs::{auth::AuthConfig, cache::QuestionsCache, capture::RequestCapture, conditional::CacheControl, db::Database, emails::EmailProtector, fetch::PhotoFetcher, notifications::{AnswerNotifier, Notifications}, proxy::TrustedProxies, ratelimit::RateLimiter, routes, state::AppState, storage::PhotoStorage, thumbnails::ThumbnailWorker, verification::PhotoVerifier, votes::VoterHasher, webhooks::WebhookDispatcher};
 use std::{net::SocketAddr, sync::Arc};
 
 /// The entry point for the application.
 ///
-/// This function initializes the environment variables, creates a connection pool
-/// to the database, sets up the server to listen on a specific address, and
-/// starts the server.
+/// This function initializes the environment variables, connects to the database
+/// selected by `DATABASE_URL` (Postgres, or SQLite for `sqlite:` URLs), sets up the
+/// server to listen on a specific address, and starts the server.
 ///
 /// # Errors
 ///
 /// Returns an error if any of the following occurs:
 /// - Failed to load environment variables
-/// - Failed to create a connection pool to the database
+/// - Failed to connect to the database
 /// - Failed to bind the server to the specified address
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -31,24 +26,47 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     let database_url = std::env::var("DATABASE_URL")?;
 
-    let pool = PgPoolOptions::new()
-        .max_connections(1000)
-        .idle_timeout(std::time::Duration::from_secs(30))
-        .connect(&database_url)
-        .await?;
+    let db = Database::connect(&database_url).await?;
+
+    // Wrap the shared state in an Arc for shared ownership and thread safety
+    let state = Arc::new(AppState {
+        db,
+        questions_cache: QuestionsCache::from_env(),
+        cache_control: CacheControl::from_env(),
+        voters: VoterHasher::from_env(),
+        emails: EmailProtector::from_env()?,
+        auth: AuthConfig::from_env()?,
+        trusted_proxies: TrustedProxies::from_env()?,
+        rate_limiter: RateLimiter::from_env()?,
+        capture: RequestCapture::from_env()?,
+        photos: PhotoStorage::from_env()?,
+        photo_fetcher: PhotoFetcher::from_env()?,
+        notifications: Notifications::from_env()?,
+    });
 
-    // Wrap the connection pool in an Arc for shared ownership and thread safety
-    let pool = Arc::new(pool);
+    if let Some(verifier) = PhotoVerifier::from_env()? {
+        verifier.spawn(state.clone());
+    }
+    if let Some(worker) = ThumbnailWorker::from_env()? {
+        worker.spawn(state.clone());
+    }
+    if let Some(dispatcher) = WebhookDispatcher::from_env()? {
+        dispatcher.spawn(state.clone());
+    }
+    if let Some(notifier) = AnswerNotifier::from_env()? {
+        notifier.spawn(state.clone());
+    }
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
 
     // Create a service factory function that handles incoming connections
-    let make_svc = make_service_fn(move |_conn: &AddrStream| {
-        // Clone the connection pool for each incoming connection
-        let pool = pool.clone();
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        // Clone the shared state for each incoming connection
+        let state = state.clone();
+        let remote_addr = conn.remote_addr();
 
         // Return a service function that handles incoming requests and passes them to the router
-        async { Ok::<_, hyper::Error>(service_fn(move |req| routes::handle_request(pool.clone(), req))) }
+        async move { Ok::<_, hyper::Error>(service_fn(move |req| routes::handle_request(state.clone(), remote_addr, req))) }
     });
 
     let server = Server::bind(&addr).serve(make_svc);
@@ -3,7 +3,10 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct NewQuestion {
     pub body: String,
+    /// Ignored for signed-in shoppers, who are named by their bearer token's claims.
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub email: String,
     pub product_id: i32,
 }
@@ -11,7 +14,132 @@ pub struct NewQuestion {
 #[derive(Deserialize)]
 pub struct NewAnswer {
     pub body: String,
+    /// Ignored for signed-in shoppers, as for `NewQuestion`.
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub email: String,
     pub photos: Vec<String>,
 }
+
+/// Orderings accepted by the `sort` query parameter of `GET /api/v1/questions`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QuestionSort {
+    /// Oldest questions first. This is the default.
+    Oldest,
+    /// Most recently asked questions first.
+    Newest,
+    /// Questions with the most helpful votes first.
+    Helpful,
+}
+
+impl QuestionSort {
+    pub fn parse(value: &str) -> Option<QuestionSort> {
+        match value {
+            "oldest" => Some(QuestionSort::Oldest),
+            "newest" => Some(QuestionSort::Newest),
+            "helpful" => Some(QuestionSort::Helpful),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuestionSort::Oldest => "oldest",
+            QuestionSort::Newest => "newest",
+            QuestionSort::Helpful => "helpful",
+        }
+    }
+}
+
+/// Body accepted by `PUT .../report`. The body is optional; an empty one reports with
+/// reason `other` and no note.
+#[derive(Deserialize, Default)]
+pub struct NewReport {
+    #[serde(default)]
+    pub reason: ReportReason,
+    pub note: Option<String>,
+}
+
+/// Why a question or answer was reported.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Spam,
+    Offensive,
+    OffTopic,
+    Inaccurate,
+    #[default]
+    Other,
+}
+
+impl ReportReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportReason::Spam => "spam",
+            ReportReason::Offensive => "offensive",
+            ReportReason::OffTopic => "off_topic",
+            ReportReason::Inaccurate => "inaccurate",
+            ReportReason::Other => "other",
+        }
+    }
+}
+
+/// Decisions a moderator can take on reported content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModerationAction {
+    /// Clears the reports and shows the content again, including content removed earlier.
+    Approve,
+    /// Keeps the content hidden and takes it out of the moderation queue.
+    Remove,
+}
+
+impl ModerationAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationAction::Approve => "approve",
+            ModerationAction::Remove => "remove",
+        }
+    }
+}
+
+/// Body of `POST /api/v1/admin/data-requests/export`.
+#[derive(Deserialize)]
+pub struct ExportRequest {
+    pub email: String,
+    /// The ticket or case the request was received under, kept in the audit log.
+    pub reference: Option<String>,
+}
+
+/// Body of `POST /api/v1/admin/data-requests/erase`.
+#[derive(Deserialize)]
+pub struct ErasureRequest {
+    pub email: String,
+    pub content: ErasurePolicy,
+    pub reference: Option<String>,
+}
+
+/// What happens to the questions and answers of an erased author.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErasurePolicy {
+    /// The content stays up under an anonymous author.
+    Keep,
+    /// The content is blanked and hidden, and answer photos are deleted.
+    Remove,
+}
+
+impl ErasurePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErasurePolicy::Keep => "keep",
+            ErasurePolicy::Remove => "remove",
+        }
+    }
+}
+
+/// Optional body of the moderation endpoints.
+#[derive(Deserialize, Default)]
+pub struct ModerationDecision {
+    pub note: Option<String>,
+}
@@ -0,0 +1,465 @@
+use crate::db::Database;
+use crate::models::{ModerationAction, ModerationDecision};
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::state::AppState;
+use crate::utils::{create_error_response, create_success_response, log_error};
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+/// A reported question, answer or photo waiting for a moderator.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct QueueItem {
+    #[serde(rename = "type")]
+    pub target_type: String,
+    pub id: i32,
+    pub product_id: i32,
+    /// The question an answer or photo belongs to; `None` for questions.
+    pub question_id: Option<i32>,
+    /// The answer a photo belongs to; `None` for questions and answers.
+    pub answer_id: Option<i32>,
+    /// The text of a question or answer, or the URL of a photo.
+    pub body: Option<String>,
+    pub report_count: i64,
+    pub last_reported_at: Option<String>,
+}
+
+/// A report that no moderator has acted on yet.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct PendingReport {
+    pub reason: String,
+    pub note: Option<String>,
+    pub reported_at: String,
+}
+
+/// An entry of the moderation log.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ModerationRecord {
+    pub id: i32,
+    pub target_type: String,
+    pub target_id: i32,
+    pub action: String,
+    pub moderator: Option<String>,
+    pub note: Option<String>,
+    pub reports_resolved: i32,
+    pub acted_at: String,
+}
+
+/// Lists reported questions, answers and photos that still await a decision, those with the most
+/// pending reports first, together with the reports themselves.
+///
+/// Content reported before individual reports were recorded is listed with a count of 0.
+pub async fn get_moderation_queue(state: Arc<AppState>, page: i32, count: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => query_moderation_queue(pool, page, count).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_moderation_queue(pool, page, count).await,
+    };
+
+    match result {
+        Ok(items) => {
+            let results: Vec<serde_json::Value> = items
+                .into_iter()
+                .map(|(item, reports)| {
+                    let mut item = serde_json::to_value(item).unwrap();
+                    item["reports"] = serde_json::to_value(reports).unwrap();
+                    item
+                })
+                .collect();
+
+            let response = serde_json::json!({ "page": page, "count": count, "results": results });
+            create_success_response(StatusCode::OK, response)
+        }
+        Err(e) => {
+            log_error("Failed to fetch moderation queue", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch moderation queue".into())
+        }
+    }
+}
+
+async fn query_moderation_queue(pool: &PgPool, page: i32, count: i32) -> Result<Vec<(QueueItem, Vec<PendingReport>)>, sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    let items = sqlx::query_as!(
+        QueueItem,
+        r#"
+        SELECT
+            target_type AS "target_type!",
+            id AS "id!",
+            product_id AS "product_id!",
+            question_id,
+            answer_id,
+            body,
+            report_count AS "report_count!",
+            last_reported_at
+        FROM (
+            SELECT
+                'question' AS target_type,
+                q.id,
+                q.product_id,
+                NULL::integer AS question_id,
+                NULL::integer AS answer_id,
+                q.body,
+                COUNT(r.id) AS report_count,
+                to_char(MAX(r.reported_at), 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS last_reported_at
+            FROM questions AS q
+            LEFT JOIN question_reports AS r ON r.question_id = q.id AND r.resolved_at IS NULL
+            WHERE q.reported = true AND q.removed_at IS NULL
+            GROUP BY q.id
+            UNION ALL
+            SELECT
+                'answer',
+                a.id,
+                q.product_id,
+                a.question_id,
+                NULL,
+                a.body,
+                COUNT(r.id),
+                to_char(MAX(r.reported_at), 'YYYY-MM-DD"T"HH24:MI:SS.MS')
+            FROM answers AS a
+            JOIN questions AS q ON q.id = a.question_id
+            LEFT JOIN answer_reports AS r ON r.answer_id = a.id AND r.resolved_at IS NULL
+            WHERE a.reported = true AND a.removed_at IS NULL
+            GROUP BY a.id, q.product_id
+            UNION ALL
+            SELECT
+                'photo',
+                ap.id,
+                q.product_id,
+                a.question_id,
+                ap.answer_id,
+                ap.url,
+                COUNT(r.id),
+                to_char(MAX(r.reported_at), 'YYYY-MM-DD"T"HH24:MI:SS.MS')
+            FROM answer_photos AS ap
+            JOIN answers AS a ON a.id = ap.answer_id
+            JOIN questions AS q ON q.id = a.question_id
+            LEFT JOIN photo_reports AS r ON r.photo_id = ap.id AND r.resolved_at IS NULL
+            WHERE ap.reported = true AND ap.removed_at IS NULL
+            GROUP BY ap.id, a.question_id, q.product_id
+        ) AS queue
+        ORDER BY report_count DESC, last_reported_at DESC NULLS LAST, target_type DESC, id
+        LIMIT $1 OFFSET $2;
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut results = Vec::with_capacity(items.len());
+    for item in items {
+        let reports = match item.target_type.as_str() {
+            "question" => {
+                sqlx::query_as!(
+                    PendingReport,
+                    r#"
+                    SELECT reason, note, to_char(reported_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "reported_at!"
+                    FROM question_reports
+                    WHERE question_id = $1 AND resolved_at IS NULL
+                    ORDER BY id;
+                    "#,
+                    item.id
+                )
+                .fetch_all(pool)
+                .await?
+            }
+            "answer" => {
+                sqlx::query_as!(
+                    PendingReport,
+                    r#"
+                    SELECT reason, note, to_char(reported_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "reported_at!"
+                    FROM answer_reports
+                    WHERE answer_id = $1 AND resolved_at IS NULL
+                    ORDER BY id;
+                    "#,
+                    item.id
+                )
+                .fetch_all(pool)
+                .await?
+            }
+            _ => {
+                sqlx::query_as!(
+                    PendingReport,
+                    r#"
+                    SELECT reason, note, to_char(reported_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "reported_at!"
+                    FROM photo_reports
+                    WHERE photo_id = $1 AND resolved_at IS NULL
+                    ORDER BY id;
+                    "#,
+                    item.id
+                )
+                .fetch_all(pool)
+                .await?
+            }
+        };
+        results.push((item, reports));
+    }
+
+    Ok(results)
+}
+
+/// Approves or removes a reported question, resolving its pending reports and recording
+/// the decision in the moderation log under the name of the `moderator`'s API key.
+pub async fn moderate_question(state: Arc<AppState>, question_id: i32, action: ModerationAction, decision: ModerationDecision, moderator: Option<&str>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => apply_question_moderation(pool, question_id, action, &decision, moderator).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::apply_question_moderation(pool, question_id, action, &decision, moderator).await,
+    };
+
+    match result {
+        Ok(Some(product_id)) => {
+            state.questions_cache.invalidate(product_id);
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Question not found".into()),
+        Err(e) => {
+            log_error("Failed to moderate question", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to moderate question".into())
+        }
+    }
+}
+
+/// Applies `action` to a question and logs it. Returns the question's product id, or `None`
+/// if there is no such question.
+async fn apply_question_moderation(pool: &PgPool, question_id: i32, action: ModerationAction, decision: &ModerationDecision, moderator: Option<&str>) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let product_id = sqlx::query_scalar!(
+        r#"
+        UPDATE questions
+        SET reported = ($2 = 'remove'),
+            removed_at = CASE WHEN $2 = 'remove' THEN COALESCE(removed_at, NOW()) END,
+            updated_at = NOW()
+        WHERE id = $1
+        RETURNING product_id;
+        "#,
+        question_id,
+        action.as_str()
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    let resolved = sqlx::query!(
+        "UPDATE question_reports SET resolved_at = NOW() WHERE question_id = $1 AND resolved_at IS NULL;",
+        question_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO moderation_actions (target_type, target_id, action, moderator, note, reports_resolved)
+        VALUES ('question', $1, $2, $3, $4, $5);
+        "#,
+        question_id,
+        action.as_str(),
+        moderator,
+        decision.note,
+        resolved as i32
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(product_id))
+}
+
+/// Approves or removes a reported answer, like [`moderate_question`].
+pub async fn moderate_answer(state: Arc<AppState>, answer_id: i32, action: ModerationAction, decision: ModerationDecision, moderator: Option<&str>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => apply_answer_moderation(pool, answer_id, action, &decision, moderator).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::apply_answer_moderation(pool, answer_id, action, &decision, moderator).await,
+    };
+
+    match result {
+        Ok(Some(product_id)) => {
+            state.questions_cache.invalidate(product_id);
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Answer not found".into()),
+        Err(e) => {
+            log_error("Failed to moderate answer", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to moderate answer".into())
+        }
+    }
+}
+
+async fn apply_answer_moderation(pool: &PgPool, answer_id: i32, action: ModerationAction, decision: &ModerationDecision, moderator: Option<&str>) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let product_id = sqlx::query_scalar!(
+        r#"
+        UPDATE answers AS a
+        SET reported = ($2 = 'remove'),
+            removed_at = CASE WHEN $2 = 'remove' THEN COALESCE(a.removed_at, NOW()) END,
+            updated_at = NOW()
+        FROM questions AS q
+        WHERE a.id = $1 AND q.id = a.question_id
+        RETURNING q.product_id;
+        "#,
+        answer_id,
+        action.as_str()
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    let resolved = sqlx::query!(
+        "UPDATE answer_reports SET resolved_at = NOW() WHERE answer_id = $1 AND resolved_at IS NULL;",
+        answer_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO moderation_actions (target_type, target_id, action, moderator, note, reports_resolved)
+        VALUES ('answer', $1, $2, $3, $4, $5);
+        "#,
+        answer_id,
+        action.as_str(),
+        moderator,
+        decision.note,
+        resolved as i32
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(product_id))
+}
+
+/// Approves or removes a reported photo. Removing a photo keeps it hidden but leaves its
+/// answer up.
+pub async fn moderate_photo(state: Arc<AppState>, photo_id: i32, action: ModerationAction, decision: ModerationDecision, moderator: Option<&str>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => apply_photo_moderation(pool, photo_id, action, &decision, moderator).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::apply_photo_moderation(pool, photo_id, action, &decision, moderator).await,
+    };
+
+    match result {
+        Ok(Some(product_id)) => {
+            state.questions_cache.invalidate(product_id);
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Photo not found".into()),
+        Err(e) => {
+            log_error("Failed to moderate photo", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to moderate photo".into())
+        }
+    }
+}
+
+async fn apply_photo_moderation(pool: &PgPool, photo_id: i32, action: ModerationAction, decision: &ModerationDecision, moderator: Option<&str>) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let product_id = sqlx::query_scalar!(
+        r#"
+        WITH p AS (
+            UPDATE answer_photos AS ap
+            SET reported = ($2 = 'remove'),
+                removed_at = CASE WHEN $2 = 'remove' THEN COALESCE(ap.removed_at, NOW()) END
+            WHERE ap.id = $1
+            RETURNING ap.answer_id
+        )
+        UPDATE answers AS a
+        SET updated_at = NOW()
+        FROM p, questions AS q
+        WHERE a.id = p.answer_id AND q.id = a.question_id
+        RETURNING q.product_id;
+        "#,
+        photo_id,
+        action.as_str()
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    let resolved = sqlx::query!(
+        "UPDATE photo_reports SET resolved_at = NOW() WHERE photo_id = $1 AND resolved_at IS NULL;",
+        photo_id
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO moderation_actions (target_type, target_id, action, moderator, note, reports_resolved)
+        VALUES ('photo', $1, $2, $3, $4, $5);
+        "#,
+        photo_id,
+        action.as_str(),
+        moderator,
+        decision.note,
+        resolved as i32
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(product_id))
+}
+
+/// Lists the moderation log, most recent actions first.
+pub async fn get_moderation_actions(state: Arc<AppState>, page: i32, count: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => query_moderation_actions(pool, page, count).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_moderation_actions(pool, page, count).await,
+    };
+
+    match result {
+        Ok(actions) => {
+            let response = serde_json::json!({ "page": page, "count": count, "results": actions });
+            create_success_response(StatusCode::OK, response)
+        }
+        Err(e) => {
+            log_error("Failed to fetch moderation actions", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch moderation actions".into())
+        }
+    }
+}
+
+async fn query_moderation_actions(pool: &PgPool, page: i32, count: i32) -> Result<Vec<ModerationRecord>, sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    sqlx::query_as!(
+        ModerationRecord,
+        r#"
+        SELECT
+            id,
+            target_type,
+            target_id,
+            action,
+            moderator,
+            note,
+            reports_resolved,
+            to_char(acted_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "acted_at!"
+        FROM moderation_actions
+        ORDER BY id DESC
+        LIMIT $1 OFFSET $2;
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,107 @@
+use hyper::body::{Bytes, HttpBody};
+use hyper::Body;
+
+/// A part of a `multipart/form-data` body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    /// The form field the part belongs to.
+    pub name: String,
+    /// The name of the uploaded file, for file fields.
+    pub filename: Option<String>,
+    /// The part's `Content-Type`, as declared by the client.
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+/// A body that is not well-formed `multipart/form-data`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidMultipart;
+
+/// The boundary of a `multipart/form-data` `Content-Type`, if it is one.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        let value = value.trim().trim_matches('"');
+        (name.trim().eq_ignore_ascii_case("boundary") && !value.is_empty() && value.len() <= 70).then(|| value.to_string())
+    })
+}
+
+/// Reads `body` into memory, giving up with `None` as soon as it exceeds `limit` bytes.
+pub async fn read_body(mut body: Body, limit: usize) -> Result<Option<Bytes>, hyper::Error> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if data.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Some(data.into()))
+}
+
+/// Splits a `multipart/form-data` body delimited by `boundary` into its parts.
+pub fn parse(body: &Bytes, boundary: &str) -> Result<Vec<Part>, InvalidMultipart> {
+    let delimiter = format!("--{}", boundary);
+    let next_delimiter = format!("\r\n--{}", boundary);
+
+    // Anything before the first delimiter is a preamble to skip.
+    let mut position = find(body, delimiter.as_bytes(), 0).ok_or(InvalidMultipart)? + delimiter.len();
+    let mut parts = Vec::new();
+    loop {
+        let rest = &body[position..];
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !rest.starts_with(b"\r\n") {
+            return Err(InvalidMultipart);
+        }
+        position += 2;
+
+        let headers_end = find(body, b"\r\n\r\n", position).ok_or(InvalidMultipart)?;
+        let headers = std::str::from_utf8(&body[position..headers_end]).map_err(|_| InvalidMultipart)?;
+        let data_start = headers_end + 4;
+        let data_end = find(body, next_delimiter.as_bytes(), data_start).ok_or(InvalidMultipart)?;
+
+        let mut part = Part { name: String::new(), filename: None, content_type: None, data: body.slice(data_start..data_end) };
+        for header in headers.split("\r\n") {
+            let (name, value) = header.split_once(':').ok_or(InvalidMultipart)?;
+            if name.trim().eq_ignore_ascii_case("content-disposition") {
+                part.name = disposition_param(value, "name").ok_or(InvalidMultipart)?;
+                part.filename = disposition_param(value, "filename");
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.trim().to_ascii_lowercase());
+            }
+        }
+        if part.name.is_empty() {
+            return Err(InvalidMultipart);
+        }
+        parts.push(part);
+        position = data_end + next_delimiter.len();
+    }
+}
+
+/// A parameter of a `Content-Disposition: form-data; name="..."` header.
+fn disposition_param(value: &str, param: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        name.trim().eq_ignore_ascii_case(param).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// The first position of `needle` in `haystack` at or after `from`.
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    let first = *needle.first()?;
+    let mut start = from;
+    while let Some(offset) = haystack.get(start..)?.iter().position(|&b| b == first) {
+        let candidate = start + offset;
+        if haystack[candidate..].starts_with(needle) {
+            return Some(candidate);
+        }
+        start = candidate + 1;
+    }
+    None
+}
@@ -0,0 +1,641 @@
+use crate::db::Database;
+use crate::emails::normalize;
+use crate::smtp::{is_valid_address, mailbox_address, Message, SmtpError, SmtpMailer};
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::state::AppState;
+use crate::utils::{create_error_response, env_var, hex, log_error};
+use hmac::{Hmac, Mac};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Response, StatusCode};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Template used unless `NOTIFY_TEMPLATE_FILE` names another, see [`Template`].
+const DEFAULT_TEMPLATE: &str = include_str!("../templates/answer_notification.txt");
+
+/// Attempts at sending a notification that keeps failing in a way that may pass, such as a
+/// `4xx` reply, by default.
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+
+/// Longest wait between two attempts at a notification.
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Path of the unsubscribe link in notification emails.
+pub const UNSUBSCRIBE_PATH: &str = "/api/v1/notifications/unsubscribe";
+
+/// Whether askers are emailed about answers, and the key their unsubscribe links are signed
+/// with.
+///
+/// Addresses are only ever stored as a hash keyed with `NOTIFY_KEY`, which the unsubscribe
+/// link carries with a MAC of it, so the link names no address and cannot be forged for
+/// someone else's. The key has to stay the same across restarts and replicas, otherwise
+/// links already sent stop working and opt-outs are no longer recognised.
+pub struct Notifications {
+    enabled: bool,
+    key: Vec<u8>,
+}
+
+impl Notifications {
+    pub fn new(enabled: bool, key: impl Into<Vec<u8>>) -> Notifications {
+        Notifications { enabled, key: key.into() }
+    }
+
+    /// Queues no notifications, and accepts no unsubscribe links.
+    pub fn disabled() -> Notifications {
+        Notifications::new(false, Vec::new())
+    }
+
+    /// Reads `NOTIFY_ANSWERS` (`off` by default, `on` to queue a notification with each
+    /// answer) and `NOTIFY_KEY`, which `on` requires. The key is read while off as well, so
+    /// links sent before notifications were turned off keep working.
+    pub fn from_env() -> Result<Notifications, String> {
+        let enabled = match std::env::var("NOTIFY_ANSWERS").as_deref() {
+            Ok("on") => true,
+            Ok("off") | Err(_) => false,
+            Ok(other) => return Err(format!("Invalid NOTIFY_ANSWERS: {}", other)),
+        };
+        let key = std::env::var("NOTIFY_KEY").unwrap_or_default();
+        if enabled && key.is_empty() {
+            return Err("NOTIFY_ANSWERS=on requires NOTIFY_KEY".into());
+        }
+
+        Ok(Notifications::new(enabled, key))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The hash opt-outs of `email` are stored under.
+    pub fn address_hash(&self, email: &str) -> String {
+        self.mac("address", normalize(email).as_bytes())
+    }
+
+    /// The token of the unsubscribe link for the address hashed as `address_hash`.
+    pub fn unsubscribe_token(&self, address_hash: &str) -> String {
+        format!("{}.{}", address_hash, &self.mac("unsubscribe", address_hash.as_bytes())[..32])
+    }
+
+    /// Returns the address hash an unsubscribe token was made for, if it is genuine.
+    pub fn verify_token(&self, token: &str) -> Option<String> {
+        let (address_hash, _) = token.split_once('.')?;
+        let genuine = !self.key.is_empty() && address_hash.len() == 64 && address_hash.bytes().all(|b| b.is_ascii_hexdigit());
+
+        // Compared through a second MAC so the time taken gives nothing away.
+        (genuine && self.mac("token", token.as_bytes()) == self.mac("token", self.unsubscribe_token(address_hash).as_bytes()))
+            .then(|| address_hash.to_string())
+    }
+
+    fn mac(&self, kind: &str, value: &[u8]) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(kind.as_bytes());
+        mac.update(&[0]);
+        mac.update(value);
+        hex(&mac.finalize().into_bytes())
+    }
+}
+
+/// A part of a parsed [`Template`].
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Variable(String),
+    Section { name: String, inverted: bool, children: Vec<Node> },
+}
+
+/// The subject and plain-text body of a notification email, written with a small subset of
+/// Mustache: `{{name}}` inserts a value as it is, `{{#name}}...{{/name}}` repeats its content
+/// for each item of a list or shows it when the value is set, and `{{^name}}...{{/name}}`
+/// shows its content when the value is unset or empty. A line holding only a section tag
+/// leaves no blank line behind.
+///
+/// The file starts with a `Subject:` line, then a blank line and the body. See
+/// `templates/answer_notification.txt` for the default and the values it is given.
+#[derive(Debug)]
+pub struct Template {
+    subject: Vec<Node>,
+    body: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let text = text.replace("\r\n", "\n");
+        let (subject, body) = text
+            .split_once("\n\n")
+            .and_then(|(subject, body)| Some((subject.strip_prefix("Subject:")?.trim(), body)))
+            .ok_or("A template starts with a Subject: line and a blank line")?;
+
+        Ok(Template { subject: parse_nodes(subject, &mut 0, None)?, body: parse_nodes(body, &mut 0, None)? })
+    }
+
+    /// Renders the subject and body with the values in `context`, a JSON object.
+    pub fn render(&self, context: &Value) -> (String, String) {
+        let (mut subject, mut body) = (String::new(), String::new());
+        render_nodes(&self.subject, &mut vec![context], &mut subject);
+        render_nodes(&self.body, &mut vec![context], &mut body);
+        (subject.trim().to_string(), body)
+    }
+}
+
+impl Default for Template {
+    fn default() -> Template {
+        Template::parse(DEFAULT_TEMPLATE).expect("the default template parses")
+    }
+}
+
+/// Parses `source` from `pos` up to the end, or up to the `{{/closing}}` tag.
+fn parse_nodes(source: &str, pos: &mut usize, closing: Option<&str>) -> Result<Vec<Node>, String> {
+    let mut nodes = Vec::new();
+    let mut text_start = *pos;
+    let push_text = |nodes: &mut Vec<Node>, text: &str| {
+        if !text.is_empty() {
+            nodes.push(Node::Text(text.to_string()));
+        }
+    };
+
+    while let Some(offset) = source[*pos..].find("{{") {
+        let open = *pos + offset;
+        let close = source[open..].find("}}").map(|i| open + i).ok_or("Unclosed {{ in template")?;
+        let end = close + 2;
+        let tag = source[open + 2..close].trim();
+        let (kind, name) = match tag.chars().next() {
+            Some(kind @ ('#' | '^' | '/')) => (Some(kind), tag[1..].trim()),
+            _ => (None, tag),
+        };
+        if name.is_empty() {
+            return Err("Empty tag in template".into());
+        }
+
+        let Some(kind) = kind else {
+            push_text(&mut nodes, &source[text_start..open]);
+            nodes.push(Node::Variable(name.to_string()));
+            (*pos, text_start) = (end, end);
+            continue;
+        };
+
+        let line_start = source[..open].rfind('\n').map_or(0, |i| i + 1).max(text_start);
+        let line_end = source[end..].find('\n').map_or(source.len(), |i| end + i + 1);
+        let standalone = source[line_start..open].trim().is_empty() && source[end..line_end].trim().is_empty();
+        let (text_end, next) = if standalone { (line_start, line_end) } else { (open, end) };
+        push_text(&mut nodes, &source[text_start..text_end]);
+        *pos = next;
+
+        if kind == '/' {
+            return match closing {
+                Some(closing) if closing == name => Ok(nodes),
+                _ => Err(format!("Unexpected {{{{/{}}}}} in template", name)),
+            };
+        }
+        let children = parse_nodes(source, pos, Some(name))?;
+        nodes.push(Node::Section { name: name.to_string(), inverted: kind == '^', children });
+        text_start = *pos;
+    }
+
+    if let Some(closing) = closing {
+        return Err(format!("Unclosed {{{{#{}}}}} in template", closing));
+    }
+    push_text(&mut nodes, &source[text_start..]);
+    *pos = source.len();
+    Ok(nodes)
+}
+
+fn render_nodes(nodes: &[Node], stack: &mut Vec<&Value>, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Variable(name) => match lookup(stack, name) {
+                Some(Value::String(value)) => out.push_str(value),
+                Some(Value::Null) | None => {}
+                Some(value) => out.push_str(&value.to_string()),
+            },
+            Node::Section { name, inverted, children } => {
+                let value = lookup(stack, name);
+                let set = match value {
+                    None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+                    Some(Value::Array(items)) => !items.is_empty(),
+                    Some(Value::String(value)) => !value.is_empty(),
+                    Some(_) => true,
+                };
+                match value {
+                    _ if *inverted && !set => render_nodes(children, stack, out),
+                    _ if *inverted => {}
+                    Some(Value::Array(items)) => {
+                        for item in items {
+                            stack.push(item);
+                            render_nodes(children, stack, out);
+                            stack.pop();
+                        }
+                    }
+                    Some(value) if set => {
+                        stack.push(value);
+                        render_nodes(children, stack, out);
+                        stack.pop();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Looks `name` up from the innermost section out.
+fn lookup<'a>(stack: &[&'a Value], name: &str) -> Option<&'a Value> {
+    stack.iter().rev().find_map(|value| value.get(name))
+}
+
+/// A queued notification claimed by the notifier, with the question and answer it is about.
+#[derive(Debug, sqlx::FromRow)]
+pub struct PendingNotification {
+    pub id: i64,
+    /// What notifications to the same address share: the asker's email lookup hash, or the
+    /// email itself when it is stored as plaintext.
+    pub recipient: String,
+    /// Attempts so far, including this one.
+    pub attempts: i32,
+    pub question_id: i32,
+    pub question_body: Option<String>,
+    pub asker_name: Option<String>,
+    pub asker_email: Option<String>,
+    pub question_reported: Option<bool>,
+    pub answer_body: Option<String>,
+    pub answerer_name: Option<String>,
+    pub answer_reported: Option<bool>,
+}
+
+/// What became of a group of notifications.
+enum Outcome {
+    Sent,
+    /// Not sent, and never will be, for the given reason.
+    Skipped(&'static str),
+    Failed(SmtpError),
+}
+
+/// Emails askers in the background about the answers their questions got.
+///
+/// Notifications are queued with each answer while [`Notifications`] are enabled. Those to
+/// the same address are held until the oldest has waited `delay`, then sent as one email
+/// rendered from `template`, with a link and a `List-Unsubscribe` header to stop them. Askers
+/// who unsubscribed, whose address was erased or is only stored hashed, are skipped, as are
+/// reported answers. Failures that may pass are retried with backoff, up to `max_attempts`
+/// times.
+pub struct AnswerNotifier {
+    /// Pause between polls when there is nothing left to send.
+    pub interval: Duration,
+    /// Addresses emailed per poll.
+    pub batch: i64,
+    /// How long a notification waits for more answers to send along with it.
+    pub delay: Duration,
+    pub max_attempts: i32,
+    /// Wait before the first retry, doubling with each attempt up to an hour.
+    pub backoff: Duration,
+    /// How long a claimed notification is left to its notifier before another may retry it.
+    pub retry_after: Duration,
+    pub mailer: SmtpMailer,
+    /// The `From` header, e.g. `Q&A <qa@example.com>`.
+    pub from: String,
+    /// Base URL the unsubscribe links point at, e.g. `https://qa.example.com`.
+    pub public_url: String,
+    pub template: Template,
+}
+
+impl AnswerNotifier {
+    /// Reads `NOTIFY_SMTP_URL` (see [`SmtpMailer::from_url`]), `NOTIFY_FROM` and
+    /// `NOTIFY_PUBLIC_URL`, which are required, and `NOTIFY_TEMPLATE_FILE`, `NOTIFY_BATCH`,
+    /// `NOTIFY_INTERVAL_SECS`, `NOTIFY_DELAY_SECS`, `NOTIFY_MAX_ATTEMPTS`,
+    /// `NOTIFY_BACKOFF_SECS` and `NOTIFY_SMTP_TIMEOUT_SECS`. There is no notifier unless `NOTIFY_ANSWERS` is `on`, nor
+    /// when `NOTIFIER` is `off`, for replicas that should leave sending to another.
+    pub fn from_env() -> Result<Option<AnswerNotifier>, String> {
+        if std::env::var("NOTIFY_ANSWERS").as_deref() != Ok("on") {
+            return Ok(None);
+        }
+        match std::env::var("NOTIFIER").as_deref() {
+            Ok("on") | Err(_) => {}
+            Ok("off") => return Ok(None),
+            Ok(other) => return Err(format!("Invalid NOTIFIER: {}", other)),
+        }
+
+        fn required(name: &str) -> Result<String, String> {
+            std::env::var(name).ok().filter(|value| !value.is_empty()).ok_or_else(|| format!("NOTIFY_ANSWERS=on requires {}", name))
+        }
+
+        let from = required("NOTIFY_FROM")?;
+        if !is_valid_address(mailbox_address(&from)) {
+            return Err(format!("Invalid NOTIFY_FROM: {}", from));
+        }
+        let timeout = Duration::from_secs(env_var("NOTIFY_SMTP_TIMEOUT_SECS", 30)?);
+        let template = match std::env::var("NOTIFY_TEMPLATE_FILE") {
+            Ok(path) => {
+                let text = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read NOTIFY_TEMPLATE_FILE {}: {}", path, e))?;
+                Template::parse(&text).map_err(|e| format!("Invalid NOTIFY_TEMPLATE_FILE {}: {}", path, e))?
+            }
+            Err(_) => Template::default(),
+        };
+
+        Ok(Some(AnswerNotifier {
+            interval: Duration::from_secs(env_var("NOTIFY_INTERVAL_SECS", 30)?),
+            batch: env_var("NOTIFY_BATCH", 20)?,
+            // Zero sends each answer on its own as soon as it is polled.
+            delay: Duration::from_secs(std::env::var("NOTIFY_DELAY_SECS").ok().map_or(Ok(600), |value| value.parse().map_err(|_| format!("Invalid NOTIFY_DELAY_SECS: {}", value)))?),
+            max_attempts: env_var("NOTIFY_MAX_ATTEMPTS", DEFAULT_MAX_ATTEMPTS)?,
+            backoff: Duration::from_secs(env_var("NOTIFY_BACKOFF_SECS", 60)?),
+            retry_after: timeout * 2,
+            mailer: SmtpMailer::from_url(&required("NOTIFY_SMTP_URL")?, timeout)?,
+            from,
+            public_url: required("NOTIFY_PUBLIC_URL")?.trim_end_matches('/').to_string(),
+            template,
+        }))
+    }
+
+    /// Runs the notifier until the process exits.
+    pub fn spawn(self, state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.process_pending(&state).await {
+                    // A full batch suggests more are waiting.
+                    Ok(emailed) if emailed as i64 == self.batch => continue,
+                    Ok(_) => {}
+                    Err(e) => log_error("Failed to claim answer notifications", &e),
+                }
+                tokio::time::sleep(self.interval).await;
+            }
+        })
+    }
+
+    /// Claims the notifications of a batch of addresses that are due and emails each address
+    /// once, returning how many addresses were claimed.
+    pub async fn process_pending(&self, state: &AppState) -> Result<usize, sqlx::Error> {
+        let (delay, retry_after) = (self.delay.as_secs() as i64, self.retry_after.as_secs() as i64);
+        let notifications = match &state.db {
+            Database::Postgres(pool) => claim_due(pool, self.batch, delay, retry_after).await?,
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => sqlite::claim_due_notifications(pool, self.batch, delay, retry_after).await?,
+        };
+
+        // Oldest first, as `UPDATE ... RETURNING` keeps no order.
+        let mut notifications = notifications;
+        let mut groups: Vec<Vec<PendingNotification>> = Vec::new();
+        notifications.sort_by_key(|notification| notification.id);
+        for notification in notifications {
+            match groups.iter_mut().find(|group| group[0].recipient == notification.recipient) {
+                Some(group) => group.push(notification),
+                None => groups.push(vec![notification]),
+            }
+        }
+
+        for group in &groups {
+            if let Err(e) = self.notify(state, group).await {
+                log_error("Failed to record answer notification", &e);
+            }
+        }
+        Ok(groups.len())
+    }
+
+    /// Emails the answers in `group`, all to the same address, and records the outcome.
+    async fn notify(&self, state: &AppState, group: &[PendingNotification]) -> Result<(), sqlx::Error> {
+        let (visible, hidden): (Vec<_>, Vec<_>) = group
+            .iter()
+            .partition(|notification| notification.question_reported != Some(true) && notification.answer_reported != Some(true));
+        if !hidden.is_empty() {
+            let ids: Vec<i64> = hidden.iter().map(|notification| notification.id).collect();
+            finish(state, &ids, "skipped", Some("Reported")).await?;
+        }
+        if visible.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<i64> = visible.iter().map(|notification| notification.id).collect();
+
+        let outcome = match visible[0].asker_email.as_deref().and_then(|stored| state.emails.open(stored)) {
+            Some(email) if is_valid_address(email.trim()) => {
+                let address_hash = state.notifications.address_hash(&email);
+                let opted_out = match &state.db {
+                    Database::Postgres(pool) => is_opted_out(pool, &address_hash).await?,
+                    #[cfg(feature = "sqlite")]
+                    Database::Sqlite(pool) => sqlite::is_opted_out(pool, &address_hash).await?,
+                };
+                if opted_out {
+                    Outcome::Skipped("Opted out")
+                } else {
+                    match self.mailer.send(&self.message(state, email.trim(), &address_hash, &visible)).await {
+                        Ok(()) => Outcome::Sent,
+                        Err(e) => Outcome::Failed(e),
+                    }
+                }
+            }
+            _ => Outcome::Skipped("No address to email"),
+        };
+
+        match outcome {
+            Outcome::Sent => finish(state, &ids, "sent", None).await,
+            Outcome::Skipped(reason) => finish(state, &ids, "skipped", Some(reason)).await,
+            Outcome::Failed(e) => {
+                let attempts = visible.iter().map(|notification| notification.attempts).max().unwrap_or_default();
+                if e.is_permanent() || attempts >= self.max_attempts {
+                    log_error("Gave up on answer notification", &e);
+                    return finish(state, &ids, "failed", Some(&e.to_string())).await;
+                }
+
+                let delay = self.backoff.saturating_mul(1 << (attempts - 1).clamp(0, 16)).min(MAX_BACKOFF).as_secs() as i64;
+                match &state.db {
+                    Database::Postgres(pool) => reschedule(pool, &ids, delay, &e.to_string()).await,
+                    #[cfg(feature = "sqlite")]
+                    Database::Sqlite(pool) => sqlite::reschedule_notifications(pool, &ids, delay, &e.to_string()).await,
+                }
+            }
+        }
+    }
+
+    fn message(&self, state: &AppState, email: &str, address_hash: &str, notifications: &[&PendingNotification]) -> Message {
+        let unsubscribe_url = format!("{}{}?token={}", self.public_url, UNSUBSCRIBE_PATH, state.notifications.unsubscribe_token(address_hash));
+
+        let mut questions: Vec<(i32, Value, Vec<Value>)> = Vec::new();
+        for notification in notifications {
+            let answer = json!({ "answer": notification.answer_body, "answerer_name": notification.answerer_name });
+            match questions.iter_mut().find(|(question_id, ..)| *question_id == notification.question_id) {
+                Some((.., answers)) => answers.push(answer),
+                None => questions.push((notification.question_id, json!(notification.question_body), vec![answer])),
+            }
+        }
+        // The name on the most recent question is the one the asker goes by now.
+        let asker_name = notifications.iter().rev().find_map(|notification| notification.asker_name.as_deref().filter(|name| !name.is_empty()));
+
+        let context = json!({
+            "asker_name": asker_name.unwrap_or("there"),
+            "answer_count": notifications.len(),
+            "one_answer": notifications.len() == 1,
+            "unsubscribe_url": unsubscribe_url,
+            "questions": questions
+                .into_iter()
+                .map(|(question_id, question, answers)| json!({ "question_id": question_id, "question": question, "answers": answers }))
+                .collect::<Vec<_>>(),
+        });
+        let (subject, body) = self.template.render(&context);
+
+        Message {
+            from: self.from.clone(),
+            to: email.to_string(),
+            subject,
+            headers: vec![
+                ("List-Unsubscribe".into(), format!("<{}>", unsubscribe_url)),
+                ("List-Unsubscribe-Post".into(), "List-Unsubscribe=One-Click".into()),
+                ("Auto-Submitted".into(), "auto-generated".into()),
+            ],
+            body,
+        }
+    }
+}
+
+/// Marks notifications `status` for good, with `reason` as their last error.
+async fn finish(state: &AppState, ids: &[i64], status: &str, reason: Option<&str>) -> Result<(), sqlx::Error> {
+    match &state.db {
+        Database::Postgres(pool) => {
+            sqlx::query!(
+                r#"
+                UPDATE answer_notifications
+                SET status = $2, last_error = $3, claimed_at = NULL, sent_at = CASE WHEN $2 = 'sent' THEN NOW() END
+                WHERE id = ANY($1);
+                "#,
+                ids,
+                status,
+                reason
+            )
+            .execute(pool)
+            .await?;
+            Ok(())
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::finish_notifications(pool, ids, status, reason).await,
+    }
+}
+
+/// Shows a page confirming the unsubscribe link, whose button posts back to it. The link
+/// does not unsubscribe on its own, as mail scanners open links to check them.
+pub async fn unsubscribe_page(state: Arc<AppState>, token: &str) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    if state.notifications.verify_token(token).is_none() {
+        return create_error_response(StatusCode::BAD_REQUEST, "Invalid unsubscribe link".into());
+    }
+
+    // A verified token is hex and a dot, so it needs no escaping.
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Unsubscribe</title></head>
+<body>
+<p>Stop getting emails about answers to your questions?</p>
+<form method="post" action="{}?token={}"><button type="submit">Unsubscribe</button></form>
+</body>
+</html>
+"#,
+        UNSUBSCRIBE_PATH, token
+    );
+    Ok(Response::builder().status(StatusCode::OK).header(CONTENT_TYPE, "text/html; charset=utf-8").body(Body::from(page)).unwrap())
+}
+
+/// Opts the address an unsubscribe link was sent to out of notifications, for the page's
+/// button and for one-click unsubscribing by mail clients (RFC 8058). Unsubscribing again
+/// changes nothing.
+pub async fn unsubscribe(state: Arc<AppState>, token: &str) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(address_hash) = state.notifications.verify_token(token) else {
+        return create_error_response(StatusCode::BAD_REQUEST, "Invalid unsubscribe link".into());
+    };
+
+    let result = match &state.db {
+        Database::Postgres(pool) => opt_out(pool, &address_hash).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::opt_out(pool, &address_hash).await,
+    };
+
+    match result {
+        Ok(()) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Body::from("You will no longer get emails about answers to your questions."))
+            .unwrap()),
+        Err(e) => {
+            log_error("Failed to unsubscribe", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to unsubscribe".into())
+        }
+    }
+}
+
+/// Claims the due notifications of up to `batch` addresses: those whose oldest notification
+/// has waited `delay` seconds and is not claimed, or whose claim is older than `retry_after`
+/// seconds. Rows locked by another notifier's claim are skipped.
+async fn claim_due(pool: &PgPool, batch: i64, delay: i64, retry_after: i64) -> Result<Vec<PendingNotification>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingNotification,
+        r#"
+        WITH pending AS (
+            SELECT n.id, COALESCE(q.asker_email_hash, lower(q.asker_email), '') AS recipient, n.created_at
+            FROM answer_notifications AS n
+            JOIN questions AS q ON q.id = n.question_id
+            WHERE n.status = 'pending' AND n.next_attempt_at <= NOW()
+                AND (n.claimed_at IS NULL OR n.claimed_at <= NOW() - make_interval(secs => $3))
+        ), due AS (
+            SELECT recipient
+            FROM pending
+            GROUP BY recipient
+            HAVING MIN(created_at) <= NOW() - make_interval(secs => $2)
+            ORDER BY MIN(created_at)
+            LIMIT $1
+        )
+        UPDATE answer_notifications AS n
+        SET attempts = n.attempts + 1, claimed_at = NOW()
+        FROM questions AS q, answers AS a
+        WHERE q.id = n.question_id AND a.id = n.answer_id
+            AND (n.claimed_at IS NULL OR n.claimed_at <= NOW() - make_interval(secs => $3))
+            AND n.id IN (
+                SELECT id
+                FROM answer_notifications
+                WHERE id IN (SELECT id FROM pending WHERE recipient IN (SELECT recipient FROM due))
+                FOR UPDATE SKIP LOCKED
+            )
+        RETURNING
+            n.id,
+            COALESCE(q.asker_email_hash, lower(q.asker_email), '') AS "recipient!",
+            n.attempts,
+            n.question_id,
+            q.body AS question_body,
+            q.asker_name,
+            q.asker_email,
+            q.reported AS question_reported,
+            a.body AS answer_body,
+            a.answerer_name,
+            a.reported AS answer_reported;
+        "#,
+        batch,
+        delay as f64,
+        retry_after as f64
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn reschedule(pool: &PgPool, ids: &[i64], delay: i64, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE answer_notifications SET next_attempt_at = NOW() + make_interval(secs => $2), claimed_at = NULL, last_error = $3 WHERE id = ANY($1);",
+        ids,
+        delay as f64,
+        error
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn is_opted_out(pool: &PgPool, address_hash: &str) -> Result<bool, sqlx::Error> {
+    let opted_out = sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM email_opt_outs WHERE address_hash = $1) AS "opted_out!";"#, address_hash)
+        .fetch_one(pool)
+        .await?;
+    Ok(opted_out)
+}
+
+async fn opt_out(pool: &PgPool, address_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("INSERT INTO email_opt_outs (address_hash) VALUES ($1) ON CONFLICT DO NOTHING;", address_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
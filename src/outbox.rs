@@ -0,0 +1,62 @@
+use crate::models::ReportReason;
+use sqlx::{Postgres, Transaction};
+
+/// A change other services may react to, recorded in the outbox in the same transaction as
+/// the change itself, so an event is recorded if and only if its write commits. The
+/// [`WebhookDispatcher`](crate::webhooks::WebhookDispatcher) delivers recorded events.
+///
+/// Payloads carry ids rather than content: receivers read the content through the API,
+/// which keeps what reporters, askers and answerers wrote out of the outbox, and out of
+/// reach of erasure requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    QuestionCreated { question_id: i32, product_id: i32 },
+    AnswerCreated { answer_id: i32, question_id: i32, product_id: i32 },
+    /// A voter marked a question helpful. Withdrawn votes are not events.
+    QuestionHelpful { question_id: i32, product_id: i32 },
+    AnswerHelpful { answer_id: i32, product_id: i32 },
+    QuestionReported { question_id: i32, product_id: i32, reason: ReportReason },
+    AnswerReported { answer_id: i32, product_id: i32, reason: ReportReason },
+}
+
+/// Every event type, as webhooks subscribe to them.
+pub const EVENT_TYPES: [&str; 6] = ["question.created", "answer.created", "question.helpful", "answer.helpful", "question.reported", "answer.reported"];
+
+impl Event {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Event::QuestionCreated { .. } => "question.created",
+            Event::AnswerCreated { .. } => "answer.created",
+            Event::QuestionHelpful { .. } => "question.helpful",
+            Event::AnswerHelpful { .. } => "answer.helpful",
+            Event::QuestionReported { .. } => "question.reported",
+            Event::AnswerReported { .. } => "answer.reported",
+        }
+    }
+
+    pub fn payload(&self) -> serde_json::Value {
+        match *self {
+            Event::QuestionCreated { question_id, product_id } | Event::QuestionHelpful { question_id, product_id } => {
+                serde_json::json!({ "question_id": question_id, "product_id": product_id })
+            }
+            Event::AnswerCreated { answer_id, question_id, product_id } => {
+                serde_json::json!({ "answer_id": answer_id, "question_id": question_id, "product_id": product_id })
+            }
+            Event::AnswerHelpful { answer_id, product_id } => serde_json::json!({ "answer_id": answer_id, "product_id": product_id }),
+            Event::QuestionReported { question_id, product_id, reason } => {
+                serde_json::json!({ "question_id": question_id, "product_id": product_id, "reason": reason.as_str() })
+            }
+            Event::AnswerReported { answer_id, product_id, reason } => {
+                serde_json::json!({ "answer_id": answer_id, "product_id": product_id, "reason": reason.as_str() })
+            }
+        }
+    }
+}
+
+/// Records `event` in the outbox as part of `tx`.
+pub async fn record(tx: &mut Transaction<'_, Postgres>, event: Event) -> Result<(), sqlx::Error> {
+    sqlx::query!("INSERT INTO outbox_events (event_type, payload) VALUES ($1, $2);", event.event_type(), event.payload())
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,453 @@
+use crate::auth::{Principal, Scope};
+use crate::db::Database;
+use crate::emails::normalize;
+use crate::models::NewReport;
+use crate::multipart::Part;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::state::AppState;
+use crate::utils::{create_error_response, create_success_response, log_error};
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+/// Most photos a single upload may carry.
+pub const MAX_UPLOAD_PHOTOS: usize = 5;
+
+/// Most photos an answer may have, not counting removed ones. The database enforces it with
+/// the `answer_photos_limit` trigger; this is what error messages quote.
+pub const MAX_ANSWER_PHOTOS: usize = 10;
+
+/// The form field photos are uploaded in.
+pub const PHOTO_FIELD: &str = "photo";
+
+/// The image formats photos may be uploaded in, recognised by their leading bytes rather
+/// than by the type or file name the client declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageType {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+}
+
+impl ImageType {
+    /// Recognises an image by its signature.
+    pub fn sniff(data: &[u8]) -> Option<ImageType> {
+        if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(ImageType::Jpeg)
+        } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageType::Png)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(ImageType::Gif)
+        } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+            Some(ImageType::Webp)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<ImageType> {
+        [ImageType::Jpeg, ImageType::Png, ImageType::Gif, ImageType::Webp]
+            .into_iter()
+            .find(|image_type| image_type.extension() == extension)
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Png => "image/png",
+            ImageType::Gif => "image/gif",
+            ImageType::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageType::Jpeg => "jpg",
+            ImageType::Png => "png",
+            ImageType::Gif => "gif",
+            ImageType::Webp => "webp",
+        }
+    }
+}
+
+/// A photo of an upload, known to be an image of an accepted type and size.
+#[derive(Debug)]
+pub struct UploadedPhoto {
+    pub image_type: ImageType,
+    pub data: Bytes,
+}
+
+/// Checks the parts of an upload form: one to [`MAX_UPLOAD_PHOTOS`] files in the
+/// [`PHOTO_FIELD`] field, each an image of at most `max_bytes` bytes.
+pub fn validate_upload(parts: Vec<Part>, max_bytes: usize) -> Result<Vec<UploadedPhoto>, (StatusCode, String)> {
+    if let Some(part) = parts.iter().find(|part| part.name != PHOTO_FIELD || part.filename.is_none()) {
+        return Err((StatusCode::BAD_REQUEST, format!("Unexpected form field: {}", part.name)));
+    }
+    if parts.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Missing photo".into()));
+    }
+    if parts.len() > MAX_UPLOAD_PHOTOS {
+        return Err((StatusCode::BAD_REQUEST, format!("Too many photos, at most {} are allowed", MAX_UPLOAD_PHOTOS)));
+    }
+
+    parts
+        .into_iter()
+        .map(|part| {
+            if part.data.len() > max_bytes {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("Photo is too large, at most {} bytes are allowed", max_bytes)));
+            }
+            let image_type = ImageType::sniff(&part.data)
+                .ok_or_else(|| (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Photo is not a JPEG, PNG, GIF or WebP image".to_string()))?;
+            Ok(UploadedPhoto { image_type, data: part.data })
+        })
+        .collect()
+}
+
+/// An answer that is not reported, with what identifies its author.
+#[derive(Debug, sqlx::FromRow)]
+pub struct AnswerOwner {
+    pub product_id: i32,
+    pub answerer_email: Option<String>,
+    pub answerer_email_hash: Option<String>,
+}
+
+/// A photo as listed by [`get_photos`], with the variants made by the
+/// [`ThumbnailWorker`](crate::thumbnails::ThumbnailWorker) once it has run.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Photo {
+    pub id: i32,
+    pub url: Option<String>,
+    pub thumbnail_url: Option<String>,
+    pub thumbnail_webp_url: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+/// Checks that `principal` may change the photos of an answer: a moderator, with the
+/// `admin` scope, or the signed-in shopper who wrote the answer. Returns the answer's
+/// product id, or else the response refusing the request.
+async fn authorize(state: &AppState, principal: &Principal, answer_id: i32) -> Result<i32, Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>>> {
+    let owner = match &state.db {
+        Database::Postgres(pool) => query_answer_owner(pool, answer_id).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_answer_owner(pool, answer_id).await,
+    };
+    let owner = match owner {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return Err(create_error_response(StatusCode::NOT_FOUND, "Answer not found".into())),
+        Err(e) => {
+            log_error("Failed to look up answer", &e);
+            return Err(create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up answer".into()));
+        }
+    };
+
+    if principal.allows(Scope::Admin) {
+        return Ok(owner.product_id);
+    }
+    match &principal.user {
+        Some(user) if is_answerer(state, &user.email, &owner) => Ok(owner.product_id),
+        Some(_) => Err(create_error_response(StatusCode::FORBIDDEN, "Only the answerer or a moderator may change the photos of an answer".into())),
+        None if state.auth.jwt.is_some() => Err(create_error_response(StatusCode::UNAUTHORIZED, "Missing bearer token".into())),
+        None => Err(create_error_response(StatusCode::FORBIDDEN, "Only the answerer or a moderator may change the photos of an answer".into())),
+    }
+}
+
+/// Whether `email` is the answerer's, however it is stored.
+fn is_answerer(state: &AppState, email: &str, owner: &AnswerOwner) -> bool {
+    if let Some(hash) = &owner.answerer_email_hash {
+        if state.emails.lookup_hashes(email).contains(hash) {
+            return true;
+        }
+    }
+    owner
+        .answerer_email
+        .as_deref()
+        .and_then(|stored| state.emails.open(stored))
+        .is_some_and(|stored| normalize(&stored) == normalize(email))
+}
+
+/// Whether `e` is the answer photo limit enforced by the `answer_photos_limit` trigger.
+fn is_photo_limit_error(e: &sqlx::Error) -> bool {
+    e.as_database_error().is_some_and(|e| e.message().contains("answer_photo_limit"))
+}
+
+/// Lists the photos of an answer that is not reported, leaving out reported photos.
+pub async fn get_photos(state: Arc<AppState>, answer_id: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => query_photos(pool, answer_id).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_photos(pool, answer_id).await,
+    };
+
+    match result {
+        Ok(Some(photos)) => create_success_response(StatusCode::OK, serde_json::json!({ "answer_id": answer_id, "results": photos })),
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Answer not found".into()),
+        Err(e) => {
+            log_error("Failed to fetch photos", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch photos".into())
+        }
+    }
+}
+
+/// Stores uploaded photos and adds them to an answer that is not reported, for its
+/// answerer or a moderator.
+///
+/// Photos are stored before their rows are written, and deleted again if writing the rows
+/// fails, so no row ever points at a missing file. An answer holds at most
+/// [`MAX_ANSWER_PHOTOS`] photos.
+pub async fn upload_photos(state: Arc<AppState>, principal: &Principal, answer_id: i32, photos: Vec<UploadedPhoto>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let product_id = match authorize(&state, principal, answer_id).await {
+        Ok(product_id) => product_id,
+        Err(response) => return response,
+    };
+
+    let mut keys = Vec::with_capacity(photos.len());
+    let mut urls = Vec::with_capacity(photos.len());
+    for photo in photos {
+        let key = format!("answers/{}/{:016x}.{}", answer_id, rand::random::<u64>(), photo.image_type.extension());
+        match state.photos.put(&key, photo.image_type.mime(), photo.data).await {
+            Ok(url) => {
+                keys.push(key);
+                urls.push(url);
+            }
+            Err(e) => {
+                log_error("Failed to store photo", &e);
+                discard(&state, &keys).await;
+                return create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to upload photos".into());
+            }
+        }
+    }
+
+    let result = match &state.db {
+        Database::Postgres(pool) => insert_photos(pool, answer_id, &urls).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::insert_answer_photos(pool, answer_id, &urls).await,
+    };
+    match result {
+        Ok(ids) => {
+            state.questions_cache.invalidate(product_id);
+            let photos: Vec<_> = ids.iter().zip(&urls).map(|(id, url)| serde_json::json!({ "id": id, "url": url })).collect();
+            create_success_response(StatusCode::CREATED, serde_json::json!({ "answer_id": answer_id, "photos": photos }))
+        }
+        Err(e) if is_photo_limit_error(&e) => {
+            discard(&state, &keys).await;
+            create_error_response(StatusCode::CONFLICT, format!("An answer can have at most {} photos", MAX_ANSWER_PHOTOS))
+        }
+        Err(e) => {
+            log_error("Failed to add photos", &e);
+            discard(&state, &keys).await;
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to upload photos".into())
+        }
+    }
+}
+
+/// Deletes a photo of an answer, with its reports and, when this service stored them, its
+/// files. Only the answerer or a moderator may.
+pub async fn delete_photo(state: Arc<AppState>, principal: &Principal, answer_id: i32, photo_id: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let product_id = match authorize(&state, principal, answer_id).await {
+        Ok(product_id) => product_id,
+        Err(response) => return response,
+    };
+
+    let result = match &state.db {
+        Database::Postgres(pool) => remove_photo(pool, answer_id, photo_id).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::remove_answer_photo(pool, answer_id, photo_id).await,
+    };
+    match result {
+        Ok(Some(urls)) => {
+            state.questions_cache.invalidate(product_id);
+            discard(&state, &stored_keys(&state, answer_id, urls.iter().flatten())).await;
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Photo not found".into()),
+        Err(e) => {
+            log_error("Failed to delete photo", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete photo".into())
+        }
+    }
+}
+
+/// Reports a photo, hiding it until a moderator approves it. Its answer stays up.
+pub async fn update_photo_report(state: Arc<AppState>, photo_id: i32, reporter: &str, report: NewReport) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => report_photo(pool, photo_id, reporter, &report).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::report_photo(pool, photo_id, reporter, &report).await,
+    };
+
+    match result {
+        Ok(Some(product_id)) => {
+            state.questions_cache.invalidate(product_id);
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Photo not found".into()),
+        Err(e) => {
+            log_error("Failed to update photo report", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update photo report".into())
+        }
+    }
+}
+
+/// The keys of the files this service stored for a photo of `answer_id`, among its `urls`.
+///
+/// Only keys under the answer's own `answers/<answer_id>/` prefix are taken, so that a
+/// photo row naming a file stored for another answer never gets that file deleted.
+pub(crate) fn stored_keys<'a>(state: &AppState, answer_id: i32, urls: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    let prefix = format!("answers/{}/", answer_id);
+    urls.into_iter().filter_map(|url| state.photos.key_of(url)).filter(|key| key.starts_with(&prefix)).collect()
+}
+
+/// Deletes stored photos, for uploads that failed and photos that were deleted or erased.
+pub(crate) async fn discard(state: &AppState, keys: &[String]) {
+    for key in keys {
+        if let Err(e) = state.photos.delete(key).await {
+            log_error("Failed to delete photo", &e);
+        }
+    }
+}
+
+async fn query_answer_owner(pool: &PgPool, answer_id: i32) -> Result<Option<AnswerOwner>, sqlx::Error> {
+    sqlx::query_as!(
+        AnswerOwner,
+        r#"
+        SELECT q.product_id, a.answerer_email, a.answerer_email_hash
+        FROM answers a
+        JOIN questions q ON q.id = a.question_id
+        WHERE a.id = $1 AND a.reported = false;
+        "#,
+        answer_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// The photos of an answer, or `None` if there is no such answer.
+async fn query_photos(pool: &PgPool, answer_id: i32) -> Result<Option<Vec<Photo>>, sqlx::Error> {
+    let found = sqlx::query_scalar!("SELECT id FROM answers WHERE id = $1 AND reported = false;", answer_id)
+        .fetch_optional(pool)
+        .await?;
+    if found.is_none() {
+        return Ok(None);
+    }
+
+    let photos = sqlx::query_as!(
+        Photo,
+        r#"
+        SELECT id, url, thumbnail_url, thumbnail_webp_url, width, height
+        FROM answer_photos
+        WHERE answer_id = $1 AND reported = false AND verify_status <> 'broken'
+        ORDER BY id;
+        "#,
+        answer_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Some(photos))
+}
+
+/// Adds a row for each of `urls` to `answer_photos`, all or none of them.
+async fn insert_photos(pool: &PgPool, answer_id: i32, urls: &[String]) -> Result<Vec<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut ids = Vec::with_capacity(urls.len());
+    for url in urls {
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO answer_photos (answer_id, url, verify_status)
+            VALUES ($1, $2, 'ok')
+            RETURNING id;
+            "#,
+            answer_id,
+            url
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        ids.push(id);
+    }
+    sqlx::query!("UPDATE answers SET updated_at = NOW() WHERE id = $1;", answer_id).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(ids)
+}
+
+/// Deletes a photo and its reports, returning the URLs of the photo and its variants, or
+/// `None` if the answer has no such photo.
+async fn remove_photo(pool: &PgPool, answer_id: i32, photo_id: i32) -> Result<Option<[Option<String>; 3]>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "DELETE FROM photo_reports WHERE photo_id = (SELECT id FROM answer_photos WHERE id = $1 AND answer_id = $2);",
+        photo_id,
+        answer_id
+    )
+    .execute(&mut tx)
+    .await?;
+    let removed = sqlx::query!(
+        "DELETE FROM answer_photos WHERE id = $1 AND answer_id = $2 RETURNING url, thumbnail_url, thumbnail_webp_url;",
+        photo_id,
+        answer_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(removed) = removed else {
+        return Ok(None);
+    };
+    sqlx::query!("UPDATE answers SET updated_at = NOW() WHERE id = $1;", answer_id).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(Some([removed.url, removed.thumbnail_url, removed.thumbnail_webp_url]))
+}
+
+/// Reports a photo and hides it. Returns the product of its answer, or `None` if there is
+/// no such photo.
+async fn report_photo(pool: &PgPool, photo_id: i32, reporter: &str, report: &NewReport) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        WITH p AS (
+            UPDATE answer_photos
+            SET reported = true
+            WHERE id = $1
+            RETURNING id, answer_id
+        ), r AS (
+            INSERT INTO photo_reports (photo_id, reason, note, reporter)
+            SELECT id, $2, $3, $4 FROM p
+        )
+        UPDATE answers AS a
+        SET updated_at = NOW()
+        FROM p, questions AS q
+        WHERE a.id = p.answer_id AND q.id = a.question_id
+        RETURNING q.product_id AS "product_id!";
+        "#,
+        photo_id,
+        report.reason.as_str(),
+        report.note,
+        reporter
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Serves a photo kept in local storage under `/photos/<key>`.
+pub async fn serve_photo(state: Arc<AppState>, key: &str) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let image_type = key.rsplit_once('.').and_then(|(_, extension)| ImageType::from_extension(extension));
+    let Some(image_type) = image_type else {
+        return create_error_response(StatusCode::NOT_FOUND, "Photo not found".into());
+    };
+
+    match state.photos.read_local(key).await {
+        Ok(Some(data)) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, image_type.mime())
+            // Keys are random and never reused, so a photo never changes.
+            .header(CACHE_CONTROL, "public, max-age=31536000, immutable")
+            .body(Body::from(data))
+            .unwrap()),
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Photo not found".into()),
+        Err(e) => {
+            log_error("Failed to read photo", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read photo".into())
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use crate::db::Database;
+use crate::emails::normalize;
+use crate::models::{ErasurePolicy, ErasureRequest, ExportRequest};
+use crate::photos;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::state::AppState;
+use crate::utils::{create_error_response, create_success_response, log_error};
+use hyper::{Body, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+/// The name erased authors are shown under.
+pub const ERASED_NAME: &str = "[deleted]";
+
+/// A question as handed to its author by a data export.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ExportedQuestion {
+    pub id: i32,
+    pub product_id: i32,
+    pub body: Option<String>,
+    pub date: Option<String>,
+    pub asker_name: Option<String>,
+    pub helpful: Option<i32>,
+    pub reported: Option<bool>,
+    pub removed: bool,
+}
+
+/// An answer as handed to its author by a data export, with its photos.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ExportedAnswer {
+    pub id: i32,
+    pub question_id: i32,
+    pub body: Option<String>,
+    pub date: Option<String>,
+    pub answerer_name: Option<String>,
+    pub helpful: Option<i32>,
+    pub reported: Option<bool>,
+    pub removed: bool,
+    pub photos: Json<Vec<ExportedPhoto>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedPhoto {
+    pub id: i32,
+    pub url: Option<String>,
+}
+
+/// An entry of the data-request audit log.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct DataRequestRecord {
+    pub id: i32,
+    /// `export` or `erase`.
+    pub kind: String,
+    /// Fingerprint of the email the request was for, see [`crate::emails::EmailProtector::fingerprint`].
+    pub subject: String,
+    /// `keep` or `remove` for erasures.
+    pub content_policy: Option<String>,
+    pub questions: i32,
+    pub answers: i32,
+    /// Photos exported, or deleted by an erasure.
+    pub photos: i32,
+    /// Name of the API key that made the request.
+    pub requested_by: Option<String>,
+    pub reference: Option<String>,
+    pub requested_at: String,
+}
+
+/// How rows belonging to an email are found: by lookup hash under any configured key, or by
+/// the plaintext email for rows stored before protection was turned on.
+pub struct Subject {
+    pub hashes: Vec<String>,
+    pub email: String,
+}
+
+impl Subject {
+    fn new(state: &AppState, email: &str) -> Subject {
+        Subject { hashes: state.emails.lookup_hashes(email), email: normalize(email) }
+    }
+}
+
+/// What an erasure changed.
+pub struct Erasure {
+    pub questions: i32,
+    pub answers: i32,
+    /// The answer of each deleted photo and the URLs of the photo and its variants, whose
+    /// stored files are left to delete.
+    pub photos: Vec<(i32, [Option<String>; 3])>,
+    /// Products whose cached question lists are now stale.
+    pub product_ids: Vec<i32>,
+}
+
+/// Exports every question and answer posted under an email, and logs the export under the
+/// name of the `requested_by` API key.
+pub async fn export_data(state: Arc<AppState>, request: ExportRequest, requested_by: Option<&str>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let subject = Subject::new(&state, &request.email);
+    let fingerprint = state.emails.fingerprint(&request.email);
+
+    let result = match &state.db {
+        Database::Postgres(pool) => query_export(pool, &subject).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_export(pool, &subject).await,
+    };
+    let (questions, answers) = match result {
+        Ok(export) => export,
+        Err(e) => {
+            log_error("Failed to export data", &e);
+            return create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to export data".into());
+        }
+    };
+
+    // Nothing is handed out without its audit record.
+    let photos = answers.iter().map(|answer| answer.photos.len()).sum::<usize>() as i32;
+    let record = NewDataRequest {
+        kind: "export",
+        subject: &fingerprint,
+        content_policy: None,
+        questions: questions.len() as i32,
+        answers: answers.len() as i32,
+        photos,
+        requested_by,
+        reference: request.reference.as_deref(),
+    };
+    let result = match &state.db {
+        Database::Postgres(pool) => insert_data_request(pool, &record).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::insert_data_request(pool, &record).await,
+    };
+
+    match result {
+        Ok(request_id) => {
+            let response = serde_json::json!({
+                "request_id": request_id,
+                "email": request.email,
+                "questions": questions,
+                "answers": answers,
+            });
+            create_success_response(StatusCode::OK, response)
+        }
+        Err(e) => {
+            log_error("Failed to record data export", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to export data".into())
+        }
+    }
+}
+
+async fn query_export(pool: &PgPool, subject: &Subject) -> Result<(Vec<ExportedQuestion>, Vec<ExportedAnswer>), sqlx::Error> {
+    let questions = sqlx::query_as!(
+        ExportedQuestion,
+        r#"
+        SELECT
+            id,
+            product_id,
+            body,
+            to_char(date_written, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS date,
+            asker_name,
+            helpful,
+            reported,
+            removed_at IS NOT NULL AS "removed!"
+        FROM questions
+        WHERE asker_email_hash = ANY($1) OR lower(asker_email) = $2
+        ORDER BY id;
+        "#,
+        &subject.hashes,
+        subject.email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let answers = sqlx::query_as!(
+        ExportedAnswer,
+        r#"
+        SELECT
+            a.id,
+            a.question_id,
+            a.body,
+            to_char(a.date_written, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS date,
+            a.answerer_name,
+            a.helpful,
+            a.reported,
+            a.removed_at IS NOT NULL AS "removed!",
+            COALESCE(
+                (SELECT json_agg(json_build_object('id', p.id, 'url', p.url) ORDER BY p.id) FROM answer_photos AS p WHERE p.answer_id = a.id),
+                '[]'
+            ) AS "photos!: Json<Vec<ExportedPhoto>>"
+        FROM answers AS a
+        WHERE a.answerer_email_hash = ANY($1) OR lower(a.answerer_email) = $2
+        ORDER BY a.id;
+        "#,
+        &subject.hashes,
+        subject.email
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok((questions, answers))
+}
+
+/// Erases an email from every question and answer posted under it, and logs the erasure
+/// under the name of the `requested_by` API key.
+///
+/// Authors are renamed to [`ERASED_NAME`] and their email and its lookup hash are cleared.
+/// With [`ErasurePolicy::Remove`] the content is blanked and hidden like content removed
+/// by a moderator, and answer photos are deleted with any files stored for them. Votes and
+/// reports are never tied to an email, so they are left alone.
+pub async fn erase_data(state: Arc<AppState>, request: ErasureRequest, requested_by: Option<&str>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let subject = Subject::new(&state, &request.email);
+    let fingerprint = state.emails.fingerprint(&request.email);
+    let reference = request.reference.as_deref();
+
+    let result = match &state.db {
+        Database::Postgres(pool) => apply_erasure(pool, &subject, request.content, &fingerprint, requested_by, reference).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::apply_erasure(pool, &subject, request.content, &fingerprint, requested_by, reference).await,
+    };
+
+    match result {
+        Ok((request_id, erasure)) => {
+            for product_id in &erasure.product_ids {
+                state.questions_cache.invalidate(*product_id);
+            }
+            let keys: Vec<String> =
+                erasure.photos.iter().flat_map(|(answer_id, urls)| photos::stored_keys(&state, *answer_id, urls.iter().flatten())).collect();
+            photos::discard(&state, &keys).await;
+
+            let response = serde_json::json!({
+                "request_id": request_id,
+                "content": request.content.as_str(),
+                "questions": erasure.questions,
+                "answers": erasure.answers,
+                "photos": erasure.photos.len(),
+            });
+            create_success_response(StatusCode::OK, response)
+        }
+        Err(e) => {
+            log_error("Failed to erase data", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to erase data".into())
+        }
+    }
+}
+
+/// Erases a subject's content and records the erasure in one transaction. Returns the id
+/// of the audit record and what was erased.
+async fn apply_erasure(pool: &PgPool, subject: &Subject, policy: ErasurePolicy, fingerprint: &str, requested_by: Option<&str>, reference: Option<&str>) -> Result<(i32, Erasure), sqlx::Error> {
+    let remove = policy == ErasurePolicy::Remove;
+    let mut tx = pool.begin().await?;
+
+    let mut product_ids = sqlx::query_scalar!(
+        r#"
+        UPDATE questions
+        SET asker_name = $3,
+            asker_email = NULL,
+            asker_email_hash = NULL,
+            body = CASE WHEN $4 THEN NULL ELSE body END,
+            reported = CASE WHEN $4 THEN true ELSE reported END,
+            removed_at = CASE WHEN $4 THEN COALESCE(removed_at, NOW()) ELSE removed_at END,
+            updated_at = NOW()
+        WHERE asker_email_hash = ANY($1) OR lower(asker_email) = $2
+        RETURNING product_id;
+        "#,
+        &subject.hashes,
+        subject.email,
+        ERASED_NAME,
+        remove
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let questions = product_ids.len() as i32;
+
+    let answers = sqlx::query!(
+        r#"
+        UPDATE answers AS a
+        SET answerer_name = $3,
+            answerer_email = NULL,
+            answerer_email_hash = NULL,
+            body = CASE WHEN $4 THEN NULL ELSE a.body END,
+            reported = CASE WHEN $4 THEN true ELSE a.reported END,
+            removed_at = CASE WHEN $4 THEN COALESCE(a.removed_at, NOW()) ELSE a.removed_at END,
+            updated_at = NOW()
+        FROM questions AS q
+        WHERE q.id = a.question_id AND (a.answerer_email_hash = ANY($1) OR lower(a.answerer_email) = $2)
+        RETURNING a.id, q.product_id;
+        "#,
+        &subject.hashes,
+        subject.email,
+        ERASED_NAME,
+        remove
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let answer_ids: Vec<i32> = answers.iter().map(|row| row.id).collect();
+    product_ids.extend(answers.iter().map(|row| row.product_id));
+    product_ids.sort_unstable();
+    product_ids.dedup();
+
+    let photos = if remove {
+        sqlx::query!(
+            "DELETE FROM photo_reports WHERE photo_id IN (SELECT id FROM answer_photos WHERE answer_id = ANY($1));",
+            &answer_ids
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM answer_photos WHERE answer_id = ANY($1) RETURNING answer_id, url, thumbnail_url, thumbnail_webp_url;",
+            &answer_ids
+        )
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .map(|row| (row.answer_id, [row.url, row.thumbnail_url, row.thumbnail_webp_url]))
+        .collect()
+    } else {
+        Vec::new()
+    };
+
+    let erasure = Erasure { questions, answers: answer_ids.len() as i32, photos, product_ids };
+    let record = NewDataRequest {
+        kind: "erase",
+        subject: fingerprint,
+        content_policy: Some(policy.as_str()),
+        questions: erasure.questions,
+        answers: erasure.answers,
+        photos: erasure.photos.len() as i32,
+        requested_by,
+        reference,
+    };
+    let request_id = insert_data_request(&mut tx, &record).await?;
+
+    tx.commit().await?;
+    Ok((request_id, erasure))
+}
+
+/// A data-request audit record about to be written.
+pub struct NewDataRequest<'a> {
+    pub kind: &'a str,
+    pub subject: &'a str,
+    pub content_policy: Option<&'a str>,
+    pub questions: i32,
+    pub answers: i32,
+    pub photos: i32,
+    pub requested_by: Option<&'a str>,
+    pub reference: Option<&'a str>,
+}
+
+async fn insert_data_request<'c>(executor: impl sqlx::PgExecutor<'c>, record: &NewDataRequest<'_>) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO data_requests (kind, subject, content_policy, questions, answers, photos, requested_by, reference)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id;
+        "#,
+        record.kind,
+        record.subject,
+        record.content_policy,
+        record.questions,
+        record.answers,
+        record.photos,
+        record.requested_by,
+        record.reference
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Lists the data-request audit log, most recent requests first.
+pub async fn get_data_requests(state: Arc<AppState>, page: i32, count: i32) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = match &state.db {
+        Database::Postgres(pool) => query_data_requests(pool, page, count).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::query_data_requests(pool, page, count).await,
+    };
+
+    match result {
+        Ok(requests) => {
+            let response = serde_json::json!({ "page": page, "count": count, "results": requests });
+            create_success_response(StatusCode::OK, response)
+        }
+        Err(e) => {
+            log_error("Failed to fetch data requests", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch data requests".into())
+        }
+    }
+}
+
+async fn query_data_requests(pool: &PgPool, page: i32, count: i32) -> Result<Vec<DataRequestRecord>, sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    sqlx::query_as!(
+        DataRequestRecord,
+        r#"
+        SELECT
+            id,
+            kind,
+            subject,
+            content_policy,
+            questions,
+            answers,
+            photos,
+            requested_by,
+            reference,
+            to_char(requested_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "requested_at!"
+        FROM data_requests
+        ORDER BY id DESC
+        LIMIT $1 OFFSET $2;
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
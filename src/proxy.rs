@@ -0,0 +1,95 @@
+use hyper::header::HeaderMap;
+use std::net::IpAddr;
+
+/// Reverse proxies whose `X-Forwarded-For` header is believed.
+///
+/// Behind a load balancer every connection comes from the balancer, so the client has to be
+/// taken from the addresses it appends to `X-Forwarded-For`. Only connections from a
+/// trusted proxy may name a client that way; anyone else could claim any address.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Parses a comma-separated list of addresses and CIDR ranges such as
+    /// `10.0.0.0/8, 192.168.1.10, fd00::/8`.
+    pub fn parse(value: &str) -> Result<TrustedProxies, String> {
+        let networks = value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| parse_network(entry).ok_or_else(|| format!("Invalid trusted proxy: {}", entry)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(TrustedProxies { networks })
+    }
+
+    /// Reads the trusted proxies from `TRUSTED_PROXIES`. Unset, no proxy is trusted and
+    /// clients are identified by the address they connect from.
+    pub fn from_env() -> Result<TrustedProxies, String> {
+        match std::env::var("TRUSTED_PROXIES") {
+            Ok(value) => TrustedProxies::parse(&value),
+            Err(_) => Ok(TrustedProxies::default()),
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|&(network, prefix)| in_network(ip, network, prefix))
+    }
+
+    /// Returns the address of the client behind a connection from `remote_ip`.
+    ///
+    /// `X-Forwarded-For` is walked from the right, as each proxy appends the address it was
+    /// connected from, and the first address that is not a trusted proxy is the client.
+    pub fn client_ip(&self, headers: &HeaderMap, remote_ip: IpAddr) -> IpAddr {
+        if !self.contains(remote_ip) {
+            return remote_ip;
+        }
+
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+
+        let mut client = remote_ip;
+        for entry in forwarded.iter().rev() {
+            let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !self.contains(ip) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+fn parse_network(entry: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match entry.split_once('/') {
+        Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (entry.parse::<IpAddr>().ok()?, None),
+    };
+    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max_prefix);
+
+    (prefix <= max_prefix).then_some((addr, prefix))
+}
+
+pub(crate) fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,138 @@
+use hyper::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Most clients whose buckets are remembered at once. When a bucket is evicted its client
+/// starts over with a full one, which only ever errs on the side of letting requests through.
+const MAX_CLIENTS: usize = 100_000;
+
+/// Which budget a request draws from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RequestClass {
+    /// GET requests.
+    Read,
+    /// Everything else: asking, answering, voting, reporting and moderating.
+    Write,
+}
+
+/// The outcome of drawing from a client's bucket, as reported in the `RateLimit-*` headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The budget per minute, which is also the largest burst allowed.
+    pub limit: u32,
+    /// Requests left in the bucket.
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset: u64,
+    /// Seconds until the next request is allowed, if this one was rejected.
+    pub retry_after: Option<u64>,
+}
+
+impl RateLimit {
+    pub fn is_limited(&self) -> bool {
+        self.retry_after.is_some()
+    }
+
+    /// Adds the `RateLimit-*` headers, and `Retry-After` for rejected requests.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("ratelimit-reset", HeaderValue::from(self.reset));
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token-bucket rate limiter with separate read and write budgets per client.
+///
+/// Each client gets a bucket per class holding up to a minute's budget of requests, refilled
+/// evenly over the minute. Buckets live in this process only, so every replica enforces the
+/// budget on its own.
+pub struct RateLimiter {
+    read_per_minute: u32,
+    write_per_minute: u32,
+    buckets: Mutex<LruCache<(RequestClass, String), Bucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing each client `read_per_minute` reads and `write_per_minute`
+    /// writes a minute. A zero budget leaves that class unlimited.
+    pub fn new(read_per_minute: u32, write_per_minute: u32) -> RateLimiter {
+        RateLimiter {
+            read_per_minute,
+            write_per_minute,
+            buckets: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_CLIENTS).unwrap())),
+        }
+    }
+
+    /// Reads the budgets from `RATE_LIMIT_READ_PER_MINUTE`, unlimited by default, and
+    /// `RATE_LIMIT_WRITE_PER_MINUTE`, 30 by default. A value that is not a whole number is an
+    /// error rather than a silent fallback to the default.
+    pub fn from_env() -> Result<RateLimiter, String> {
+        let budget = |name: &str, default: u32| match std::env::var(name) {
+            Ok(value) => value.parse().map_err(|_| format!("Invalid {}: {}", name, value)),
+            Err(_) => Ok(default),
+        };
+
+        Ok(RateLimiter::new(budget("RATE_LIMIT_READ_PER_MINUTE", 0)?, budget("RATE_LIMIT_WRITE_PER_MINUTE", 30)?))
+    }
+
+    /// A limiter that lets every request through.
+    pub fn disabled() -> RateLimiter {
+        RateLimiter::new(0, 0)
+    }
+
+    /// Takes a request of `class` from `client`'s bucket. Returns `None` when the class is
+    /// unlimited.
+    pub fn check(&self, class: RequestClass, client: &str) -> Option<RateLimit> {
+        self.check_at(class, client, Instant::now())
+    }
+
+    /// Like [`check`](Self::check), at the given point in time.
+    pub fn check_at(&self, class: RequestClass, client: &str, now: Instant) -> Option<RateLimit> {
+        let limit = match class {
+            RequestClass::Read => self.read_per_minute,
+            RequestClass::Write => self.write_per_minute,
+        };
+        if limit == 0 {
+            return None;
+        }
+
+        let capacity = f64::from(limit);
+        let per_second = capacity / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_or_insert_mut((class, client.to_string()), || Bucket { tokens: capacity, updated: now });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+
+        let retry_after = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(seconds((1.0 - bucket.tokens) / per_second))
+        };
+
+        Some(RateLimit {
+            limit,
+            remaining: bucket.tokens as u32,
+            reset: seconds((capacity - bucket.tokens) / per_second),
+            retry_after,
+        })
+    }
+}
+
+/// Rounds up to whole seconds, the resolution of the headers.
+fn seconds(secs: f64) -> u64 {
+    secs.max(0.0).ceil() as u64
+}
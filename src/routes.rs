@@ -1,38 +1,217 @@
-use crate::models::{NewAnswer, NewQuestion};
+use crate::auth::{authenticate, AuthError, Principal, Scope};
+use crate::capture::{self, CapturedRequest, MAX_CAPTURED_BODY};
+use crate::ratelimit::RequestClass;
+use crate::models::{ErasureRequest, ExportRequest, ModerationAction, ModerationDecision, NewAnswer, NewQuestion, NewReport, QuestionSort};
+use crate::moderation::{get_moderation_actions, get_moderation_queue, moderate_answer, moderate_photo, moderate_question};
+use crate::multipart;
+use crate::notifications::{unsubscribe, unsubscribe_page, UNSUBSCRIBE_PATH};
+use crate::photos::{delete_photo, get_photos, serve_photo, update_photo_report, upload_photos, validate_upload, MAX_ANSWER_PHOTOS, MAX_UPLOAD_PHOTOS};
+use crate::privacy::{erase_data, export_data, get_data_requests};
 use crate::utils::{
-    create_error_response, get_page_count, parse_query_parameters,
+    create_error_response, get_page_count, log_error, parse_optional_body, parse_query_parameters,
 };
 
 use crate::handlers::{
-    get_questions, get_answers, add_question, add_answer, update_question_helpful, update_question_report, update_answer_helpful, update_answer_report
+    get_questions, get_questions_batch, get_qa_stats, search_questions, get_answers, add_question, add_answer, update_question_helpful, remove_question_helpful, update_question_report, update_answer_helpful, remove_answer_helpful, update_answer_report, get_metrics
 };
 
-use hyper::{Body, Request, Response, StatusCode};
-use sqlx::PgPool;
+use crate::state::AppState;
+use hyper::header::{HeaderValue, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
 use std::collections::HashMap;
 
-pub async fn handle_request(pool: Arc<PgPool>, req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+/// Routes a request received from `remote_addr`, the peer address of the connection.
+///
+/// Every request is first authenticated by its API key and bearer token and checked against
+/// the scope its route requires, see [`required_scope`]. It is then counted against its
+/// client's rate limit, see [`rate_limit_client`]; failing authentication costs its address
+/// a write instead, see [`charge_failed_authentication`]. With capture on, the request is written
+/// to the capture file once it is handled, see [`RequestCapture`](crate::capture::RequestCapture);
+/// only bodies up to [`MAX_CAPTURED_BODY`] bytes are captured.
+pub async fn handle_request(state: Arc<AppState>, remote_addr: SocketAddr, req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    if !state.capture.captures(req.uri().path()) {
+        return authorize_request(state, remote_addr, req).await;
+    }
+
+    let (started, arrived) = (Instant::now(), SystemTime::now());
+    let (parts, body) = req.into_parts();
+    let (captured_body, body) = capture::read_body(body, MAX_CAPTURED_BODY).await?;
+    let client_ip = state.trusted_proxies.client_ip(&parts.headers, remote_addr.ip());
+    let voter = state.voters.voter(None, &parts.headers, client_ip).unwrap_or_default();
+    let mut captured = CapturedRequest::new(&parts, captured_body.as_deref().unwrap_or_default(), &voter, arrived);
+
+    let response = authorize_request(state.clone(), remote_addr, Request::from_parts(parts, body)).await?;
+    captured.status = response.status().as_u16();
+    captured.duration_ms = started.elapsed().as_millis() as u64;
+    state.capture.record(&captured);
+
+    Ok(response)
+}
+
+/// Authenticates, authorizes and rate limits a request before routing it.
+async fn authorize_request(state: Arc<AppState>, remote_addr: SocketAddr, req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let client_ip = state.trusted_proxies.client_ip(req.headers(), remote_addr.ip());
+    let principal = match authenticate(&state.db, &state.auth, req.headers()).await {
+        Ok(principal) => principal,
+        Err(AuthError::InvalidKey) => return charge_failed_authentication(&state, client_ip, create_error_response(StatusCode::UNAUTHORIZED, "Invalid API key".into())?),
+        Err(AuthError::InvalidToken) => return charge_failed_authentication(&state, client_ip, bearer_error_response("Invalid bearer token")?),
+        Err(AuthError::ExpiredToken) => return charge_failed_authentication(&state, client_ip, bearer_error_response("Expired bearer token")?),
+        Err(AuthError::Database(e)) => {
+            log_error("Failed to authenticate request", &e);
+            return create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to authenticate request".into());
+        }
+    };
+
+    let Some(scope) = required_scope(req.method(), req.uri().path()) else {
+        return route(state, remote_addr.ip(), principal, req).await;
+    };
+
+    if !principal.allows(scope) {
+        return match (&principal.key_name, &principal.user) {
+            // Shoppers sign in rather than hold keys, so outside admin routes they are
+            // pointed at the bearer token.
+            (None, None) if scope != Scope::Admin && state.auth.jwt.is_some() => {
+                create_error_response(StatusCode::UNAUTHORIZED, "Missing bearer token".into())
+            }
+            (None, None) => create_error_response(StatusCode::UNAUTHORIZED, "Missing API key".into()),
+            (Some(_), _) => create_error_response(StatusCode::FORBIDDEN, format!("API key lacks the {} scope", scope.as_str())),
+            (None, Some(_)) => create_error_response(StatusCode::FORBIDDEN, format!("Bearer token lacks the {} scope", scope.as_str())),
+        };
+    }
+
+    let class = if req.method() == Method::GET { RequestClass::Read } else { RequestClass::Write };
+
+    let is_vote = req.uri().path().ends_with("/helpful");
+    let rate_limit = state.rate_limiter.check(class, &rate_limit_client(&principal, client_ip, is_vote));
+    let mut response = match rate_limit {
+        Some(limit) if limit.is_limited() => create_error_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests".into())?,
+        _ => route(state, client_ip, principal, req).await?,
+    };
+
+    if let Some(limit) = rate_limit {
+        limit.apply(response.headers_mut());
+    }
+
+    Ok(response)
+}
+
+/// Draws a request that failed authentication from its address's write budget, whatever
+/// its method, so that keys and tokens cannot be guessed faster than that budget allows.
+/// Once it is spent, `response` gives way to a `429`.
+fn charge_failed_authentication(state: &AppState, client_ip: IpAddr, response: Response<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let rate_limit = state.rate_limiter.check(RequestClass::Write, &format!("ip:{}", client_ip));
+    let mut response = match rate_limit {
+        Some(limit) if limit.is_limited() => create_error_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests".into())?,
+        _ => response,
+    };
+
+    if let Some(limit) = rate_limit {
+        limit.apply(response.headers_mut());
+    }
+
+    Ok(response)
+}
+
+/// Dispatches an authenticated request from `client_ip` to its handler.
+async fn route(state: Arc<AppState>, client_ip: IpAddr, principal: Principal, req: Request<Body>) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
     match (req.method(), req.uri().path()) {
         (&hyper::Method::GET, "/api/v1/questions") => {
             let params: HashMap<String, String> = parse_query_parameters(req.uri().query());
 
             if let Some(product_id) = params.get("product_id").and_then(|v| v.parse::<i32>().ok()) {
-                let (page, count) = get_page_count(&params);
+                let (page, count) = match get_page_count(&params) {
+                    Ok(page_count) => page_count,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
+
+                let sort = match params.get("sort").map(|v| QuestionSort::parse(v)) {
+                    Some(Some(sort)) => sort,
+                    Some(None) => return create_error_response(StatusCode::BAD_REQUEST, "Invalid sort query parameter".into()),
+                    None => QuestionSort::Oldest,
+                };
 
                 for key in params.keys() {
-                    if key != "product_id" && key != "page" && key != "count" {
+                    if key != "product_id" && key != "page" && key != "count" && key != "sort" {
                         return create_error_response(StatusCode::BAD_REQUEST, format!("Unexpected query parameter: {}", key));
                     }
                 }
 
-                get_questions(pool, product_id, page, count).await
+                get_questions(state, req.headers(), product_id, page, count, sort).await
             } else {
                 if !params.contains_key("product_id") {
                     return create_error_response(StatusCode::BAD_REQUEST, "Missing product_id query parameter".to_string())
                 }
-                return create_error_response(StatusCode::BAD_REQUEST, "Invalid product_id query parameter".to_string());
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid product_id query parameter".to_string())
+            }
+        }
+        (&hyper::Method::GET, "/api/v1/questions/batch") => {
+            let params: HashMap<String, String> = parse_query_parameters(req.uri().query());
+
+            let product_ids = match params.get("product_ids").map(|v| parse_product_ids(v)) {
+                Some(Ok(product_ids)) => product_ids,
+                Some(Err(message)) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                None => return create_error_response(StatusCode::BAD_REQUEST, "Missing product_ids query parameter".into()),
+            };
+            let top = match params.get("top").map(|v| v.parse::<i32>()) {
+                Some(Ok(top)) if (0..=MAX_BATCH_TOP).contains(&top) => top,
+                Some(_) => return create_error_response(StatusCode::BAD_REQUEST, "Invalid top query parameter".into()),
+                None => DEFAULT_BATCH_TOP,
+            };
+            let sort = match params.get("sort").map(|v| QuestionSort::parse(v)) {
+                Some(Some(sort)) => sort,
+                Some(None) => return create_error_response(StatusCode::BAD_REQUEST, "Invalid sort query parameter".into()),
+                None => QuestionSort::Helpful,
+            };
+
+            for key in params.keys() {
+                if key != "product_ids" && key != "top" && key != "sort" {
+                    return create_error_response(StatusCode::BAD_REQUEST, format!("Unexpected query parameter: {}", key));
+                }
+            }
+
+            get_questions_batch(state, req.headers(), &product_ids, top, sort).await
+        }
+        (&hyper::Method::GET, "/api/v1/questions/search") => {
+            let params: HashMap<String, String> = parse_query_parameters(req.uri().query());
+
+            let Some(product_id) = params.get("product_id") else {
+                return create_error_response(StatusCode::BAD_REQUEST, "Missing product_id query parameter".into());
+            };
+            let Ok(product_id) = product_id.parse::<i32>() else {
+                return create_error_response(StatusCode::BAD_REQUEST, "Invalid product_id query parameter".into());
+            };
+            let query = match params.get("q").map(|q| q.trim()) {
+                Some(q) if !q.is_empty() && q.chars().count() <= MAX_SEARCH_LEN => q.to_string(),
+                Some(_) => return create_error_response(StatusCode::BAD_REQUEST, "Invalid q query parameter".into()),
+                None => return create_error_response(StatusCode::BAD_REQUEST, "Missing q query parameter".into()),
+            };
+            let (page, count) = match get_page_count(&params) {
+                Ok(page_count) => page_count,
+                Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+            };
+
+            for key in params.keys() {
+                if key != "product_id" && key != "q" && key != "page" && key != "count" {
+                    return create_error_response(StatusCode::BAD_REQUEST, format!("Unexpected query parameter: {}", key));
+                }
+            }
+
+            search_questions(state, req.headers(), product_id, &query, page, count).await
+        }
+        (&hyper::Method::GET, path) if path.starts_with("/api/v1/products/") && path.ends_with("/qa-stats") => {
+            let product_id = path
+                .strip_prefix("/api/v1/products/")
+                .and_then(|v| v.strip_suffix("/qa-stats"))
+                .and_then(|v| v.parse::<i32>().ok());
+
+            if let Some(product_id) = product_id {
+                get_qa_stats(state, product_id).await
+            } else {
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid product_id path parameter".into())
             }
         }
         (&hyper::Method::GET, path) if path.starts_with("/api/v1/questions/") && path.ends_with("/answers") => {
@@ -41,7 +220,10 @@ pub async fn handle_request(pool: Arc<PgPool>, req: Request<Body>) -> Result<Res
 
             if let Ok(question_id) = question_id_str.parse::<i32>() {
                 let params: HashMap<String, String> = parse_query_parameters(req.uri().query());
-                let (page, count) = get_page_count(&params);
+                let (page, count) = match get_page_count(&params) {
+                    Ok(page_count) => page_count,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
 
                 for key in params.keys() {
                     if key != "page" && key != "count" {
@@ -49,20 +231,22 @@ pub async fn handle_request(pool: Arc<PgPool>, req: Request<Body>) -> Result<Res
                     }
                 }
 
-                get_answers(pool, question_id, page, count).await
+                get_answers(state, req.headers(), question_id, page, count).await
             } else {
-                return create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into());
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into())
             }
         }
         (&hyper::Method::POST, "/api/v1/questions") => {
             let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
-            let body_str = String::from_utf8(body_bytes.to_vec())?;
 
-            let question_data: Result<NewQuestion, _> = serde_json::from_str(&body_str);
-            if let Ok(question_data) = question_data {
-                add_question(pool, question_data).await
+            let question_data: Result<NewQuestion, _> = serde_json::from_slice(&body_bytes);
+            if let Ok(mut question_data) = question_data {
+                if let Err((status, message)) = identify_author(&state, &principal, &mut question_data.name, &mut question_data.email) {
+                    return create_error_response(status, message);
+                }
+                add_question(state, question_data).await
             } else {
-                return create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into());
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into())
             }
         }
         (&hyper::Method::POST, path) if path.starts_with("/api/v1/questions/") && path.ends_with("/answers") => {
@@ -73,16 +257,25 @@ pub async fn handle_request(pool: Arc<PgPool>, req: Request<Body>) -> Result<Res
 
             if let Some(question_id) = question_id {
                 let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
-                let body_str = String::from_utf8(body_bytes.to_vec())?;
 
-                let answer_data: Result<NewAnswer, _> = serde_json::from_str(&body_str);
-                if let Ok(answer_data) = answer_data {
-                    add_answer(pool, question_id, answer_data).await
+                let answer_data: Result<NewAnswer, _> = serde_json::from_slice(&body_bytes);
+                if let Ok(mut answer_data) = answer_data {
+                    if answer_data.photos.len() > MAX_ANSWER_PHOTOS {
+                        return create_error_response(StatusCode::BAD_REQUEST, format!("Too many photos, at most {} are allowed", MAX_ANSWER_PHOTOS));
+                    }
+                    // Files stored here are only added by uploading them to the answer.
+                    if answer_data.photos.iter().any(|url| state.photos.key_of(url).is_some()) {
+                        return create_error_response(StatusCode::BAD_REQUEST, "Uploaded photos cannot be added by URL".into());
+                    }
+                    if let Err((status, message)) = identify_author(&state, &principal, &mut answer_data.name, &mut answer_data.email) {
+                        return create_error_response(status, message);
+                    }
+                    add_answer(state, question_id, answer_data).await
                 } else {
-                    return create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into());
+                    create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into())
                 }
             } else {
-                return create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into());
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into())
             }
         }
         (&hyper::Method::PUT, path) if path.starts_with("/api/v1/questions/") && path.ends_with("/helpful") => {
@@ -92,9 +285,29 @@ pub async fn handle_request(pool: Arc<PgPool>, req: Request<Body>) -> Result<Res
                 .and_then(|v| v.parse::<i32>().ok());
 
             if let Some(question_id) = question_id {
-                update_question_helpful(pool, question_id).await
+                let voter = match state.voters.voter(principal.user.as_ref().map(|user| user.subject.as_str()), req.headers(), client_ip) {
+                    Ok(voter) => voter,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
+                update_question_helpful(state, question_id, &voter).await
             } else {
-                return create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into());
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into())
+            }
+        }
+        (&hyper::Method::DELETE, path) if path.starts_with("/api/v1/questions/") && path.ends_with("/helpful") => {
+            let question_id = path
+                .strip_prefix("/api/v1/questions/")
+                .and_then(|v| v.strip_suffix("/helpful"))
+                .and_then(|v| v.parse::<i32>().ok());
+
+            if let Some(question_id) = question_id {
+                let voter = match state.voters.voter(principal.user.as_ref().map(|user| user.subject.as_str()), req.headers(), client_ip) {
+                    Ok(voter) => voter,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
+                remove_question_helpful(state, question_id, &voter).await
+            } else {
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into())
             }
         }
         (&hyper::Method::PUT, path) if path.starts_with("/api/v1/questions/") && path.ends_with("/report") => {
@@ -104,9 +317,19 @@ pub async fn handle_request(pool: Arc<PgPool>, req: Request<Body>) -> Result<Res
                 .and_then(|v| v.parse::<i32>().ok());
 
             if let Some(question_id) = question_id {
-                update_question_report(pool, question_id).await
+                let reporter = match state.voters.voter(principal.user.as_ref().map(|user| user.subject.as_str()), req.headers(), client_ip) {
+                    Ok(reporter) => reporter,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+                match parse_optional_body::<NewReport>(&body_bytes) {
+                    Ok(report) if exceeds_note_length(&report.note) => create_error_response(StatusCode::BAD_REQUEST, "Report note is too long".into()),
+                    Ok(report) => update_question_report(state, question_id, &reporter, report).await,
+                    Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into()),
+                }
             } else {
-                return create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into());
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into())
             }
         }
         (&hyper::Method::PUT, path) if path.starts_with("/api/v1/answers/") && path.ends_with("/helpful") => {
@@ -116,9 +339,29 @@ pub async fn handle_request(pool: Arc<PgPool>, req: Request<Body>) -> Result<Res
                 .and_then(|v| v.parse::<i32>().ok());
 
             if let Some(answer_id) = answer_id {
-                update_answer_helpful(pool, answer_id).await
+                let voter = match state.voters.voter(principal.user.as_ref().map(|user| user.subject.as_str()), req.headers(), client_ip) {
+                    Ok(voter) => voter,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
+                update_answer_helpful(state, answer_id, &voter).await
             } else {
-                return create_error_response(StatusCode::BAD_REQUEST, "Invalid answer_id path parameter".into());
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid answer_id path parameter".into())
+            }
+        }
+        (&hyper::Method::DELETE, path) if path.starts_with("/api/v1/answers/") && path.ends_with("/helpful") => {
+            let answer_id = path
+                .strip_prefix("/api/v1/answers/")
+                .and_then(|v| v.strip_suffix("/helpful"))
+                .and_then(|v| v.parse::<i32>().ok());
+
+            if let Some(answer_id) = answer_id {
+                let voter = match state.voters.voter(principal.user.as_ref().map(|user| user.subject.as_str()), req.headers(), client_ip) {
+                    Ok(voter) => voter,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
+                remove_answer_helpful(state, answer_id, &voter).await
+            } else {
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid answer_id path parameter".into())
             }
         }
         (&hyper::Method::PUT, path) if path.starts_with("/api/v1/answers/") && path.ends_with("/report") => {
@@ -128,11 +371,314 @@ pub async fn handle_request(pool: Arc<PgPool>, req: Request<Body>) -> Result<Res
                 .and_then(|v| v.parse::<i32>().ok());
 
             if let Some(answer_id) = answer_id {
-                update_answer_report(pool, answer_id).await
+                let reporter = match state.voters.voter(principal.user.as_ref().map(|user| user.subject.as_str()), req.headers(), client_ip) {
+                    Ok(reporter) => reporter,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+                match parse_optional_body::<NewReport>(&body_bytes) {
+                    Ok(report) if exceeds_note_length(&report.note) => create_error_response(StatusCode::BAD_REQUEST, "Report note is too long".into()),
+                    Ok(report) => update_answer_report(state, answer_id, &reporter, report).await,
+                    Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into()),
+                }
+            } else {
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid answer_id path parameter".into())
+            }
+        }
+        (&hyper::Method::POST, path) if path.starts_with("/api/v1/answers/") && path.ends_with("/photos") => {
+            let answer_id = path
+                .strip_prefix("/api/v1/answers/")
+                .and_then(|v| v.strip_suffix("/photos"))
+                .and_then(|v| v.parse::<i32>().ok());
+
+            if let Some(answer_id) = answer_id {
+                let boundary = req.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).and_then(multipart::boundary);
+                let Some(boundary) = boundary else {
+                    return create_error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Expected a multipart/form-data body".into());
+                };
+                // Room for every photo at its largest, plus the form's own headers and delimiters.
+                let limit = state.photos.max_bytes * MAX_UPLOAD_PHOTOS + 64 * 1024;
+                let Some(body_bytes) = multipart::read_body(req.into_body(), limit).await? else {
+                    return create_error_response(StatusCode::PAYLOAD_TOO_LARGE, "Upload is too large".into());
+                };
+
+                match multipart::parse(&body_bytes, &boundary).map(|parts| validate_upload(parts, state.photos.max_bytes)) {
+                    Ok(Ok(photos)) => upload_photos(state, &principal, answer_id, photos).await,
+                    Ok(Err((status, message))) => create_error_response(status, message),
+                    Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid multipart body".into()),
+                }
+            } else {
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid answer_id path parameter".into())
+            }
+        }
+        (&hyper::Method::GET, path) if path.starts_with("/api/v1/answers/") && path.ends_with("/photos") => {
+            let answer_id = path
+                .strip_prefix("/api/v1/answers/")
+                .and_then(|v| v.strip_suffix("/photos"))
+                .and_then(|v| v.parse::<i32>().ok());
+
+            if let Some(answer_id) = answer_id {
+                get_photos(state, answer_id).await
             } else {
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid answer_id path parameter".into())
+            }
+        }
+        (&hyper::Method::DELETE, path) if path.starts_with("/api/v1/answers/") && path.contains("/photos/") => {
+            let Some((answer_id, photo_id)) = path.strip_prefix("/api/v1/answers/").and_then(|v| v.split_once("/photos/")) else {
+                return create_error_response(StatusCode::NOT_FOUND, "Path not found".into());
+            };
+            let Ok(answer_id) = answer_id.parse::<i32>() else {
                 return create_error_response(StatusCode::BAD_REQUEST, "Invalid answer_id path parameter".into());
+            };
+            let Ok(photo_id) = photo_id.parse::<i32>() else {
+                return create_error_response(StatusCode::BAD_REQUEST, "Invalid photo_id path parameter".into());
+            };
+
+            delete_photo(state, &principal, answer_id, photo_id).await
+        }
+        (&hyper::Method::PUT, path) if path.starts_with("/api/v1/photos/") && path.ends_with("/report") => {
+            let photo_id = path
+                .strip_prefix("/api/v1/photos/")
+                .and_then(|v| v.strip_suffix("/report"))
+                .and_then(|v| v.parse::<i32>().ok());
+
+            if let Some(photo_id) = photo_id {
+                let reporter = match state.voters.voter(principal.user.as_ref().map(|user| user.subject.as_str()), req.headers(), client_ip) {
+                    Ok(reporter) => reporter,
+                    Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+                };
+                let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+                match parse_optional_body::<NewReport>(&body_bytes) {
+                    Ok(report) if exceeds_note_length(&report.note) => create_error_response(StatusCode::BAD_REQUEST, "Report note is too long".into()),
+                    Ok(report) => update_photo_report(state, photo_id, &reporter, report).await,
+                    Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into()),
+                }
+            } else {
+                create_error_response(StatusCode::BAD_REQUEST, "Invalid photo_id path parameter".into())
             }
         }
-        _ => return create_error_response(StatusCode::NOT_FOUND, "Path not found".into()),
+        (&hyper::Method::GET, path) if path.starts_with("/photos/") => serve_photo(state, &path["/photos/".len()..]).await,
+        (method, UNSUBSCRIBE_PATH) if method == Method::GET || method == Method::POST => {
+            let params: HashMap<String, String> = parse_query_parameters(req.uri().query());
+            let Some(token) = params.get("token") else {
+                return create_error_response(StatusCode::BAD_REQUEST, "Missing token query parameter".into());
+            };
+
+            if method == Method::GET {
+                unsubscribe_page(state, token).await
+            } else {
+                unsubscribe(state, token).await
+            }
+        }
+        (&hyper::Method::GET, "/api/v1/admin/reports") => {
+            let params: HashMap<String, String> = parse_query_parameters(req.uri().query());
+            let (page, count) = match get_page_count(&params) {
+                Ok(page_count) => page_count,
+                Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+            };
+
+            get_moderation_queue(state, page, count).await
+        }
+        (&hyper::Method::GET, "/api/v1/admin/actions") => {
+            let params: HashMap<String, String> = parse_query_parameters(req.uri().query());
+            let (page, count) = match get_page_count(&params) {
+                Ok(page_count) => page_count,
+                Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+            };
+
+            get_moderation_actions(state, page, count).await
+        }
+        (&hyper::Method::POST, path) if path.starts_with("/api/v1/admin/questions/") => {
+            let Some((question_id, action)) = parse_moderation_path(path, "/api/v1/admin/questions/") else {
+                return create_error_response(StatusCode::NOT_FOUND, "Path not found".into());
+            };
+            let Some(question_id) = question_id else {
+                return create_error_response(StatusCode::BAD_REQUEST, "Invalid question_id path parameter".into());
+            };
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+            match parse_optional_body::<ModerationDecision>(&body_bytes) {
+                Ok(decision) if exceeds_note_length(&decision.note) => create_error_response(StatusCode::BAD_REQUEST, "Moderation note is too long".into()),
+                Ok(decision) => moderate_question(state, question_id, action, decision, principal.key_name.as_deref()).await,
+                Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into()),
+            }
+        }
+        (&hyper::Method::POST, path) if path.starts_with("/api/v1/admin/answers/") => {
+            let Some((answer_id, action)) = parse_moderation_path(path, "/api/v1/admin/answers/") else {
+                return create_error_response(StatusCode::NOT_FOUND, "Path not found".into());
+            };
+            let Some(answer_id) = answer_id else {
+                return create_error_response(StatusCode::BAD_REQUEST, "Invalid answer_id path parameter".into());
+            };
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+            match parse_optional_body::<ModerationDecision>(&body_bytes) {
+                Ok(decision) if exceeds_note_length(&decision.note) => create_error_response(StatusCode::BAD_REQUEST, "Moderation note is too long".into()),
+                Ok(decision) => moderate_answer(state, answer_id, action, decision, principal.key_name.as_deref()).await,
+                Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into()),
+            }
+        }
+        (&hyper::Method::POST, path) if path.starts_with("/api/v1/admin/photos/") => {
+            let Some((photo_id, action)) = parse_moderation_path(path, "/api/v1/admin/photos/") else {
+                return create_error_response(StatusCode::NOT_FOUND, "Path not found".into());
+            };
+            let Some(photo_id) = photo_id else {
+                return create_error_response(StatusCode::BAD_REQUEST, "Invalid photo_id path parameter".into());
+            };
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+            match parse_optional_body::<ModerationDecision>(&body_bytes) {
+                Ok(decision) if exceeds_note_length(&decision.note) => create_error_response(StatusCode::BAD_REQUEST, "Moderation note is too long".into()),
+                Ok(decision) => moderate_photo(state, photo_id, action, decision, principal.key_name.as_deref()).await,
+                Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into()),
+            }
+        }
+        (&hyper::Method::GET, "/api/v1/admin/data-requests") => {
+            let params: HashMap<String, String> = parse_query_parameters(req.uri().query());
+            let (page, count) = match get_page_count(&params) {
+                Ok(page_count) => page_count,
+                Err(message) => return create_error_response(StatusCode::BAD_REQUEST, message),
+            };
+
+            get_data_requests(state, page, count).await
+        }
+        (&hyper::Method::POST, "/api/v1/admin/data-requests/export") => {
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+            match serde_json::from_slice::<ExportRequest>(&body_bytes) {
+                Ok(request) if !is_email(&request.email) => create_error_response(StatusCode::BAD_REQUEST, "Invalid email".into()),
+                Ok(request) if exceeds_note_length(&request.reference) => create_error_response(StatusCode::BAD_REQUEST, "Reference is too long".into()),
+                Ok(request) => export_data(state, request, principal.key_name.as_deref()).await,
+                Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into()),
+            }
+        }
+        (&hyper::Method::POST, "/api/v1/admin/data-requests/erase") => {
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+
+            match serde_json::from_slice::<ErasureRequest>(&body_bytes) {
+                Ok(request) if !is_email(&request.email) => create_error_response(StatusCode::BAD_REQUEST, "Invalid email".into()),
+                Ok(request) if exceeds_note_length(&request.reference) => create_error_response(StatusCode::BAD_REQUEST, "Reference is too long".into()),
+                Ok(request) => erase_data(state, request, principal.key_name.as_deref()).await,
+                Err(_) => create_error_response(StatusCode::BAD_REQUEST, "Invalid request body".into()),
+            }
+        }
+        (&hyper::Method::GET, "/metrics") => get_metrics(state),
+        _ => create_error_response(StatusCode::NOT_FOUND, "Path not found".into()),
+    }
+}
+
+/// The scope a request needs: `admin` for the moderation and data-request endpoints, `read` for any other
+/// GET and `write` for everything else. `/metrics` stays open to the monitoring scraper, photos
+/// to the pages that show them, and unsubscribe links to the askers they are emailed to.
+fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    if path == "/metrics" || path == UNSUBSCRIBE_PATH || (method == Method::GET && path.starts_with("/photos/")) {
+        None
+    } else if path.starts_with("/api/v1/admin/") {
+        Some(Scope::Admin)
+    } else if method == Method::GET {
+        Some(Scope::Read)
+    } else {
+        Some(Scope::Write)
+    }
+}
+
+/// The client a request is rate limited as: the signed-in shopper, else the API key, else
+/// the client's address. Shoppers come first so that a storefront calling with one key on
+/// behalf of all of them does not share a single budget.
+///
+/// Helpful votes from anyone but a signed-in shopper are limited by address even under an
+/// API key. Such votes are told apart by an `X-User-Token` the client makes up, so a script
+/// could otherwise cast as many as its key's budget allows under fresh tokens. The cost is
+/// that a storefront relaying votes shares one budget for them, unless it signs shoppers in
+/// or names them through a trusted proxy's `X-Forwarded-For`.
+fn rate_limit_client(principal: &Principal, client_ip: IpAddr, is_vote: bool) -> String {
+    match (&principal.user, &principal.key_name) {
+        (Some(user), _) => format!("user:{}", user.subject),
+        (None, Some(key_name)) if !is_vote => format!("key:{}", key_name),
+        (None, _) => format!("ip:{}", client_ip),
+    }
+}
+
+/// Fills in the `name` and `email` a new question or answer is posted under.
+///
+/// Signed-in shoppers are named by their bearer token's claims, whatever the body says.
+/// Once bearer tokens are configured they are required to post; until then the body's
+/// `name` and `email` are used as sent.
+fn identify_author(state: &AppState, principal: &Principal, name: &mut String, email: &mut String) -> Result<(), (StatusCode, String)> {
+    match &principal.user {
+        Some(user) => {
+            *name = user.name.clone();
+            *email = user.email.clone();
+            Ok(())
+        }
+        None if state.auth.jwt.is_some() => Err((StatusCode::UNAUTHORIZED, "Missing bearer token".into())),
+        None if name.is_empty() || email.is_empty() => Err((StatusCode::BAD_REQUEST, "Invalid request body".into())),
+        None => Ok(()),
     }
 }
+
+/// A `401` for a rejected bearer token, with the challenge RFC 6750 asks for.
+fn bearer_error_response(message: &str) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut response = create_error_response(StatusCode::UNAUTHORIZED, message.into())?;
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static(r#"Bearer error="invalid_token""#));
+    Ok(response)
+}
+
+/// Most products a batch request may ask for.
+const MAX_BATCH_PRODUCTS: usize = 50;
+
+/// Questions returned per product by a batch request unless `top` says otherwise, and the
+/// most it may ask for.
+const DEFAULT_BATCH_TOP: i32 = 3;
+const MAX_BATCH_TOP: i32 = 10;
+
+/// Parses the comma-separated `product_ids` of a batch request, dropping repeats.
+fn parse_product_ids(value: &str) -> Result<Vec<i32>, String> {
+    let mut product_ids = Vec::new();
+    for id in value.split(',') {
+        let id = id.trim().parse::<i32>().map_err(|_| "Invalid product_ids query parameter".to_string())?;
+        if !product_ids.contains(&id) {
+            product_ids.push(id);
+        }
+    }
+
+    if product_ids.len() > MAX_BATCH_PRODUCTS {
+        return Err(format!("Too many product_ids, at most {} are allowed", MAX_BATCH_PRODUCTS));
+    }
+    Ok(product_ids)
+}
+
+/// Longest search accepted, in characters.
+const MAX_SEARCH_LEN: usize = 200;
+
+/// Longest note accepted with a report or a moderation decision, or reference accepted with
+/// a data request, in characters.
+const MAX_NOTE_LEN: usize = 1000;
+
+fn exceeds_note_length(note: &Option<String>) -> bool {
+    note.as_ref().is_some_and(|note| note.chars().count() > MAX_NOTE_LEN)
+}
+
+/// Whether a data request names something that could have been posted as an email.
+fn is_email(email: &str) -> bool {
+    email.trim().contains('@')
+}
+
+/// Splits `{prefix}{id}/approve` and `{prefix}{id}/remove` paths into the id, if it parses,
+/// and the action. Returns `None` for any other path.
+fn parse_moderation_path(path: &str, prefix: &str) -> Option<(Option<i32>, ModerationAction)> {
+    let rest = path.strip_prefix(prefix)?;
+    let (id, action) = if let Some(id) = rest.strip_suffix("/approve") {
+        (id, ModerationAction::Approve)
+    } else if let Some(id) = rest.strip_suffix("/remove") {
+        (id, ModerationAction::Remove)
+    } else {
+        return None;
+    };
+
+    Some((id.parse::<i32>().ok(), action))
+}
@@ -0,0 +1,345 @@
+use crate::utils::hex;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rand::RngCore;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_native_tls::native_tls;
+
+/// Longest line of a base64-encoded body, as RFC 2045 allows.
+const BASE64_LINE_LEN: usize = 76;
+
+/// How the connection to the SMTP server is secured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// Plain text throughout, for a relay on the same host or a trusted network.
+    None,
+    /// Upgraded with `STARTTLS` before anything else is sent, usually on port 587.
+    StartTls,
+    /// TLS from the start, usually on port 465.
+    Tls,
+}
+
+/// Why an email could not be sent.
+#[derive(Debug)]
+pub enum SmtpError {
+    Io(std::io::Error),
+    Tls(native_tls::Error),
+    /// The server refused a command, with its reply.
+    Rejected { code: u16, message: String },
+    /// The server lacks an extension the configuration relies on.
+    Unsupported(&'static str),
+    /// The server sent something other than an SMTP reply.
+    Protocol(String),
+    Timeout,
+}
+
+impl SmtpError {
+    /// Whether sending again could succeed: a `5xx` reply or a missing extension will not
+    /// change, while `4xx` replies and connection trouble may pass.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            SmtpError::Rejected { code, .. } => *code >= 500,
+            SmtpError::Unsupported(_) => true,
+            SmtpError::Io(_) | SmtpError::Tls(_) | SmtpError::Protocol(_) | SmtpError::Timeout => false,
+        }
+    }
+}
+
+impl std::fmt::Display for SmtpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SmtpError::Io(e) => write!(f, "{}", e),
+            SmtpError::Tls(e) => write!(f, "{}", e),
+            SmtpError::Rejected { code, message } => write!(f, "SMTP server answered {} {}", code, message),
+            SmtpError::Unsupported(extension) => write!(f, "SMTP server does not support {}", extension),
+            SmtpError::Protocol(line) => write!(f, "Unexpected SMTP reply: {}", line),
+            SmtpError::Timeout => write!(f, "Sending the email timed out"),
+        }
+    }
+}
+
+impl std::error::Error for SmtpError {}
+
+impl From<std::io::Error> for SmtpError {
+    fn from(e: std::io::Error) -> SmtpError {
+        SmtpError::Io(e)
+    }
+}
+
+impl From<native_tls::Error> for SmtpError {
+    fn from(e: native_tls::Error) -> SmtpError {
+        SmtpError::Tls(e)
+    }
+}
+
+/// A plain-text email.
+#[derive(Clone, Debug)]
+pub struct Message {
+    /// The `From` header, an address with or without a display name, e.g.
+    /// `Q&A <qa@example.com>`.
+    pub from: String,
+    /// The recipient's address.
+    pub to: String,
+    pub subject: String,
+    /// Further headers, such as `List-Unsubscribe`.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Message {
+    /// The address in the `From` header, which bounces are sent back to.
+    pub fn sender(&self) -> &str {
+        mailbox_address(&self.from)
+    }
+
+    /// The message as sent after `DATA`, with lines ending in CRLF and the body in base64
+    /// so that it passes through any server whatever its characters.
+    pub fn format(&self) -> String {
+        let domain = self.sender().rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+        let mut id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut id);
+        let id = hex(&id);
+
+        let mut headers = vec![
+            ("From".to_string(), encode_mailbox(&self.from)),
+            ("To".to_string(), self.to.clone()),
+            ("Subject".to_string(), encode_header(&self.subject)),
+            ("Date".to_string(), httpdate::fmt_http_date(SystemTime::now())),
+            ("Message-ID".to_string(), format!("<{}@{}>", id, domain)),
+            ("MIME-Version".to_string(), "1.0".to_string()),
+            ("Content-Type".to_string(), "text/plain; charset=utf-8".to_string()),
+            ("Content-Transfer-Encoding".to_string(), "base64".to_string()),
+        ];
+        headers.extend(self.headers.iter().map(|(name, value)| (name.clone(), encode_header(value))));
+
+        let mut text = String::new();
+        for (name, value) in headers {
+            // A line break in a value would start a header of the sender's choosing.
+            let value: String = value.chars().filter(|c| *c != '\r' && *c != '\n').collect();
+            text.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        text.push_str("\r\n");
+
+        let body = BASE64.encode(self.body.replace("\r\n", "\n").replace('\n', "\r\n"));
+        for line in body.as_bytes().chunks(BASE64_LINE_LEN) {
+            text.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+            text.push_str("\r\n");
+        }
+        text
+    }
+}
+
+/// The address in `mailbox`, written `Name <address>` or as a bare address.
+pub fn mailbox_address(mailbox: &str) -> &str {
+    match mailbox.rsplit_once('<') {
+        Some((_, address)) => address.trim_end().trim_end_matches('>'),
+        None => mailbox.trim(),
+    }
+}
+
+/// Writes the display name of `mailbox` outside of ASCII as an RFC 2047 encoded word.
+fn encode_mailbox(mailbox: &str) -> String {
+    match mailbox.rsplit_once('<') {
+        Some((name, address)) => format!("{} <{}", encode_header(name.trim()), address.trim()),
+        None => mailbox.trim().to_string(),
+    }
+}
+
+/// Writes a header value outside of ASCII as an RFC 2047 encoded word.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", BASE64.encode(value))
+    }
+}
+
+/// Whether `address` is fit to be sent to: a single `local@domain` with nothing an SMTP
+/// command or a header could be broken with.
+pub fn is_valid_address(address: &str) -> bool {
+    let Some((local, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && address.len() <= 254
+        && address.chars().all(|c| c.is_ascii_graphic() && !matches!(c, '<' | '>' | '(' | ')' | ',' | ';' | '\\' | '"'))
+}
+
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+type Stream = BufReader<Box<dyn Connection>>;
+
+/// Sends emails through an SMTP server, one connection per email.
+///
+/// Only what sending notifications takes is spoken: `EHLO`, `STARTTLS`, `AUTH PLAIN`,
+/// `MAIL`, `RCPT`, `DATA` and `QUIT`. Credentials are never sent over a plain connection.
+#[derive(Clone, Debug)]
+pub struct SmtpMailer {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub credentials: Option<(String, String)>,
+    /// How long a whole email has to be sent in.
+    pub timeout: Duration,
+}
+
+impl SmtpMailer {
+    /// Parses a server URL: `smtp://host:port` for a plain connection, `smtp+starttls://`
+    /// or `smtps://`, each with an optional `user:password@` percent-encoded.
+    pub fn from_url(url: &str, timeout: Duration) -> Result<SmtpMailer, String> {
+        let parsed = reqwest::Url::parse(url).map_err(|_| format!("Invalid SMTP URL: {}", url))?;
+        let (security, default_port) = match parsed.scheme() {
+            "smtp" => (SmtpSecurity::None, 25),
+            "smtp+starttls" => (SmtpSecurity::StartTls, 587),
+            "smtps" => (SmtpSecurity::Tls, 465),
+            scheme => return Err(format!("Unsupported SMTP URL scheme: {}", scheme)),
+        };
+        let host = parsed.host_str().filter(|host| !host.is_empty()).ok_or_else(|| format!("SMTP URL has no host: {}", url))?;
+
+        let credentials = match (parsed.username(), parsed.password()) {
+            ("", None) => None,
+            (user, password) => Some((percent_decode(user)?, percent_decode(password.unwrap_or_default())?)),
+        };
+        if credentials.is_some() && security == SmtpSecurity::None {
+            return Err("SMTP credentials are only sent over smtp+starttls:// or smtps://".into());
+        }
+
+        Ok(SmtpMailer {
+            host: host.trim_start_matches('[').trim_end_matches(']').to_string(),
+            port: parsed.port().unwrap_or(default_port),
+            security,
+            credentials,
+            timeout,
+        })
+    }
+
+    pub async fn send(&self, message: &Message) -> Result<(), SmtpError> {
+        tokio::time::timeout(self.timeout, self.transact(message)).await.map_err(|_| SmtpError::Timeout)?
+    }
+
+    async fn transact(&self, message: &Message) -> Result<(), SmtpError> {
+        let sender = message.sender();
+        if !is_valid_address(sender) || !is_valid_address(&message.to) {
+            return Err(SmtpError::Rejected { code: 553, message: "Invalid sender or recipient address".into() });
+        }
+        let hello = sender.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+
+        let tcp = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let mut stream: Stream = match self.security {
+            SmtpSecurity::Tls => BufReader::new(Box::new(self.tls(tcp).await?)),
+            SmtpSecurity::None | SmtpSecurity::StartTls => BufReader::new(Box::new(tcp)),
+        };
+        expect(read_reply(&mut stream).await?, 220)?;
+
+        let mut extensions = command(&mut stream, &format!("EHLO {}", hello), 250).await?;
+        if self.security == SmtpSecurity::StartTls {
+            if !has_extension(&extensions, "STARTTLS") {
+                return Err(SmtpError::Unsupported("STARTTLS"));
+            }
+            command(&mut stream, "STARTTLS", 220).await?;
+            stream = BufReader::new(Box::new(self.tls(stream.into_inner()).await?));
+            extensions = command(&mut stream, &format!("EHLO {}", hello), 250).await?;
+        }
+
+        if let Some((user, password)) = &self.credentials {
+            if !has_extension(&extensions, "AUTH") {
+                return Err(SmtpError::Unsupported("AUTH"));
+            }
+            let token = BASE64.encode(format!("\0{}\0{}", user, password));
+            command(&mut stream, &format!("AUTH PLAIN {}", token), 235).await?;
+        }
+
+        command(&mut stream, &format!("MAIL FROM:<{}>", sender), 250).await?;
+        command(&mut stream, &format!("RCPT TO:<{}>", message.to), 250).await?;
+        command(&mut stream, "DATA", 354).await?;
+
+        // Lines starting with a dot are stuffed with another, so none ends the data early.
+        let mut data = String::new();
+        for line in message.format().split_terminator("\r\n") {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push_str(".\r\n");
+        stream.get_mut().write_all(data.as_bytes()).await?;
+        stream.get_mut().flush().await?;
+        expect(read_reply(&mut stream).await?, 250)?;
+
+        // The email is accepted, so a server that hangs up rudely changes nothing.
+        let _ = command(&mut stream, "QUIT", 221).await;
+        Ok(())
+    }
+
+    async fn tls<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: S) -> Result<tokio_native_tls::TlsStream<S>, SmtpError> {
+        let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+        Ok(connector.connect(&self.host, stream).await?)
+    }
+}
+
+struct Reply {
+    code: u16,
+    /// The reply's lines, without their codes.
+    lines: Vec<String>,
+}
+
+async fn read_reply(stream: &mut Stream) -> Result<Reply, SmtpError> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(SmtpError::Protocol("connection closed".into()));
+        }
+        let line = line.trim_end();
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok()).ok_or_else(|| SmtpError::Protocol(line.to_string()))?;
+        lines.push(line.get(4..).unwrap_or_default().to_string());
+
+        // `250-` continues a reply over more lines, `250 ` ends it.
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(Reply { code, lines });
+        }
+    }
+}
+
+fn expect(reply: Reply, code: u16) -> Result<Reply, SmtpError> {
+    if reply.code == code || (code == 250 && reply.code == 251) {
+        Ok(reply)
+    } else {
+        Err(SmtpError::Rejected { code: reply.code, message: reply.lines.join(" ") })
+    }
+}
+
+async fn command(stream: &mut Stream, line: &str, code: u16) -> Result<Reply, SmtpError> {
+    stream.get_mut().write_all(format!("{}\r\n", line).as_bytes()).await?;
+    stream.get_mut().flush().await?;
+    expect(read_reply(stream).await?, code)
+}
+
+/// Whether an `EHLO` reply lists `extension`. Its first line greets rather than lists.
+fn has_extension(reply: &Reply, extension: &str) -> bool {
+    reply.lines.iter().skip(1).any(|line| line.split_whitespace().next().is_some_and(|name| name.eq_ignore_ascii_case(extension)))
+}
+
+fn percent_decode(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            bytes.push(hex.ok_or("Invalid percent-encoding in SMTP URL")?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| "Invalid percent-encoding in SMTP URL".to_string())
+}
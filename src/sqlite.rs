@@ -0,0 +1,1766 @@
+use crate::auth::ApiKeyRecord;
+use crate::cache::QuestionsCache;
+use crate::emails::{EmailColumn, SealedEmail};
+use crate::handlers::QaStats;
+use crate::loadtest::{IdKind, PurgeReport, LOAD_TEST_NAME};
+use crate::models::{ErasurePolicy, ModerationAction, ModerationDecision, NewAnswer, NewQuestion, NewReport, QuestionSort};
+use crate::moderation::{ModerationRecord, PendingReport, QueueItem};
+use crate::notifications::PendingNotification;
+use crate::outbox::Event;
+use crate::photos::{AnswerOwner, Photo};
+use crate::privacy::{DataRequestRecord, Erasure, ExportedAnswer, ExportedQuestion, NewDataRequest, Subject, ERASED_NAME};
+use crate::thumbnails::{PendingPhoto, StoredVariants};
+use crate::webhooks::{DeadLetter, PendingDelivery, WebhookRecord};
+use crate::utils::{
+    create_error_response, create_success_response, log_error,
+};
+use hyper::{Body, Response, StatusCode};
+use sqlx::sqlite::{Sqlite, SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::Transaction;
+use std::str::FromStr;
+
+/// Extended result code SQLite reports for `SQLITE_CONSTRAINT_FOREIGNKEY`.
+const FOREIGN_KEY_VIOLATION: &str = "787";
+
+/// Opens the SQLite database at `database_url` and brings its schema up to date.
+///
+/// In-memory databases live only as long as their connection, so they are served by a
+/// single connection that is never recycled.
+pub async fn connect(database_url: &str) -> Result<SqlitePool, Box<dyn std::error::Error + Send + Sync>> {
+    let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+
+    let pool_options = if database_url.contains(":memory:") {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+    } else {
+        SqlitePoolOptions::new()
+            .max_connections(16)
+            .idle_timeout(std::time::Duration::from_secs(30))
+    };
+
+    let pool = pool_options.connect_with(options).await?;
+
+    sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+
+    Ok(pool)
+}
+
+/// SQLite counterpart of the query behind [`crate::handlers::get_questions`].
+///
+/// Builds the same nested questions → answers → photos JSON document using SQLite's
+/// JSON1 functions, alongside the Unix time of the product's most recent write.
+pub async fn query_questions(pool: &SqlitePool, product_id: i32, page: i32, count: i32, sort: QuestionSort) -> Result<(String, Option<i64>), sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    let row: Option<(String, Option<i64>)> = sqlx::query_as(
+        r#"
+        SELECT
+            json_group_array(
+                json_object(
+                    'question_id',          q.id,
+                    'question_body',        q.body,
+                    'question_date',        q.date_written,
+                    'asker_name',           q.asker_name,
+                    'question_helpfulness', q.helpful,
+                    'reported',             json(CASE WHEN q.reported THEN 'true' ELSE 'false' END),
+                    'answers', json((
+                        SELECT json_group_object(
+                            CAST(a.id AS TEXT),
+                            json_object(
+                                'id',            a.id,
+                                'body',          a.body,
+                                'date',          a.date_written,
+                                'answerer_name', a.answerer_name,
+                                'helpfulness',   a.helpful,
+                                'photos', json((
+                                    SELECT json_group_array(
+                                        json_object(
+                                            'id',                 ap.id,
+                                            'url',                ap.url,
+                                            'thumbnail_url',      ap.thumbnail_url,
+                                            'thumbnail_webp_url', ap.thumbnail_webp_url,
+                                            'width',              ap.width,
+                                            'height',             ap.height
+                                        )
+                                    )
+                                    FROM answer_photos AS ap
+                                    WHERE ap.answer_id = a.id AND ap.reported = false AND ap.verify_status <> 'broken'
+                                ))
+                            )
+                        )
+                        FROM answers a
+                        WHERE a.question_id = q.id AND a.reported = false
+                    ))
+                )
+            ) AS results,
+            (
+                SELECT CAST(strftime('%s', MAX(
+                    MAX(
+                        COALESCE(mq.updated_at, mq.date_written),
+                        COALESCE((
+                            SELECT MAX(COALESCE(ma.updated_at, ma.date_written))
+                            FROM answers ma
+                            WHERE ma.question_id = mq.id
+                        ), '')
+                    )
+                )) AS INTEGER)
+                FROM questions mq
+                WHERE mq.product_id = ?1
+            ) AS last_modified
+        FROM (
+            SELECT *
+            FROM questions
+            WHERE product_id = ?1 AND reported = false
+            ORDER BY
+                CASE WHEN ?4 = 'helpful' THEN helpful END DESC,
+                CASE WHEN ?4 = 'newest' THEN date_written END DESC,
+                CASE WHEN ?4 = 'newest' THEN id END DESC,
+                id
+            LIMIT ?2 OFFSET ?3
+        ) AS q;
+        "#,
+    )
+    .bind(product_id)
+    .bind(limit)
+    .bind(offset)
+    .bind(sort.as_str())
+    .fetch_optional(pool)
+    .await
+    .inspect_err(|e| log_error("Failed to fetch data from the database", e))?;
+
+    Ok(row.unwrap_or_else(|| ("[]".to_string(), None)))
+}
+
+/// SQLite counterpart of the query behind [`crate::handlers::get_questions_batch`]. Product
+/// ids are bound as a JSON array.
+pub async fn query_questions_batch(pool: &SqlitePool, product_ids: &[i32], top: i32, sort: QuestionSort) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT
+            json_group_array(
+                json_object(
+                    'product_id', p.product_id,
+                    'question_count', (
+                        SELECT COUNT(*)
+                        FROM questions
+                        WHERE product_id = p.product_id AND reported = false
+                    ),
+                    'questions', json((
+                        SELECT json_group_array(
+                            json_object(
+                                'question_id',          q.id,
+                                'question_body',        q.body,
+                                'question_date',        q.date_written,
+                                'asker_name',           q.asker_name,
+                                'question_helpfulness', q.helpful,
+                                'reported',             json(CASE WHEN q.reported THEN 'true' ELSE 'false' END),
+                                'answers', json((
+                                    SELECT json_group_object(
+                                        CAST(a.id AS TEXT),
+                                        json_object(
+                                            'id',            a.id,
+                                            'body',          a.body,
+                                            'date',          a.date_written,
+                                            'answerer_name', a.answerer_name,
+                                            'helpfulness',   a.helpful,
+                                            'photos', json((
+                                                SELECT json_group_array(
+                                                    json_object(
+                                                        'id',                 ap.id,
+                                                        'url',                ap.url,
+                                                        'thumbnail_url',      ap.thumbnail_url,
+                                                        'thumbnail_webp_url', ap.thumbnail_webp_url,
+                                                        'width',              ap.width,
+                                                        'height',             ap.height
+                                                    )
+                                                )
+                                                FROM answer_photos AS ap
+                                                WHERE ap.answer_id = a.id AND ap.reported = false AND ap.verify_status <> 'broken'
+                                            ))
+                                        )
+                                    )
+                                    FROM answers a
+                                    WHERE a.question_id = q.id AND a.reported = false
+                                ))
+                            )
+                        )
+                        FROM (
+                            SELECT *
+                            FROM questions
+                            WHERE product_id = p.product_id AND reported = false
+                            ORDER BY
+                                CASE WHEN ?3 = 'helpful' THEN helpful END DESC,
+                                CASE WHEN ?3 = 'newest' THEN date_written END DESC,
+                                CASE WHEN ?3 = 'newest' THEN id END DESC,
+                                id
+                            LIMIT ?2
+                        ) AS q
+                    ))
+                )
+            ) AS results
+        FROM (
+            SELECT CAST(value AS INTEGER) AS product_id
+            FROM json_each(?1)
+            ORDER BY key
+        ) AS p;
+        "#,
+    )
+    .bind(serde_json::to_string(product_ids).unwrap())
+    .bind(top as i64)
+    .bind(sort.as_str())
+    .fetch_one(pool)
+    .await
+    .inspect_err(|e| log_error("Failed to fetch data from the database", e))
+}
+
+/// SQLite counterpart of the query behind [`crate::handlers::search_questions`].
+///
+/// Uses the FTS5 indexes, which only understand plain terms: every word of `query` has to
+/// match, and phrases, `or` and `-` are not supported.
+pub async fn query_search(pool: &SqlitePool, product_id: i32, query: &str, page: i32, count: i32) -> Result<String, sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    sqlx::query_scalar(
+        r#"
+        WITH matches AS (
+            SELECT q.id AS question_id, bm25(questions_fts) AS rank
+            FROM questions_fts
+            JOIN questions q ON q.id = questions_fts.rowid
+            WHERE questions_fts MATCH ?2 AND q.product_id = ?1 AND q.reported = false
+            UNION ALL
+            SELECT a.question_id, bm25(answers_fts)
+            FROM answers_fts
+            JOIN answers a ON a.id = answers_fts.rowid
+            JOIN questions q ON q.id = a.question_id
+            WHERE answers_fts MATCH ?2 AND q.product_id = ?1 AND q.reported = false AND a.reported = false
+        ),
+        ranked AS (
+            SELECT question_id, MIN(rank) AS rank
+            FROM matches
+            GROUP BY question_id
+            ORDER BY rank, question_id
+            LIMIT ?3 OFFSET ?4
+        )
+        SELECT
+            json_group_array(
+                json_object(
+                    'question_id',          q.id,
+                    'question_body',        q.body,
+                    'question_date',        q.date_written,
+                    'asker_name',           q.asker_name,
+                    'question_helpfulness', q.helpful,
+                    'reported',             json(CASE WHEN q.reported THEN 'true' ELSE 'false' END),
+                    'question_snippet', (
+                        SELECT snippet(questions_fts, 0, '<mark>', '</mark>', ' … ', 30)
+                        FROM questions_fts
+                        WHERE questions_fts MATCH ?2 AND questions_fts.rowid = q.id
+                    ),
+                    'answers', json((
+                        SELECT json_group_object(
+                            CAST(a.id AS TEXT),
+                            json_object(
+                                'id',            a.id,
+                                'body',          a.body,
+                                'date',          a.date_written,
+                                'answerer_name', a.answerer_name,
+                                'helpfulness',   a.helpful,
+                                'snippet', (
+                                    SELECT snippet(answers_fts, 0, '<mark>', '</mark>', ' … ', 30)
+                                    FROM answers_fts
+                                    WHERE answers_fts MATCH ?2 AND answers_fts.rowid = a.id
+                                ),
+                                'photos', json((
+                                    SELECT json_group_array(
+                                        json_object(
+                                            'id',                 ap.id,
+                                            'url',                ap.url,
+                                            'thumbnail_url',      ap.thumbnail_url,
+                                            'thumbnail_webp_url', ap.thumbnail_webp_url,
+                                            'width',              ap.width,
+                                            'height',             ap.height
+                                        )
+                                    )
+                                    FROM answer_photos AS ap
+                                    WHERE ap.answer_id = a.id AND ap.reported = false AND ap.verify_status <> 'broken'
+                                ))
+                            )
+                        )
+                        FROM answers a
+                        WHERE a.question_id = q.id AND a.reported = false
+                    ))
+                )
+            ) AS results
+        FROM (
+            SELECT q.*
+            FROM ranked r
+            JOIN questions q ON q.id = r.question_id
+            ORDER BY r.rank, q.id
+        ) AS q;
+        "#,
+    )
+    .bind(product_id)
+    .bind(fts_query(query))
+    .bind(limit)
+    .bind(offset)
+    .fetch_one(pool)
+    .await
+    .inspect_err(|e| log_error("Failed to search questions", e))
+}
+
+/// Quotes every word of a search so FTS5 reads none of it as query syntax.
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// SQLite counterpart of the query behind [`crate::handlers::get_qa_stats`].
+pub async fn query_qa_stats(pool: &SqlitePool, product_id: i32) -> Result<QaStats, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        WITH q AS (
+            SELECT id, body, helpful, date_written
+            FROM questions
+            WHERE product_id = ?1 AND reported = false
+        ),
+        a AS (
+            SELECT answers.id, answers.question_id, answers.date_written
+            FROM answers
+            JOIN q ON q.id = answers.question_id
+            WHERE answers.reported = false
+        ),
+        top AS (
+            SELECT id, body, helpful
+            FROM q
+            WHERE helpful > 0
+            ORDER BY helpful DESC, id
+            LIMIT 1
+        )
+        SELECT
+            (SELECT COUNT(*) FROM q) AS question_count,
+            (SELECT COUNT(DISTINCT question_id) FROM a) AS answered_count,
+            (SELECT COUNT(*) FROM a) AS answer_count,
+            (SELECT COUNT(*) FROM answer_photos AS ap JOIN a ON a.id = ap.answer_id WHERE ap.reported = false AND ap.verify_status <> 'broken') AS photo_count,
+            NULLIF(MAX(COALESCE((SELECT MAX(date_written) FROM q), ''), COALESCE((SELECT MAX(date_written) FROM a), '')), '') AS last_activity,
+            (SELECT id FROM top) AS top_question_id,
+            (SELECT body FROM top) AS top_question_body,
+            (SELECT helpful FROM top) AS top_question_helpfulness;
+        "#,
+    )
+    .bind(product_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// SQLite counterpart of the query behind [`crate::handlers::get_answers`].
+pub async fn query_answers(pool: &SqlitePool, question_id: i32, page: i32, count: i32) -> Result<(String, Option<i64>), sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    let row: Option<(String, Option<i64>)> = sqlx::query_as(
+        r#"
+        SELECT
+            json_group_array(
+                json_object(
+                    'answer_id',     a.id,
+                    'body',          a.body,
+                    'date',          a.date_written,
+                    'answerer_name', a.answerer_name,
+                    'helpfulness',   a.helpful,
+                    'photos', json((
+                        SELECT json_group_array(
+                            json_object(
+                                'id',                 ap.id,
+                                'url',                ap.url,
+                                'thumbnail_url',      ap.thumbnail_url,
+                                'thumbnail_webp_url', ap.thumbnail_webp_url,
+                                'width',              ap.width,
+                                'height',             ap.height
+                            )
+                        )
+                        FROM answer_photos ap
+                        WHERE ap.answer_id = a.id AND ap.reported = false AND ap.verify_status <> 'broken'
+                    ))
+                )
+            ) AS results,
+            (
+                SELECT CAST(strftime('%s', MAX(COALESCE(ma.updated_at, ma.date_written))) AS INTEGER)
+                FROM answers ma
+                WHERE ma.question_id = ?1
+            ) AS last_modified
+        FROM (
+            SELECT *
+            FROM answers
+            WHERE question_id = ?1 AND reported = false
+            ORDER BY id
+            LIMIT ?2 OFFSET ?3
+        ) AS a;
+        "#,
+    )
+    .bind(question_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_optional(pool)
+    .await
+    .inspect_err(|e| log_error("Failed to fetch data from the database", e))?;
+
+    Ok(row.unwrap_or_else(|| ("[]".to_string(), None)))
+}
+
+pub async fn add_question(pool: &SqlitePool, cache: &QuestionsCache, question_data: NewQuestion, email: &SealedEmail) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = insert_question(pool, &question_data, email).await;
+
+    match result {
+        Ok(question_id) => {
+            cache.invalidate(question_data.product_id);
+
+            let response = serde_json::json!({ "question_id": question_id });
+            create_success_response(StatusCode::CREATED, response)
+        }
+        Err(e) => {
+            log_error("Failed to add question", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add question".into())
+        }
+    }
+}
+
+/// SQLite counterpart of the insert behind [`crate::handlers::add_question`].
+async fn insert_question(pool: &SqlitePool, question_data: &NewQuestion, email: &SealedEmail) -> Result<i32, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let question_id: i32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO questions (product_id, body, date_written, asker_name, asker_email, asker_email_hash, reported, helpful)
+        VALUES (?1, ?2, strftime('%Y-%m-%dT%H:%M:%f', 'now'), ?3, ?4, ?5, false, 0)
+        RETURNING id;
+        "#,
+    )
+    .bind(question_data.product_id)
+    .bind(&question_data.body)
+    .bind(&question_data.name)
+    .bind(&email.stored)
+    .bind(&email.hash)
+    .fetch_one(&mut tx)
+    .await?;
+    record_event(&mut tx, Event::QuestionCreated { question_id, product_id: question_data.product_id }).await?;
+
+    tx.commit().await?;
+    Ok(question_id)
+}
+
+pub async fn add_answer(pool: &SqlitePool, cache: &QuestionsCache, question_id: i32, answer_data: NewAnswer, email: &SealedEmail, notify: bool) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = insert_answer(pool, question_id, &answer_data, email, notify).await;
+
+    match result {
+        Ok((answer_id, product_id)) => {
+            cache.invalidate(product_id);
+
+            let response = serde_json::json!({ "answer_id": answer_id });
+            create_success_response(StatusCode::CREATED, response)
+        }
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+            create_error_response(StatusCode::NOT_FOUND, "Question not found".into())
+        }
+        Err(e) => {
+            log_error("Failed to add answer", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add answer".into())
+        }
+    }
+}
+
+/// SQLite counterpart of the insert behind [`crate::handlers::add_answer`].
+async fn insert_answer(pool: &SqlitePool, question_id: i32, answer_data: &NewAnswer, email: &SealedEmail, notify: bool) -> Result<(i32, i32), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // The foreign key is only checked once the insert has run, so the question's product is
+    // looked up after it rather than returned by it.
+    let answer_id = sqlx::query(
+        r#"
+        INSERT INTO answers (question_id, body, date_written, answerer_name, answerer_email, answerer_email_hash, reported, helpful)
+        VALUES (?1, ?2, strftime('%Y-%m-%dT%H:%M:%f', 'now'), ?3, ?4, ?5, false, 0);
+        "#,
+    )
+    .bind(question_id)
+    .bind(&answer_data.body)
+    .bind(&answer_data.name)
+    .bind(&email.stored)
+    .bind(&email.hash)
+    .execute(&mut tx)
+    .await?
+    .last_insert_rowid() as i32;
+    let product_id: i32 = sqlx::query_scalar("SELECT product_id FROM questions WHERE id = ?1;")
+        .bind(question_id)
+        .fetch_one(&mut tx)
+        .await?;
+
+    for url in &answer_data.photos {
+        sqlx::query(
+            r#"
+            INSERT INTO answer_photos (answer_id, url)
+            VALUES (?1, ?2);
+            "#,
+        )
+        .bind(answer_id)
+        .bind(url)
+        .execute(&mut tx)
+        .await?;
+    }
+    record_event(&mut tx, Event::AnswerCreated { answer_id, question_id, product_id }).await?;
+
+    if notify {
+        sqlx::query(
+            r#"
+            INSERT INTO answer_notifications (question_id, answer_id)
+            SELECT id, ?2
+            FROM questions
+            WHERE id = ?1 AND NOT COALESCE(asker_email_hash = ?3 OR (?3 IS NULL AND lower(asker_email) = lower(?4)), false);
+            "#,
+        )
+        .bind(question_id)
+        .bind(answer_id)
+        .bind(&email.hash)
+        .bind(&email.stored)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok((answer_id, product_id))
+}
+
+/// SQLite counterpart of [`crate::outbox::record`].
+pub async fn record_event(tx: &mut Transaction<'_, Sqlite>, event: Event) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO outbox_events (event_type, payload) VALUES (?1, ?2);")
+        .bind(event.event_type())
+        .bind(event.payload().to_string())
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+/// SQLite counterpart of the vote bookkeeping behind [`crate::handlers::update_question_helpful`].
+pub async fn set_question_vote(pool: &SqlitePool, question_id: i32, voter: &str, helpful: bool) -> Result<Option<(i32, bool)>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Writing the vote first takes the database's write lock for the rest of the transaction
+    let result = if helpful {
+        sqlx::query("INSERT INTO question_votes (question_id, voter) VALUES (?1, ?2) ON CONFLICT DO NOTHING;")
+    } else {
+        sqlx::query("DELETE FROM question_votes WHERE question_id = ?1 AND voter = ?2;")
+    }
+    .bind(question_id)
+    .bind(voter)
+    .execute(&mut tx)
+    .await;
+
+    let changed = match result {
+        Ok(done) => done.rows_affected() > 0,
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let product_id: Option<i32> = sqlx::query_scalar("SELECT product_id FROM questions WHERE id = ?1;")
+        .bind(question_id)
+        .fetch_optional(&mut tx)
+        .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    if changed {
+        sqlx::query("UPDATE questions SET helpful = helpful + ?2, updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1;")
+            .bind(question_id)
+            .bind(if helpful { 1 } else { -1 })
+            .execute(&mut tx)
+            .await?;
+    }
+    if changed && helpful {
+        record_event(&mut tx, Event::QuestionHelpful { question_id, product_id }).await?;
+    }
+
+    tx.commit().await?;
+    Ok(Some((product_id, changed)))
+}
+
+pub async fn update_question_report(pool: &SqlitePool, cache: &QuestionsCache, question_id: i32, reporter: &str, report: NewReport) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = report_question(pool, question_id, reporter, &report).await;
+
+    match result {
+        Ok(Some(product_id)) => {
+            cache.invalidate(product_id);
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Question not found".into()),
+        Err(e) => {
+            log_error("Failed to update question report", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update question report".into())
+        }
+    }
+}
+
+async fn report_question(pool: &SqlitePool, question_id: i32, reporter: &str, report: &NewReport) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query("INSERT INTO question_reports (question_id, reason, note, reporter) VALUES (?1, ?2, ?3, ?4);")
+        .bind(question_id)
+        .bind(report.reason.as_str())
+        .bind(&report.note)
+        .bind(reporter)
+        .execute(&mut tx)
+        .await;
+
+    match result {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let product_id: Option<i32> = sqlx::query_scalar("UPDATE questions SET reported = true, updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1 RETURNING product_id;")
+        .bind(question_id)
+        .fetch_optional(&mut tx)
+        .await?;
+    if let Some(product_id) = product_id {
+        record_event(&mut tx, Event::QuestionReported { question_id, product_id, reason: report.reason }).await?;
+    }
+
+    tx.commit().await?;
+    Ok(product_id)
+}
+
+/// SQLite counterpart of the vote bookkeeping behind [`crate::handlers::update_answer_helpful`].
+pub async fn set_answer_vote(pool: &SqlitePool, answer_id: i32, voter: &str, helpful: bool) -> Result<Option<(i32, bool)>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = if helpful {
+        sqlx::query("INSERT INTO answer_votes (answer_id, voter) VALUES (?1, ?2) ON CONFLICT DO NOTHING;")
+    } else {
+        sqlx::query("DELETE FROM answer_votes WHERE answer_id = ?1 AND voter = ?2;")
+    }
+    .bind(answer_id)
+    .bind(voter)
+    .execute(&mut tx)
+    .await;
+
+    let changed = match result {
+        Ok(done) => done.rows_affected() > 0,
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let product_id: Option<i32> = sqlx::query_scalar("SELECT q.product_id FROM answers AS a JOIN questions AS q ON q.id = a.question_id WHERE a.id = ?1;")
+        .bind(answer_id)
+        .fetch_optional(&mut tx)
+        .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    if changed {
+        sqlx::query("UPDATE answers SET helpful = helpful + ?2, updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1;")
+            .bind(answer_id)
+            .bind(if helpful { 1 } else { -1 })
+            .execute(&mut tx)
+            .await?;
+    }
+    if changed && helpful {
+        record_event(&mut tx, Event::AnswerHelpful { answer_id, product_id }).await?;
+    }
+
+    tx.commit().await?;
+    Ok(Some((product_id, changed)))
+}
+
+pub async fn update_answer_report(pool: &SqlitePool, cache: &QuestionsCache, answer_id: i32, reporter: &str, report: NewReport) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    let result = report_answer(pool, answer_id, reporter, &report).await;
+
+    match result {
+        Ok(Some(product_id)) => {
+            cache.invalidate(product_id);
+            create_success_response(StatusCode::NO_CONTENT, serde_json::Value::Null)
+        }
+        Ok(None) => create_error_response(StatusCode::NOT_FOUND, "Answer not found".into()),
+        Err(e) => {
+            log_error("Failed to update answer report", &e);
+            create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update answer report".into())
+        }
+    }
+}
+
+async fn report_answer(pool: &SqlitePool, answer_id: i32, reporter: &str, report: &NewReport) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query("INSERT INTO answer_reports (answer_id, reason, note, reporter) VALUES (?1, ?2, ?3, ?4);")
+        .bind(answer_id)
+        .bind(report.reason.as_str())
+        .bind(&report.note)
+        .bind(reporter)
+        .execute(&mut tx)
+        .await;
+
+    match result {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let product_id: Option<i32> = sqlx::query_scalar("UPDATE answers SET reported = true, updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1 RETURNING (SELECT product_id FROM questions WHERE questions.id = answers.question_id);")
+        .bind(answer_id)
+        .fetch_optional(&mut tx)
+        .await?;
+    if let Some(product_id) = product_id {
+        record_event(&mut tx, Event::AnswerReported { answer_id, product_id, reason: report.reason }).await?;
+    }
+
+    tx.commit().await?;
+    Ok(product_id)
+}
+
+/// SQLite counterpart of the query behind [`crate::moderation::get_moderation_queue`].
+pub async fn query_moderation_queue(pool: &SqlitePool, page: i32, count: i32) -> Result<Vec<(QueueItem, Vec<PendingReport>)>, sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    let items: Vec<QueueItem> = sqlx::query_as(
+        r#"
+        SELECT *
+        FROM (
+            SELECT
+                'question' AS target_type,
+                q.id,
+                q.product_id,
+                NULL AS question_id,
+                NULL AS answer_id,
+                q.body,
+                COUNT(r.id) AS report_count,
+                MAX(r.reported_at) AS last_reported_at
+            FROM questions AS q
+            LEFT JOIN question_reports AS r ON r.question_id = q.id AND r.resolved_at IS NULL
+            WHERE q.reported = true AND q.removed_at IS NULL
+            GROUP BY q.id
+            UNION ALL
+            SELECT
+                'answer',
+                a.id,
+                q.product_id,
+                a.question_id,
+                NULL,
+                a.body,
+                COUNT(r.id),
+                MAX(r.reported_at)
+            FROM answers AS a
+            JOIN questions AS q ON q.id = a.question_id
+            LEFT JOIN answer_reports AS r ON r.answer_id = a.id AND r.resolved_at IS NULL
+            WHERE a.reported = true AND a.removed_at IS NULL
+            GROUP BY a.id, q.product_id
+            UNION ALL
+            SELECT
+                'photo',
+                ap.id,
+                q.product_id,
+                a.question_id,
+                ap.answer_id,
+                ap.url,
+                COUNT(r.id),
+                MAX(r.reported_at)
+            FROM answer_photos AS ap
+            JOIN answers AS a ON a.id = ap.answer_id
+            JOIN questions AS q ON q.id = a.question_id
+            LEFT JOIN photo_reports AS r ON r.photo_id = ap.id AND r.resolved_at IS NULL
+            WHERE ap.reported = true AND ap.removed_at IS NULL
+            GROUP BY ap.id, a.question_id, q.product_id
+        ) AS queue
+        ORDER BY report_count DESC, last_reported_at IS NULL, last_reported_at DESC, target_type DESC, id
+        LIMIT ?1 OFFSET ?2;
+        "#,
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    let mut results = Vec::with_capacity(items.len());
+    for item in items {
+        let sql = match item.target_type.as_str() {
+            "question" => "SELECT reason, note, reported_at FROM question_reports WHERE question_id = ?1 AND resolved_at IS NULL ORDER BY id;",
+            "answer" => "SELECT reason, note, reported_at FROM answer_reports WHERE answer_id = ?1 AND resolved_at IS NULL ORDER BY id;",
+            _ => "SELECT reason, note, reported_at FROM photo_reports WHERE photo_id = ?1 AND resolved_at IS NULL ORDER BY id;",
+        };
+        let reports = sqlx::query_as(sql).bind(item.id).fetch_all(pool).await?;
+        results.push((item, reports));
+    }
+
+    Ok(results)
+}
+
+/// SQLite counterpart of the updates behind [`crate::moderation::moderate_question`].
+pub async fn apply_question_moderation(pool: &SqlitePool, question_id: i32, action: ModerationAction, decision: &ModerationDecision, moderator: Option<&str>) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let product_id: Option<i32> = sqlx::query_scalar(
+        r#"
+        UPDATE questions
+        SET reported = (?2 = 'remove'),
+            removed_at = CASE WHEN ?2 = 'remove' THEN COALESCE(removed_at, strftime('%Y-%m-%dT%H:%M:%f', 'now')) END,
+            updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now')
+        WHERE id = ?1
+        RETURNING product_id;
+        "#,
+    )
+    .bind(question_id)
+    .bind(action.as_str())
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    let resolved = sqlx::query("UPDATE question_reports SET resolved_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE question_id = ?1 AND resolved_at IS NULL;")
+        .bind(question_id)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+    sqlx::query("INSERT INTO moderation_actions (target_type, target_id, action, moderator, note, reports_resolved) VALUES ('question', ?1, ?2, ?3, ?4, ?5);")
+        .bind(question_id)
+        .bind(action.as_str())
+        .bind(moderator)
+        .bind(&decision.note)
+        .bind(resolved as i32)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Some(product_id))
+}
+
+/// SQLite counterpart of the updates behind [`crate::moderation::moderate_answer`].
+pub async fn apply_answer_moderation(pool: &SqlitePool, answer_id: i32, action: ModerationAction, decision: &ModerationDecision, moderator: Option<&str>) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let product_id: Option<i32> = sqlx::query_scalar(
+        r#"
+        UPDATE answers
+        SET reported = (?2 = 'remove'),
+            removed_at = CASE WHEN ?2 = 'remove' THEN COALESCE(removed_at, strftime('%Y-%m-%dT%H:%M:%f', 'now')) END,
+            updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now')
+        WHERE id = ?1
+        RETURNING (SELECT product_id FROM questions WHERE questions.id = answers.question_id);
+        "#,
+    )
+    .bind(answer_id)
+    .bind(action.as_str())
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(product_id) = product_id else {
+        return Ok(None);
+    };
+
+    let resolved = sqlx::query("UPDATE answer_reports SET resolved_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE answer_id = ?1 AND resolved_at IS NULL;")
+        .bind(answer_id)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+    sqlx::query("INSERT INTO moderation_actions (target_type, target_id, action, moderator, note, reports_resolved) VALUES ('answer', ?1, ?2, ?3, ?4, ?5);")
+        .bind(answer_id)
+        .bind(action.as_str())
+        .bind(moderator)
+        .bind(&decision.note)
+        .bind(resolved as i32)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Some(product_id))
+}
+
+/// SQLite counterpart of the update behind [`crate::moderation::moderate_photo`].
+pub async fn apply_photo_moderation(pool: &SqlitePool, photo_id: i32, action: ModerationAction, decision: &ModerationDecision, moderator: Option<&str>) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let answer_id: Option<i32> = sqlx::query_scalar(
+        r#"
+        UPDATE answer_photos
+        SET reported = (?2 = 'remove'),
+            removed_at = CASE WHEN ?2 = 'remove' THEN COALESCE(removed_at, strftime('%Y-%m-%dT%H:%M:%f', 'now')) END
+        WHERE id = ?1
+        RETURNING answer_id;
+        "#,
+    )
+    .bind(photo_id)
+    .bind(action.as_str())
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(answer_id) = answer_id else {
+        return Ok(None);
+    };
+    let product_id: i32 = sqlx::query_scalar(
+        "UPDATE answers SET updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1 RETURNING (SELECT product_id FROM questions WHERE questions.id = answers.question_id);",
+    )
+    .bind(answer_id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    let resolved = sqlx::query("UPDATE photo_reports SET resolved_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE photo_id = ?1 AND resolved_at IS NULL;")
+        .bind(photo_id)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+    sqlx::query("INSERT INTO moderation_actions (target_type, target_id, action, moderator, note, reports_resolved) VALUES ('photo', ?1, ?2, ?3, ?4, ?5);")
+        .bind(photo_id)
+        .bind(action.as_str())
+        .bind(moderator)
+        .bind(&decision.note)
+        .bind(resolved as i32)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Some(product_id))
+}
+
+/// SQLite counterpart of the query behind [`crate::moderation::get_moderation_actions`].
+pub async fn query_moderation_actions(pool: &SqlitePool, page: i32, count: i32) -> Result<Vec<ModerationRecord>, sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    sqlx::query_as(
+        r#"
+        SELECT id, target_type, target_id, action, moderator, note, reports_resolved, acted_at
+        FROM moderation_actions
+        ORDER BY id DESC
+        LIMIT ?1 OFFSET ?2;
+        "#,
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+}
+
+/// Looks up the name and scopes of an unrevoked API key by its hash.
+pub async fn find_api_key(pool: &SqlitePool, key_hash: &str) -> Result<Option<(String, String)>, sqlx::Error> {
+    sqlx::query_as("SELECT name, scopes FROM api_keys WHERE key_hash = ?1 AND revoked_at IS NULL;")
+        .bind(key_hash)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn insert_api_key(pool: &SqlitePool, name: &str, key_prefix: &str, key_hash: &str, scopes: &str) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar("INSERT INTO api_keys (name, key_prefix, key_hash, scopes) VALUES (?1, ?2, ?3, ?4) RETURNING id;")
+        .bind(name)
+        .bind(key_prefix)
+        .bind(key_hash)
+        .bind(scopes)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn list_api_keys(pool: &SqlitePool) -> Result<Vec<ApiKeyRecord>, sqlx::Error> {
+    sqlx::query_as("SELECT id, name, key_prefix, scopes, created_at, revoked_at FROM api_keys ORDER BY id;")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn revoke_api_key(pool: &SqlitePool, id: i32) -> Result<u64, sqlx::Error> {
+    let done = sqlx::query("UPDATE api_keys SET revoked_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1 AND revoked_at IS NULL;")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(done.rows_affected())
+}
+
+/// The table, email column and hash column behind an [`EmailColumn`].
+fn email_table(column: EmailColumn) -> (&'static str, &'static str, &'static str) {
+    match column {
+        EmailColumn::Asker => ("questions", "asker_email", "asker_email_hash"),
+        EmailColumn::Answerer => ("answers", "answerer_email", "answerer_email_hash"),
+    }
+}
+
+/// SQLite counterpart of the batch query behind [`crate::emails::reencrypt_emails`].
+pub async fn email_batch(pool: &SqlitePool, column: EmailColumn, pattern: &str, after: i32, limit: i64) -> Result<Vec<(i32, String)>, sqlx::Error> {
+    let (table, email, _) = email_table(column);
+    sqlx::query_as(&format!(
+        "SELECT id, {email} FROM {table} WHERE id > ?1 AND {email} IS NOT NULL AND {email} NOT LIKE ?2 ORDER BY id LIMIT ?3;"
+    ))
+    .bind(after)
+    .bind(pattern)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn update_email(pool: &SqlitePool, column: EmailColumn, id: i32, sealed: &SealedEmail) -> Result<(), sqlx::Error> {
+    let (table, email, email_hash) = email_table(column);
+    sqlx::query(&format!("UPDATE {table} SET {email} = ?2, {email_hash} = ?3 WHERE id = ?1;"))
+        .bind(id)
+        .bind(&sealed.stored)
+        .bind(&sealed.hash)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// SQLite counterpart of the queries behind [`crate::privacy::export_data`]. Lookup hashes
+/// are bound as a JSON array.
+pub async fn query_export(pool: &SqlitePool, subject: &Subject) -> Result<(Vec<ExportedQuestion>, Vec<ExportedAnswer>), sqlx::Error> {
+    let hashes = serde_json::to_string(&subject.hashes).unwrap();
+
+    let questions = sqlx::query_as(
+        r#"
+        SELECT id, product_id, body, date_written AS date, asker_name, helpful, reported, removed_at IS NOT NULL AS removed
+        FROM questions
+        WHERE asker_email_hash IN (SELECT value FROM json_each(?1)) OR lower(asker_email) = ?2
+        ORDER BY id;
+        "#,
+    )
+    .bind(&hashes)
+    .bind(&subject.email)
+    .fetch_all(pool)
+    .await?;
+
+    let answers = sqlx::query_as(
+        r#"
+        SELECT
+            a.id,
+            a.question_id,
+            a.body,
+            a.date_written AS date,
+            a.answerer_name,
+            a.helpful,
+            a.reported,
+            a.removed_at IS NOT NULL AS removed,
+            (
+                SELECT json_group_array(json_object('id', p.id, 'url', p.url))
+                FROM (SELECT id, url FROM answer_photos WHERE answer_id = a.id ORDER BY id) AS p
+            ) AS photos
+        FROM answers AS a
+        WHERE a.answerer_email_hash IN (SELECT value FROM json_each(?1)) OR lower(a.answerer_email) = ?2
+        ORDER BY a.id;
+        "#,
+    )
+    .bind(&hashes)
+    .bind(&subject.email)
+    .fetch_all(pool)
+    .await?;
+
+    Ok((questions, answers))
+}
+
+/// SQLite counterpart of the erasure behind [`crate::privacy::erase_data`].
+pub async fn apply_erasure(pool: &SqlitePool, subject: &Subject, policy: ErasurePolicy, fingerprint: &str, requested_by: Option<&str>, reference: Option<&str>) -> Result<(i32, Erasure), sqlx::Error> {
+    let hashes = serde_json::to_string(&subject.hashes).unwrap();
+    let remove = policy == ErasurePolicy::Remove;
+    let mut tx = pool.begin().await?;
+
+    let mut product_ids: Vec<i32> = sqlx::query_scalar(
+        r#"
+        UPDATE questions
+        SET asker_name = ?3,
+            asker_email = NULL,
+            asker_email_hash = NULL,
+            body = CASE WHEN ?4 THEN NULL ELSE body END,
+            reported = CASE WHEN ?4 THEN true ELSE reported END,
+            removed_at = CASE WHEN ?4 THEN COALESCE(removed_at, strftime('%Y-%m-%dT%H:%M:%f', 'now')) ELSE removed_at END,
+            updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now')
+        WHERE asker_email_hash IN (SELECT value FROM json_each(?1)) OR lower(asker_email) = ?2
+        RETURNING product_id;
+        "#,
+    )
+    .bind(&hashes)
+    .bind(&subject.email)
+    .bind(ERASED_NAME)
+    .bind(remove)
+    .fetch_all(&mut tx)
+    .await?;
+    let questions = product_ids.len() as i32;
+
+    let answers: Vec<(i32, i32)> = sqlx::query_as(
+        r#"
+        UPDATE answers
+        SET answerer_name = ?3,
+            answerer_email = NULL,
+            answerer_email_hash = NULL,
+            body = CASE WHEN ?4 THEN NULL ELSE body END,
+            reported = CASE WHEN ?4 THEN true ELSE reported END,
+            removed_at = CASE WHEN ?4 THEN COALESCE(removed_at, strftime('%Y-%m-%dT%H:%M:%f', 'now')) ELSE removed_at END,
+            updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now')
+        WHERE answerer_email_hash IN (SELECT value FROM json_each(?1)) OR lower(answerer_email) = ?2
+        RETURNING id, (SELECT product_id FROM questions WHERE questions.id = answers.question_id);
+        "#,
+    )
+    .bind(&hashes)
+    .bind(&subject.email)
+    .bind(ERASED_NAME)
+    .bind(remove)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let answer_ids: Vec<i32> = answers.iter().map(|(id, _)| *id).collect();
+    product_ids.extend(answers.iter().map(|(_, product_id)| *product_id));
+    product_ids.sort_unstable();
+    product_ids.dedup();
+
+    let photos = if remove {
+        let answer_ids = serde_json::to_string(&answer_ids).unwrap();
+        sqlx::query("DELETE FROM photo_reports WHERE photo_id IN (SELECT id FROM answer_photos WHERE answer_id IN (SELECT value FROM json_each(?1)));")
+            .bind(&answer_ids)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query_as("DELETE FROM answer_photos WHERE answer_id IN (SELECT value FROM json_each(?1)) RETURNING answer_id, url, thumbnail_url, thumbnail_webp_url;")
+            .bind(&answer_ids)
+            .fetch_all(&mut tx)
+            .await?
+            .into_iter()
+            .map(|(answer_id, url, thumbnail_url, thumbnail_webp_url)| (answer_id, [url, thumbnail_url, thumbnail_webp_url]))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let erasure = Erasure { questions, answers: answer_ids.len() as i32, photos, product_ids };
+    let record = NewDataRequest {
+        kind: "erase",
+        subject: fingerprint,
+        content_policy: Some(policy.as_str()),
+        questions: erasure.questions,
+        answers: erasure.answers,
+        photos: erasure.photos.len() as i32,
+        requested_by,
+        reference,
+    };
+    let request_id = insert_data_request(&mut tx, &record).await?;
+
+    tx.commit().await?;
+    Ok((request_id, erasure))
+}
+
+pub async fn insert_data_request<'c>(executor: impl sqlx::SqliteExecutor<'c>, record: &NewDataRequest<'_>) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO data_requests (kind, subject, content_policy, questions, answers, photos, requested_by, reference)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        RETURNING id;
+        "#,
+    )
+    .bind(record.kind)
+    .bind(record.subject)
+    .bind(record.content_policy)
+    .bind(record.questions)
+    .bind(record.answers)
+    .bind(record.photos)
+    .bind(record.requested_by)
+    .bind(record.reference)
+    .fetch_one(executor)
+    .await
+}
+
+/// SQLite counterpart of the query behind [`crate::privacy::get_data_requests`].
+pub async fn query_data_requests(pool: &SqlitePool, page: i32, count: i32) -> Result<Vec<DataRequestRecord>, sqlx::Error> {
+    let limit = count as i64;
+    let offset = (page as i64 - 1) * limit;
+
+    sqlx::query_as(
+        r#"
+        SELECT id, kind, subject, content_policy, questions, answers, photos, requested_by, reference, requested_at
+        FROM data_requests
+        ORDER BY id DESC
+        LIMIT ?1 OFFSET ?2;
+        "#,
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+}
+
+/// SQLite counterpart of [`crate::loadtest::load_ids`].
+pub async fn load_test_ids(pool: &SqlitePool, kind: IdKind) -> Result<Vec<i32>, sqlx::Error> {
+    let sql = match kind {
+        IdKind::Product => "SELECT DISTINCT product_id FROM questions WHERE reported = false ORDER BY product_id;",
+        IdKind::Question => "SELECT id FROM questions WHERE reported = false ORDER BY id;",
+    };
+    sqlx::query_scalar(sql).fetch_all(pool).await
+}
+
+/// SQLite counterpart of [`crate::loadtest::purge_load_test_data`].
+pub async fn purge_load_test_data(pool: &SqlitePool, pattern: &str) -> Result<PurgeReport, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let question_ids: Vec<i32> = sqlx::query_scalar(r#"SELECT id FROM questions WHERE asker_name = ?1 AND body LIKE ?2 ESCAPE '\';"#)
+        .bind(LOAD_TEST_NAME)
+        .bind(pattern)
+        .fetch_all(&mut tx)
+        .await?;
+    let question_ids = serde_json::to_string(&question_ids).unwrap();
+    let answer_ids: Vec<i32> = sqlx::query_scalar(
+        r#"SELECT id FROM answers WHERE question_id IN (SELECT value FROM json_each(?1)) OR (answerer_name = ?2 AND body LIKE ?3 ESCAPE '\');"#,
+    )
+    .bind(&question_ids)
+    .bind(LOAD_TEST_NAME)
+    .bind(pattern)
+    .fetch_all(&mut tx)
+    .await?;
+    let answer_ids = serde_json::to_string(&answer_ids).unwrap();
+
+    for sql in [
+        "DELETE FROM answer_notifications WHERE answer_id IN (SELECT value FROM json_each(?1));",
+        "DELETE FROM photo_reports WHERE photo_id IN (SELECT id FROM answer_photos WHERE answer_id IN (SELECT value FROM json_each(?1)));",
+        "DELETE FROM moderation_actions WHERE target_type = 'photo' AND target_id IN (SELECT id FROM answer_photos WHERE answer_id IN (SELECT value FROM json_each(?1)));",
+        "DELETE FROM answer_photos WHERE answer_id IN (SELECT value FROM json_each(?1));",
+        "DELETE FROM answer_votes WHERE answer_id IN (SELECT value FROM json_each(?1));",
+        "DELETE FROM answer_reports WHERE answer_id IN (SELECT value FROM json_each(?1));",
+        "DELETE FROM moderation_actions WHERE target_type = 'answer' AND target_id IN (SELECT value FROM json_each(?1));",
+    ] {
+        sqlx::query(sql).bind(&answer_ids).execute(&mut tx).await?;
+    }
+    let answers = sqlx::query("DELETE FROM answers WHERE id IN (SELECT value FROM json_each(?1));")
+        .bind(&answer_ids)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+    for sql in [
+        "DELETE FROM question_votes WHERE question_id IN (SELECT value FROM json_each(?1));",
+        "DELETE FROM question_reports WHERE question_id IN (SELECT value FROM json_each(?1));",
+        "DELETE FROM moderation_actions WHERE target_type = 'question' AND target_id IN (SELECT value FROM json_each(?1));",
+    ] {
+        sqlx::query(sql).bind(&question_ids).execute(&mut tx).await?;
+    }
+    let questions = sqlx::query("DELETE FROM questions WHERE id IN (SELECT value FROM json_each(?1));")
+        .bind(&question_ids)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+    tx.commit().await?;
+    Ok(PurgeReport { questions, answers })
+}
+
+/// SQLite counterpart of the answer lookup behind [`crate::photos::upload_photos`] and
+/// [`crate::photos::delete_photo`].
+pub async fn query_answer_owner(pool: &SqlitePool, answer_id: i32) -> Result<Option<AnswerOwner>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT q.product_id, a.answerer_email, a.answerer_email_hash
+        FROM answers a
+        JOIN questions q ON q.id = a.question_id
+        WHERE a.id = ?1 AND a.reported = false;
+        "#,
+    )
+    .bind(answer_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// SQLite counterpart of the query behind [`crate::photos::get_photos`].
+pub async fn query_photos(pool: &SqlitePool, answer_id: i32) -> Result<Option<Vec<Photo>>, sqlx::Error> {
+    let found: Option<i32> = sqlx::query_scalar("SELECT id FROM answers WHERE id = ?1 AND reported = false;")
+        .bind(answer_id)
+        .fetch_optional(pool)
+        .await?;
+    if found.is_none() {
+        return Ok(None);
+    }
+
+    let photos = sqlx::query_as(
+        r#"
+        SELECT id, url, thumbnail_url, thumbnail_webp_url, width, height
+        FROM answer_photos
+        WHERE answer_id = ?1 AND reported = false AND verify_status <> 'broken'
+        ORDER BY id;
+        "#,
+    )
+    .bind(answer_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(Some(photos))
+}
+
+/// SQLite counterpart of the insert behind [`crate::photos::upload_photos`].
+pub async fn insert_answer_photos(pool: &SqlitePool, answer_id: i32, urls: &[String]) -> Result<Vec<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut ids = Vec::with_capacity(urls.len());
+    for url in urls {
+        let id = sqlx::query_scalar("INSERT INTO answer_photos (answer_id, url, verify_status) VALUES (?1, ?2, 'ok') RETURNING id;")
+            .bind(answer_id)
+            .bind(url)
+            .fetch_one(&mut tx)
+            .await?;
+        ids.push(id);
+    }
+    sqlx::query("UPDATE answers SET updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1;")
+        .bind(answer_id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(ids)
+}
+
+/// SQLite counterpart of the delete behind [`crate::photos::delete_photo`].
+pub async fn remove_answer_photo(pool: &SqlitePool, answer_id: i32, photo_id: i32) -> Result<Option<[Option<String>; 3]>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM photo_reports WHERE photo_id = (SELECT id FROM answer_photos WHERE id = ?1 AND answer_id = ?2);")
+        .bind(photo_id)
+        .bind(answer_id)
+        .execute(&mut tx)
+        .await?;
+    let removed: Option<(Option<String>, Option<String>, Option<String>)> =
+        sqlx::query_as("DELETE FROM answer_photos WHERE id = ?1 AND answer_id = ?2 RETURNING url, thumbnail_url, thumbnail_webp_url;")
+            .bind(photo_id)
+            .bind(answer_id)
+            .fetch_optional(&mut tx)
+            .await?;
+    let Some((url, thumbnail_url, thumbnail_webp_url)) = removed else {
+        return Ok(None);
+    };
+    sqlx::query("UPDATE answers SET updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1;")
+        .bind(answer_id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some([url, thumbnail_url, thumbnail_webp_url]))
+}
+
+/// SQLite counterpart of the report behind [`crate::photos::update_photo_report`].
+pub async fn report_photo(pool: &SqlitePool, photo_id: i32, reporter: &str, report: &NewReport) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let answer_id: Option<i32> = sqlx::query_scalar("UPDATE answer_photos SET reported = true WHERE id = ?1 RETURNING answer_id;")
+        .bind(photo_id)
+        .fetch_optional(&mut tx)
+        .await?;
+    let Some(answer_id) = answer_id else {
+        return Ok(None);
+    };
+
+    sqlx::query("INSERT INTO photo_reports (photo_id, reason, note, reporter) VALUES (?1, ?2, ?3, ?4);")
+        .bind(photo_id)
+        .bind(report.reason.as_str())
+        .bind(&report.note)
+        .bind(reporter)
+        .execute(&mut tx)
+        .await?;
+
+    let product_id: Option<i32> = sqlx::query_scalar("UPDATE answers SET updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = ?1 RETURNING (SELECT product_id FROM questions WHERE questions.id = answers.question_id);")
+        .bind(answer_id)
+        .fetch_optional(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(product_id)
+}
+
+/// SQLite counterpart of the claim behind [`crate::thumbnails::ThumbnailWorker::process_pending`].
+/// A single connection serves the database, so claims never race.
+pub async fn claim_pending_photos(pool: &SqlitePool, batch: i64, retry_after: i64, backfill: bool) -> Result<Vec<PendingPhoto>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let photos: Vec<PendingPhoto> = sqlx::query_as(
+        r#"
+        SELECT ap.id, ap.answer_id, q.product_id, ap.url, ap.variants_attempts + 1 AS attempts
+        FROM answer_photos ap
+        JOIN answers a ON a.id = ap.answer_id
+        JOIN questions q ON q.id = a.question_id
+        WHERE (ap.variants_status = 'pending' OR (?3 AND ap.variants_status = 'backfill')) AND ap.verify_status = 'ok'
+            AND (ap.variants_claimed_at IS NULL OR ap.variants_claimed_at <= strftime('%Y-%m-%dT%H:%M:%f', 'now', '-' || ?2 || ' seconds'))
+        ORDER BY ap.variants_status = 'backfill', ap.id
+        LIMIT ?1;
+        "#,
+    )
+    .bind(batch)
+    .bind(retry_after)
+    .bind(backfill)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let ids = serde_json::to_string(&photos.iter().map(|photo| photo.id).collect::<Vec<_>>()).unwrap();
+    sqlx::query(
+        r#"
+        UPDATE answer_photos
+        SET variants_attempts = variants_attempts + 1, variants_claimed_at = strftime('%Y-%m-%dT%H:%M:%f', 'now')
+        WHERE id IN (SELECT value FROM json_each(?1));
+        "#,
+    )
+    .bind(&ids)
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(photos)
+}
+
+/// SQLite counterpart of the update recording the variants of a photo.
+pub async fn record_photo_variants(pool: &SqlitePool, photo_id: i32, variants: &StoredVariants) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let done = sqlx::query(
+        r#"
+        UPDATE answer_photos
+        SET thumbnail_url = ?2, thumbnail_webp_url = ?3, width = ?4, height = ?5,
+            variants_status = 'ready', variants_claimed_at = NULL
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(photo_id)
+    .bind(&variants.thumbnail_url)
+    .bind(&variants.thumbnail_webp_url)
+    .bind(variants.width)
+    .bind(variants.height)
+    .execute(&mut tx)
+    .await?;
+    if done.rows_affected() == 0 {
+        return Ok(false);
+    }
+    sqlx::query("UPDATE answers SET updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = (SELECT answer_id FROM answer_photos WHERE id = ?1);")
+        .bind(photo_id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// SQLite counterpart of the update giving up on the variants of a photo.
+pub async fn mark_photo_variants_failed(pool: &SqlitePool, photo_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE answer_photos SET variants_status = 'failed', variants_claimed_at = NULL WHERE id = ?1;")
+        .bind(photo_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// SQLite counterpart of the claim behind [`crate::verification::PhotoVerifier::process_pending`].
+/// A single connection serves the database, so claims never race.
+pub async fn claim_unverified_photos(pool: &SqlitePool, batch: i64, retry_after: i64) -> Result<Vec<PendingPhoto>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let photos: Vec<PendingPhoto> = sqlx::query_as(
+        r#"
+        SELECT ap.id, ap.answer_id, q.product_id, ap.url, ap.verify_attempts + 1 AS attempts
+        FROM answer_photos ap
+        JOIN answers a ON a.id = ap.answer_id
+        JOIN questions q ON q.id = a.question_id
+        WHERE ap.verify_status = 'pending'
+            AND (ap.verify_claimed_at IS NULL OR ap.verify_claimed_at <= strftime('%Y-%m-%dT%H:%M:%f', 'now', '-' || ?2 || ' seconds'))
+        ORDER BY ap.id
+        LIMIT ?1;
+        "#,
+    )
+    .bind(batch)
+    .bind(retry_after)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let ids = serde_json::to_string(&photos.iter().map(|photo| photo.id).collect::<Vec<_>>()).unwrap();
+    sqlx::query(
+        r#"
+        UPDATE answer_photos
+        SET verify_attempts = verify_attempts + 1, verify_claimed_at = strftime('%Y-%m-%dT%H:%M:%f', 'now')
+        WHERE id IN (SELECT value FROM json_each(?1));
+        "#,
+    )
+    .bind(&ids)
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(photos)
+}
+
+/// SQLite counterpart of the update marking a photo as verified.
+pub async fn mark_photo_verified(pool: &SqlitePool, photo_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE answer_photos SET verify_status = 'ok', verify_error = NULL, verified_at = strftime('%Y-%m-%dT%H:%M:%f', 'now'), verify_claimed_at = NULL WHERE id = ?1;",
+    )
+    .bind(photo_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// SQLite counterpart of the update flagging a photo as broken.
+pub async fn mark_photo_broken(pool: &SqlitePool, photo_id: i32, error: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "UPDATE answer_photos SET verify_status = 'broken', verify_error = ?2, verified_at = strftime('%Y-%m-%dT%H:%M:%f', 'now'), verify_claimed_at = NULL WHERE id = ?1;",
+    )
+    .bind(photo_id)
+    .bind(error)
+    .execute(&mut tx)
+    .await?;
+    sqlx::query("UPDATE answers SET updated_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id = (SELECT answer_id FROM answer_photos WHERE id = ?1);")
+        .bind(photo_id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn insert_webhook(pool: &SqlitePool, url: &str, secret: &str, events: &str) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar("INSERT INTO webhooks (url, secret, events) VALUES (?1, ?2, ?3) RETURNING id;")
+        .bind(url)
+        .bind(secret)
+        .bind(events)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn list_webhooks(pool: &SqlitePool) -> Result<Vec<WebhookRecord>, sqlx::Error> {
+    sqlx::query_as("SELECT id, url, events, created_at FROM webhooks ORDER BY id;")
+        .fetch_all(pool)
+        .await
+}
+
+/// SQLite counterpart of [`crate::webhooks::delete_webhook`]. Foreign keys are enforced, so
+/// deliveries and dead letters go with their webhook.
+pub async fn delete_webhook(pool: &SqlitePool, id: i32) -> Result<u64, sqlx::Error> {
+    let done = sqlx::query("DELETE FROM webhooks WHERE id = ?1;").bind(id).execute(pool).await?;
+    Ok(done.rows_affected())
+}
+
+pub async fn list_dead_letters(pool: &SqlitePool) -> Result<Vec<DeadLetter>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT l.id, l.event_id, e.event_type, l.webhook_id, l.attempts, l.last_error, l.failed_at
+        FROM webhook_dead_letters AS l
+        JOIN outbox_events AS e ON e.id = l.event_id
+        ORDER BY l.id;
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// SQLite counterpart of [`crate::webhooks::redeliver`].
+pub async fn redeliver_dead_letter(pool: &SqlitePool, id: i64) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let done = sqlx::query("INSERT INTO webhook_deliveries (event_id, webhook_id) SELECT event_id, webhook_id FROM webhook_dead_letters WHERE id = ?1;")
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("DELETE FROM webhook_dead_letters WHERE id = ?1;").bind(id).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(done.rows_affected())
+}
+
+/// SQLite counterpart of the fan-out behind [`crate::webhooks::WebhookDispatcher::process_pending`].
+pub async fn fan_out_events(pool: &SqlitePool, batch: i64) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM outbox_events WHERE dispatched_at IS NULL ORDER BY id LIMIT ?1;")
+        .bind(batch)
+        .fetch_all(&mut tx)
+        .await?;
+    let ids = serde_json::to_string(&ids).unwrap();
+
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_deliveries (event_id, webhook_id)
+        SELECT e.id, w.id
+        FROM outbox_events AS e
+        JOIN webhooks AS w ON w.events = '' OR ',' || w.events || ',' LIKE '%,' || e.event_type || ',%'
+        WHERE e.id IN (SELECT value FROM json_each(?1))
+        ORDER BY e.id, w.id;
+        "#,
+    )
+    .bind(&ids)
+    .execute(&mut tx)
+    .await?;
+    sqlx::query("UPDATE outbox_events SET dispatched_at = strftime('%Y-%m-%dT%H:%M:%f', 'now') WHERE id IN (SELECT value FROM json_each(?1));")
+        .bind(&ids)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// SQLite counterpart of the claim behind [`crate::webhooks::WebhookDispatcher::process_pending`].
+/// A single connection serves the database, so claims never race.
+pub async fn claim_due_deliveries(pool: &SqlitePool, batch: i64, retry_after: i64) -> Result<Vec<PendingDelivery>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let deliveries: Vec<PendingDelivery> = sqlx::query_as(
+        r#"
+        SELECT d.id, d.event_id, d.attempts + 1 AS attempts, w.url, w.secret, e.event_type, e.payload, e.created_at
+        FROM webhook_deliveries AS d
+        JOIN webhooks AS w ON w.id = d.webhook_id
+        JOIN outbox_events AS e ON e.id = d.event_id
+        WHERE d.delivered_at IS NULL AND d.next_attempt_at <= strftime('%Y-%m-%dT%H:%M:%f', 'now')
+            AND (d.claimed_at IS NULL OR d.claimed_at <= strftime('%Y-%m-%dT%H:%M:%f', 'now', '-' || ?2 || ' seconds'))
+        ORDER BY d.id
+        LIMIT ?1;
+        "#,
+    )
+    .bind(batch)
+    .bind(retry_after)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let ids = serde_json::to_string(&deliveries.iter().map(|delivery| delivery.id).collect::<Vec<_>>()).unwrap();
+    sqlx::query(
+        r#"
+        UPDATE webhook_deliveries
+        SET attempts = attempts + 1, claimed_at = strftime('%Y-%m-%dT%H:%M:%f', 'now')
+        WHERE id IN (SELECT value FROM json_each(?1));
+        "#,
+    )
+    .bind(&ids)
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(deliveries)
+}
+
+pub async fn mark_delivered(pool: &SqlitePool, delivery_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE webhook_deliveries SET delivered_at = strftime('%Y-%m-%dT%H:%M:%f', 'now'), claimed_at = NULL, last_error = NULL WHERE id = ?1;")
+        .bind(delivery_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn reschedule_delivery(pool: &SqlitePool, delivery_id: i64, delay: i64, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE webhook_deliveries
+        SET next_attempt_at = strftime('%Y-%m-%dT%H:%M:%f', 'now', '+' || ?2 || ' seconds'), claimed_at = NULL, last_error = ?3
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(delivery_id)
+    .bind(delay)
+    .bind(error)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// SQLite counterpart of the move of a delivery to the dead letters.
+pub async fn dead_letter_delivery(pool: &SqlitePool, delivery_id: i64, error: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT INTO webhook_dead_letters (event_id, webhook_id, attempts, last_error) SELECT event_id, webhook_id, attempts, ?2 FROM webhook_deliveries WHERE id = ?1;")
+        .bind(delivery_id)
+        .bind(error)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("DELETE FROM webhook_deliveries WHERE id = ?1;").bind(delivery_id).execute(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// SQLite counterpart of the claim behind [`crate::notifications::AnswerNotifier::process_pending`].
+/// A single connection serves the database, so claims never race.
+pub async fn claim_due_notifications(pool: &SqlitePool, batch: i64, delay: i64, retry_after: i64) -> Result<Vec<PendingNotification>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let notifications: Vec<PendingNotification> = sqlx::query_as(
+        r#"
+        WITH pending AS (
+            SELECT n.id, COALESCE(q.asker_email_hash, lower(q.asker_email), '') AS recipient, n.created_at
+            FROM answer_notifications AS n
+            JOIN questions AS q ON q.id = n.question_id
+            WHERE n.status = 'pending' AND n.next_attempt_at <= strftime('%Y-%m-%dT%H:%M:%f', 'now')
+                AND (n.claimed_at IS NULL OR n.claimed_at <= strftime('%Y-%m-%dT%H:%M:%f', 'now', '-' || ?3 || ' seconds'))
+        ), due AS (
+            SELECT recipient
+            FROM pending
+            GROUP BY recipient
+            HAVING MIN(created_at) <= strftime('%Y-%m-%dT%H:%M:%f', 'now', '-' || ?2 || ' seconds')
+            ORDER BY MIN(created_at)
+            LIMIT ?1
+        )
+        SELECT
+            n.id,
+            p.recipient,
+            n.attempts + 1 AS attempts,
+            n.question_id,
+            q.body AS question_body,
+            q.asker_name,
+            q.asker_email,
+            q.reported AS question_reported,
+            a.body AS answer_body,
+            a.answerer_name,
+            a.reported AS answer_reported
+        FROM pending AS p
+        JOIN answer_notifications AS n ON n.id = p.id
+        JOIN questions AS q ON q.id = n.question_id
+        JOIN answers AS a ON a.id = n.answer_id
+        WHERE p.recipient IN (SELECT recipient FROM due)
+        ORDER BY n.id;
+        "#,
+    )
+    .bind(batch)
+    .bind(delay)
+    .bind(retry_after)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let ids = serde_json::to_string(&notifications.iter().map(|notification| notification.id).collect::<Vec<_>>()).unwrap();
+    sqlx::query(
+        r#"
+        UPDATE answer_notifications
+        SET attempts = attempts + 1, claimed_at = strftime('%Y-%m-%dT%H:%M:%f', 'now')
+        WHERE id IN (SELECT value FROM json_each(?1));
+        "#,
+    )
+    .bind(&ids)
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(notifications)
+}
+
+pub async fn reschedule_notifications(pool: &SqlitePool, ids: &[i64], delay: i64, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE answer_notifications
+        SET next_attempt_at = strftime('%Y-%m-%dT%H:%M:%f', 'now', '+' || ?2 || ' seconds'), claimed_at = NULL, last_error = ?3
+        WHERE id IN (SELECT value FROM json_each(?1));
+        "#,
+    )
+    .bind(serde_json::to_string(ids).unwrap())
+    .bind(delay)
+    .bind(error)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn finish_notifications(pool: &SqlitePool, ids: &[i64], status: &str, reason: Option<&str>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE answer_notifications
+        SET status = ?2, last_error = ?3, claimed_at = NULL,
+            sent_at = CASE WHEN ?2 = 'sent' THEN strftime('%Y-%m-%dT%H:%M:%f', 'now') END
+        WHERE id IN (SELECT value FROM json_each(?1));
+        "#,
+    )
+    .bind(serde_json::to_string(ids).unwrap())
+    .bind(status)
+    .bind(reason)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn is_opted_out(pool: &SqlitePool, address_hash: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM email_opt_outs WHERE address_hash = ?1);")
+        .bind(address_hash)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn opt_out(pool: &SqlitePool, address_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO email_opt_outs (address_hash) VALUES (?1) ON CONFLICT DO NOTHING;")
+        .bind(address_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use crate::auth::AuthConfig;
+use crate::cache::QuestionsCache;
+use crate::capture::RequestCapture;
+use crate::conditional::CacheControl;
+use crate::db::Database;
+use crate::emails::EmailProtector;
+use crate::fetch::PhotoFetcher;
+use crate::notifications::Notifications;
+use crate::proxy::TrustedProxies;
+use crate::ratelimit::RateLimiter;
+use crate::storage::PhotoStorage;
+use crate::votes::VoterHasher;
+
+/// State shared by every connection, handed to the router behind an `Arc`.
+pub struct AppState {
+    pub db: Database,
+    pub questions_cache: QuestionsCache,
+    pub cache_control: CacheControl,
+    pub voters: VoterHasher,
+    pub emails: EmailProtector,
+    pub auth: AuthConfig,
+    pub trusted_proxies: TrustedProxies,
+    pub rate_limiter: RateLimiter,
+    pub capture: RequestCapture,
+    pub photos: PhotoStorage,
+    pub photo_fetcher: PhotoFetcher,
+    pub notifications: Notifications,
+}
@@ -0,0 +1,329 @@
+use crate::utils::hex;
+use hmac::{Hmac, Mac};
+use hyper::body::Bytes;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Largest photo accepted by default, in bytes.
+pub const DEFAULT_MAX_PHOTO_BYTES: usize = 5 * 1024 * 1024;
+
+/// Where uploaded photos are kept, and the URLs they are served from.
+///
+/// Photos are stored under keys such as `answers/12/3f9c0e1a2b4d5c6e.jpg`, made only of
+/// lowercase letters, digits, `/`, `.` and `_`, so keys never need escaping in paths or URLs.
+pub struct PhotoStorage {
+    pub backend: StorageBackend,
+    /// Largest photo accepted, in bytes.
+    pub max_bytes: usize,
+}
+
+/// The store behind [`PhotoStorage`].
+pub enum StorageBackend {
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+/// Why a photo could not be stored or read.
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    Http(reqwest::Error),
+    /// The object store answered with an error status.
+    Status(u16, String),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "{}", e),
+            StorageError::Http(e) => write!(f, "{}", e),
+            StorageError::Status(status, body) => write!(f, "object store returned {}: {}", status, body),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl PhotoStorage {
+    /// Keeps photos in the directory `root`, served by this service under `/photos/`
+    /// unless `public_url` names another base URL for them.
+    pub fn local(root: impl Into<PathBuf>, public_url: Option<String>) -> PhotoStorage {
+        let public_url = public_url.unwrap_or_else(|| "/photos".to_string());
+        PhotoStorage {
+            backend: StorageBackend::Local(LocalStorage { root: root.into(), public_url }),
+            max_bytes: DEFAULT_MAX_PHOTO_BYTES,
+        }
+    }
+
+    /// Reads the storage from the environment. `PHOTO_STORAGE` is `local` (the default),
+    /// keeping photos under `PHOTO_STORAGE_DIR` (`./photos` by default), or `s3`, see
+    /// [`S3Storage::from_env`]. `PHOTO_PUBLIC_URL` overrides the base URL photos are served
+    /// from and `PHOTO_MAX_BYTES` the largest photo accepted.
+    pub fn from_env() -> Result<PhotoStorage, String> {
+        let public_url = std::env::var("PHOTO_PUBLIC_URL").ok().filter(|url| !url.is_empty());
+        let mut storage = match std::env::var("PHOTO_STORAGE").as_deref() {
+            Ok("local") | Err(_) => {
+                let root = std::env::var("PHOTO_STORAGE_DIR").unwrap_or_else(|_| "photos".to_string());
+                PhotoStorage::local(root, public_url)
+            }
+            Ok("s3") => PhotoStorage {
+                backend: StorageBackend::S3(S3Storage::from_env(public_url)?),
+                max_bytes: DEFAULT_MAX_PHOTO_BYTES,
+            },
+            Ok(other) => return Err(format!("Unknown PHOTO_STORAGE: {}", other)),
+        };
+
+        if let Ok(value) = std::env::var("PHOTO_MAX_BYTES") {
+            storage.max_bytes = value
+                .parse()
+                .ok()
+                .filter(|bytes| *bytes > 0)
+                .ok_or_else(|| format!("Invalid PHOTO_MAX_BYTES: {}", value))?;
+        }
+        Ok(storage)
+    }
+
+    /// Stores `data` under `key` and returns the URL it is served from.
+    pub async fn put(&self, key: &str, content_type: &str, data: Bytes) -> Result<String, StorageError> {
+        match &self.backend {
+            StorageBackend::Local(local) => local.put(key, data).await,
+            StorageBackend::S3(s3) => s3.put(key, content_type, data).await,
+        }
+    }
+
+    /// Deletes the photo stored under `key`. Deleting a missing photo is not an error.
+    pub async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match &self.backend {
+            StorageBackend::Local(local) => local.delete(key).await,
+            StorageBackend::S3(s3) => s3.delete(key).await,
+        }
+    }
+
+    /// The key of the photo served from `url`, if this storage holds it.
+    pub fn key_of(&self, url: &str) -> Option<String> {
+        let public_url = match &self.backend {
+            StorageBackend::Local(local) => &local.public_url,
+            StorageBackend::S3(s3) => &s3.public_url,
+        };
+        url.strip_prefix(public_url.as_str())?.strip_prefix('/').filter(|key| is_valid_key(key)).map(str::to_string)
+    }
+
+    /// Reads the photo stored under `key`, `None` when there is no such photo.
+    pub async fn get(&self, key: &str) -> Result<Option<Bytes>, StorageError> {
+        if !is_valid_key(key) {
+            return Ok(None);
+        }
+        match &self.backend {
+            StorageBackend::Local(local) => local.get(key).await,
+            StorageBackend::S3(s3) => s3.get(key).await,
+        }
+    }
+
+    /// Reads a photo kept on the local filesystem, for serving it under `/photos/`. `None`
+    /// when the photos are stored elsewhere or there is no such photo.
+    pub async fn read_local(&self, key: &str) -> Result<Option<Bytes>, StorageError> {
+        match &self.backend {
+            StorageBackend::Local(_) => self.get(key).await,
+            StorageBackend::S3(_) => Ok(None),
+        }
+    }
+}
+
+/// Whether `key` is a key photos can be stored under, which also keeps keys from leaving
+/// the storage directory.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.split('/').all(|segment| !segment.is_empty() && !segment.starts_with('.'))
+        && key.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"/._".contains(&b))
+}
+
+/// Photos kept in a directory on the local filesystem.
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+}
+
+impl LocalStorage {
+    async fn put(&self, key: &str, data: Bytes) -> Result<String, StorageError> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(StorageError::Io)?;
+        }
+        // Written aside and renamed, so a photo is never served half written.
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, &data).await.map_err(StorageError::Io)?;
+        tokio::fs::rename(&partial, &path).await.map_err(StorageError::Io)?;
+        Ok(format!("{}/{}", self.public_url, key))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, StorageError> {
+        match tokio::fs::read(self.root.join(key)).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StorageError::Io(e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(StorageError::Io(e)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+/// Photos kept in a bucket of an S3-compatible object store, addressed path-style as
+/// `<endpoint>/<bucket>/<key>` and signed with AWS Signature Version 4.
+pub struct S3Storage {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    public_url: String,
+    client: reqwest::Client,
+}
+
+impl S3Storage {
+    /// Stores photos in `bucket` at `endpoint`, e.g. `https://s3.eu-west-1.amazonaws.com`.
+    /// They are served from `public_url`, by default the bucket's own URL.
+    pub fn new(endpoint: &str, bucket: &str, region: &str, access_key_id: &str, secret_access_key: &str, public_url: Option<String>) -> S3Storage {
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+        S3Storage {
+            public_url: public_url.unwrap_or_else(|| format!("{}/{}", endpoint, bucket)),
+            endpoint,
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Reads `S3_ENDPOINT`, `S3_BUCKET`, `S3_REGION` (`us-east-1` by default),
+    /// `S3_ACCESS_KEY_ID` and `S3_SECRET_ACCESS_KEY`.
+    pub fn from_env(public_url: Option<String>) -> Result<S3Storage, String> {
+        let var = |name: &str| std::env::var(name).map_err(|_| format!("PHOTO_STORAGE=s3 requires {}", name));
+        let region = std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        Ok(S3Storage::new(
+            &var("S3_ENDPOINT")?,
+            &var("S3_BUCKET")?,
+            &region,
+            &var("S3_ACCESS_KEY_ID")?,
+            &var("S3_SECRET_ACCESS_KEY")?,
+            public_url,
+        ))
+    }
+
+    async fn put(&self, key: &str, content_type: &str, data: Bytes) -> Result<String, StorageError> {
+        let request = self.client.put(self.object_url(key)).header("content-type", content_type);
+        self.send(request, "PUT", key, Some(content_type), data).await?;
+        Ok(format!("{}/{}", self.public_url, key))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, StorageError> {
+        match self.send(self.client.get(self.object_url(key)), "GET", key, None, Bytes::new()).await {
+            Ok(data) => Ok(Some(data)),
+            Err(StorageError::Status(404, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        // S3 answers deletes of missing objects with 204 as well.
+        self.send(self.client.delete(self.object_url(key)), "DELETE", key, None, Bytes::new()).await?;
+        Ok(())
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!("{}/{}/{}", self.endpoint, self.bucket, key)
+    }
+
+    /// Signs and sends `request`, returning the body of a successful response.
+    async fn send(&self, request: reqwest::RequestBuilder, method: &str, key: &str, content_type: Option<&str>, data: Bytes) -> Result<Bytes, StorageError> {
+        let host = self.endpoint.split_once("://").map_or(self.endpoint.as_str(), |(_, host)| host);
+        let path = format!("/{}/{}", self.bucket, key);
+        let signed = self.sign(method, host, &path, content_type, &data, SystemTime::now());
+
+        let mut request = request.body(data);
+        for (name, value) in signed {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.map_err(StorageError::Http)?;
+        let status = response.status();
+        if status.is_success() {
+            return response.bytes().await.map_err(StorageError::Http);
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(StorageError::Status(status.as_u16(), body.chars().take(500).collect()))
+    }
+
+    /// The headers that sign a request for `path` on `host` at `now`: `x-amz-date`,
+    /// `x-amz-content-sha256` and `authorization`.
+    pub fn sign(&self, method: &str, host: &str, path: &str, content_type: Option<&str>, payload: &[u8], now: SystemTime) -> Vec<(&'static str, String)> {
+        let (date, time) = utc_date_time(now);
+        let amz_date = format!("{}T{}Z", date, time);
+        let payload_hash = hex(&Sha256::digest(payload));
+
+        let mut headers = vec![("host", host.to_string()), ("x-amz-content-sha256", payload_hash.clone()), ("x-amz-date", amz_date.clone())];
+        if let Some(content_type) = content_type {
+            headers.insert(0, ("content-type", content_type.to_string()));
+        }
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+        let canonical_request = format!("{}\n{}\n\n{}\n{}\n{}", method, path, canonical_headers, signed_headers, payload_hash);
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())));
+
+        let key = [date.as_str(), self.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.secret_access_key).into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        vec![
+            ("x-amz-date", amz_date),
+            ("x-amz-content-sha256", payload_hash),
+            (
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key_id, scope, signed_headers, signature
+                ),
+            ),
+        ]
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Formats `at` as the UTC `YYYYMMDD` date and `HHMMSS` time used by Signature Version 4.
+fn utc_date_time(at: SystemTime) -> (String, String) {
+    let secs = at.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+
+    // Converts days since 1970-01-01 to a civil date, after Howard Hinnant's `civil_from_days`.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        format!("{:04}{:02}{:02}", year, month, day),
+        format!("{:02}{:02}{:02}", secs / 3_600, secs / 60 % 60, secs % 60),
+    )
+}
@@ -0,0 +1,320 @@
+use crate::db::Database;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::state::AppState;
+use crate::storage::StorageError;
+use crate::utils::{env_var, log_error};
+use hyper::body::Bytes;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{ImageReader, Limits};
+use sqlx::PgPool;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Longest side of a thumbnail by default, in pixels.
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 320;
+
+/// Attempts at making the variants of a photo before it is marked `failed`.
+pub const MAX_ATTEMPTS: i32 = 3;
+
+/// Widest and tallest photo decoded, which keeps a small file declaring a huge image from
+/// exhausting memory.
+const MAX_DIMENSION: u32 = 12_000;
+
+/// Quality of JPEG thumbnails, out of 100.
+const JPEG_QUALITY: u8 = 80;
+
+/// A photo claimed by the worker for making its variants.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct PendingPhoto {
+    pub id: i32,
+    pub answer_id: i32,
+    pub product_id: i32,
+    pub url: Option<String>,
+    /// Attempts so far, including this one.
+    pub attempts: i32,
+}
+
+/// Where the variants of a photo were stored, and the size of the original.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredVariants {
+    pub thumbnail_url: String,
+    pub thumbnail_webp_url: String,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// The variants of a photo: a JPEG thumbnail and the same thumbnail as WebP.
+#[derive(Debug)]
+pub struct Variants {
+    /// Width of the original photo, in pixels.
+    pub width: u32,
+    /// Height of the original photo, in pixels.
+    pub height: u32,
+    pub thumbnail: Vec<u8>,
+    pub thumbnail_webp: Vec<u8>,
+}
+
+/// Why the variants of a photo could not be made.
+#[derive(Debug)]
+pub enum VariantError {
+    /// The photo could not be fetched.
+    Fetch(String),
+    Storage(StorageError),
+    Image(image::ImageError),
+}
+
+impl std::fmt::Display for VariantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VariantError::Fetch(message) => write!(f, "{}", message),
+            VariantError::Storage(e) => write!(f, "{}", e),
+            VariantError::Image(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for VariantError {}
+
+/// Decodes a photo and makes its variants, scaled down to fit in a `size` pixel square.
+/// Photos already small enough keep their size.
+pub fn make_variants(data: &[u8], size: u32) -> Result<Variants, image::ImageError> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    reader.limits(limits);
+    let image = reader.decode()?;
+
+    let (width, height) = (image.width(), image.height());
+    let thumbnail = if width > size || height > size { image.thumbnail(size, size) } else { image };
+
+    let mut jpeg = Vec::new();
+    thumbnail.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY))?;
+    let mut webp = Vec::new();
+    thumbnail.to_rgba8().write_with_encoder(WebPEncoder::new_lossless(&mut webp))?;
+
+    Ok(Variants { width, height, thumbnail: jpeg, thumbnail_webp: webp })
+}
+
+/// Makes thumbnails and WebP variants of answer photos in the background.
+///
+/// Photos are picked up once they are verified, as soon as they are written for uploads and
+/// once the [`PhotoVerifier`](crate::verification::PhotoVerifier) has fetched them for photos
+/// registered by URL, so responses list them with null variants until the worker gets to
+/// them. A photo that cannot be made into variants is retried after `retry_after`, up to
+/// [`MAX_ATTEMPTS`] times.
+///
+/// Photos added before the worker existed are marked `backfill` by its migration and only
+/// get variants when `backfill` is set, after the photos waiting on new answers.
+pub struct ThumbnailWorker {
+    /// Pause between polls when there is nothing left to process.
+    pub interval: Duration,
+    /// Photos claimed per poll.
+    pub batch: i64,
+    /// Longest side of a thumbnail, in pixels.
+    pub size: u32,
+    /// How long a claimed photo is left to its worker before another may retry it.
+    pub retry_after: Duration,
+    /// Whether photos added before the worker existed get variants too.
+    pub backfill: bool,
+}
+
+impl ThumbnailWorker {
+    /// Creates a worker for new photos only.
+    pub fn new(interval: Duration, batch: i64, size: u32, retry_after: Duration) -> ThumbnailWorker {
+        ThumbnailWorker { interval, batch, size, retry_after, backfill: false }
+    }
+
+    /// Reads `THUMBNAIL_WORKER` (`on` by default, `off` to make no variants),
+    /// `THUMBNAIL_BACKFILL` (`off` by default), `THUMBNAIL_SIZE`, `THUMBNAIL_BATCH` and
+    /// `THUMBNAIL_INTERVAL_SECS`.
+    pub fn from_env() -> Result<Option<ThumbnailWorker>, String> {
+        match std::env::var("THUMBNAIL_WORKER").as_deref() {
+            Ok("on") | Err(_) => {}
+            Ok("off") => return Ok(None),
+            Ok(other) => return Err(format!("Invalid THUMBNAIL_WORKER: {}", other)),
+        }
+        let backfill = match std::env::var("THUMBNAIL_BACKFILL").as_deref() {
+            Ok("on") => true,
+            Ok("off") | Err(_) => false,
+            Ok(other) => return Err(format!("Invalid THUMBNAIL_BACKFILL: {}", other)),
+        };
+
+        let worker = ThumbnailWorker::new(
+            Duration::from_secs(env_var("THUMBNAIL_INTERVAL_SECS", 5)?),
+            env_var("THUMBNAIL_BATCH", 20)?,
+            env_var("THUMBNAIL_SIZE", DEFAULT_THUMBNAIL_SIZE)?,
+            Duration::from_secs(300),
+        );
+        Ok(Some(ThumbnailWorker { backfill, ..worker }))
+    }
+
+    /// Runs the worker until the process exits.
+    pub fn spawn(self, state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.process_pending(&state).await {
+                    // A full batch suggests more are waiting.
+                    Ok(processed) if processed as i64 == self.batch => continue,
+                    Ok(_) => {}
+                    Err(e) => log_error("Failed to claim photos for thumbnails", &e),
+                }
+                tokio::time::sleep(self.interval).await;
+            }
+        })
+    }
+
+    /// Claims a batch of pending photos and makes their variants, returning how many
+    /// photos were claimed.
+    pub async fn process_pending(&self, state: &AppState) -> Result<usize, sqlx::Error> {
+        let retry_after = self.retry_after.as_secs() as i64;
+        let photos = match &state.db {
+            Database::Postgres(pool) => claim_pending(pool, self.batch, retry_after, self.backfill).await?,
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => sqlite::claim_pending_photos(pool, self.batch, retry_after, self.backfill).await?,
+        };
+
+        for photo in &photos {
+            let result = self.make_stored_variants(state, photo).await;
+            let recorded = match &result {
+                Ok(variants) => self.record_variants(state, photo, variants).await,
+                Err(e) => {
+                    log_error(&format!("Failed to make variants of photo {}", photo.id), e);
+                    self.record_failure(state, photo).await
+                }
+            };
+            if let Err(e) = recorded {
+                log_error("Failed to record photo variants", &e);
+            }
+        }
+        Ok(photos.len())
+    }
+
+    async fn make_stored_variants(&self, state: &AppState, photo: &PendingPhoto) -> Result<StoredVariants, VariantError> {
+        let url = photo.url.as_deref().ok_or_else(|| VariantError::Fetch("Photo has no URL".into()))?;
+        let data = self.fetch(state, url).await?;
+
+        let size = self.size;
+        let variants = tokio::task::spawn_blocking(move || make_variants(&data, size))
+            .await
+            .expect("making variants does not panic")
+            .map_err(VariantError::Image)?;
+
+        // Variants are named after the photo, whose id is never reused.
+        let key = format!("answers/{}/{}_thumb", photo.answer_id, photo.id);
+        let thumbnail_url = state.photos.put(&format!("{}.jpg", key), "image/jpeg", variants.thumbnail.into()).await.map_err(VariantError::Storage)?;
+        let thumbnail_webp_url =
+            state.photos.put(&format!("{}.webp", key), "image/webp", variants.thumbnail_webp.into()).await.map_err(VariantError::Storage)?;
+
+        Ok(StoredVariants { thumbnail_url, thumbnail_webp_url, width: variants.width as i32, height: variants.height as i32 })
+    }
+
+    /// Reads a photo from storage when it is one of ours, and over HTTP otherwise.
+    async fn fetch(&self, state: &AppState, url: &str) -> Result<Bytes, VariantError> {
+        if let Some(key) = state.photos.key_of(url) {
+            return state.photos.get(&key).await.map_err(VariantError::Storage)?.ok_or_else(|| VariantError::Fetch(format!("No photo stored at {}", key)));
+        }
+        let fetched = state.photo_fetcher.fetch(url, state.photos.max_bytes).await.map_err(|e| VariantError::Fetch(e.to_string()))?;
+        Ok(fetched.data)
+    }
+
+    async fn record_variants(&self, state: &AppState, photo: &PendingPhoto, variants: &StoredVariants) -> Result<(), sqlx::Error> {
+        let recorded = match &state.db {
+            Database::Postgres(pool) => record_variants(pool, photo.id, variants).await?,
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => sqlite::record_photo_variants(pool, photo.id, variants).await?,
+        };
+        if recorded {
+            state.questions_cache.invalidate(photo.product_id);
+        } else {
+            // The photo was deleted while its variants were being made.
+            for url in [&variants.thumbnail_url, &variants.thumbnail_webp_url] {
+                if let Some(key) = state.photos.key_of(url) {
+                    state.photos.delete(&key).await.ok();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn record_failure(&self, state: &AppState, photo: &PendingPhoto) -> Result<(), sqlx::Error> {
+        if photo.attempts < MAX_ATTEMPTS {
+            // Left claimed, so that it is retried once its claim runs out.
+            return Ok(());
+        }
+        match &state.db {
+            Database::Postgres(pool) => mark_failed(pool, photo.id).await,
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => sqlite::mark_photo_variants_failed(pool, photo.id).await,
+        }
+    }
+}
+
+/// Claims up to `batch` pending photos, and with `backfill` photos marked for backfill after
+/// them, that are not claimed or whose claim is older than `retry_after` seconds. Rows
+/// locked by another worker's claim are skipped.
+async fn claim_pending(pool: &PgPool, batch: i64, retry_after: i64, backfill: bool) -> Result<Vec<PendingPhoto>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingPhoto,
+        r#"
+        UPDATE answer_photos AS ap
+        SET variants_attempts = ap.variants_attempts + 1, variants_claimed_at = NOW()
+        FROM answers a, questions q
+        WHERE a.id = ap.answer_id AND q.id = a.question_id AND ap.id IN (
+            SELECT id
+            FROM answer_photos
+            WHERE (variants_status = 'pending' OR ($3 AND variants_status = 'backfill')) AND verify_status = 'ok'
+                AND (variants_claimed_at IS NULL OR variants_claimed_at <= NOW() - make_interval(secs => $2))
+            ORDER BY variants_status = 'backfill', id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING ap.id, ap.answer_id, q.product_id, ap.url, ap.variants_attempts AS attempts;
+        "#,
+        batch,
+        retry_after as f64,
+        backfill
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Records the variants of a photo and touches its answer, returning whether the photo
+/// still exists.
+async fn record_variants(pool: &PgPool, photo_id: i32, variants: &StoredVariants) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let done = sqlx::query!(
+        r#"
+        UPDATE answer_photos
+        SET thumbnail_url = $2, thumbnail_webp_url = $3, width = $4, height = $5,
+            variants_status = 'ready', variants_claimed_at = NULL
+        WHERE id = $1;
+        "#,
+        photo_id,
+        variants.thumbnail_url,
+        variants.thumbnail_webp_url,
+        variants.width,
+        variants.height
+    )
+    .execute(&mut tx)
+    .await?;
+    if done.rows_affected() == 0 {
+        return Ok(false);
+    }
+    sqlx::query!("UPDATE answers SET updated_at = NOW() WHERE id = (SELECT answer_id FROM answer_photos WHERE id = $1);", photo_id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+async fn mark_failed(pool: &PgPool, photo_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query!("UPDATE answer_photos SET variants_status = 'failed', variants_claimed_at = NULL WHERE id = $1;", photo_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -1,41 +1,109 @@
-use hyper::{Body, Response, StatusCode};
+use hyper::{body::Bytes, Body, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
 
 pub fn parse_query_parameters(query: Option<&str>) -> HashMap<String, String> {
     query
         .map(|q| {
-            q.split('&')
-                .filter_map(|p| {
-                    let mut parts = p.split('=');
-                    let key = parts.next().unwrap_or("").to_string();
-                    let value = parts.next().unwrap_or("").to_string();
-                    if key.is_empty() || value.is_empty() {
-                        None
-                    } else {
-                        Some((key, value))
-                    }
-                })
+            form_urlencoded::parse(q.as_bytes())
+                .filter(|(key, value)| !key.is_empty() && !value.is_empty())
+                .map(|(key, value)| (key.into_owned(), value.into_owned()))
                 .collect()
         })
         .unwrap_or_default()
 }
 
-pub fn get_page_count(params: &HashMap<String, String>) -> (i32, i32) {
-    let page = params
-        .get("page")
-        .and_then(|v| v.parse::<i32>().ok())
-        .unwrap_or(1);
-    let count = params
-        .get("count")
-        .and_then(|v| v.parse::<i32>().ok())
-        .unwrap_or(5);
-    (page, count)
+/// Largest `count` served per page; larger counts are cut down to it.
+pub const MAX_PAGE_COUNT: i32 = 100;
+
+/// Reads the `page` and `count` pagination parameters, defaulting to page 1 of 5.
+///
+/// Both must be positive integers when present; otherwise the name of the offending
+/// parameter is returned as an error message. `count` is capped at [`MAX_PAGE_COUNT`].
+pub fn get_page_count(params: &HashMap<String, String>) -> Result<(i32, i32), String> {
+    let page = match params.get("page") {
+        Some(v) => v
+            .parse::<i32>()
+            .ok()
+            .filter(|page| *page > 0)
+            .ok_or_else(|| "Invalid page query parameter".to_string())?,
+        None => 1,
+    };
+    let count = match params.get("count") {
+        Some(v) => v
+            .parse::<i32>()
+            .ok()
+            .filter(|count| *count > 0)
+            .map(|count| count.min(MAX_PAGE_COUNT))
+            .ok_or_else(|| "Invalid count query parameter".to_string())?,
+        None => 5,
+    };
+    Ok((page, count))
+}
+
+/// Parses a JSON request body that may be left empty, in which case `T::default()` is used.
+pub fn parse_optional_body<T: DeserializeOwned + Default>(body: &[u8]) -> Result<T, serde_json::Error> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(body)
+}
+
+/// Reads a positive number from the environment variable `name`, or `default` when it is
+/// unset. Anything else is an `Invalid <name>` error, for the `from_env` constructors.
+pub fn env_var<T: FromStr + PartialOrd + Default>(name: &str, default: T) -> Result<T, String> {
+    match std::env::var(name) {
+        Ok(value) => value.parse().ok().filter(|value| *value > T::default()).ok_or_else(|| format!("Invalid {}: {}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Lowercase hex of `bytes`, for digests, signatures and random tokens.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Wraps a serialized `results` array, such as the JSON text produced by the database, in the
+/// `GET /api/v1/questions` envelope without parsing it again.
+pub fn questions_envelope(product_id: i32, results: &str) -> Bytes {
+    let mut body = String::with_capacity(results.len() + 64);
+    write!(body, r#"{{"product_id":{},"results":{}}}"#, product_id, results).unwrap();
+    body.into()
 }
 
+/// Wraps a serialized `results` array in the `GET /api/v1/questions/batch` envelope.
+pub fn batch_envelope(top: i32, results: &str) -> Bytes {
+    let mut body = String::with_capacity(results.len() + 32);
+    write!(body, r#"{{"top":{},"results":{}}}"#, top, results).unwrap();
+    body.into()
+}
+
+/// Wraps a serialized `results` array in the `GET /api/v1/questions/:question_id/answers` envelope.
+pub fn answers_envelope(question_id: i32, page: i32, count: i32, results: &str) -> Bytes {
+    let mut body = String::with_capacity(results.len() + 96);
+    write!(
+        body,
+        r#"{{"question_id":{},"page":{},"count":{},"results":{}}}"#,
+        question_id, page, count, results
+    )
+    .unwrap();
+    body.into()
+}
+
+/// Builds a JSON response. `204 No Content` responses are sent without a body.
 pub fn create_success_response(
     status: StatusCode,
     body: serde_json::Value,
 ) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    if status == StatusCode::NO_CONTENT {
+        return Ok(Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap());
+    }
+
     Ok(Response::builder()
         .status(status)
         .header("content-type", "application/json")
@@ -52,3 +120,49 @@ pub fn create_error_response(
         .body(message.into())
         .unwrap())
 }
+
+/// Logs a failed operation with its error.
+///
+/// Database errors can quote the row they failed on, for instance in the `Failing row
+/// contains (...)` detail of a constraint violation, so anything that looks like an email
+/// address is masked first. Handlers log through this rather than printing errors directly.
+pub fn log_error(context: &str, error: &impl std::fmt::Debug) {
+    println!("{}", format_error(context, error));
+}
+
+/// The line [`log_error`] prints.
+pub fn format_error(context: &str, error: &impl std::fmt::Debug) -> String {
+    redact_emails(&format!("{}: {:?}", context, error))
+}
+
+/// Replaces every email-like `local@domain.tld` in `text` with `[email]`.
+pub fn redact_emails(text: &str) -> String {
+    let is_local = |c: char| c.is_alphanumeric() || "._%+-'".contains(c);
+    let is_domain = |c: char| c.is_alphanumeric() || ".-".contains(c);
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('@') {
+        let local_start = rest[..at]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_local(c))
+            .last()
+            .map_or(at, |(i, _)| i);
+        let domain = &rest[at + 1..];
+        let domain_len = domain.find(|c: char| !is_domain(c)).unwrap_or(domain.len());
+        let domain = domain[..domain_len].trim_end_matches('.');
+
+        if local_start < at && domain.contains('.') {
+            redacted.push_str(&rest[..local_start]);
+            redacted.push_str("[email]");
+            rest = &rest[at + 1 + domain.len()..];
+        } else {
+            redacted.push_str(&rest[..=at]);
+            rest = &rest[at + 1..];
+        }
+    }
+    redacted.push_str(rest);
+
+    redacted
+}
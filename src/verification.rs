@@ -0,0 +1,234 @@
+use crate::db::Database;
+use crate::fetch::FetchError;
+use crate::photos::ImageType;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::state::AppState;
+use crate::storage::StorageError;
+use crate::thumbnails::PendingPhoto;
+use crate::utils::{env_var, log_error};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Attempts at fetching a photo that keeps failing in a way that may pass, such as a timeout,
+/// before it is flagged as broken.
+pub const MAX_ATTEMPTS: i32 = 3;
+
+/// Why a photo failed verification.
+#[derive(Debug)]
+pub enum VerifyError {
+    MissingUrl,
+    Fetch(FetchError),
+    Storage(StorageError),
+    /// The photo is stored here, but its file is gone.
+    NotStored,
+    /// The server declared a media type other than an image.
+    NotImage(Option<String>),
+    /// The body is not a JPEG, PNG, GIF or WebP image, whatever its declared type.
+    UnsupportedImage,
+}
+
+impl VerifyError {
+    /// Whether verifying again could succeed, see [`FetchError::is_transient`].
+    pub fn is_transient(&self) -> bool {
+        match self {
+            VerifyError::Fetch(e) => e.is_transient(),
+            VerifyError::Storage(_) => true,
+            VerifyError::MissingUrl | VerifyError::NotStored | VerifyError::NotImage(_) | VerifyError::UnsupportedImage => false,
+        }
+    }
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::MissingUrl => write!(f, "Photo has no URL"),
+            VerifyError::Fetch(e) => write!(f, "{}", e),
+            VerifyError::Storage(e) => write!(f, "{}", e),
+            VerifyError::NotStored => write!(f, "Photo is no longer stored"),
+            VerifyError::NotImage(Some(content_type)) => write!(f, "Photo is served as {}, not an image", content_type),
+            VerifyError::NotImage(None) => write!(f, "Photo is served without a content type"),
+            VerifyError::UnsupportedImage => write!(f, "Photo is not a JPEG, PNG, GIF or WebP image"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Checks in the background that photos registered by URL with an answer resolve to images.
+///
+/// Each pending photo is fetched with the state's [`PhotoFetcher`](crate::fetch::PhotoFetcher),
+/// which keeps the fetch off private networks and within its time and size limits. A photo
+/// that is served as an image and sniffs as one is marked `ok`, and only then gets its
+/// thumbnails. Anything else flags it as `broken`, which leaves it out of responses, though
+/// failures that may pass are retried after `retry_after`, up to [`MAX_ATTEMPTS`] times.
+/// Photos added before verification existed were marked `ok` by its migration, so they are
+/// never fetched.
+pub struct PhotoVerifier {
+    /// Pause between polls when there is nothing left to verify.
+    pub interval: Duration,
+    /// Photos claimed per poll.
+    pub batch: i64,
+    /// How long a claimed photo is left to its verifier before another may retry it.
+    pub retry_after: Duration,
+}
+
+impl PhotoVerifier {
+    pub fn new(interval: Duration, batch: i64, retry_after: Duration) -> PhotoVerifier {
+        PhotoVerifier { interval, batch, retry_after }
+    }
+
+    /// Reads `PHOTO_VERIFIER` (`on` by default, `off` to verify no photos, which then stay
+    /// pending), `PHOTO_VERIFY_BATCH` and `PHOTO_VERIFY_INTERVAL_SECS`.
+    pub fn from_env() -> Result<Option<PhotoVerifier>, String> {
+        match std::env::var("PHOTO_VERIFIER").as_deref() {
+            Ok("on") | Err(_) => {}
+            Ok("off") => return Ok(None),
+            Ok(other) => return Err(format!("Invalid PHOTO_VERIFIER: {}", other)),
+        }
+
+        Ok(Some(PhotoVerifier::new(
+            Duration::from_secs(env_var("PHOTO_VERIFY_INTERVAL_SECS", 5)?),
+            env_var("PHOTO_VERIFY_BATCH", 20)?,
+            Duration::from_secs(300),
+        )))
+    }
+
+    /// Runs the verifier until the process exits.
+    pub fn spawn(self, state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.process_pending(&state).await {
+                    // A full batch suggests more are waiting.
+                    Ok(processed) if processed as i64 == self.batch => continue,
+                    Ok(_) => {}
+                    Err(e) => log_error("Failed to claim photos for verification", &e),
+                }
+                tokio::time::sleep(self.interval).await;
+            }
+        })
+    }
+
+    /// Claims a batch of unverified photos and verifies them, returning how many photos
+    /// were claimed.
+    pub async fn process_pending(&self, state: &AppState) -> Result<usize, sqlx::Error> {
+        let retry_after = self.retry_after.as_secs() as i64;
+        let photos = match &state.db {
+            Database::Postgres(pool) => claim_unverified(pool, self.batch, retry_after).await?,
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => sqlite::claim_unverified_photos(pool, self.batch, retry_after).await?,
+        };
+
+        for photo in &photos {
+            let recorded = match verify(state, photo).await {
+                Ok(()) => record_verified(state, photo).await,
+                Err(e) if e.is_transient() && photo.attempts < MAX_ATTEMPTS => {
+                    // Left claimed, so that it is retried once its claim runs out.
+                    log_error(&format!("Failed to verify photo {}", photo.id), &e);
+                    Ok(())
+                }
+                Err(e) => record_broken(state, photo, &e).await,
+            };
+            if let Err(e) = recorded {
+                log_error("Failed to record photo verification", &e);
+            }
+        }
+        Ok(photos.len())
+    }
+}
+
+/// Checks that a photo resolves to an image, reading it from storage when it is one of ours.
+async fn verify(state: &AppState, photo: &PendingPhoto) -> Result<(), VerifyError> {
+    let url = photo.url.as_deref().ok_or(VerifyError::MissingUrl)?;
+
+    let data = if let Some(key) = state.photos.key_of(url) {
+        state.photos.get(&key).await.map_err(VerifyError::Storage)?.ok_or(VerifyError::NotStored)?
+    } else {
+        let fetched = state.photo_fetcher.fetch(url, state.photos.max_bytes).await.map_err(VerifyError::Fetch)?;
+        match fetched.content_type {
+            Some(content_type) if content_type.starts_with("image/") => {}
+            content_type => return Err(VerifyError::NotImage(content_type)),
+        }
+        fetched.data
+    };
+
+    ImageType::sniff(&data).map(|_| ()).ok_or(VerifyError::UnsupportedImage)
+}
+
+async fn record_verified(state: &AppState, photo: &PendingPhoto) -> Result<(), sqlx::Error> {
+    match &state.db {
+        Database::Postgres(pool) => mark_verified(pool, photo.id).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::mark_photo_verified(pool, photo.id).await,
+    }
+}
+
+async fn record_broken(state: &AppState, photo: &PendingPhoto, error: &VerifyError) -> Result<(), sqlx::Error> {
+    let error = error.to_string();
+    match &state.db {
+        Database::Postgres(pool) => mark_broken(pool, photo.id, &error).await?,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::mark_photo_broken(pool, photo.id, &error).await?,
+    }
+    // Broken photos drop out of the cached responses listing them.
+    state.questions_cache.invalidate(photo.product_id);
+    Ok(())
+}
+
+/// Claims up to `batch` unverified photos that are not claimed, or whose claim is older than
+/// `retry_after` seconds. Rows locked by another verifier's claim are skipped.
+async fn claim_unverified(pool: &PgPool, batch: i64, retry_after: i64) -> Result<Vec<PendingPhoto>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingPhoto,
+        r#"
+        UPDATE answer_photos AS ap
+        SET verify_attempts = ap.verify_attempts + 1, verify_claimed_at = NOW()
+        FROM answers a, questions q
+        WHERE a.id = ap.answer_id AND q.id = a.question_id AND ap.id IN (
+            SELECT id
+            FROM answer_photos
+            WHERE verify_status = 'pending'
+                AND (verify_claimed_at IS NULL OR verify_claimed_at <= NOW() - make_interval(secs => $2))
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING ap.id, ap.answer_id, q.product_id, ap.url, ap.verify_attempts AS attempts;
+        "#,
+        batch,
+        retry_after as f64
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn mark_verified(pool: &PgPool, photo_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE answer_photos SET verify_status = 'ok', verify_error = NULL, verified_at = NOW(), verify_claimed_at = NULL WHERE id = $1;",
+        photo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Flags a photo as broken and touches its answer, whose listing no longer shows it.
+async fn mark_broken(pool: &PgPool, photo_id: i32, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH p AS (
+            UPDATE answer_photos
+            SET verify_status = 'broken', verify_error = $2, verified_at = NOW(), verify_claimed_at = NULL
+            WHERE id = $1
+            RETURNING answer_id
+        )
+        UPDATE answers SET updated_at = NOW() FROM p WHERE answers.id = p.answer_id;
+        "#,
+        photo_id,
+        error
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
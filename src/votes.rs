@@ -0,0 +1,73 @@
+use crate::utils::hex;
+use hyper::header::HeaderMap;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+/// Header through which clients identify the user casting a helpful vote.
+pub const USER_TOKEN_HEADER: &str = "x-user-token";
+
+/// Longest user token accepted, in bytes.
+const MAX_USER_TOKEN_LEN: usize = 128;
+
+/// Derives the voter identity that helpful votes are recorded under.
+///
+/// A vote belongs to the signed-in shopper named by the request's bearer token, else to the
+/// user token sent in `X-User-Token` or, without one, to the client's IP address. Each is
+/// stored only as a SHA-256 hash keyed with `VOTER_HASH_KEY`, so the votes table holds no
+/// addresses and tokens cannot be read back from it.
+///
+/// User tokens are chosen by the client, so one voter can pose as many by sending fresh
+/// ones. Only signed-in shoppers are really one vote each; votes under a user token are kept
+/// in check by the write rate limit of the address they come from instead.
+pub struct VoterHasher {
+    key: Vec<u8>,
+}
+
+impl VoterHasher {
+    pub fn new(key: impl Into<Vec<u8>>) -> VoterHasher {
+        VoterHasher { key: key.into() }
+    }
+
+    /// Reads the hashing key from `VOTER_HASH_KEY`. The key has to stay the same across
+    /// restarts and replicas, otherwise voters are no longer recognised.
+    pub fn from_env() -> VoterHasher {
+        let key = std::env::var("VOTER_HASH_KEY").unwrap_or_else(|_| {
+            println!("VOTER_HASH_KEY is not set, voter IP addresses are hashed without a key");
+            String::new()
+        });
+
+        VoterHasher::new(key)
+    }
+
+    /// Returns the voter identity for a request from `remote_ip` made by `user`, the subject
+    /// of its bearer token, or an error message when the `X-User-Token` header is present
+    /// but not a usable token.
+    pub fn voter(&self, user: Option<&str>, headers: &HeaderMap, remote_ip: IpAddr) -> Result<String, String> {
+        if let Some(user) = user {
+            return Ok(self.hash("user", user.as_bytes()));
+        }
+
+        match headers.get(USER_TOKEN_HEADER) {
+            Some(token) if !token.is_empty() => {
+                let token = token
+                    .to_str()
+                    .ok()
+                    .filter(|token| token.len() <= MAX_USER_TOKEN_LEN)
+                    .ok_or_else(|| "Invalid X-User-Token header".to_string())?;
+                Ok(self.hash("token", token.as_bytes()))
+            }
+            _ => Ok(self.hash("ip", remote_ip.to_string().as_bytes())),
+        }
+    }
+
+    fn hash(&self, kind: &str, value: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key);
+        hasher.update([0]);
+        hasher.update(kind);
+        hasher.update([0]);
+        hasher.update(value);
+
+        hex(&hasher.finalize())
+    }
+}
@@ -0,0 +1,439 @@
+use crate::db::Database;
+use crate::outbox::EVENT_TYPES;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::state::AppState;
+use crate::utils::{env_var, hex, log_error};
+use hmac::{Hmac, Mac};
+use hyper::header::CONTENT_TYPE;
+use rand::RngCore;
+use reqwest::redirect::Policy;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest wait between two attempts at a delivery.
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Header naming the event type of a delivery.
+pub const EVENT_HEADER: &str = "x-qa-event";
+/// Header carrying the id of a delivery, the same across its retries.
+pub const DELIVERY_HEADER: &str = "x-qa-delivery";
+/// Header carrying the signature of a delivery, see [`signature`].
+pub const SIGNATURE_HEADER: &str = "x-qa-signature";
+
+/// A registered webhook, as listed by the admin CLI. Its secret is only shown when created.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct WebhookRecord {
+    pub id: i32,
+    pub url: String,
+    /// Comma-separated event types, or empty for every event.
+    pub events: String,
+    pub created_at: String,
+}
+
+/// A delivery that ran out of attempts.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub event_id: i64,
+    pub event_type: String,
+    pub webhook_id: i32,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub failed_at: String,
+}
+
+/// A delivery claimed by the dispatcher, with what it takes to send it.
+#[derive(Debug, sqlx::FromRow)]
+pub struct PendingDelivery {
+    pub id: i64,
+    pub event_id: i64,
+    /// Attempts so far, including this one.
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+    pub event_type: String,
+    /// The event's payload, as JSON text.
+    pub payload: String,
+    pub created_at: String,
+}
+
+/// Checks a list of event types to subscribe to, returning it comma-separated.
+pub fn parse_events(events: &[String]) -> Result<String, String> {
+    if let Some(unknown) = events.iter().find(|event| !EVENT_TYPES.contains(&event.as_str())) {
+        return Err(format!("Unknown event type: {}", unknown));
+    }
+    Ok(events.join(","))
+}
+
+/// Registers a webhook for `events`, or for every event when empty, and returns its id and
+/// the secret deliveries to it are signed with.
+pub async fn create_webhook(db: &Database, url: &str, events: &str) -> Result<(i32, String), sqlx::Error> {
+    let mut secret = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let secret = format!("whsec_{}", hex(&secret));
+
+    let id = match db {
+        Database::Postgres(pool) => {
+            sqlx::query_scalar!("INSERT INTO webhooks (url, secret, events) VALUES ($1, $2, $3) RETURNING id;", url, secret, events)
+                .fetch_one(pool)
+                .await?
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::insert_webhook(pool, url, &secret, events).await?,
+    };
+
+    Ok((id, secret))
+}
+
+pub async fn list_webhooks(db: &Database) -> Result<Vec<WebhookRecord>, sqlx::Error> {
+    match db {
+        Database::Postgres(pool) => {
+            sqlx::query_as!(
+                WebhookRecord,
+                r#"
+                SELECT id, url, events, to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "created_at!"
+                FROM webhooks
+                ORDER BY id;
+                "#
+            )
+            .fetch_all(pool)
+            .await
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::list_webhooks(pool).await,
+    }
+}
+
+/// Deletes a webhook with its pending deliveries and dead letters. Returns `false` if there
+/// is no such webhook.
+pub async fn delete_webhook(db: &Database, id: i32) -> Result<bool, sqlx::Error> {
+    let deleted = match db {
+        Database::Postgres(pool) => sqlx::query!("DELETE FROM webhooks WHERE id = $1;", id).execute(pool).await?.rows_affected(),
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::delete_webhook(pool, id).await?,
+    };
+    Ok(deleted > 0)
+}
+
+pub async fn list_dead_letters(db: &Database) -> Result<Vec<DeadLetter>, sqlx::Error> {
+    match db {
+        Database::Postgres(pool) => {
+            sqlx::query_as!(
+                DeadLetter,
+                r#"
+                SELECT
+                    l.id,
+                    l.event_id,
+                    e.event_type,
+                    l.webhook_id,
+                    l.attempts,
+                    l.last_error,
+                    to_char(l.failed_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "failed_at!"
+                FROM webhook_dead_letters AS l
+                JOIN outbox_events AS e ON e.id = l.event_id
+                ORDER BY l.id;
+                "#
+            )
+            .fetch_all(pool)
+            .await
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::list_dead_letters(pool).await,
+    }
+}
+
+/// Queues a dead letter for delivery again, with a fresh set of attempts. Returns `false` if
+/// there is no such dead letter.
+pub async fn redeliver(db: &Database, id: i64) -> Result<bool, sqlx::Error> {
+    let requeued = match db {
+        Database::Postgres(pool) => {
+            sqlx::query!(
+                r#"
+                WITH l AS (
+                    DELETE FROM webhook_dead_letters WHERE id = $1 RETURNING event_id, webhook_id
+                )
+                INSERT INTO webhook_deliveries (event_id, webhook_id)
+                SELECT event_id, webhook_id FROM l;
+                "#,
+                id
+            )
+            .execute(pool)
+            .await?
+            .rows_affected()
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::redeliver_dead_letter(pool, id).await?,
+    };
+    Ok(requeued > 0)
+}
+
+/// Signs a delivery sent at `timestamp`, in seconds since the Unix epoch, as
+/// `t=<timestamp>,v1=<hex HMAC-SHA256 of "<timestamp>.<body>">` keyed with the webhook's
+/// secret. Receivers recompute it to check a delivery came from here, and reject old
+/// timestamps to stop replays.
+pub fn signature(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("t={},v1={}", timestamp, hex(&mac.finalize().into_bytes()))
+}
+
+/// Delivers the events in the outbox to the webhooks subscribed to them.
+///
+/// Each poll fans undispatched events out to a delivery per subscribed webhook, then sends
+/// the deliveries that are due as signed `POST`s of
+/// `{"id", "type", "created_at", "data"}`. A delivery answered with anything but a `2xx`
+/// is retried after `backoff`, doubling with every attempt up to an hour, and moves to the
+/// dead letters after `max_attempts`. Deliveries are at least once and not ordered, so
+/// receivers should skip event ids they have already seen.
+pub struct WebhookDispatcher {
+    /// Pause between polls when there is nothing left to deliver.
+    pub interval: Duration,
+    /// Events fanned out and deliveries claimed per poll.
+    pub batch: i64,
+    pub max_attempts: i32,
+    /// Wait before the first retry of a delivery.
+    pub backoff: Duration,
+    /// How long a claimed delivery is left to its dispatcher before another may retry it.
+    pub retry_after: Duration,
+    client: reqwest::Client,
+}
+
+impl WebhookDispatcher {
+    pub fn new(interval: Duration, batch: i64, max_attempts: i32, backoff: Duration, timeout: Duration) -> WebhookDispatcher {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .redirect(Policy::none())
+            .build()
+            .expect("a client without custom TLS settings always builds");
+        // A claim outlives any attempt, so an attempt in flight is never sent twice.
+        let retry_after = timeout * 2;
+        WebhookDispatcher { interval, batch, max_attempts, backoff, retry_after, client }
+    }
+
+    /// Reads `WEBHOOK_DISPATCHER` (`on` by default, `off` to leave events in the outbox),
+    /// `WEBHOOK_BATCH`, `WEBHOOK_INTERVAL_SECS`, `WEBHOOK_MAX_ATTEMPTS`,
+    /// `WEBHOOK_BACKOFF_SECS` and `WEBHOOK_TIMEOUT_SECS`.
+    pub fn from_env() -> Result<Option<WebhookDispatcher>, String> {
+        match std::env::var("WEBHOOK_DISPATCHER").as_deref() {
+            Ok("on") | Err(_) => {}
+            Ok("off") => return Ok(None),
+            Ok(other) => return Err(format!("Invalid WEBHOOK_DISPATCHER: {}", other)),
+        }
+
+        Ok(Some(WebhookDispatcher::new(
+            Duration::from_secs(env_var("WEBHOOK_INTERVAL_SECS", 5)?),
+            env_var("WEBHOOK_BATCH", 50)?,
+            env_var("WEBHOOK_MAX_ATTEMPTS", 8)?,
+            Duration::from_secs(env_var("WEBHOOK_BACKOFF_SECS", 30)?),
+            Duration::from_secs(env_var("WEBHOOK_TIMEOUT_SECS", 10)?),
+        )))
+    }
+
+    /// Runs the dispatcher until the process exits.
+    pub fn spawn(self, state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.process_pending(&state).await {
+                    // A full batch suggests more are waiting.
+                    Ok(processed) if processed as i64 == self.batch => continue,
+                    Ok(_) => {}
+                    Err(e) => log_error("Failed to dispatch webhook deliveries", &e),
+                }
+                tokio::time::sleep(self.interval).await;
+            }
+        })
+    }
+
+    /// Fans out a batch of events, then claims a batch of due deliveries and sends them,
+    /// returning how many deliveries were claimed.
+    pub async fn process_pending(&self, state: &AppState) -> Result<usize, sqlx::Error> {
+        let retry_after = self.retry_after.as_secs() as i64;
+        let mut deliveries = match &state.db {
+            Database::Postgres(pool) => {
+                fan_out(pool, self.batch).await?;
+                claim_due(pool, self.batch, retry_after).await?
+            }
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => {
+                sqlite::fan_out_events(pool, self.batch).await?;
+                sqlite::claim_due_deliveries(pool, self.batch, retry_after).await?
+            }
+        };
+        // Sent oldest first, as `UPDATE ... RETURNING` keeps no order.
+        deliveries.sort_by_key(|delivery| delivery.id);
+
+        for delivery in &deliveries {
+            let recorded = match self.send(delivery).await {
+                Ok(()) => record_delivered(state, delivery).await,
+                Err(error) => self.record_failure(state, delivery, &error).await,
+            };
+            if let Err(e) = recorded {
+                log_error("Failed to record webhook delivery", &e);
+            }
+        }
+        Ok(deliveries.len())
+    }
+
+    async fn send(&self, delivery: &PendingDelivery) -> Result<(), String> {
+        let data: serde_json::Value = serde_json::from_str(&delivery.payload).map_err(|e| e.to_string())?;
+        let body = serde_json::json!({
+            "id": delivery.event_id,
+            "type": delivery.event_type,
+            "created_at": delivery.created_at,
+            "data": data,
+        })
+        .to_string();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let response = self
+            .client
+            .post(&delivery.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event_type)
+            .header(DELIVERY_HEADER, delivery.id)
+            .header(SIGNATURE_HEADER, signature(&delivery.secret, timestamp, body.as_bytes()))
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Webhook answered {}", response.status().as_u16()))
+        }
+    }
+
+    async fn record_failure(&self, state: &AppState, delivery: &PendingDelivery, error: &str) -> Result<(), sqlx::Error> {
+        if delivery.attempts >= self.max_attempts {
+            log_error(&format!("Gave up on webhook delivery {}", delivery.id), &error);
+            return match &state.db {
+                Database::Postgres(pool) => dead_letter(pool, delivery.id, error).await,
+                #[cfg(feature = "sqlite")]
+                Database::Sqlite(pool) => sqlite::dead_letter_delivery(pool, delivery.id, error).await,
+            };
+        }
+
+        let delay = self.backoff.saturating_mul(1 << (delivery.attempts - 1).min(16)).min(MAX_BACKOFF).as_secs() as i64;
+        match &state.db {
+            Database::Postgres(pool) => reschedule(pool, delivery.id, delay, error).await,
+            #[cfg(feature = "sqlite")]
+            Database::Sqlite(pool) => sqlite::reschedule_delivery(pool, delivery.id, delay, error).await,
+        }
+    }
+}
+
+async fn record_delivered(state: &AppState, delivery: &PendingDelivery) -> Result<(), sqlx::Error> {
+    match &state.db {
+        Database::Postgres(pool) => mark_delivered(pool, delivery.id).await,
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlite::mark_delivered(pool, delivery.id).await,
+    }
+}
+
+/// Adds a delivery for each webhook subscribed to each of up to `batch` undispatched events,
+/// and marks the events dispatched. Events no webhook is subscribed to are only marked.
+async fn fan_out(pool: &PgPool, batch: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH e AS (
+            SELECT id, event_type
+            FROM outbox_events
+            WHERE dispatched_at IS NULL
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        ), d AS (
+            INSERT INTO webhook_deliveries (event_id, webhook_id)
+            SELECT e.id, w.id
+            FROM e
+            JOIN webhooks AS w ON w.events = '' OR ',' || w.events || ',' LIKE '%,' || e.event_type || ',%'
+        )
+        UPDATE outbox_events
+        SET dispatched_at = NOW()
+        WHERE id IN (SELECT id FROM e);
+        "#,
+        batch
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Claims up to `batch` due deliveries that are not claimed, or whose claim is older than
+/// `retry_after` seconds. Rows locked by another dispatcher's claim are skipped.
+async fn claim_due(pool: &PgPool, batch: i64, retry_after: i64) -> Result<Vec<PendingDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingDelivery,
+        r#"
+        UPDATE webhook_deliveries AS d
+        SET attempts = d.attempts + 1, claimed_at = NOW()
+        FROM webhooks AS w, outbox_events AS e
+        WHERE w.id = d.webhook_id AND e.id = d.event_id AND d.id IN (
+            SELECT id
+            FROM webhook_deliveries
+            WHERE delivered_at IS NULL AND next_attempt_at <= NOW()
+                AND (claimed_at IS NULL OR claimed_at <= NOW() - make_interval(secs => $2))
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING
+            d.id,
+            d.event_id,
+            d.attempts,
+            w.url,
+            w.secret,
+            e.event_type,
+            e.payload::TEXT AS "payload!",
+            to_char(e.created_at, 'YYYY-MM-DD"T"HH24:MI:SS.MS') AS "created_at!";
+        "#,
+        batch,
+        retry_after as f64
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn mark_delivered(pool: &PgPool, delivery_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!("UPDATE webhook_deliveries SET delivered_at = NOW(), claimed_at = NULL, last_error = NULL WHERE id = $1;", delivery_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+async fn reschedule(pool: &PgPool, delivery_id: i64, delay: i64, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET next_attempt_at = NOW() + make_interval(secs => $2), claimed_at = NULL, last_error = $3 WHERE id = $1;",
+        delivery_id,
+        delay as f64,
+        error
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Moves a delivery to the dead letters.
+async fn dead_letter(pool: &PgPool, delivery_id: i64, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH d AS (
+            DELETE FROM webhook_deliveries WHERE id = $1 RETURNING event_id, webhook_id, attempts
+        )
+        INSERT INTO webhook_dead_letters (event_id, webhook_id, attempts, last_error)
+        SELECT event_id, webhook_id, attempts, $2 FROM d;
+        "#,
+        delivery_id,
+        error
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Method, StatusCode};
+use serde_json::json;
+
+#[tokio::test]
+async fn get_answers_returns_answers_with_photos() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let answer_id = app.add_answer(question_id, "Answer", &["http://img/a.jpg"]).await;
+
+    let res = app.get(&format!("/api/v1/questions/{}/answers", question_id)).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let body = res.json();
+    assert_eq!(body["question_id"], question_id);
+    assert_eq!(body["page"], 1);
+    assert_eq!(body["count"], 5);
+
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["answer_id"], answer_id);
+    assert_eq!(results[0]["body"], "Answer");
+    assert_eq!(results[0]["answerer_name"], "answerer");
+    assert_eq!(results[0]["helpfulness"], 0);
+    assert!(results[0]["date"].is_string());
+    assert!(results[0].get("answerer_email").is_none());
+    assert_eq!(results[0]["photos"].as_array().unwrap().len(), 1);
+    assert_eq!(results[0]["photos"][0]["url"], "http://img/a.jpg");
+}
+
+#[tokio::test]
+async fn get_answers_for_unknown_question_is_empty() {
+    let app = TestApp::spawn().await;
+
+    let res = app.get("/api/v1/questions/999/answers").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json(), json!({ "question_id": 999, "page": 1, "count": 5, "results": [] }));
+}
+
+#[tokio::test]
+async fn get_answers_paginates() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let mut ids = Vec::new();
+    for i in 0..4 {
+        ids.push(app.add_answer(question_id, &format!("Answer {}", i), &[]).await);
+    }
+
+    let page_ids = |res: &common::TestResponse| -> Vec<i64> {
+        res.json()["results"].as_array().unwrap().iter().map(|a| a["answer_id"].as_i64().unwrap()).collect()
+    };
+
+    let res = app.get(&format!("/api/v1/questions/{}/answers?count=3", question_id)).await;
+    assert_eq!(page_ids(&res), ids[..3]);
+
+    let res = app.get(&format!("/api/v1/questions/{}/answers?page=2&count=3", question_id)).await;
+    assert_eq!(res.json()["page"], 2);
+    assert_eq!(res.json()["count"], 3);
+    assert_eq!(page_ids(&res), ids[3..]);
+
+    let res = app.get(&format!("/api/v1/questions/{}/answers?page=3&count=3", question_id)).await;
+    assert_eq!(page_ids(&res), Vec::<i64>::new());
+}
+
+#[tokio::test]
+async fn get_answers_rejects_bad_params() {
+    let app = TestApp::spawn().await;
+
+    for (uri, message) in [
+        ("/api/v1/questions/abc/answers", "Invalid question_id path parameter"),
+        ("/api/v1/questions//answers", "Invalid question_id path parameter"),
+        ("/api/v1/questions/1/answers?page=0", "Invalid page query parameter"),
+        ("/api/v1/questions/1/answers?count=many", "Invalid count query parameter"),
+        ("/api/v1/questions/1/answers?product_id=1", "Unexpected query parameter: product_id"),
+    ] {
+        let res = app.get(uri).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(res.text(), message, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn get_answers_hides_reported_answers() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let kept = app.add_answer(question_id, "Kept", &[]).await;
+    let reported = app.add_answer(question_id, "Reported", &[]).await;
+
+    let res = app.put(&format!("/api/v1/answers/{}/report", reported)).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert!(res.body.is_empty());
+
+    let res = app.get(&format!("/api/v1/questions/{}/answers", question_id)).await;
+    let results = res.json()["results"].as_array().unwrap().clone();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["answer_id"], kept);
+}
+
+#[tokio::test]
+async fn add_answer_returns_created_id() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+
+    let res = app
+        .post(
+            &format!("/api/v1/questions/{}/answers", question_id),
+            json!({ "body": "A", "name": "n", "email": "n@example.com", "photos": [] }),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert!(res.json()["answer_id"].is_i64());
+}
+
+#[tokio::test]
+async fn add_answer_to_unknown_question_is_not_found() {
+    let app = TestApp::spawn().await;
+
+    let res = app
+        .post("/api/v1/questions/424242/answers", json!({ "body": "A", "name": "n", "email": "e", "photos": [] }))
+        .await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(res.text(), "Question not found");
+}
+
+#[tokio::test]
+async fn add_answer_rejects_bad_path_and_malformed_bodies() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+
+    let res = app
+        .post("/api/v1/questions/abc/answers", json!({ "body": "A", "name": "n", "email": "e", "photos": [] }))
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.text(), "Invalid question_id path parameter");
+
+    for body in [
+        json!({ "body": "A", "name": "n", "email": "e" }),
+        json!({ "body": "A", "name": "n", "email": "e", "photos": "http://img/a.jpg" }),
+        json!([]),
+    ] {
+        let res = app.post(&format!("/api/v1/questions/{}/answers", question_id), body.clone()).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(res.text(), "Invalid request body");
+    }
+
+    let res = app.request(Method::POST, &format!("/api/v1/questions/{}/answers", question_id), "{").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn answer_helpful_increments_and_returns_no_content() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let answer_id = app.add_answer(question_id, "Answer", &[]).await;
+
+    let res = app.put(&format!("/api/v1/answers/{}/helpful", answer_id)).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert!(res.body.is_empty());
+
+    let res = app.get(&format!("/api/v1/questions/{}/answers", question_id)).await;
+    assert_eq!(res.json()["results"][0]["helpfulness"], 1);
+}
+
+#[tokio::test]
+async fn answer_updates_reject_bad_and_unknown_ids() {
+    let app = TestApp::spawn().await;
+
+    for action in ["helpful", "report"] {
+        let res = app.put(&format!("/api/v1/answers/1.5/{}", action)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.text(), "Invalid answer_id path parameter");
+
+        let res = app.put(&format!("/api/v1/answers/424242/{}", action)).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert_eq!(res.text(), "Answer not found");
+    }
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use qa_rs::auth::{self, Scope};
+use serde_json::json;
+
+async fn spawn_with_anonymous(scopes: &[Scope]) -> TestApp {
+    let scopes = scopes.to_vec();
+    TestApp::spawn_with(move |state| state.auth.anonymous_scopes = scopes).await
+}
+
+fn question() -> String {
+    json!({ "body": "Q", "name": "n", "email": "n@example.com", "product_id": 1 }).to_string()
+}
+
+#[tokio::test]
+async fn anonymous_requests_get_the_configured_scopes() {
+    let app = spawn_with_anonymous(&[Scope::Read]).await;
+
+    assert_eq!(app.get("/api/v1/questions?product_id=1").await.status, StatusCode::OK);
+
+    for (method, uri) in [
+        (Method::POST, "/api/v1/questions"),
+        (Method::PUT, "/api/v1/questions/1/helpful"),
+        (Method::PUT, "/api/v1/answers/1/report"),
+        (Method::GET, "/api/v1/admin/reports"),
+        (Method::POST, "/api/v1/admin/questions/1/approve"),
+    ] {
+        let res = app.request(method, uri, Body::empty()).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED, "{}", uri);
+        assert_eq!(res.text(), "Missing API key");
+    }
+
+    let app = spawn_with_anonymous(&[]).await;
+    let res = app.get("/api/v1/questions?product_id=1").await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+    // Metrics stay reachable for the scraper.
+    assert_eq!(app.get("/metrics").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn keys_are_limited_to_their_scopes() {
+    let app = spawn_with_anonymous(&[]).await;
+    let reader = app.create_api_key("reader", &[Scope::Read]).await;
+    let writer = app.create_api_key("writer", &[Scope::Read, Scope::Write]).await;
+    let admin = app.create_api_key("admin", &[Scope::Admin]).await;
+
+    let res = app.get_with_headers("/api/v1/questions?product_id=1", &[("x-api-key", &reader)]).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app.request_with_headers(Method::POST, "/api/v1/questions", &[("x-api-key", &reader)], question()).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(res.text(), "API key lacks the write scope");
+
+    let res = app.request_with_headers(Method::POST, "/api/v1/questions", &[("x-api-key", &writer)], question()).await;
+    assert_eq!(res.status, StatusCode::CREATED);
+
+    let res = app.get_with_headers("/api/v1/admin/reports", &[("x-api-key", &writer)]).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(res.text(), "API key lacks the admin scope");
+
+    // Admin keys can also read and write.
+    let res = app.get_with_headers("/api/v1/admin/reports", &[("x-api-key", &admin)]).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let res = app.request_with_headers(Method::POST, "/api/v1/questions", &[("x-api-key", &admin)], question()).await;
+    assert_eq!(res.status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn unknown_and_revoked_keys_are_rejected() {
+    let app = TestApp::spawn().await;
+    let key = app.create_api_key("client", &[Scope::Read]).await;
+
+    // A bad key is rejected even where anonymous access would be allowed.
+    for bad in ["qa_0000", "", "not a key"] {
+        let res = app.get_with_headers("/api/v1/questions?product_id=1", &[("x-api-key", bad)]).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED, "{:?}", bad);
+        assert_eq!(res.text(), "Invalid API key");
+    }
+
+    let id = auth::list_api_keys(&app.state.db).await.unwrap()[0].id;
+    assert!(auth::revoke_api_key(&app.state.db, id).await.unwrap());
+    assert!(!auth::revoke_api_key(&app.state.db, id).await.unwrap());
+
+    let res = app.get_with_headers("/api/v1/questions?product_id=1", &[("x-api-key", &key)]).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn only_key_hashes_are_stored() {
+    let app = TestApp::spawn().await;
+    let key = app.create_api_key("storefront", &[Scope::Read, Scope::Write]).await;
+
+    let keys = auth::list_api_keys(&app.state.db).await.unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].name, "storefront");
+    assert_eq!(keys[0].scopes, "read,write");
+    assert!(keys[0].revoked_at.is_none());
+    assert!(key.starts_with(&keys[0].key_prefix));
+    assert!(keys[0].key_prefix.len() < key.len());
+
+    let stored = serde_json::to_string(&keys).unwrap();
+    assert!(!stored.contains(&key));
+}
+
+#[tokio::test]
+async fn moderation_actions_record_the_api_key() {
+    let app = spawn_with_anonymous(&[Scope::Read, Scope::Write]).await;
+    let admin = app.create_api_key("moderator-ann", &[Scope::Admin]).await;
+    let question_id = app.add_question(1, "Question").await;
+    app.put(&format!("/api/v1/questions/{}/report", question_id)).await;
+
+    let res = app
+        .request_with_headers(Method::POST, &format!("/api/v1/admin/questions/{}/remove", question_id), &[("x-api-key", &admin)], Body::empty())
+        .await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    let res = app.get_with_headers("/api/v1/admin/actions", &[("x-api-key", &admin)]).await;
+    assert_eq!(res.json()["results"][0]["moderator"], "moderator-ann");
+}
+
+#[test]
+fn scope_lists_are_validated() {
+    assert_eq!(auth::parse_scopes("read, write").unwrap(), vec![Scope::Read, Scope::Write]);
+    assert_eq!(auth::parse_scopes("").unwrap(), vec![]);
+    assert_eq!(auth::parse_scopes("read,delete").unwrap_err(), "Unknown scope: delete");
+}
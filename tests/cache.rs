@@ -0,0 +1,116 @@
+mod common;
+
+use common::TestApp;
+use hyper::StatusCode;
+use qa_rs::cache::{CacheStats, MAX_PAGES_PER_PRODUCT};
+use qa_rs::utils::MAX_PAGE_COUNT;
+
+fn stats(app: &TestApp) -> CacheStats {
+    app.state.questions_cache.stats()
+}
+
+/// Makes sure both products are cached before the next write.
+async fn warm(app: &TestApp) {
+    app.get("/api/v1/questions?product_id=1").await;
+    app.get("/api/v1/questions?product_id=2").await;
+    assert_eq!(stats(app).products, 2);
+}
+
+#[tokio::test]
+async fn repeated_question_lists_are_served_from_the_cache() {
+    let app = TestApp::spawn().await;
+    app.add_question(1, "Question").await;
+
+    let first = app.get("/api/v1/questions?product_id=1").await;
+    let second = app.get("/api/v1/questions?product_id=1").await;
+    assert_eq!(first.status, StatusCode::OK);
+    assert_eq!(first.body, second.body);
+    assert_eq!((stats(&app).hits, stats(&app).misses), (1, 1));
+
+    // Every page, count and sort combination is cached separately.
+    app.get("/api/v1/questions?product_id=1&page=2").await;
+    app.get("/api/v1/questions?product_id=1&count=10").await;
+    app.get("/api/v1/questions?product_id=1&sort=newest").await;
+    assert_eq!((stats(&app).hits, stats(&app).misses), (1, 4));
+    assert_eq!(stats(&app).products, 1);
+}
+
+#[tokio::test]
+async fn pages_cached_per_product_are_bounded() {
+    let app = TestApp::spawn().await;
+
+    for page in 1..=MAX_PAGES_PER_PRODUCT + 5 {
+        app.get(&format!("/api/v1/questions?product_id=1&page={}", page)).await;
+    }
+    assert_eq!(stats(&app).pages, MAX_PAGES_PER_PRODUCT);
+
+    // The oldest pages made room for the newest.
+    let misses = stats(&app).misses;
+    app.get(&format!("/api/v1/questions?product_id=1&page={}", MAX_PAGES_PER_PRODUCT + 5)).await;
+    app.get("/api/v1/questions?product_id=1&page=1").await;
+    assert_eq!(stats(&app).misses, misses + 1);
+
+    // Counts beyond the largest one share its page.
+    app.get(&format!("/api/v1/questions?product_id=2&count={}", MAX_PAGE_COUNT)).await;
+    let res = app.get("/api/v1/questions?product_id=2&count=1000000").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(stats(&app).misses, misses + 2);
+}
+
+#[tokio::test]
+async fn writes_invalidate_the_touched_product() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let other_question_id = app.add_question(2, "Other product").await;
+    let answer_id = app.add_answer(question_id, "Answer", &[]).await;
+
+    let list = "/api/v1/questions?product_id=1";
+
+    warm(&app).await;
+    app.add_question(1, "Another question").await;
+    assert_eq!(app.get(list).await.json()["results"].as_array().unwrap().len(), 2);
+
+    warm(&app).await;
+    app.add_answer(question_id, "Another answer", &[]).await;
+    assert_eq!(app.get(list).await.json()["results"][0]["answers"].as_object().unwrap().len(), 2);
+
+    warm(&app).await;
+    app.put(&format!("/api/v1/questions/{}/helpful", question_id)).await;
+    assert_eq!(app.get(list).await.json()["results"][0]["question_helpfulness"], 1);
+
+    warm(&app).await;
+    app.put(&format!("/api/v1/answers/{}/helpful", answer_id)).await;
+    assert_eq!(app.get(list).await.json()["results"][0]["answers"][answer_id.to_string()]["helpfulness"], 1);
+
+    warm(&app).await;
+    app.put(&format!("/api/v1/answers/{}/report", answer_id)).await;
+    assert_eq!(app.get(list).await.json()["results"][0]["answers"].as_object().unwrap().len(), 1);
+
+    warm(&app).await;
+    app.put(&format!("/api/v1/questions/{}/report", question_id)).await;
+    assert_eq!(app.get(list).await.json()["results"].as_array().unwrap().len(), 1);
+
+    // Product 2 stayed cached throughout.
+    let hits = stats(&app).hits;
+    let res = app.get("/api/v1/questions?product_id=2").await;
+    assert_eq!(res.json()["results"][0]["question_id"], other_question_id);
+    assert_eq!(stats(&app).hits, hits + 1);
+    assert_eq!(stats(&app).invalidations, 6);
+}
+
+#[tokio::test]
+async fn metrics_report_cache_counters() {
+    let app = TestApp::spawn().await;
+    app.get("/api/v1/questions?product_id=1").await;
+    app.get("/api/v1/questions?product_id=1").await;
+    app.get("/api/v1/questions?product_id=1").await;
+
+    let res = app.get("/metrics").await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let text = res.text();
+    assert!(text.contains("qa_questions_cache_hits_total 2\n"), "{}", text);
+    assert!(text.contains("qa_questions_cache_misses_total 1\n"), "{}", text);
+    assert!(text.contains("qa_questions_cache_products 1\n"), "{}", text);
+    assert!(text.contains("qa_questions_cache_pages 1\n"), "{}", text);
+}
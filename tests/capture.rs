@@ -0,0 +1,104 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use qa_rs::capture::{parse_capture, route_name, RequestCapture, MAX_CAPTURED_BODY, REDACTED_EMAIL};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static CAPTURE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn capture_path() -> PathBuf {
+    let name = format!("qa_capture_{}_{}.jsonl", std::process::id(), CAPTURE_COUNTER.fetch_add(1, Ordering::SeqCst));
+    let path = std::env::temp_dir().join(name);
+    std::fs::remove_file(&path).ok();
+    path
+}
+
+#[tokio::test]
+async fn handled_requests_are_captured_without_emails() {
+    let path = capture_path();
+    let capture = RequestCapture::to_file(&path).unwrap();
+    let app = TestApp::spawn_with(|state| state.capture = capture).await;
+
+    let res = app
+        .post(
+            "/api/v1/questions",
+            json!({ "body": "Write to me at bob@example.org", "name": "bob", "email": "bob@example.org", "product_id": 5 }),
+        )
+        .await;
+    let question_id = res.json()["question_id"].as_i64().unwrap();
+    app.get("/api/v1/questions?product_id=5&count=2").await;
+    app.request_with_headers(Method::PUT, &format!("/api/v1/questions/{}/helpful", question_id), &[("x-user-token", "shopper-1")], Body::empty())
+        .await;
+    app.put("/api/v1/questions/999999/helpful").await;
+
+    // Admin requests are not captured.
+    app.get("/api/v1/admin/reports").await;
+    app.post("/api/v1/admin/data-requests/export", json!({ "email": "bob@example.org" })).await;
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert!(!text.contains("bob@example.org"), "{}", text);
+    let requests = parse_capture(&text).unwrap();
+    let summary: Vec<(&str, &str, Option<&str>, u16)> =
+        requests.iter().map(|request| (request.method.as_str(), request.path.as_str(), request.query.as_deref(), request.status)).collect();
+    assert_eq!(
+        summary,
+        [
+            ("POST", "/api/v1/questions", None, 201),
+            ("GET", "/api/v1/questions", Some("product_id=5&count=2"), 200),
+            ("PUT", format!("/api/v1/questions/{}/helpful", question_id).as_str(), None, 204),
+            ("PUT", "/api/v1/questions/999999/helpful", None, 404),
+        ]
+    );
+
+    assert_eq!(
+        requests[0].body,
+        Some(json!({ "body": "Write to me at [email]", "name": "bob", "email": REDACTED_EMAIL, "product_id": 5 }))
+    );
+    assert_eq!(requests[1].body, None);
+
+    // Requests from one voter share a pseudonym, which is not the token itself.
+    assert_eq!(requests[0].client, requests[1].client);
+    assert_ne!(requests[2].client, requests[0].client);
+    assert_eq!(requests[2].client.len(), 16);
+    assert!(requests.windows(2).all(|pair| pair[0].at_ms <= pair[1].at_ms));
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_handled_but_not_captured() {
+    let path = capture_path();
+    let capture = RequestCapture::to_file(&path).unwrap();
+    let app = TestApp::spawn_with(|state| state.capture = capture).await;
+
+    let mut body = json!({ "body": "Padded", "name": "bob", "email": "bob@example.org", "product_id": 5 }).to_string();
+    body.push_str(&" ".repeat(MAX_CAPTURED_BODY));
+    let res = app.request(Method::POST, "/api/v1/questions", body).await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    let requests = parse_capture(&text).unwrap();
+    assert_eq!((requests[0].status, &requests[0].body), (201, &None));
+}
+
+#[test]
+fn capture_files_parse_in_order_of_arrival() {
+    let text = r#"
+{"at_ms":20,"method":"GET","path":"/api/v1/questions","query":"product_id=1","body":null,"client":"a","status":200,"duration_ms":3}
+
+{"at_ms":10,"method":"POST","path":"/api/v1/questions","query":null,"body":{"body":"Hi"},"client":"b","status":201,"duration_ms":5}
+"#;
+    let requests = parse_capture(text).unwrap();
+    assert_eq!(requests.iter().map(|request| request.at_ms).collect::<Vec<_>>(), [10, 20]);
+    assert_eq!(requests[0].body, Some(json!({ "body": "Hi" })));
+
+    let error = parse_capture("{\"at_ms\":1}\nnot json").unwrap_err();
+    assert!(error.starts_with("Invalid captured request on line 1"), "{}", error);
+
+    assert_eq!(route_name("PUT", "/api/v1/answers/12/helpful"), "PUT /api/v1/answers/:id/helpful");
+    assert_eq!(route_name("GET", "/api/v1/products/7/qa-stats"), "GET /api/v1/products/:id/qa-stats");
+    assert_eq!(route_name("GET", "/api/v1/questions"), "GET /api/v1/questions");
+}
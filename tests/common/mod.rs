@@ -0,0 +1,247 @@
+#![allow(dead_code)]
+
+use hyper::{body::Bytes, header::HeaderMap, Body, Method, Request, StatusCode};
+use qa_rs::{auth::{self, AuthConfig, Scope}, cache::QuestionsCache, capture::RequestCapture, conditional::CacheControl, db::Database, emails::EmailProtector, fetch::PhotoFetcher, notifications::Notifications, proxy::TrustedProxies, ratelimit::RateLimiter, routes, state::AppState, storage::PhotoStorage, votes::VoterHasher};
+use sqlx::{Connection, Executor, PgConnection};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+static DATABASE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A router backed by a disposable database.
+///
+/// The backend is picked from `TEST_DATABASE_URL`, falling back to `DATABASE_URL`. For
+/// Postgres a fresh database is created next to the one the URL points at and dropped
+/// again when the app goes out of scope; for `sqlite:` URLs an in-memory database is used.
+/// The questions cache is enabled so every test also exercises its invalidation. Requests
+/// without an API key get every scope and rate limiting is off unless a test configures
+/// otherwise with [`TestApp::spawn_with`]. Photos are stored in a temporary directory
+/// removed along with the database.
+pub struct TestApp {
+    pub state: Arc<AppState>,
+    pub photo_dir: PathBuf,
+    cleanup: Option<(String, String)>,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| {
+            panic!("response body is not JSON ({}): {:?}", e, String::from_utf8_lossy(&self.body))
+        })
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|v| v.to_str().unwrap())
+    }
+}
+
+impl TestApp {
+    pub async fn spawn() -> TestApp {
+        TestApp::spawn_with(|_| {}).await
+    }
+
+    /// Spawns an app whose state is adjusted by `configure` before the first request.
+    pub async fn spawn_with(configure: impl FnOnce(&mut AppState)) -> TestApp {
+        dotenv::dotenv().ok();
+
+        let base_url = std::env::var("TEST_DATABASE_URL")
+            .or_else(|_| std::env::var("DATABASE_URL"))
+            .expect("TEST_DATABASE_URL or DATABASE_URL must be set to run the integration tests");
+
+        let database_name = format!(
+            "qa_test_{}_{}",
+            std::process::id(),
+            DATABASE_COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let photo_dir = std::env::temp_dir().join(format!("{}_photos", database_name));
+
+        if base_url.starts_with("sqlite:") {
+            let db = Database::connect("sqlite::memory:").await.expect("failed to open in-memory SQLite database");
+            return TestApp { state: app_state(db, &photo_dir, configure), photo_dir, cleanup: None };
+        }
+
+        let mut admin = PgConnection::connect(&base_url).await.expect("failed to connect to Postgres");
+        admin
+            .execute(format!("CREATE DATABASE {}", database_name).as_str())
+            .await
+            .expect("failed to create test database");
+        admin.close().await.ok();
+
+        let db = Database::connect(&with_database_name(&base_url, &database_name))
+            .await
+            .expect("failed to connect to test database");
+
+        TestApp { state: app_state(db, &photo_dir, configure), photo_dir, cleanup: Some((base_url, database_name)) }
+    }
+
+    pub async fn request(&self, method: Method, uri: &str, body: impl Into<Body>) -> TestResponse {
+        self.request_with_headers(method, uri, &[], body).await
+    }
+
+    pub async fn request_with_headers(&self, method: Method, uri: &str, headers: &[(&str, &str)], body: impl Into<Body>) -> TestResponse {
+        self.request_from(SocketAddr::from(([127, 0, 0, 1], 50000)), method, uri, headers, body).await
+    }
+
+    /// Sends a request as if it arrived on a connection from `remote_addr`.
+    pub async fn request_from(&self, remote_addr: SocketAddr, method: Method, uri: &str, headers: &[(&str, &str)], body: impl Into<Body>) -> TestResponse {
+        let mut req = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let req = req.body(body.into()).unwrap();
+
+        let res = routes::handle_request(self.state.clone(), remote_addr, req).await.expect("router returned an error");
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+        TestResponse { status, headers, body }
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.request(Method::GET, uri, Body::empty()).await
+    }
+
+    pub async fn get_with_headers(&self, uri: &str, headers: &[(&str, &str)]) -> TestResponse {
+        self.request_with_headers(Method::GET, uri, headers, Body::empty()).await
+    }
+
+    pub async fn post(&self, uri: &str, body: serde_json::Value) -> TestResponse {
+        self.request(Method::POST, uri, body.to_string()).await
+    }
+
+    pub async fn put(&self, uri: &str) -> TestResponse {
+        self.request(Method::PUT, uri, Body::empty()).await
+    }
+
+    /// Sends a helpful vote (`PUT`) or withdraws it (`DELETE`) with the given user token.
+    pub async fn vote(&self, method: Method, uri: &str, user_token: &str) -> TestResponse {
+        self.request_with_headers(method, uri, &[("x-user-token", user_token)], Body::empty()).await
+    }
+
+    /// Dates every question and answer back to 2020, so that a write made straight after
+    /// moves Last-Modified forward. Cached lists are not touched, so call it before reading.
+    pub async fn backdate(&self) {
+        for sql in [
+            "UPDATE questions SET date_written = '2020-01-01T00:00:00', updated_at = NULL;",
+            "UPDATE answers SET date_written = '2020-01-01T00:00:00', updated_at = NULL;",
+        ] {
+            match &self.state.db {
+                Database::Postgres(pool) => sqlx::query(sql).execute(pool).await.map(drop),
+                #[cfg(feature = "sqlite")]
+                Database::Sqlite(pool) => sqlx::query(sql).execute(pool).await.map(drop),
+            }
+            .unwrap();
+        }
+    }
+
+    /// Creates an API key with the given scopes and returns it.
+    pub async fn create_api_key(&self, name: &str, scopes: &[Scope]) -> String {
+        let (_, key) = auth::create_api_key(&self.state.db, name, scopes).await.expect("failed to create API key");
+        key
+    }
+
+    /// Creates a question through the API and returns its id.
+    pub async fn add_question(&self, product_id: i32, body: &str) -> i64 {
+        let res = self
+            .post(
+                "/api/v1/questions",
+                serde_json::json!({
+                    "body": body,
+                    "name": "asker",
+                    "email": "asker@example.com",
+                    "product_id": product_id,
+                }),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+        res.json()["question_id"].as_i64().unwrap()
+    }
+
+    /// Creates an answer through the API and returns its id.
+    pub async fn add_answer(&self, question_id: i64, body: &str, photos: &[&str]) -> i64 {
+        let res = self
+            .post(
+                &format!("/api/v1/questions/{}/answers", question_id),
+                serde_json::json!({
+                    "body": body,
+                    "name": "answerer",
+                    "email": "answerer@example.com",
+                    "photos": photos,
+                }),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+        res.json()["answer_id"].as_i64().unwrap()
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.photo_dir).ok();
+        if let Some((base_url, database_name)) = self.cleanup.take() {
+            // Drop runs inside the test's runtime, so the async cleanup gets a runtime of its own.
+            std::thread::spawn(move || {
+                tokio::runtime::Runtime::new().unwrap().block_on(async move {
+                    if let Ok(mut admin) = PgConnection::connect(&base_url).await {
+                        let _ = admin
+                            .execute(format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", database_name).as_str())
+                            .await;
+                    }
+                });
+            })
+            .join()
+            .ok();
+        }
+    }
+}
+
+fn app_state(db: Database, photo_dir: &Path, configure: impl FnOnce(&mut AppState)) -> Arc<AppState> {
+    let mut state = AppState {
+        db,
+        questions_cache: QuestionsCache::new(100, Duration::from_secs(60)),
+        cache_control: CacheControl::default(),
+        voters: VoterHasher::new("test"),
+        emails: EmailProtector::plaintext(),
+        auth: AuthConfig { anonymous_scopes: vec![Scope::Read, Scope::Write, Scope::Admin], jwt: None },
+        trusted_proxies: TrustedProxies::default(),
+        rate_limiter: RateLimiter::disabled(),
+        capture: RequestCapture::disabled(),
+        photos: PhotoStorage::local(photo_dir, None),
+        // Tests fetch photos from stand-in servers on localhost.
+        photo_fetcher: PhotoFetcher { allow_private: true, ..PhotoFetcher::new(Duration::from_secs(5)) },
+        notifications: Notifications::disabled(),
+    };
+    configure(&mut state);
+
+    Arc::new(state)
+}
+
+/// Replaces the database name in a Postgres connection URL.
+fn with_database_name(url: &str, database_name: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (url, None),
+    };
+    let (server, _) = base.rsplit_once('/').expect("DATABASE_URL has no database name");
+
+    match query {
+        Some(query) => format!("{}/{}?{}", server, database_name, query),
+        None => format!("{}/{}", server, database_name),
+    }
+}
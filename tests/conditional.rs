@@ -0,0 +1,107 @@
+mod common;
+
+use common::TestApp;
+use hyper::StatusCode;
+
+const QUESTIONS: &str = "/api/v1/questions?product_id=1";
+
+#[tokio::test]
+async fn question_lists_carry_validators_and_cache_control() {
+    let app = TestApp::spawn().await;
+    app.add_question(1, "Question").await;
+
+    let res = app.get(QUESTIONS).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let etag = res.header("etag").unwrap();
+    assert!(etag.starts_with('"') && etag.ends_with('"') && !etag.starts_with("W/"), "{}", etag);
+    assert!(httpdate::parse_http_date(res.header("last-modified").unwrap()).is_ok());
+    assert_eq!(res.header("cache-control"), Some("public, no-cache"));
+
+    // Cached and freshly queried responses carry the same validators.
+    assert_eq!(app.get(QUESTIONS).await.header("etag"), Some(etag));
+}
+
+#[tokio::test]
+async fn matching_if_none_match_returns_not_modified() {
+    let app = TestApp::spawn().await;
+    app.add_question(1, "Question").await;
+
+    let etag = app.get(QUESTIONS).await.header("etag").unwrap().to_string();
+
+    for if_none_match in [etag.clone(), format!("W/{}", etag), format!("\"other\", {}", etag), "*".to_string()] {
+        let res = app.get_with_headers(QUESTIONS, &[("if-none-match", &if_none_match)]).await;
+        assert_eq!(res.status, StatusCode::NOT_MODIFIED, "{}", if_none_match);
+        assert!(res.body.is_empty());
+        assert_eq!(res.header("etag"), Some(etag.as_str()));
+        assert_eq!(res.header("cache-control"), Some("public, no-cache"));
+    }
+
+    let res = app.get_with_headers(QUESTIONS, &[("if-none-match", "\"other\"")]).await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn writes_change_the_etag() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let etag = app.get(QUESTIONS).await.header("etag").unwrap().to_string();
+
+    app.put(&format!("/api/v1/questions/{}/helpful", question_id)).await;
+
+    let res = app.get_with_headers(QUESTIONS, &[("if-none-match", &etag)]).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_ne!(res.header("etag"), Some(etag.as_str()));
+}
+
+#[tokio::test]
+async fn if_modified_since_is_honoured_when_there_is_no_if_none_match() {
+    let app = TestApp::spawn().await;
+    app.add_question(1, "Question").await;
+    let last_modified = app.get(QUESTIONS).await.header("last-modified").unwrap().to_string();
+
+    let res = app.get_with_headers(QUESTIONS, &[("if-modified-since", &last_modified)]).await;
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+
+    let res = app.get_with_headers(QUESTIONS, &[("if-modified-since", "Thu, 01 Jan 2015 00:00:00 GMT")]).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app.get_with_headers(QUESTIONS, &[("if-modified-since", "not a date")]).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    // If-None-Match takes precedence over If-Modified-Since.
+    let res = app
+        .get_with_headers(QUESTIONS, &[("if-none-match", "\"other\""), ("if-modified-since", &last_modified)])
+        .await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn answer_lists_support_conditional_requests() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let answer_id = app.add_answer(question_id, "Answer", &[]).await;
+    let uri = format!("/api/v1/questions/{}/answers", question_id);
+
+    let res = app.get(&uri).await;
+    let etag = res.header("etag").unwrap().to_string();
+    assert!(res.header("last-modified").is_some());
+    assert_eq!(res.header("cache-control"), Some("public, no-cache"));
+
+    let res = app.get_with_headers(&uri, &[("if-none-match", &etag)]).await;
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+
+    app.put(&format!("/api/v1/answers/{}/helpful", answer_id)).await;
+
+    let res = app.get_with_headers(&uri, &[("if-none-match", &etag)]).await;
+    assert_eq!(res.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn empty_lists_have_an_etag_but_no_last_modified() {
+    let app = TestApp::spawn().await;
+
+    let res = app.get(QUESTIONS).await;
+    assert!(res.header("etag").is_some());
+    assert!(res.header("last-modified").is_none());
+}
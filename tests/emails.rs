@@ -0,0 +1,169 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use qa_rs::db::Database;
+use qa_rs::emails::{reencrypt_emails, EmailKey, EmailProtection, EmailProtector, ReencryptReport};
+use qa_rs::utils::format_error;
+
+const KEY_A: [u8; 32] = [1; 32];
+const KEY_B: [u8; 32] = [2; 32];
+
+fn encrypting(key: &[u8; 32], old_keys: &[&[u8; 32]]) -> EmailProtector {
+    let old_keys = old_keys.iter().map(|key| EmailKey::new(key)).collect();
+    EmailProtector::new(EmailProtection::Encrypt, Some(EmailKey::new(key)), old_keys).unwrap()
+}
+
+/// The email and hash columns of every question and answer.
+async fn stored_emails(app: &TestApp) -> Vec<(String, Option<String>)> {
+    let sql = "SELECT asker_email, asker_email_hash FROM questions UNION ALL SELECT answerer_email, answerer_email_hash FROM answers";
+    match &app.state.db {
+        Database::Postgres(pool) => sqlx::query_as(sql).fetch_all(pool).await.unwrap(),
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlx::query_as(sql).fetch_all(pool).await.unwrap(),
+    }
+}
+
+/// Creates answered questions, one of them reported, and a moderation action, and returns
+/// every read endpoint's response body.
+async fn read_everything(app: &TestApp) -> Vec<String> {
+    let question_id = app.add_question(1, "Does it run small?").await;
+    let answer_id = app.add_answer(question_id, "No", &["https://example.com/a.jpg"]).await;
+    let reported_id = app.add_question(1, "Is it waterproof?").await;
+    app.request(Method::PUT, &format!("/api/v1/questions/{}/report", reported_id), Body::empty()).await;
+    app.request(Method::PUT, &format!("/api/v1/answers/{}/report", answer_id), Body::empty()).await;
+    let res = app.request(Method::POST, &format!("/api/v1/admin/answers/{}/approve", answer_id), Body::empty()).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    let mut bodies = Vec::new();
+    for uri in [
+        "/api/v1/questions?product_id=1".to_string(),
+        format!("/api/v1/questions/{}/answers", question_id),
+        "/api/v1/admin/reports".to_string(),
+        "/api/v1/admin/actions".to_string(),
+        "/metrics".to_string(),
+    ] {
+        let res = app.get(&uri).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", uri);
+        bodies.push(res.text());
+    }
+    assert!(bodies[0].contains("Does it run small?") && bodies[0].contains("answerer"), "{}", bodies[0]);
+    assert!(bodies[2].contains("Is it waterproof?"), "{}", bodies[2]);
+
+    bodies
+}
+
+fn assert_no_emails(bodies: &[String]) {
+    for body in bodies {
+        assert!(!body.contains("asker@example.com") && !body.contains("answerer@example.com"), "{}", body);
+        assert!(!body.contains("enc:v1:"), "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn read_endpoints_never_return_emails() {
+    let app = TestApp::spawn().await;
+    assert_no_emails(&read_everything(&app).await);
+
+    let app = TestApp::spawn_with(|state| state.emails = encrypting(&KEY_A, &[])).await;
+    assert_no_emails(&read_everything(&app).await);
+}
+
+#[tokio::test]
+async fn emails_are_encrypted_at_rest() {
+    let app = TestApp::spawn_with(|state| state.emails = encrypting(&KEY_A, &[])).await;
+    let question_id = app.add_question(1, "Q").await;
+    app.add_answer(question_id, "A", &[]).await;
+
+    let stored = stored_emails(&app).await;
+    assert_eq!(stored.len(), 2);
+    for (email, hash) in &stored {
+        assert!(email.starts_with("enc:v1:") && !email.contains('@'), "{}", email);
+        assert_eq!(hash.as_ref().map(String::len), Some(64));
+    }
+
+    let emails = &app.state.emails;
+    assert_eq!(emails.open(&stored[0].0).as_deref(), Some("asker@example.com"));
+    assert_eq!(emails.open(&stored[1].0).as_deref(), Some("answerer@example.com"));
+
+    // The same address always hashes the same, whatever its case, but encrypts differently.
+    let (first, second) = (emails.seal("Asker@Example.com "), emails.seal("asker@example.com"));
+    assert_eq!(first.hash, stored[0].1);
+    assert_eq!(first.hash, second.hash);
+    assert_ne!(first.stored, second.stored);
+
+    // Without the key, or with a tampered value, nothing can be read back.
+    assert_eq!(encrypting(&KEY_B, &[]).open(&stored[0].0), None);
+    let mut tampered = stored[0].0.clone();
+    tampered.replace_range(tampered.len() - 4.., "AAAA");
+    assert_eq!(emails.open(&tampered), None);
+}
+
+#[tokio::test]
+async fn hashed_emails_cannot_be_read_back() {
+    let hashing = || EmailProtector::new(EmailProtection::Hash, Some(EmailKey::new(&KEY_A)), vec![]).unwrap();
+    let app = TestApp::spawn_with(move |state| state.emails = hashing()).await;
+    app.add_question(1, "Q").await;
+
+    let (email, hash) = stored_emails(&app).await.remove(0);
+    assert_eq!(email, format!("hmac:v1:{}", &email["hmac:v1:".len()..]));
+    assert!(email.ends_with(hash.as_deref().unwrap()));
+    assert_eq!(app.state.emails.open(&email), None);
+}
+
+#[tokio::test]
+async fn reencrypt_seals_existing_rows_and_rotates_keys() {
+    let app = TestApp::spawn().await;
+    for product_id in 1..=3 {
+        let question_id = app.add_question(product_id, "Q").await;
+        app.add_answer(question_id, "A", &[]).await;
+    }
+    assert!(stored_emails(&app).await.iter().all(|(email, hash)| email.contains('@') && hash.is_none()));
+
+    // A batch size of one makes sure every batch is picked up.
+    let key_a = encrypting(&KEY_A, &[]);
+    let report = reencrypt_emails(&app.state.db, &key_a, 1).await.unwrap();
+    assert_eq!(report, ReencryptReport { reencrypted: 6, skipped: 0 });
+    let stored = stored_emails(&app).await;
+    assert!(stored.iter().all(|(email, _)| key_a.open(email).is_some_and(|email| email.ends_with("@example.com"))));
+
+    // Running it again has nothing left to do.
+    assert_eq!(reencrypt_emails(&app.state.db, &key_a, 2).await.unwrap().reencrypted, 0);
+
+    // Rotating moves everything to the new key, after which the old one can be dropped.
+    let key_b = encrypting(&KEY_B, &[&KEY_A]);
+    assert_eq!(reencrypt_emails(&app.state.db, &key_b, 4).await.unwrap().reencrypted, 6);
+    let only_b = encrypting(&KEY_B, &[]);
+    assert!(stored_emails(&app).await.iter().all(|(email, _)| only_b.open(email).is_some()));
+
+    // Rows sealed with a key that is gone are reported rather than lost.
+    let report = reencrypt_emails(&app.state.db, &key_a, 4).await.unwrap();
+    assert_eq!(report, ReencryptReport { reencrypted: 0, skipped: 6 });
+}
+
+#[test]
+fn logged_errors_mask_emails() {
+    let error = r#"Database(PgDatabaseError { detail: Some("Failing row contains (7, 1, Q, 2023-05-01, asker, Asker.Name+qa@mail.example.com, f, 0)."), .. })"#;
+    let line = format_error("Failed to add question", &error);
+
+    assert!(line.starts_with("Failed to add question: "));
+    assert!(!line.contains("mail.example.com") && !line.contains("Asker.Name"), "{}", line);
+    assert!(line.contains("asker, [email], f, 0)."), "{}", line);
+
+    // Other uses of @ are left alone.
+    let line = format_error("Failed", &"@ 10:00 user@localhost");
+    assert!(line.contains("@ 10:00 user@localhost"), "{}", line);
+}
+
+#[test]
+fn handlers_log_through_the_masking_logger() {
+    for (file, source) in [
+        ("handlers.rs", include_str!("../src/handlers.rs")),
+        ("sqlite.rs", include_str!("../src/sqlite.rs")),
+        ("moderation.rs", include_str!("../src/moderation.rs")),
+        ("privacy.rs", include_str!("../src/privacy.rs")),
+        ("routes.rs", include_str!("../src/routes.rs")),
+    ] {
+        assert!(!source.contains("println!") && !source.contains("eprintln!"), "{} prints directly", file);
+    }
+}
@@ -0,0 +1,180 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use qa_rs::{auth::Scope, jwt::JwtVerifier};
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECRET: &[u8] = b"storefront-secret";
+const JWKS: &str = include_str!("fixtures/jwks.json");
+const RSA_KEY: &[u8] = include_bytes!("fixtures/jwt_rs256.pem");
+
+/// Spawns an app that only lets anonymous requests read, and accepts HS256 tokens signed with
+/// [`SECRET`] and RS256 tokens signed with the key in `fixtures/`.
+async fn spawn() -> TestApp {
+    TestApp::spawn_with(|state| {
+        state.auth.anonymous_scopes = vec![Scope::Read];
+        state.auth.jwt = Some(JwtVerifier::new(Some(SECRET), Some(JWKS), Some("storefront".into()), None).unwrap());
+    })
+    .await
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+fn claims(sub: &str) -> Value {
+    json!({ "sub": sub, "name": "Shopper", "email": "shopper@example.com", "iss": "storefront", "exp": now() + 600 })
+}
+
+fn hs256(claims: &Value) -> String {
+    encode(&Header::new(Algorithm::HS256), claims, &EncodingKey::from_secret(SECRET)).unwrap()
+}
+
+fn rs256(kid: Option<&str>, claims: &Value) -> String {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = kid.map(String::from);
+    encode(&header, claims, &EncodingKey::from_rsa_pem(RSA_KEY).unwrap()).unwrap()
+}
+
+async fn post_question(app: &TestApp, token: Option<&str>) -> common::TestResponse {
+    let authorization = token.map(|token| format!("Bearer {}", token));
+    let headers: Vec<(&str, &str)> = authorization.iter().map(|value| ("authorization", value.as_str())).collect();
+    let body = json!({ "body": "Does it run small?", "name": "spoofed", "email": "spoofed@example.com", "product_id": 7 });
+
+    app.request_with_headers(Method::POST, "/api/v1/questions", &headers, body.to_string()).await
+}
+
+#[tokio::test]
+async fn authors_are_named_by_token_claims() {
+    let app = spawn().await;
+
+    let res = post_question(&app, Some(&hs256(&claims("user-1")))).await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+    let question_id = res.json()["question_id"].as_i64().unwrap();
+
+    // No name or email is needed in the body once the token supplies them.
+    let res = app
+        .request_with_headers(
+            Method::POST,
+            &format!("/api/v1/questions/{}/answers", question_id),
+            &[("authorization", &format!("Bearer {}", rs256(Some("storefront-1"), &claims("user-2"))))],
+            json!({ "body": "True to size.", "photos": [] }).to_string(),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+
+    let body = app.get("/api/v1/questions?product_id=7").await.json();
+    let question = &body["results"][0];
+    assert_eq!(question["asker_name"], "Shopper");
+    let answer = question["answers"].as_object().unwrap().values().next().unwrap();
+    assert_eq!(answer["answerer_name"], "Shopper");
+}
+
+#[tokio::test]
+async fn posting_requires_a_token_once_configured() {
+    let app = spawn().await;
+
+    let res = post_question(&app, None).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.text(), "Missing bearer token");
+
+    // An API key with the write scope is still not a shopper.
+    let key = app.create_api_key("importer", &[Scope::Write]).await;
+    let res = app
+        .request_with_headers(
+            Method::POST,
+            "/api/v1/questions",
+            &[("x-api-key", &key)],
+            json!({ "body": "Q", "name": "n", "email": "n@example.com", "product_id": 7 }).to_string(),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.text(), "Missing bearer token");
+}
+
+#[tokio::test]
+async fn rejects_expired_and_malformed_tokens() {
+    let app = spawn().await;
+
+    let mut expired = claims("user-1");
+    expired["exp"] = json!(now() - 3600);
+    let res = post_question(&app, Some(&hs256(&expired))).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.text(), "Expired bearer token");
+    assert_eq!(res.header("www-authenticate"), Some(r#"Bearer error="invalid_token""#));
+
+    let mut wrong_issuer = claims("user-1");
+    wrong_issuer["iss"] = json!("elsewhere");
+    let mut no_email = claims("user-1");
+    no_email.as_object_mut().unwrap().remove("email");
+    let mut no_expiry = claims("user-1");
+    no_expiry.as_object_mut().unwrap().remove("exp");
+    let wrong_secret = encode(&Header::new(Algorithm::HS256), &claims("user-1"), &EncodingKey::from_secret(b"guess")).unwrap();
+    let mut tampered = hs256(&claims("user-1"));
+    tampered.pop();
+
+    for token in [
+        "not-a-jwt".to_string(),
+        wrong_secret,
+        tampered,
+        hs256(&wrong_issuer),
+        hs256(&no_email),
+        hs256(&no_expiry),
+        rs256(Some("unknown-kid"), &claims("user-1")),
+    ] {
+        let res = post_question(&app, Some(&token)).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED, "{}", token);
+        assert_eq!(res.text(), "Invalid bearer token");
+    }
+
+    let res = app.get_with_headers("/api/v1/questions?product_id=7", &[("authorization", "Basic dXNlcjpwYXNz")]).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.text(), "Invalid bearer token");
+}
+
+#[tokio::test]
+async fn tokens_grant_write_but_not_admin() {
+    let app = spawn().await;
+    let token = format!("Bearer {}", rs256(None, &claims("user-1")));
+
+    let res = app.request_with_headers(Method::PUT, "/api/v1/questions/1/helpful", &[], Body::empty()).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(res.text(), "Missing bearer token");
+
+    let question_id = post_question(&app, Some(&hs256(&claims("user-1")))).await.json()["question_id"].as_i64().unwrap();
+    let uri = format!("/api/v1/questions/{}/helpful", question_id);
+    let res = app.request_with_headers(Method::PUT, &uri, &[("authorization", &token)], Body::empty()).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    let res = app.get_with_headers("/api/v1/admin/reports", &[("authorization", &token)]).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    assert_eq!(res.text(), "Bearer token lacks the admin scope");
+}
+
+#[tokio::test]
+async fn votes_belong_to_the_token_subject() {
+    let app = spawn().await;
+    let question_id = post_question(&app, Some(&hs256(&claims("user-1")))).await.json()["question_id"].as_i64().unwrap();
+    let uri = format!("/api/v1/questions/{}/helpful", question_id);
+
+    // The same shopper voting with fresh tokens and user tokens is still one voter.
+    for user_token in ["a", "b"] {
+        let authorization = format!("Bearer {}", hs256(&claims("user-2")));
+        let headers = [("authorization", authorization.as_str()), ("x-user-token", user_token)];
+        let res = app.request_with_headers(Method::PUT, &uri, &headers, Body::empty()).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+    }
+
+    let body = app.get("/api/v1/questions?product_id=7").await.json();
+    assert_eq!(body["results"][0]["question_helpfulness"], 1);
+}
+
+#[test]
+fn verifier_needs_a_key() {
+    assert!(JwtVerifier::new(None, None, None, None).is_err());
+    assert!(JwtVerifier::new(None, Some(r#"{"keys": []}"#), None, None).is_err());
+    assert!(JwtVerifier::new(None, Some("not json"), None, None).is_err());
+}
@@ -0,0 +1,188 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use qa_rs::db::Database;
+use qa_rs::loadtest::{load_ids, load_test_tag, parse_ids, purge_load_test_data, IdKind, IdSampler, IdSource, Popularity, PurgeReport, RequestStats, Slo, LOAD_TEST_NAME};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use serde_json::json;
+
+async fn post_question(app: &TestApp, name: &str, body: &str) -> i64 {
+    let res = app
+        .post("/api/v1/questions", json!({ "body": body, "name": name, "email": "lt@example.com", "product_id": 50 }))
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    res.json()["question_id"].as_i64().unwrap()
+}
+
+async fn post_answer(app: &TestApp, question_id: i64, name: &str, body: &str) -> i64 {
+    let res = app
+        .post(
+            &format!("/api/v1/questions/{}/answers", question_id),
+            json!({ "body": body, "name": name, "email": "lt@example.com", "photos": ["https://images.example.com/1.jpg"] }),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    res.json()["answer_id"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn purge_deletes_only_tagged_load_test_rows() {
+    let app = TestApp::spawn().await;
+    let tagged = |run: &str| format!("{} Does it fit?", load_test_tag(run));
+
+    // Everything run_1 touched: its posts, votes and reports, a reported photo, and a shopper's answer to them.
+    let question_id = post_question(&app, LOAD_TEST_NAME, &tagged("run_1")).await;
+    let answer_id = post_answer(&app, question_id, LOAD_TEST_NAME, &tagged("run_1")).await;
+    post_answer(&app, question_id, "shopper", "Yes").await;
+    app.put(&format!("/api/v1/questions/{}/helpful", question_id)).await;
+    app.put(&format!("/api/v1/answers/{}/helpful", answer_id)).await;
+    let photo_id = app.get(&format!("/api/v1/answers/{}/photos", answer_id)).await.json()["results"][0]["id"].clone();
+    app.put(&format!("/api/v1/photos/{}/report", photo_id)).await;
+    app.request(Method::POST, &format!("/api/v1/admin/photos/{}/remove", photo_id), Body::empty()).await;
+    app.put(&format!("/api/v1/answers/{}/report", answer_id)).await;
+    app.request(Method::POST, &format!("/api/v1/admin/answers/{}/remove", answer_id), Body::empty()).await;
+    app.put(&format!("/api/v1/questions/{}/report", question_id)).await;
+
+    // A real question that run_1 answered, and rows that only look like load-test rows.
+    let real_id = app.add_question(50, "Is it warm?").await;
+    post_answer(&app, real_id, LOAD_TEST_NAME, &tagged("run_1")).await;
+    post_question(&app, "shopper", &tagged("run_1")).await;
+    post_question(&app, LOAD_TEST_NAME, &tagged("run-1")).await;
+    post_question(&app, LOAD_TEST_NAME, &tagged("runX1")).await;
+
+    let report = purge_load_test_data(&app.state.db, Some("run_1")).await.unwrap();
+    assert_eq!(report, PurgeReport { questions: 1, answers: 3 });
+    assert_eq!(purge_load_test_data(&app.state.db, Some("run_1")).await.unwrap(), PurgeReport::default());
+
+    assert_eq!(
+        question_bodies(&app).await,
+        [
+            "Is it warm?".to_string(),
+            tagged("run_1"),
+            tagged("run-1"),
+            tagged("runX1"),
+        ]
+    );
+    let answers = app.get(&format!("/api/v1/questions/{}/answers", real_id)).await;
+    assert_eq!(answers.json()["results"], json!([]));
+    assert!(app.get("/api/v1/admin/actions").await.json()["results"].as_array().unwrap().is_empty());
+
+    // Without a run every run goes, and nothing else.
+    let report = purge_load_test_data(&app.state.db, None).await.unwrap();
+    assert_eq!(report, PurgeReport { questions: 2, answers: 0 });
+    assert_eq!(question_bodies(&app).await, ["Is it warm?".to_string(), tagged("run_1")]);
+}
+
+#[tokio::test]
+async fn ids_are_read_from_visible_questions() {
+    let app = TestApp::spawn().await;
+    let first = app.add_question(7, "Does it fit?").await as i32;
+    let second = app.add_question(3, "Is it warm?").await as i32;
+    let third = app.add_question(7, "Is it heavy?").await as i32;
+    let reported = app.add_question(9, "Reported").await;
+    app.put(&format!("/api/v1/questions/{}/report", reported)).await;
+
+    assert_eq!(load_ids(&app.state.db, IdKind::Product).await.unwrap(), [3, 7]);
+    assert_eq!(load_ids(&app.state.db, IdKind::Question).await.unwrap(), [first, second, third]);
+}
+
+#[test]
+fn id_sources_and_popularity_parse() {
+    assert_eq!("database".parse(), Ok(IdSource::Database));
+    assert_eq!("file:ids.txt".parse(), Ok(IdSource::File(PathBuf::from("ids.txt"))));
+    assert_eq!("range:1-1000011".parse(), Ok(IdSource::Range(1, 1000011)));
+    for source in ["", "file:", "range:5-1", "range:1", "range:a-b", "db"] {
+        assert!(source.parse::<IdSource>().is_err(), "{}", source);
+    }
+
+    assert_eq!(parse_ids("1, 2 3\n# popular\n4 # and more\n\n"), Ok(vec![1, 2, 3, 4]));
+    assert_eq!(parse_ids("1\nx"), Err("Invalid id \"x\"".to_string()));
+
+    assert_eq!("uniform".parse(), Ok(Popularity::Uniform));
+    assert_eq!("zipf:1.2".parse(), Ok(Popularity::Zipf { exponent: 1.2 }));
+    assert_eq!("hot:0.2:0.8".parse(), Ok(Popularity::HotSet { ids: 0.2, requests: 0.8 }));
+    for popularity in ["zipf", "zipf:0", "zipf:-1", "zipf:inf", "hot:0.2", "hot:0:0.8", "hot:0.2:1.5", "normal"] {
+        assert!(popularity.parse::<Popularity>().is_err(), "{}", popularity);
+    }
+}
+
+/// How often each id is drawn in 10,000 draws, most drawn first.
+fn draw(popularity: Popularity, ids: i32, seed: u64) -> Vec<(i32, usize)> {
+    let sampler = IdSampler::new((1..=ids).collect(), popularity, &mut StdRng::seed_from_u64(seed)).unwrap();
+    let mut rng = StdRng::seed_from_u64(99);
+    let mut counts = HashMap::new();
+    for _ in 0..10_000 {
+        *counts.entry(sampler.sample(&mut rng)).or_insert(0) += 1;
+    }
+    let mut counts: Vec<(i32, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+#[test]
+fn samplers_follow_their_popularity() {
+    let uniform = draw(Popularity::Uniform, 10, 1);
+    assert_eq!(uniform.len(), 10);
+    assert!(uniform.iter().all(|(_, count)| (800..1200).contains(count)), "{:?}", uniform);
+
+    // With 100 ids and an exponent of 1 the top id takes about a fifth of the requests, and
+    // the top ten more than half.
+    let zipf = draw(Popularity::Zipf { exponent: 1.0 }, 100, 1);
+    assert!((1700..2500).contains(&zipf[0].1), "{:?}", zipf);
+    assert!(zipf.iter().take(10).map(|(_, count)| count).sum::<usize>() > 5000);
+
+    // A fifth of the ids takes 80% of the requests.
+    let hot = draw(Popularity::HotSet { ids: 0.2, requests: 0.8 }, 50, 1);
+    assert!((7700..8300).contains(&hot.iter().take(10).map(|(_, count)| count).sum::<usize>()), "{:?}", hot);
+
+    // Which ids are popular depends on the seed alone, not on id order.
+    let top = |seed| draw(Popularity::Zipf { exponent: 1.0 }, 100, seed)[0].0;
+    assert_eq!(top(1), top(1));
+    assert!((2..10).any(|seed| top(seed) != top(1)));
+
+    assert!(IdSampler::new(Vec::new(), Popularity::Uniform, &mut StdRng::seed_from_u64(0)).is_err());
+}
+
+#[test]
+fn slo_breaches_are_reported_per_objective() {
+    // 90 fast responses, 8 slower ones and 2 very slow ones.
+    let stats = RequestStats { times: BTreeMap::from([(5, 90), (120, 8), (900, 2)]), successes: 97, failures: 3 };
+    assert_eq!((stats.percentile(50.0), stats.percentile(95.0), stats.percentile(99.0)), (Some(5), Some(120), Some(900)));
+    assert_eq!(stats.error_rate(), 3.0);
+    assert_eq!(RequestStats::default().percentile(95.0), None);
+    assert_eq!(RequestStats::default().error_rate(), 0.0);
+
+    let met = Slo { p95: Some(120), p99: Some(1000), error_rate: Some(3.0) };
+    assert!(met.breaches("GET /", &stats).is_empty());
+    assert!(Slo::default().breaches("GET /", &stats).is_empty());
+
+    let breached = Slo { p95: Some(100), p99: Some(500), error_rate: Some(1.0) };
+    assert_eq!(
+        breached.breaches("GET /", &stats),
+        [
+            "GET /: p95 of 120 ms is over 100 ms",
+            "GET /: p99 of 900 ms is over 500 ms",
+            "GET /: 3.00% of requests failed, over 1%",
+        ]
+    );
+
+    // Merged stats count every request.
+    let mut total = RequestStats { times: BTreeMap::from([(5, 10)]), successes: 10, failures: 0 };
+    total.merge(&stats);
+    assert_eq!(total.times, BTreeMap::from([(5, 100), (120, 8), (900, 2)]));
+    assert_eq!((total.successes, total.failures), (107, 3));
+}
+
+/// Every question body, read straight from the database since purging bypasses the cache.
+async fn question_bodies(app: &TestApp) -> Vec<String> {
+    let sql = "SELECT body FROM questions ORDER BY id";
+    match &app.state.db {
+        Database::Postgres(pool) => sqlx::query_scalar(sql).fetch_all(pool).await.unwrap(),
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlx::query_scalar(sql).fetch_all(pool).await.unwrap(),
+    }
+}
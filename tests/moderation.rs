@@ -0,0 +1,232 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use qa_rs::auth::Scope;
+use serde_json::{json, Value};
+
+async fn report(app: &TestApp, uri: &str, body: Value) -> StatusCode {
+    app.request(Method::PUT, uri, body.to_string()).await.status
+}
+
+async fn queue(app: &TestApp) -> Vec<Value> {
+    let res = app.get("/api/v1/admin/reports?count=20").await;
+    assert_eq!(res.status, StatusCode::OK);
+    res.json()["results"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn reports_keep_their_reason_and_note() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let uri = format!("/api/v1/questions/{}/report", question_id);
+
+    assert_eq!(report(&app, &uri, json!({ "reason": "spam", "note": "Links to a shop" })).await, StatusCode::NO_CONTENT);
+    // The body is optional.
+    assert_eq!(app.put(&uri).await.status, StatusCode::NO_CONTENT);
+
+    let queue = queue(&app).await;
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue[0]["type"], "question");
+    assert_eq!(queue[0]["id"], question_id);
+    assert_eq!(queue[0]["product_id"], 1);
+    assert_eq!(queue[0]["question_id"], Value::Null);
+    assert_eq!(queue[0]["body"], "Question");
+    assert_eq!(queue[0]["report_count"], 2);
+    assert!(queue[0]["last_reported_at"].is_string());
+
+    let reports = queue[0]["reports"].as_array().unwrap();
+    assert_eq!(reports[0]["reason"], "spam");
+    assert_eq!(reports[0]["note"], "Links to a shop");
+    assert!(reports[0]["reported_at"].is_string());
+    assert_eq!(reports[1]["reason"], "other");
+    assert_eq!(reports[1]["note"], Value::Null);
+}
+
+#[tokio::test]
+async fn reports_reject_malformed_bodies() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let answer_id = app.add_answer(question_id, "Answer", &[]).await;
+
+    for uri in [format!("/api/v1/questions/{}/report", question_id), format!("/api/v1/answers/{}/report", answer_id)] {
+        for body in [json!({ "reason": "boring" }), json!({ "note": 5 }), json!([])] {
+            let res = app.request(Method::PUT, &uri, body.to_string()).await;
+            assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", body);
+            assert_eq!(res.text(), "Invalid request body");
+        }
+
+        let res = app.request(Method::PUT, &uri, json!({ "note": "x".repeat(1001) }).to_string()).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.text(), "Report note is too long");
+    }
+
+    assert!(queue(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn queue_lists_the_most_reported_content_first() {
+    let app = TestApp::spawn().await;
+    let once = app.add_question(1, "Reported once").await;
+    let twice = app.add_question(2, "Reported twice").await;
+    let answer_id = app.add_answer(once, "Reported three times", &[]).await;
+
+    report(&app, &format!("/api/v1/questions/{}/report", once), json!({})).await;
+    for _ in 0..2 {
+        report(&app, &format!("/api/v1/questions/{}/report", twice), json!({ "reason": "off_topic" })).await;
+    }
+    for _ in 0..3 {
+        report(&app, &format!("/api/v1/answers/{}/report", answer_id), json!({ "reason": "offensive" })).await;
+    }
+
+    let queue = queue(&app).await;
+    let entries: Vec<_> = queue.iter().map(|item| (item["type"].clone(), item["id"].clone(), item["report_count"].clone())).collect();
+    assert_eq!(
+        entries,
+        vec![
+            (json!("answer"), json!(answer_id), json!(3)),
+            (json!("question"), json!(twice), json!(2)),
+            (json!("question"), json!(once), json!(1)),
+        ]
+    );
+    assert_eq!(queue[0]["question_id"], once);
+    assert_eq!(queue[0]["product_id"], 1);
+
+    let res = app.get("/api/v1/admin/reports?page=2&count=2").await;
+    assert_eq!(res.json()["results"].as_array().unwrap().len(), 1);
+    assert_eq!(res.json()["results"][0]["id"], once);
+}
+
+#[tokio::test]
+async fn approving_restores_content_and_logs_the_action() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    for _ in 0..2 {
+        report(&app, &format!("/api/v1/questions/{}/report", question_id), json!({ "reason": "spam" })).await;
+    }
+    assert_eq!(app.get("/api/v1/questions?product_id=1").await.json()["results"], json!([]));
+
+    let key = app.create_api_key("mod-1", &[Scope::Admin]).await;
+    let res = app
+        .request_with_headers(
+            Method::POST,
+            &format!("/api/v1/admin/questions/{}/approve", question_id),
+            &[("x-api-key", &key)],
+            json!({ "note": "Not spam" }).to_string(),
+        )
+        .await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert!(res.body.is_empty());
+
+    let res = app.get("/api/v1/questions?product_id=1").await;
+    assert_eq!(res.json()["results"][0]["question_id"], question_id);
+    assert_eq!(res.json()["results"][0]["reported"], false);
+    assert!(queue(&app).await.is_empty());
+
+    let res = app.get("/api/v1/admin/actions").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let action = &res.json()["results"][0];
+    assert_eq!(action["target_type"], "question");
+    assert_eq!(action["target_id"], question_id);
+    assert_eq!(action["action"], "approve");
+    assert_eq!(action["moderator"], "mod-1");
+    assert_eq!(action["note"], "Not spam");
+    assert_eq!(action["reports_resolved"], 2);
+    assert!(action["acted_at"].is_string());
+
+    // Reporting approved content queues it again with only the new reports.
+    report(&app, &format!("/api/v1/questions/{}/report", question_id), json!({})).await;
+    assert_eq!(queue(&app).await[0]["report_count"], 1);
+}
+
+#[tokio::test]
+async fn removing_keeps_content_hidden_and_out_of_the_queue() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let answer_id = app.add_answer(question_id, "Answer", &[]).await;
+    report(&app, &format!("/api/v1/answers/{}/report", answer_id), json!({ "reason": "offensive" })).await;
+
+    let res = app.request(Method::POST, &format!("/api/v1/admin/answers/{}/remove", answer_id), Body::empty()).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    assert!(queue(&app).await.is_empty());
+    let list = format!("/api/v1/questions/{}/answers", question_id);
+    assert_eq!(app.get(&list).await.json()["results"], json!([]));
+
+    // Further reports are kept but do not bring removed content back into the queue.
+    report(&app, &format!("/api/v1/answers/{}/report", answer_id), json!({})).await;
+    assert!(queue(&app).await.is_empty());
+
+    // Removal can be undone by approving.
+    app.request(Method::POST, &format!("/api/v1/admin/answers/{}/approve", answer_id), Body::empty()).await;
+    assert_eq!(app.get(&list).await.json()["results"][0]["answer_id"], answer_id);
+
+    let res = app.get("/api/v1/admin/actions").await;
+    let actions: Vec<_> = res.json()["results"].as_array().unwrap().iter().map(|a| (a["action"].clone(), a["reports_resolved"].clone())).collect();
+    assert_eq!(actions, vec![(json!("approve"), json!(1)), (json!("remove"), json!(1))]);
+}
+
+#[tokio::test]
+async fn moderation_rejects_bad_and_unknown_targets() {
+    let app = TestApp::spawn().await;
+
+    for (uri, status, message) in [
+        ("/api/v1/admin/questions/abc/approve", StatusCode::BAD_REQUEST, "Invalid question_id path parameter"),
+        ("/api/v1/admin/questions/424242/approve", StatusCode::NOT_FOUND, "Question not found"),
+        ("/api/v1/admin/questions/424242/remove", StatusCode::NOT_FOUND, "Question not found"),
+        ("/api/v1/admin/questions/1/publish", StatusCode::NOT_FOUND, "Path not found"),
+        ("/api/v1/admin/answers/abc/remove", StatusCode::BAD_REQUEST, "Invalid answer_id path parameter"),
+        ("/api/v1/admin/answers/424242/approve", StatusCode::NOT_FOUND, "Answer not found"),
+    ] {
+        let res = app.request(Method::POST, uri, Body::empty()).await;
+        assert_eq!(res.status, status, "{}", uri);
+        assert_eq!(res.text(), message, "{}", uri);
+    }
+
+    let question_id = app.add_question(1, "Question").await;
+    let res = app
+        .post(&format!("/api/v1/admin/questions/{}/remove", question_id), json!({ "note": "x".repeat(1001) }))
+        .await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.text(), "Moderation note is too long");
+}
+
+#[tokio::test]
+async fn reported_photos_are_hidden_without_their_answer() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let answer_id = app.add_answer(question_id, "Answer", &["https://example.com/ok.jpg", "https://example.com/rude.jpg"]).await;
+    let photos = app.get(&format!("/api/v1/answers/{}/photos", answer_id)).await.json()["results"].clone();
+    let photo_id = photos[1]["id"].as_i64().unwrap();
+
+    let uri = format!("/api/v1/photos/{}/report", photo_id);
+    assert_eq!(report(&app, &uri, json!({ "reason": "offensive" })).await, StatusCode::NO_CONTENT);
+    assert_eq!(report(&app, "/api/v1/photos/999999/report", json!({})).await, StatusCode::NOT_FOUND);
+
+    let res = app.get(&format!("/api/v1/questions/{}/answers", question_id)).await;
+    assert_eq!(res.json()["results"][0]["answer_id"], answer_id);
+    assert_eq!(res.json()["results"][0]["photos"], json!([{ "id": photos[0]["id"], "url": "https://example.com/ok.jpg", "thumbnail_url": null, "thumbnail_webp_url": null, "width": null, "height": null }]));
+
+    let items = queue(&app).await;
+    assert_eq!(items.len(), 1);
+    assert_eq!((&items[0]["type"], &items[0]["id"], &items[0]["answer_id"]), (&json!("photo"), &json!(photo_id), &json!(answer_id)));
+    assert_eq!(items[0]["question_id"], question_id);
+    assert_eq!(items[0]["body"], "https://example.com/rude.jpg");
+    assert_eq!(items[0]["reports"][0]["reason"], "offensive");
+
+    let res = app.request(Method::POST, &format!("/api/v1/admin/photos/{}/approve", photo_id), Body::empty()).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT, "{}", res.text());
+    assert_eq!(app.get(&format!("/api/v1/answers/{}/photos", answer_id)).await.json()["results"].as_array().unwrap().len(), 2);
+    assert!(queue(&app).await.is_empty());
+
+    report(&app, &uri, json!({})).await;
+    let res = app.request(Method::POST, &format!("/api/v1/admin/photos/{}/remove", photo_id), Body::empty()).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert_eq!(app.get(&format!("/api/v1/answers/{}/photos", answer_id)).await.json()["results"].as_array().unwrap().len(), 1);
+    assert!(queue(&app).await.is_empty());
+
+    let action = &app.get("/api/v1/admin/actions").await.json()["results"][0];
+    assert_eq!((&action["target_type"], &action["target_id"], &action["action"]), (&json!("photo"), &json!(photo_id), &json!("remove")));
+    let res = app.request(Method::POST, "/api/v1/admin/photos/x/remove", Body::empty()).await;
+    assert_eq!(res.text(), "Invalid photo_id path parameter");
+}
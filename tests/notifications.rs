@@ -0,0 +1,252 @@
+mod common;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use qa_rs::emails::{EmailKey, EmailProtection, EmailProtector};
+use qa_rs::notifications::{AnswerNotifier, Notifications, Template};
+use qa_rs::smtp::{SmtpMailer, SmtpSecurity};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// An email as the sink received it, with its body decoded.
+struct Mail {
+    to: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Mail {
+    fn header(&self, name: &str) -> &str {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map_or("", |(_, value)| value)
+    }
+}
+
+/// A local SMTP server recording what it is sent, and refusing recipients with `reject`'s
+/// reply while it is set.
+struct SmtpSink {
+    port: u16,
+    mails: Arc<Mutex<Vec<Mail>>>,
+    reject: Arc<Mutex<Option<&'static str>>>,
+}
+
+impl SmtpSink {
+    async fn spawn() -> SmtpSink {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (mails, reject) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(None)));
+        let sink = SmtpSink { port, mails: mails.clone(), reject: reject.clone() };
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (mails, reject) = (mails.clone(), reject.clone());
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    write.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+
+                    let mut to = String::new();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let reply: &[u8] = match line.split_whitespace().next().unwrap_or_default().to_ascii_uppercase().as_str() {
+                            "EHLO" => b"250-sink\r\n250-8BITMIME\r\n250 SIZE 1000000\r\n",
+                            "MAIL" => b"250 OK\r\n",
+                            "RCPT" => match *reject.lock().unwrap() {
+                                Some(reply) => reply.as_bytes(),
+                                None => {
+                                    to = line.trim_start_matches("RCPT TO:<").trim_end_matches('>').to_string();
+                                    b"250 OK\r\n"
+                                }
+                            },
+                            "DATA" => {
+                                write.write_all(b"354 Go ahead\r\n").await.unwrap();
+                                let mut data = Vec::new();
+                                while let Ok(Some(line)) = lines.next_line().await {
+                                    if line == "." {
+                                        break;
+                                    }
+                                    data.push(line.strip_prefix('.').map_or(line.clone(), str::to_string));
+                                }
+                                mails.lock().unwrap().push(parse_mail(&to, &data));
+                                b"250 Queued\r\n"
+                            }
+                            "QUIT" => {
+                                write.write_all(b"221 Bye\r\n").await.unwrap();
+                                break;
+                            }
+                            _ => b"502 Unknown command\r\n",
+                        };
+                        write.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+        sink
+    }
+
+    fn take(&self) -> Vec<Mail> {
+        std::mem::take(&mut *self.mails.lock().unwrap())
+    }
+}
+
+fn parse_mail(to: &str, data: &[String]) -> Mail {
+    let blank = data.iter().position(|line| line.is_empty()).unwrap();
+    let headers = data[..blank].iter().map(|line| line.split_once(": ").map(|(name, value)| (name.to_string(), value.to_string())).unwrap()).collect();
+    let body = BASE64.decode(data[blank + 1..].concat()).unwrap();
+    Mail { to: to.to_string(), headers, body: String::from_utf8(body).unwrap().replace("\r\n", "\n") }
+}
+
+fn notifier(sink: &SmtpSink, delay: Duration) -> AnswerNotifier {
+    AnswerNotifier {
+        interval: Duration::from_secs(1),
+        batch: 10,
+        delay,
+        max_attempts: 3,
+        backoff: Duration::ZERO,
+        retry_after: Duration::from_secs(10),
+        mailer: SmtpMailer {
+            host: "127.0.0.1".into(),
+            port: sink.port,
+            security: SmtpSecurity::None,
+            credentials: None,
+            timeout: Duration::from_secs(5),
+        },
+        from: "Q&A <qa@example.com>".into(),
+        public_url: "https://qa.example.com".into(),
+        template: Template::default(),
+    }
+}
+
+async fn spawn_notifying() -> TestApp {
+    TestApp::spawn_with(|state| {
+        state.notifications = Notifications::new(true, "test-key");
+        // Encrypted emails differ for every row, so askers are recognised by their hash.
+        state.emails = EmailProtector::new(EmailProtection::Encrypt, Some(EmailKey::new(&[7; 32])), Vec::new()).unwrap();
+    })
+    .await
+}
+
+async fn post_as(app: &TestApp, uri: &str, email: &str, body: serde_json::Value) -> i64 {
+    let mut body = body;
+    body["name"] = "Sam".into();
+    body["email"] = email.into();
+    let res = app.post(uri, body).await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+    let json = res.json();
+    json["question_id"].as_i64().or(json["answer_id"].as_i64()).unwrap()
+}
+
+#[tokio::test]
+async fn answers_are_batched_into_one_email_per_asker() {
+    let sink = SmtpSink::spawn().await;
+    let app = spawn_notifying().await;
+    let first = app.add_question(5, "Does it fold?").await;
+    let second = app.add_question(6, "Is it waterproof?").await;
+    let other = post_as(&app, "/api/v1/questions", "other@example.com", serde_json::json!({ "body": "Any spares?", "product_id": 5 })).await;
+
+    app.add_answer(first, "Flat, in seconds", &[]).await;
+    app.add_answer(first, "Mine folds too", &[]).await;
+    app.add_answer(second, "Only splash-proof", &[]).await;
+    app.add_answer(other, "Yes, from the shop", &[]).await;
+    // Askers are not told about their own answers.
+    post_as(&app, &format!("/api/v1/questions/{}/answers", other), "Other@example.com", serde_json::json!({ "body": "Found them", "photos": [] })).await;
+
+    // Answers wait for more to come along before they are sent.
+    assert_eq!(notifier(&sink, Duration::from_secs(3600)).process_pending(&app.state).await.unwrap(), 0);
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 2);
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 0);
+
+    let mails = sink.take();
+    assert_eq!(mails.len(), 2);
+    let mail = mails.iter().find(|mail| mail.to == "asker@example.com").unwrap();
+    assert_eq!(mail.header("From"), "Q&A <qa@example.com>");
+    assert_eq!(mail.header("Subject"), "Your questions have 3 new answers");
+    assert!(mail.header("List-Unsubscribe").starts_with("<https://qa.example.com/api/v1/notifications/unsubscribe?token="));
+    assert_eq!(mail.header("List-Unsubscribe-Post"), "List-Unsubscribe=One-Click");
+    assert!(mail.body.starts_with("Hi asker,\n\nYou asked: \"Does it fold?\"\n\nanswerer answered:\nFlat, in seconds\n\nanswerer answered:\nMine folds too\n"), "{}", mail.body);
+    assert!(mail.body.contains("You asked: \"Is it waterproof?\"\n\nanswerer answered:\nOnly splash-proof\n"), "{}", mail.body);
+
+    let mail = mails.iter().find(|mail| mail.to == "other@example.com").unwrap();
+    assert_eq!(mail.header("Subject"), "Your question has a new answer");
+    assert!(mail.body.contains("Yes, from the shop") && !mail.body.contains("Found them"), "{}", mail.body);
+}
+
+#[tokio::test]
+async fn nothing_is_queued_while_notifications_are_off() {
+    let sink = SmtpSink::spawn().await;
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(5, "Does it fold?").await;
+    app.add_answer(question_id, "Flat", &[]).await;
+
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 0);
+    assert!(sink.take().is_empty());
+}
+
+#[tokio::test]
+async fn unsubscribed_askers_are_no_longer_emailed() {
+    let sink = SmtpSink::spawn().await;
+    let app = spawn_notifying().await;
+    let question_id = app.add_question(5, "Does it fold?").await;
+    app.add_answer(question_id, "Flat", &[]).await;
+    notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap();
+
+    let mail = sink.take().pop().unwrap();
+    let link = mail.header("List-Unsubscribe").trim_matches(|c| c == '<' || c == '>').trim_start_matches("https://qa.example.com").to_string();
+    assert!(mail.body.contains(&format!("https://qa.example.com{}", link)));
+
+    // Opening the link only asks for confirmation, as mail scanners open links too.
+    let res = app.get(&link).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.text().contains("<form method=\"post\""));
+    let tampered = link.replace("token=", "token=0");
+    assert_eq!(app.get(&tampered).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(app.request(Method::POST, &tampered, Body::empty()).await.status, StatusCode::BAD_REQUEST);
+
+    app.add_answer(question_id, "Folds flat", &[]).await;
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(sink.take().len(), 1);
+
+    for _ in 0..2 {
+        let res = app.request(Method::POST, &link, "List-Unsubscribe=One-Click").await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.text());
+    }
+    app.add_answer(question_id, "Folds in half", &[]).await;
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 0);
+    assert!(sink.take().is_empty());
+}
+
+#[tokio::test]
+async fn temporary_smtp_failures_are_retried_and_permanent_ones_are_not() {
+    let sink = SmtpSink::spawn().await;
+    let app = spawn_notifying().await;
+    let question_id = app.add_question(5, "Does it fold?").await;
+    app.add_answer(question_id, "Flat", &[]).await;
+
+    *sink.reject.lock().unwrap() = Some("451 4.3.0 Try again later\r\n");
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 1);
+    *sink.reject.lock().unwrap() = None;
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(sink.take().len(), 1);
+
+    app.add_answer(question_id, "Folds flat", &[]).await;
+    *sink.reject.lock().unwrap() = Some("550 5.1.1 No such mailbox\r\n");
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 1);
+    *sink.reject.lock().unwrap() = None;
+    assert_eq!(notifier(&sink, Duration::ZERO).process_pending(&app.state).await.unwrap(), 0);
+    assert!(sink.take().is_empty());
+}
+
+#[test]
+fn templates_render_sections_and_reject_broken_ones() {
+    let template = Template::parse("Subject: {{#one}}One{{/one}}{{^one}}{{count}}{{/one}} new\n\n{{#items}}\n- {{name}} ({{unit}})\n{{/items}}\nBye").unwrap();
+    let context = serde_json::json!({ "one": false, "count": 2, "unit": "kg", "items": [{ "name": "a" }, { "name": "b" }] });
+    assert_eq!(template.render(&context), ("2 new".to_string(), "- a (kg)\n- b (kg)\nBye".to_string()));
+
+    assert!(Template::parse("No subject").is_err());
+    assert!(Template::parse("Subject: Hi\n\n{{#items}}unclosed").is_err());
+    assert!(Template::parse("Subject: Hi\n\n{{#items}}{{/other}}").is_err());
+    Template::default();
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use qa_rs::auth::Scope;
+use qa_rs::db::Database;
+use qa_rs::emails::{EmailKey, EmailProtection, EmailProtector};
+use serde_json::{json, Value};
+
+fn encrypting(key: &[u8; 32], old_keys: &[&[u8; 32]]) -> EmailProtector {
+    let old_keys = old_keys.iter().map(|key| EmailKey::new(key)).collect();
+    EmailProtector::new(EmailProtection::Encrypt, Some(EmailKey::new(key)), old_keys).unwrap()
+}
+
+async fn export(app: &TestApp, email: &str) -> Value {
+    let res = app.post("/api/v1/admin/data-requests/export", json!({ "email": email })).await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.text());
+    res.json()
+}
+
+async fn erase(app: &TestApp, email: &str, content: &str) -> Value {
+    let res = app.post("/api/v1/admin/data-requests/erase", json!({ "email": email, "content": content })).await;
+    assert_eq!(res.status, StatusCode::OK, "{}", res.text());
+    res.json()
+}
+
+async fn add_question_as(app: &TestApp, email: &str, body: &str) -> i64 {
+    let res = app
+        .post("/api/v1/questions", json!({ "body": body, "name": "other", "email": email, "product_id": 1 }))
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    res.json()["question_id"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn export_returns_everything_posted_under_an_email() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Does it run small?").await;
+    let answer_id = app.add_answer(question_id, "No", &["https://example.com/a.jpg", "https://example.com/b.jpg"]).await;
+    add_question_as(&app, "someone@example.com", "Is it waterproof?").await;
+
+    // Addresses match whatever their case.
+    let body = export(&app, " Asker@Example.COM").await;
+    let questions = body["questions"].as_array().unwrap();
+    assert_eq!(questions.len(), 1);
+    assert_eq!(questions[0]["id"], question_id);
+    assert_eq!(questions[0]["body"], "Does it run small?");
+    assert_eq!(questions[0]["asker_name"], "asker");
+    assert_eq!(questions[0]["removed"], false);
+    assert_eq!(body["answers"], json!([]));
+
+    let body = export(&app, "answerer@example.com").await;
+    let answers = body["answers"].as_array().unwrap();
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0]["id"], answer_id);
+    assert_eq!(answers[0]["question_id"], question_id);
+    let urls: Vec<&str> = answers[0]["photos"].as_array().unwrap().iter().map(|photo| photo["url"].as_str().unwrap()).collect();
+    assert_eq!(urls, ["https://example.com/a.jpg", "https://example.com/b.jpg"]);
+
+    assert_eq!(export(&app, "nobody@example.com").await["questions"], json!([]));
+}
+
+#[tokio::test]
+async fn export_finds_encrypted_emails_under_old_keys() {
+    let app = TestApp::spawn_with(|state| state.emails = encrypting(&[2; 32], &[&[1; 32]])).await;
+    app.add_question(1, "Sealed with the current key").await;
+    let old_id = app.add_question(1, "Sealed with the old key").await;
+    let legacy_id = app.add_question(1, "Stored before encryption").await;
+
+    let sealed = encrypting(&[1; 32], &[]).seal("asker@example.com");
+    let old = "UPDATE questions SET asker_email = $1, asker_email_hash = $2 WHERE id = $3";
+    let legacy = "UPDATE questions SET asker_email = 'Asker@example.com', asker_email_hash = NULL WHERE id = $1";
+    match &app.state.db {
+        Database::Postgres(pool) => {
+            sqlx::query(old).bind(&sealed.stored).bind(&sealed.hash).bind(old_id as i32).execute(pool).await.unwrap();
+            sqlx::query(legacy).bind(legacy_id as i32).execute(pool).await.unwrap();
+        }
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => {
+            let (old, legacy) = (old.replace('$', "?"), legacy.replace('$', "?"));
+            sqlx::query(&old).bind(&sealed.stored).bind(&sealed.hash).bind(old_id as i32).execute(pool).await.unwrap();
+            sqlx::query(&legacy).bind(legacy_id as i32).execute(pool).await.unwrap();
+        }
+    }
+
+    let body = export(&app, "asker@example.com").await;
+    assert_eq!(body["questions"].as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn erasure_keeping_content_anonymises_the_author() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Does it run small?").await;
+    app.add_answer(question_id, "No", &["https://example.com/a.jpg"]).await;
+
+    // Fill the cache so the erasure has to invalidate it.
+    assert_eq!(app.get("/api/v1/questions?product_id=1").await.json()["results"][0]["asker_name"], "asker");
+
+    let body = erase(&app, "asker@example.com", "keep").await;
+    assert_eq!((body["questions"].as_i64(), body["answers"].as_i64(), body["photos"].as_i64()), (Some(1), Some(0), Some(0)));
+
+    let question = &app.get("/api/v1/questions?product_id=1").await.json()["results"][0];
+    assert_eq!(question["asker_name"], "[deleted]");
+    assert_eq!(question["question_body"], "Does it run small?");
+
+    // Nothing is left to find under the address, and the answerer is untouched.
+    assert_eq!(export(&app, "asker@example.com").await["questions"], json!([]));
+    assert_eq!(export(&app, "answerer@example.com").await["answers"][0]["photos"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn erasure_removing_content_hides_it_and_deletes_photos() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Does it run small?").await;
+    app.add_answer(question_id, "No", &["https://example.com/a.jpg", "https://example.com/b.jpg"]).await;
+    app.add_answer(question_id, "Yes", &[]).await;
+    let answers_uri = format!("/api/v1/questions/{}/answers", question_id);
+    assert_eq!(app.get(&answers_uri).await.json()["results"].as_array().unwrap().len(), 2);
+
+    let body = erase(&app, "answerer@example.com", "remove").await;
+    assert_eq!((body["questions"].as_i64(), body["answers"].as_i64(), body["photos"].as_i64()), (Some(0), Some(2), Some(2)));
+    assert_eq!(body["content"], "remove");
+
+    assert_eq!(app.get(&answers_uri).await.json()["results"], json!([]));
+    let question = &app.get("/api/v1/questions?product_id=1").await.json()["results"][0];
+    assert_eq!(question["answers"], json!({}));
+
+    // Removed content is neither in the moderation queue nor under the address any more.
+    assert_eq!(app.get("/api/v1/admin/reports").await.json()["results"], json!([]));
+    assert_eq!(export(&app, "answerer@example.com").await["answers"], json!([]));
+}
+
+#[tokio::test]
+async fn erasure_removing_content_deletes_reported_photos() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Does it run small?").await;
+    let answer_id = app.add_answer(question_id, "No", &["https://example.com/a.jpg"]).await;
+    let photo_id = app.get(&format!("/api/v1/answers/{}/photos", answer_id)).await.json()["results"][0]["id"].clone();
+    let res = app.request(Method::PUT, &format!("/api/v1/photos/{}/report", photo_id), json!({ "reason": "offensive" }).to_string()).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    let body = erase(&app, "answerer@example.com", "remove").await;
+    assert_eq!((body["answers"].as_i64(), body["photos"].as_i64()), (Some(1), Some(1)));
+    assert_eq!(app.get("/api/v1/admin/reports").await.json()["results"], json!([]));
+}
+
+#[tokio::test]
+async fn erasure_removing_content_deletes_stored_photo_files() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Does it run small?").await;
+    let answer_id = app.add_answer(question_id, "No", &[]).await;
+    let form = b"--b\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"a.png\"\r\n\r\n\x89PNG\r\n\x1a\n\r\n--b--\r\n";
+    let res = app
+        .request_with_headers(Method::POST, &format!("/api/v1/answers/{}/photos", answer_id), &[("content-type", "multipart/form-data; boundary=b")], form.to_vec())
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+    let url = res.json()["photos"][0]["url"].as_str().unwrap().to_string();
+    let key = url.strip_prefix("/photos/").unwrap();
+    assert!(app.photo_dir.join(key).exists());
+
+    // Erasing someone whose photo row names the file, from before such URLs were refused,
+    // leaves the file to the answer it was uploaded for.
+    let res = app.post(&format!("/api/v1/questions/{}/answers", question_id), json!({ "body": "Same", "name": "o", "email": "other@example.com", "photos": [] })).await;
+    let other_id = res.json()["answer_id"].as_i64().unwrap();
+    let sql = format!("INSERT INTO answer_photos (answer_id, url) VALUES ({}, '{}');", other_id, url);
+    match &app.state.db {
+        Database::Postgres(pool) => sqlx::query(&sql).execute(pool).await.map(drop),
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlx::query(&sql).execute(pool).await.map(drop),
+    }
+    .unwrap();
+    assert_eq!(erase(&app, "other@example.com", "remove").await["photos"], 1);
+    assert!(app.photo_dir.join(key).exists());
+
+    erase(&app, "answerer@example.com", "remove").await;
+    assert!(!app.photo_dir.join(key).exists());
+    assert_eq!(app.get(&url).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn data_requests_are_audited_without_the_email() {
+    let app = TestApp::spawn_with(|state| state.auth.anonymous_scopes = vec![Scope::Read, Scope::Write]).await;
+    let admin = app.create_api_key("privacy-desk", &[Scope::Admin]).await;
+    let writer = app.create_api_key("storefront", &[Scope::Write]).await;
+    app.add_question(1, "Does it run small?").await;
+
+    let send = |key: &str, uri: &str, body: Value| {
+        let (app, key, uri) = (&app, key.to_string(), uri.to_string());
+        async move { app.request_with_headers(Method::POST, &uri, &[("x-api-key", &key)], body.to_string()).await }
+    };
+
+    let res = send(&writer, "/api/v1/admin/data-requests/export", json!({ "email": "asker@example.com" })).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+
+    for (body, message) in [
+        (json!({ "email": "asker", "content": "keep" }), "Invalid email"),
+        (json!({ "email": "asker@example.com", "content": "shred" }), "Invalid request body"),
+        (json!({ "email": "asker@example.com" }), "Invalid request body"),
+        (json!({ "email": "asker@example.com", "content": "keep", "reference": "x".repeat(1001) }), "Reference is too long"),
+    ] {
+        let res = send(&admin, "/api/v1/admin/data-requests/erase", body).await;
+        assert_eq!((res.status, res.text().as_str()), (StatusCode::BAD_REQUEST, message));
+    }
+
+    let res = send(&admin, "/api/v1/admin/data-requests/export", json!({ "email": "asker@example.com", "reference": "TICKET-1" })).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let res = send(&admin, "/api/v1/admin/data-requests/erase", json!({ "email": "ASKER@example.com", "content": "remove", "reference": "TICKET-1" })).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let res = app.request_with_headers(Method::GET, "/api/v1/admin/data-requests", &[("x-api-key", &admin)], Body::empty()).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(!res.text().contains("asker@example.com"), "{}", res.text());
+
+    let results = res.json()["results"].as_array().unwrap().clone();
+    assert_eq!(results.len(), 2);
+    let (erasure, export) = (&results[0], &results[1]);
+    assert_eq!((erasure["kind"].as_str(), erasure["content_policy"].as_str()), (Some("erase"), Some("remove")));
+    assert_eq!((export["kind"].as_str(), export["content_policy"].as_str()), (Some("export"), None));
+    assert_eq!((erasure["questions"].as_i64(), export["questions"].as_i64()), (Some(1), Some(1)));
+    for record in [erasure, export] {
+        assert_eq!(record["requested_by"], "privacy-desk");
+        assert_eq!(record["reference"], "TICKET-1");
+    }
+    // Both requests are about the same person, whatever the case they were made in.
+    assert_eq!(erasure["subject"], export["subject"]);
+    assert_eq!(erasure["subject"].as_str().unwrap().len(), 64);
+}
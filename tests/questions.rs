@@ -0,0 +1,286 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Method, StatusCode};
+use serde_json::json;
+
+#[tokio::test]
+async fn get_questions_returns_nested_answers_and_photos() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(10, "Does it fit?").await;
+    let answer_id = app.add_answer(question_id, "Yes", &["http://img/1.jpg", "http://img/2.jpg"]).await;
+    app.add_answer(question_id, "No photos", &[]).await;
+
+    let res = app.get("/api/v1/questions?product_id=10").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["product_id"], 10);
+
+    let results = res.json()["results"].as_array().unwrap().clone();
+    assert_eq!(results.len(), 1);
+
+    let question = &results[0];
+    assert_eq!(question["question_id"], question_id);
+    assert_eq!(question["question_body"], "Does it fit?");
+    assert_eq!(question["asker_name"], "asker");
+    assert_eq!(question["question_helpfulness"], 0);
+    assert_eq!(question["reported"], false);
+    assert!(question["question_date"].is_string());
+    assert!(question.get("asker_email").is_none());
+
+    let answers = question["answers"].as_object().unwrap();
+    assert_eq!(answers.len(), 2);
+
+    let answer = &answers[&answer_id.to_string()];
+    assert_eq!(answer["id"], answer_id);
+    assert_eq!(answer["body"], "Yes");
+    assert_eq!(answer["answerer_name"], "answerer");
+    assert_eq!(answer["helpfulness"], 0);
+    let urls: Vec<_> = answer["photos"].as_array().unwrap().iter().map(|p| p["url"].clone()).collect();
+    assert_eq!(urls, vec![json!("http://img/1.jpg"), json!("http://img/2.jpg")]);
+}
+
+#[tokio::test]
+async fn get_questions_for_unknown_product_is_empty() {
+    let app = TestApp::spawn().await;
+
+    let res = app.get("/api/v1/questions?product_id=999").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json(), json!({ "product_id": 999, "results": [] }));
+}
+
+#[tokio::test]
+async fn get_questions_paginates() {
+    let app = TestApp::spawn().await;
+    let mut ids = Vec::new();
+    for i in 0..7 {
+        ids.push(app.add_question(20, &format!("Question {}", i)).await);
+    }
+
+    let page_ids = |res: &common::TestResponse| -> Vec<i64> {
+        res.json()["results"].as_array().unwrap().iter().map(|q| q["question_id"].as_i64().unwrap()).collect()
+    };
+
+    let res = app.get("/api/v1/questions?product_id=20").await;
+    assert_eq!(page_ids(&res), ids[..5]);
+
+    let res = app.get("/api/v1/questions?product_id=20&page=2").await;
+    assert_eq!(page_ids(&res), ids[5..]);
+
+    let res = app.get("/api/v1/questions?product_id=20&page=3").await;
+    assert_eq!(page_ids(&res), Vec::<i64>::new());
+
+    let res = app.get("/api/v1/questions?product_id=20&page=2&count=3").await;
+    assert_eq!(page_ids(&res), ids[3..6]);
+
+    let res = app.get("/api/v1/questions?product_id=20&count=100").await;
+    assert_eq!(page_ids(&res), ids);
+}
+
+#[tokio::test]
+async fn get_questions_sorts() {
+    let app = TestApp::spawn().await;
+    let first = app.add_question(25, "First").await;
+    let second = app.add_question(25, "Second").await;
+    let third = app.add_question(25, "Third").await;
+    app.vote(Method::PUT, &format!("/api/v1/questions/{}/helpful", second), "alice").await;
+    app.vote(Method::PUT, &format!("/api/v1/questions/{}/helpful", second), "bob").await;
+    app.vote(Method::PUT, &format!("/api/v1/questions/{}/helpful", third), "alice").await;
+
+    let ids = |res: &common::TestResponse| -> Vec<i64> {
+        res.json()["results"].as_array().unwrap().iter().map(|q| q["question_id"].as_i64().unwrap()).collect()
+    };
+
+    assert_eq!(ids(&app.get("/api/v1/questions?product_id=25").await), vec![first, second, third]);
+    assert_eq!(ids(&app.get("/api/v1/questions?product_id=25&sort=oldest").await), vec![first, second, third]);
+    assert_eq!(ids(&app.get("/api/v1/questions?product_id=25&sort=newest").await), vec![third, second, first]);
+    assert_eq!(ids(&app.get("/api/v1/questions?product_id=25&sort=helpful").await), vec![second, third, first]);
+}
+
+#[tokio::test]
+async fn get_questions_rejects_bad_query_params() {
+    let app = TestApp::spawn().await;
+
+    for (uri, message) in [
+        ("/api/v1/questions", "Missing product_id query parameter"),
+        ("/api/v1/questions?product_id=abc", "Invalid product_id query parameter"),
+        ("/api/v1/questions?product_id=1&page=0", "Invalid page query parameter"),
+        ("/api/v1/questions?product_id=1&page=x", "Invalid page query parameter"),
+        ("/api/v1/questions?product_id=1&count=0", "Invalid count query parameter"),
+        ("/api/v1/questions?product_id=1&count=-5", "Invalid count query parameter"),
+        ("/api/v1/questions?product_id=1&sort=random", "Invalid sort query parameter"),
+        ("/api/v1/questions?product_id=1&color=red", "Unexpected query parameter: color"),
+    ] {
+        let res = app.get(uri).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(res.text(), message, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn get_questions_hides_reported_questions_and_answers() {
+    let app = TestApp::spawn().await;
+    let kept = app.add_question(30, "Kept").await;
+    let reported = app.add_question(30, "Reported").await;
+    let kept_answer = app.add_answer(kept, "Kept answer", &[]).await;
+    let reported_answer = app.add_answer(kept, "Reported answer", &[]).await;
+
+    assert_eq!(app.put(&format!("/api/v1/questions/{}/report", reported)).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(app.put(&format!("/api/v1/answers/{}/report", reported_answer)).await.status, StatusCode::NO_CONTENT);
+
+    let res = app.get("/api/v1/questions?product_id=30").await;
+    let results = res.json()["results"].as_array().unwrap().clone();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["question_id"], kept);
+
+    let answers = results[0]["answers"].as_object().unwrap();
+    assert_eq!(answers.keys().cloned().collect::<Vec<_>>(), vec![kept_answer.to_string()]);
+}
+
+#[tokio::test]
+async fn batch_returns_counts_and_top_questions_per_product() {
+    let app = TestApp::spawn().await;
+    let first = app.add_question(30, "First").await;
+    let popular = app.add_question(30, "Popular").await;
+    let answer_id = app.add_answer(popular, "Yes", &["http://img/1.jpg"]).await;
+    app.add_question(30, "Third").await;
+    app.put(&format!("/api/v1/questions/{}/helpful", popular)).await;
+    let reported = app.add_question(31, "Reported").await;
+    app.put(&format!("/api/v1/questions/{}/report", reported)).await;
+    let only = app.add_question(32, "Only").await;
+
+    // Products come back in the order asked for, repeats dropped, with the most helpful first.
+    let res = app.get("/api/v1/questions/batch?product_ids=32,30,31,999,30&top=2").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let body = res.json();
+    assert_eq!(body["top"], 2);
+    let products: Vec<(i64, i64)> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| (p["product_id"].as_i64().unwrap(), p["question_count"].as_i64().unwrap()))
+        .collect();
+    assert_eq!(products, [(32, 1), (30, 3), (31, 0), (999, 0)]);
+
+    let ids = |product: &serde_json::Value| -> Vec<i64> {
+        product["questions"].as_array().unwrap().iter().map(|q| q["question_id"].as_i64().unwrap()).collect()
+    };
+    assert_eq!(ids(&body["results"][0]), [only]);
+    assert_eq!(ids(&body["results"][1]), [popular, first]);
+    assert_eq!(body["results"][2]["questions"], json!([]));
+
+    // Questions have the shape of the questions list.
+    let question = &body["results"][1]["questions"][0];
+    assert_eq!(question["question_body"], "Popular");
+    assert_eq!(question["question_helpfulness"], 1);
+    assert_eq!(question["answers"][answer_id.to_string()]["photos"][0]["url"], "http://img/1.jpg");
+
+    let res = app.get("/api/v1/questions/batch?product_ids=30&top=0").await;
+    assert_eq!(res.json()["results"], json!([{ "product_id": 30, "question_count": 3, "questions": [] }]));
+
+    let res = app.get("/api/v1/questions/batch?product_ids=30&sort=newest&top=1").await;
+    assert_eq!(res.json()["results"][0]["questions"][0]["question_body"], "Third");
+}
+
+#[tokio::test]
+async fn batch_rejects_bad_query_params() {
+    let app = TestApp::spawn().await;
+    let too_many = (1..=51).map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+
+    for (uri, message) in [
+        ("/api/v1/questions/batch".to_string(), "Missing product_ids query parameter"),
+        ("/api/v1/questions/batch?product_ids=1,x".to_string(), "Invalid product_ids query parameter"),
+        ("/api/v1/questions/batch?product_ids=1,,2".to_string(), "Invalid product_ids query parameter"),
+        (format!("/api/v1/questions/batch?product_ids={}", too_many), "Too many product_ids, at most 50 are allowed"),
+        ("/api/v1/questions/batch?product_ids=1&top=11".to_string(), "Invalid top query parameter"),
+        ("/api/v1/questions/batch?product_ids=1&top=-1".to_string(), "Invalid top query parameter"),
+        ("/api/v1/questions/batch?product_ids=1&sort=random".to_string(), "Invalid sort query parameter"),
+        ("/api/v1/questions/batch?product_ids=1&page=2".to_string(), "Unexpected query parameter: page"),
+    ] {
+        let res = app.get(&uri).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(res.text(), message);
+    }
+
+    // Fifty products, repeats aside, are fine.
+    let res = app.get(&format!("/api/v1/questions/batch?product_ids={},1", &too_many[..too_many.rfind(',').unwrap()])).await;
+    assert_eq!(res.json()["results"].as_array().unwrap().len(), 50);
+}
+
+#[tokio::test]
+async fn add_question_returns_created_id() {
+    let app = TestApp::spawn().await;
+
+    let res = app
+        .post("/api/v1/questions", json!({ "body": "New?", "name": "n", "email": "n@example.com", "product_id": 40 }))
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert!(res.json()["question_id"].is_i64());
+
+    let res = app.get("/api/v1/questions?product_id=40").await;
+    assert_eq!(res.json()["results"][0]["question_body"], "New?");
+}
+
+#[tokio::test]
+async fn add_question_rejects_malformed_bodies() {
+    let app = TestApp::spawn().await;
+
+    let bodies: Vec<Vec<u8>> = vec![
+        b"".to_vec(),
+        b"not json".to_vec(),
+        b"{\"body\": \"missing fields\"}".to_vec(),
+        b"{\"body\": \"b\", \"name\": \"n\", \"email\": \"e\", \"product_id\": \"forty\"}".to_vec(),
+        vec![0xff, 0xfe, 0xfd],
+    ];
+
+    for body in bodies {
+        let res = app.request(Method::POST, "/api/v1/questions", body.clone()).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST, "{:?}", body);
+        assert_eq!(res.text(), "Invalid request body");
+    }
+}
+
+#[tokio::test]
+async fn question_helpful_increments_and_returns_no_content() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(50, "Helpful?").await;
+
+    for voter in ["alice", "bob"] {
+        let res = app.vote(Method::PUT, &format!("/api/v1/questions/{}/helpful", question_id), voter).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert!(res.body.is_empty());
+    }
+
+    let res = app.get("/api/v1/questions?product_id=50").await;
+    assert_eq!(res.json()["results"][0]["question_helpfulness"], 2);
+}
+
+#[tokio::test]
+async fn question_updates_reject_bad_and_unknown_ids() {
+    let app = TestApp::spawn().await;
+
+    for action in ["helpful", "report"] {
+        let res = app.put(&format!("/api/v1/questions/abc/{}", action)).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert_eq!(res.text(), "Invalid question_id path parameter");
+
+        let res = app.put(&format!("/api/v1/questions/424242/{}", action)).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert_eq!(res.text(), "Question not found");
+    }
+}
+
+#[tokio::test]
+async fn unknown_routes_are_not_found() {
+    let app = TestApp::spawn().await;
+
+    for (method, uri) in [
+        (Method::GET, "/"),
+        (Method::GET, "/api/v1/answers"),
+        (Method::DELETE, "/api/v1/questions"),
+        (Method::PATCH, "/api/v1/questions/1/helpful"),
+    ] {
+        let res = app.request(method, uri, hyper::Body::empty()).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND, "{}", uri);
+        assert_eq!(res.text(), "Path not found");
+    }
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use common::TestApp;
+use hyper::{header::HeaderMap, Body, Method, StatusCode};
+use qa_rs::{
+    proxy::TrustedProxies,
+    ratelimit::{RateLimiter, RequestClass},
+};
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+async fn spawn(read_per_minute: u32, write_per_minute: u32) -> TestApp {
+    TestApp::spawn_with(move |state| {
+        state.rate_limiter = RateLimiter::new(read_per_minute, write_per_minute);
+        state.trusted_proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+    })
+    .await
+}
+
+fn from(ip: [u8; 4]) -> SocketAddr {
+    SocketAddr::from((ip, 40000))
+}
+
+fn question() -> String {
+    json!({ "body": "Q", "name": "n", "email": "n@example.com", "product_id": 1 }).to_string()
+}
+
+#[tokio::test]
+async fn writes_beyond_the_budget_are_rejected() {
+    let app = spawn(0, 2).await;
+
+    for remaining in ["1", "0"] {
+        let res = app.request(Method::POST, "/api/v1/questions", question()).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(res.header("ratelimit-limit"), Some("2"));
+        assert_eq!(res.header("ratelimit-remaining"), Some(remaining));
+    }
+
+    let res = app.request(Method::PUT, "/api/v1/questions/1/helpful", Body::empty()).await;
+    assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.text(), "Too many requests");
+    assert_eq!(res.header("ratelimit-remaining"), Some("0"));
+    // Two writes a minute refill one every 30 seconds.
+    assert_eq!(res.header("retry-after"), Some("30"));
+    assert_eq!(res.header("ratelimit-reset"), Some("60"));
+
+    // Reads have a budget of their own, unlimited here.
+    let res = app.get("/api/v1/questions?product_id=1").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.header("ratelimit-limit"), None);
+
+    // So does /metrics, which is never limited.
+    assert_eq!(app.get("/metrics").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn failed_authentication_draws_from_the_address_budget() {
+    let app = spawn(0, 2).await;
+
+    // Even reads, which are unlimited here, cost a write when their key is wrong.
+    for remaining in ["1", "0"] {
+        let res = app.get_with_headers("/api/v1/questions?product_id=1", &[("x-api-key", "qa_0000")]).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(res.header("ratelimit-remaining"), Some(remaining));
+    }
+
+    let res = app.get_with_headers("/api/v1/questions?product_id=1", &[("x-api-key", "qa_0001")]).await;
+    assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+    let res = app.request(Method::POST, "/api/v1/questions", question()).await;
+    assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Other addresses are unaffected.
+    let res = app.request_from(from([127, 0, 0, 2]), Method::GET, "/api/v1/questions?product_id=1", &[("x-api-key", "qa_0000")], Body::empty()).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn clients_have_separate_buckets() {
+    let app = spawn(1, 0).await;
+    let key = app.create_api_key("storefront", &[qa_rs::auth::Scope::Read]).await;
+
+    let get = |addr: SocketAddr, headers: Vec<(&'static str, String)>| {
+        let app = &app;
+        async move {
+            let headers: Vec<(&str, &str)> = headers.iter().map(|(name, value)| (*name, value.as_str())).collect();
+            app.request_from(addr, Method::GET, "/api/v1/questions?product_id=1", &headers, Body::empty()).await.status
+        }
+    };
+
+    assert_eq!(get(from([192, 0, 2, 1]), vec![]).await, StatusCode::OK);
+    assert_eq!(get(from([192, 0, 2, 1]), vec![]).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(get(from([192, 0, 2, 2]), vec![]).await, StatusCode::OK);
+
+    // A key is limited as itself, wherever it connects from.
+    assert_eq!(get(from([192, 0, 2, 1]), vec![("x-api-key", key.clone())]).await, StatusCode::OK);
+    assert_eq!(get(from([192, 0, 2, 3]), vec![("x-api-key", key)]).await, StatusCode::TOO_MANY_REQUESTS);
+
+    // Behind a trusted proxy the client is taken from X-Forwarded-For...
+    let forwarded = |client: &str| vec![("x-forwarded-for", format!("{}, 10.0.0.7", client))];
+    assert_eq!(get(from([10, 0, 0, 1]), forwarded("198.51.100.1")).await, StatusCode::OK);
+    assert_eq!(get(from([10, 0, 0, 1]), forwarded("198.51.100.2")).await, StatusCode::OK);
+    assert_eq!(get(from([10, 0, 0, 2]), forwarded("198.51.100.1")).await, StatusCode::TOO_MANY_REQUESTS);
+
+    // ...but anyone else's header is ignored.
+    assert_eq!(get(from([192, 0, 2, 4]), forwarded("198.51.100.3")).await, StatusCode::OK);
+    assert_eq!(get(from([192, 0, 2, 4]), forwarded("198.51.100.4")).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn votes_under_user_tokens_are_limited_by_address() {
+    let app = spawn(0, 2).await;
+    let key = app.create_api_key("storefront", &[qa_rs::auth::Scope::Write]).await;
+    let question_id = app.add_question(1, "Question").await;
+    let uri = format!("/api/v1/questions/{}/helpful", question_id);
+
+    let vote = |addr: SocketAddr, token: &'static str| {
+        let (app, uri, key) = (&app, &uri, &key);
+        async move {
+            let headers = [("x-api-key", key.as_str()), ("x-user-token", token)];
+            app.request_from(addr, Method::PUT, uri, &headers, Body::empty()).await.status
+        }
+    };
+
+    // Fresh tokens do not buy fresh votes, even under a key.
+    assert_eq!(vote(from([192, 0, 2, 1]), "a").await, StatusCode::NO_CONTENT);
+    assert_eq!(vote(from([192, 0, 2, 1]), "b").await, StatusCode::NO_CONTENT);
+    assert_eq!(vote(from([192, 0, 2, 1]), "c").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(vote(from([192, 0, 2, 2]), "c").await, StatusCode::NO_CONTENT);
+
+    // The key's own budget is untouched by them.
+    let res = app.request_with_headers(Method::POST, "/api/v1/questions", &[("x-api-key", &key)], question()).await;
+    assert_eq!(res.status, StatusCode::CREATED);
+}
+
+#[test]
+fn buckets_refill_over_the_minute() {
+    let limiter = RateLimiter::new(60, 6);
+    let start = Instant::now();
+
+    for _ in 0..6 {
+        assert!(!limiter.check_at(RequestClass::Write, "ip:a", start).unwrap().is_limited());
+    }
+    let limit = limiter.check_at(RequestClass::Write, "ip:a", start).unwrap();
+    assert_eq!((limit.retry_after, limit.reset), (Some(10), 60));
+
+    // One write comes back every ten seconds, and never more than the budget.
+    let limit = limiter.check_at(RequestClass::Write, "ip:a", start + Duration::from_secs(10)).unwrap();
+    assert_eq!((limit.retry_after, limit.remaining), (None, 0));
+    let limit = limiter.check_at(RequestClass::Write, "ip:a", start + Duration::from_secs(3600)).unwrap();
+    assert_eq!(limit.remaining, 5);
+
+    assert!(limiter.check_at(RequestClass::Read, "ip:a", start).is_some());
+    assert!(RateLimiter::disabled().check_at(RequestClass::Read, "ip:a", start).is_none());
+}
+
+#[test]
+fn trusted_proxies_name_the_client() {
+    let proxies = TrustedProxies::parse("10.0.0.0/8, 192.168.1.10, fd00::/8").unwrap();
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let headers = |value: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    };
+
+    assert!(proxies.contains(ip("10.1.2.3")) && proxies.contains(ip("fd12::1")));
+    assert!(!proxies.contains(ip("192.168.1.11")) && !proxies.contains(ip("11.0.0.1")));
+
+    let client = proxies.client_ip(&headers("203.0.113.9, 198.51.100.1, 192.168.1.10"), ip("10.0.0.1"));
+    assert_eq!(client, ip("198.51.100.1"));
+    let client = proxies.client_ip(&headers("not-an-ip, 10.0.0.5"), ip("10.0.0.1"));
+    assert_eq!(client, ip("10.0.0.5"));
+    assert_eq!(proxies.client_ip(&HeaderMap::new(), ip("10.0.0.1")), ip("10.0.0.1"));
+
+    assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+    assert!(TrustedProxies::parse("proxy.internal").is_err());
+}
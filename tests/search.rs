@@ -0,0 +1,101 @@
+mod common;
+
+use common::{TestApp, TestResponse};
+use hyper::StatusCode;
+use serde_json::Value;
+
+fn question_ids(res: &TestResponse) -> Vec<i64> {
+    res.json()["results"].as_array().unwrap().iter().map(|q| q["question_id"].as_i64().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn search_matches_question_and_answer_bodies() {
+    let app = TestApp::spawn().await;
+    let small = app.add_question(1, "Does this jacket run small?").await;
+    let answer_id = app.add_answer(small, "Order a size up, the sleeves are short", &["http://img/1.jpg"]).await;
+    let waterproof = app.add_question(1, "Is the jacket waterproof?").await;
+    let shipping = app.add_question(1, "How long does shipping take?").await;
+    app.add_answer(shipping, "The jacket arrived in two days", &[]).await;
+    app.add_question(2, "Is this jacket warm?").await;
+
+    let res = app.get("/api/v1/questions/search?product_id=1&q=sleeves").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.json()["product_id"], 1);
+    assert_eq!(question_ids(&res), [small]);
+
+    // Results have the shape of the questions list, with snippets where bodies match.
+    let question = &res.json()["results"][0];
+    assert_eq!(question["question_body"], "Does this jacket run small?");
+    assert_eq!(question["question_snippet"], Value::Null);
+    let answer = &question["answers"][answer_id.to_string()];
+    assert_eq!(answer["body"], "Order a size up, the sleeves are short");
+    assert!(answer["snippet"].as_str().unwrap().contains("<mark>sleeves</mark>"), "{}", answer);
+    assert_eq!(answer["photos"][0]["url"], "http://img/1.jpg");
+
+    // Words are matched by their stem, and the product is respected.
+    let res = app.get("/api/v1/questions/search?product_id=1&q=running").await;
+    assert_eq!(question_ids(&res), [small]);
+    assert!(res.json()["results"][0]["question_snippet"].as_str().unwrap().contains("<mark>run</mark>"));
+
+    let mut ids = question_ids(&app.get("/api/v1/questions/search?product_id=1&q=jacket").await);
+    ids.sort_unstable();
+    assert_eq!(ids, [small, waterproof, shipping]);
+
+    // Queries are URL decoded and every word has to match.
+    assert_eq!(question_ids(&app.get("/api/v1/questions/search?product_id=1&q=jacket%20waterproof").await), [waterproof]);
+    assert_eq!(question_ids(&app.get("/api/v1/questions/search?product_id=1&q=two+days").await), [shipping]);
+    assert!(question_ids(&app.get("/api/v1/questions/search?product_id=1&q=zipper").await).is_empty());
+}
+
+#[tokio::test]
+async fn search_ranks_and_paginates() {
+    let app = TestApp::spawn().await;
+    let passing = app.add_question(3, "Are the laces long enough, and is the sole made of rubber or foam?").await;
+    let focused = app.add_question(3, "Laces: do the laces come in spare laces?").await;
+
+    let res = app.get("/api/v1/questions/search?product_id=3&q=laces").await;
+    assert_eq!(question_ids(&res), [focused, passing]);
+
+    let res = app.get("/api/v1/questions/search?product_id=3&q=laces&page=2&count=1").await;
+    assert_eq!(question_ids(&res), [passing]);
+}
+
+#[tokio::test]
+async fn search_escapes_bodies_and_skips_reported_content() {
+    let app = TestApp::spawn().await;
+    let markup = app.add_question(4, "Is <b>this</b> safe & sound?").await;
+    let reported = app.add_question(4, "Is it safe for kids?").await;
+    app.put(&format!("/api/v1/questions/{}/report", reported)).await;
+
+    let res = app.get("/api/v1/questions/search?product_id=4&q=safe").await;
+    assert_eq!(question_ids(&res), [markup]);
+    let snippet = res.json()["results"][0]["question_snippet"].as_str().unwrap().to_string();
+    assert!(snippet.contains("this&lt;/b&gt; <mark>safe</mark>") && !snippet.contains("<b>"), "{}", snippet);
+    assert!(snippet.contains("&amp; sound"), "{}", snippet);
+    assert_eq!(res.json()["results"][0]["question_body"], "Is <b>this</b> safe & sound?");
+}
+
+#[tokio::test]
+async fn search_validates_query_parameters() {
+    let app = TestApp::spawn().await;
+
+    for (uri, message) in [
+        ("/api/v1/questions/search?q=fit", "Missing product_id query parameter"),
+        ("/api/v1/questions/search?product_id=x&q=fit", "Invalid product_id query parameter"),
+        ("/api/v1/questions/search?product_id=1", "Missing q query parameter"),
+        ("/api/v1/questions/search?product_id=1&q=%20%20", "Invalid q query parameter"),
+        ("/api/v1/questions/search?product_id=1&q=fit&sort=newest", "Unexpected query parameter: sort"),
+    ] {
+        let res = app.get(uri).await;
+        assert_eq!((res.status, res.text().as_str()), (StatusCode::BAD_REQUEST, message), "{}", uri);
+    }
+
+    let long = format!("/api/v1/questions/search?product_id=1&q={}", "a".repeat(201));
+    assert_eq!(app.get(&long).await.text(), "Invalid q query parameter");
+
+    // Search syntax is never an error, whatever the backend makes of it.
+    for q in ["%22unbalanced", "-", "a%20OR", "NEAR(x)", "*"] {
+        let res = app.get(&format!("/api/v1/questions/search?product_id=1&q={}", q)).await;
+        assert_eq!(res.status, StatusCode::OK, "{}: {}", q, res.text());
+    }
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::TestApp;
+use hyper::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn qa_stats_summarise_visible_questions_and_answers() {
+    let app = TestApp::spawn().await;
+    let answered = app.add_question(40, "Does it fit?").await;
+    app.add_answer(answered, "Yes", &["http://img/1.jpg", "http://img/2.jpg"]).await;
+    app.add_answer(answered, "Mostly", &[]).await;
+    let popular = app.add_question(40, "Is it warm?").await;
+    app.add_answer(popular, "Very", &["http://img/3.jpg"]).await;
+    let newest = app.add_question(40, "Is it machine washable?").await;
+    app.put(&format!("/api/v1/questions/{}/helpful", popular)).await;
+
+    // Reported content, and other products, are not counted.
+    let reported = app.add_question(40, "Reported").await;
+    app.add_answer(reported, "Hidden", &["http://img/4.jpg"]).await;
+    app.put(&format!("/api/v1/questions/{}/report", reported)).await;
+    let reported_answer = app.add_answer(answered, "Reported", &[]).await;
+    app.put(&format!("/api/v1/answers/{}/report", reported_answer)).await;
+    app.add_question(41, "Elsewhere").await;
+
+    let res = app.get("/api/v1/products/40/qa-stats").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let stats = res.json();
+    assert_eq!(stats["product_id"], 40);
+    assert_eq!(stats["question_count"], 3);
+    assert_eq!(stats["answered_count"], 2);
+    assert_eq!(stats["unanswered_count"], 1);
+    assert_eq!(stats["answer_count"], 3);
+    assert_eq!(stats["photo_count"], 3);
+    assert_eq!(stats["top_question"], json!({ "question_id": popular, "question_body": "Is it warm?", "question_helpfulness": 1 }));
+
+    // The last activity is the newest visible post, the washing question.
+    let questions = app.get("/api/v1/questions?product_id=40&sort=newest").await.json();
+    assert_eq!(questions["results"][0]["question_id"], newest);
+    let posted = questions["results"][0]["question_date"].as_str().unwrap().to_string();
+    assert_eq!(stats["last_activity"].as_str().unwrap()[..19], posted[..19]);
+}
+
+#[tokio::test]
+async fn qa_stats_of_a_product_without_questions_are_empty() {
+    let app = TestApp::spawn().await;
+    app.add_question(42, "Nobody finds this helpful").await;
+
+    let res = app.get("/api/v1/products/999/qa-stats").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(
+        res.json(),
+        json!({
+            "product_id": 999,
+            "question_count": 0,
+            "answered_count": 0,
+            "unanswered_count": 0,
+            "answer_count": 0,
+            "photo_count": 0,
+            "last_activity": null,
+            "top_question": null,
+        })
+    );
+
+    // Without helpful votes there is no top question.
+    assert_eq!(app.get("/api/v1/products/42/qa-stats").await.json()["top_question"], json!(null));
+
+    let res = app.get("/api/v1/products/abc/qa-stats").await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.text(), "Invalid product_id path parameter");
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use common::TestApp;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use image::{ImageFormat, RgbImage};
+use qa_rs::db::Database;
+use qa_rs::thumbnails::{make_variants, ThumbnailWorker, MAX_ATTEMPTS};
+use qa_rs::verification::PhotoVerifier;
+use std::convert::Infallible;
+use std::io::Cursor;
+use std::time::Duration;
+
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let image = RgbImage::from_fn(width, height, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, 128]));
+    let mut data = Vec::new();
+    image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png).unwrap();
+    data
+}
+
+fn worker() -> ThumbnailWorker {
+    ThumbnailWorker::new(Duration::from_secs(1), 10, 320, Duration::ZERO)
+}
+
+/// Serves a PNG at `/photo.png`, text at `/notes.txt` and 404 anywhere else.
+async fn image_host() -> String {
+    let make_svc = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let response = match req.uri().path() {
+                "/photo.png" => Response::builder().header("content-type", "image/png").body(Body::from(png(640, 960))).unwrap(),
+                "/notes.txt" => Response::new(Body::from("not an image")),
+                _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap(),
+            };
+            Ok::<_, Infallible>(response)
+        }))
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+    let host = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    host
+}
+
+async fn answer_photos(app: &TestApp, question_id: i64) -> Vec<serde_json::Value> {
+    let res = app.get(&format!("/api/v1/questions/{}/answers", question_id)).await;
+    res.json()["results"][0]["photos"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn uploaded_photos_get_thumbnails() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(3, "How wide is it?").await;
+    let answer_id = app.add_answer(question_id, "Wide, see", &[]).await;
+
+    let boundary = "thumbs";
+    let mut body = format!("--{}\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"wide.png\"\r\n\r\n", boundary).into_bytes();
+    body.extend_from_slice(&png(800, 400));
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let content_type = format!("multipart/form-data; boundary={}", boundary);
+    let res = app
+        .request_with_headers(Method::POST, &format!("/api/v1/answers/{}/photos", answer_id), &[("content-type", &content_type)], body)
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+
+    // Until the worker gets to it, a photo has no variants.
+    app.backdate().await;
+    let res = app.get(&format!("/api/v1/questions/{}/answers", question_id)).await;
+    let last_modified = res.header("last-modified").unwrap().to_string();
+    let photos = res.json()["results"][0]["photos"].as_array().unwrap().clone();
+    assert_eq!(photos[0]["thumbnail_url"], serde_json::Value::Null);
+    assert_eq!(photos[0]["width"], serde_json::Value::Null);
+
+    assert_eq!(worker().process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(worker().process_pending(&app.state).await.unwrap(), 0);
+
+    // Recording the variants changes the answer for conditional requests.
+    let res = app.get_with_headers(&format!("/api/v1/questions/{}/answers", question_id), &[("if-modified-since", &last_modified)]).await;
+    assert_eq!(res.status, StatusCode::OK);
+
+    let photos = answer_photos(&app, question_id).await;
+    assert_eq!((photos[0]["width"].as_i64(), photos[0]["height"].as_i64()), (Some(800), Some(400)));
+    let thumbnail_url = photos[0]["thumbnail_url"].as_str().unwrap();
+    assert_eq!(thumbnail_url, format!("/photos/answers/{}/{}_thumb.jpg", answer_id, photos[0]["id"]));
+
+    let res = app.get(thumbnail_url).await;
+    assert_eq!(res.header("content-type"), Some("image/jpeg"));
+    let thumbnail = image::load_from_memory_with_format(&res.body, ImageFormat::Jpeg).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (320, 160));
+
+    let res = app.get(photos[0]["thumbnail_webp_url"].as_str().unwrap()).await;
+    assert_eq!(res.header("content-type"), Some("image/webp"));
+    let thumbnail = image::load_from_memory_with_format(&res.body, ImageFormat::WebP).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (320, 160));
+
+    // The questions listing carries the variants too.
+    let res = app.get("/api/v1/questions?product_id=3").await;
+    let answers = res.json()["results"][0]["answers"].clone();
+    assert_eq!(answers[answer_id.to_string()]["photos"][0]["thumbnail_url"], thumbnail_url);
+}
+
+#[tokio::test]
+async fn registered_photos_are_fetched_once_verified() {
+    let host = image_host().await;
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(4, "Is it tall?").await;
+    let urls = [format!("{}/photo.png", host), format!("{}/missing.png", host), format!("{}/notes.txt", host)];
+    app.add_answer(question_id, "Very", &[&urls[0], &urls[1], &urls[2]]).await;
+
+    // Photos registered by URL wait for verification, which flags the broken ones.
+    assert_eq!(worker().process_pending(&app.state).await.unwrap(), 0);
+    let verifier = PhotoVerifier::new(Duration::from_secs(1), 10, Duration::ZERO);
+    assert_eq!(verifier.process_pending(&app.state).await.unwrap(), 3);
+
+    assert_eq!(worker().process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(worker().process_pending(&app.state).await.unwrap(), 0);
+
+    let photos = answer_photos(&app, question_id).await;
+    assert_eq!(photos.len(), 1);
+    assert_eq!(photos[0]["url"], urls[0]);
+    assert_eq!((photos[0]["width"].as_i64(), photos[0]["height"].as_i64()), (Some(640), Some(960)));
+    assert!(photos[0]["thumbnail_url"].as_str().unwrap().starts_with("/photos/answers/"));
+}
+
+#[tokio::test]
+async fn photos_from_before_the_worker_wait_for_backfill() {
+    let host = image_host().await;
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(4, "Is it tall?").await;
+    let url = format!("{}/photo.png", host);
+    app.add_answer(question_id, "Very", &[&url]).await;
+
+    // As the migration leaves photos that were already stored.
+    let sql = "UPDATE answer_photos SET verify_status = 'ok', variants_status = 'backfill'";
+    match &app.state.db {
+        Database::Postgres(pool) => sqlx::query(sql).execute(pool).await.map(drop),
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(pool) => sqlx::query(sql).execute(pool).await.map(drop),
+    }
+    .unwrap();
+
+    assert_eq!(worker().process_pending(&app.state).await.unwrap(), 0);
+    let backfill = ThumbnailWorker { backfill: true, ..worker() };
+    assert_eq!(backfill.process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(backfill.process_pending(&app.state).await.unwrap(), 0);
+
+    let photos = answer_photos(&app, question_id).await;
+    assert_eq!((photos[0]["width"].as_i64(), photos[0]["height"].as_i64()), (Some(640), Some(960)));
+}
+
+#[tokio::test]
+async fn photos_that_cannot_be_decoded_are_given_up_on() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(4, "Is it tall?").await;
+    let answer_id = app.add_answer(question_id, "Very", &[]).await;
+
+    // A PNG signature passes the upload checks, but nothing can be decoded from it.
+    let boundary = "thumbs";
+    let mut body = format!("--{}\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"cut.png\"\r\n\r\n", boundary).into_bytes();
+    body.extend_from_slice(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let content_type = format!("multipart/form-data; boundary={}", boundary);
+    let res = app
+        .request_with_headers(Method::POST, &format!("/api/v1/answers/{}/photos", answer_id), &[("content-type", &content_type)], body)
+        .await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.text());
+
+    for _ in 0..MAX_ATTEMPTS {
+        assert_eq!(worker().process_pending(&app.state).await.unwrap(), 1);
+    }
+    assert_eq!(worker().process_pending(&app.state).await.unwrap(), 0);
+
+    let photos = answer_photos(&app, question_id).await;
+    assert_eq!(photos[0]["thumbnail_url"], serde_json::Value::Null);
+}
+
+#[test]
+fn small_photos_keep_their_size() {
+    let variants = make_variants(&png(100, 50), 320).unwrap();
+    assert_eq!((variants.width, variants.height), (100, 50));
+    let thumbnail = image::load_from_memory(&variants.thumbnail).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (100, 50));
+
+    assert!(make_variants(b"not an image", 320).is_err());
+}
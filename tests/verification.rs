@@ -0,0 +1,158 @@
+mod common;
+
+use common::TestApp;
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use qa_rs::fetch::{is_blocked, FetchError, PhotoFetcher};
+use qa_rs::verification::{PhotoVerifier, MAX_ATTEMPTS};
+use std::convert::Infallible;
+use std::time::Duration;
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+fn verifier() -> PhotoVerifier {
+    PhotoVerifier::new(Duration::from_secs(1), 10, Duration::ZERO)
+}
+
+/// Serves photos both good and bad, and the ways a URL can fail to be one.
+async fn photo_host() -> String {
+    let make_svc = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let response = Response::builder();
+            let response = match req.uri().path() {
+                "/photo.png" => response.header(CONTENT_TYPE, "image/png").body(Body::from(PNG)),
+                "/notes.txt" => response.header(CONTENT_TYPE, "text/plain; charset=utf-8").body(Body::from("not an image")),
+                "/fake.png" => response.header(CONTENT_TYPE, "image/png").body(Body::from("not an image either")),
+                "/moved.png" => response.status(StatusCode::FOUND).header(LOCATION, "/photo.png").body(Body::empty()),
+                "/loop.png" => response.status(StatusCode::FOUND).header(LOCATION, "/loop.png").body(Body::empty()),
+                "/huge.png" => response.header(CONTENT_TYPE, "image/png").body(Body::from([PNG, &[0; 64 * 1024]].concat())),
+                "/chunked.png" => {
+                    // Sent without a length, so only the bytes read can give its size away.
+                    let (mut sender, body) = Body::channel();
+                    tokio::spawn(async move {
+                        for _ in 0..128 {
+                            if sender.send_data(PNG.into()).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+                    response.header(CONTENT_TYPE, "image/png").body(body)
+                }
+                "/flaky.png" => response.status(StatusCode::SERVICE_UNAVAILABLE).body(Body::empty()),
+                "/slow.png" => {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    response.header(CONTENT_TYPE, "image/png").body(Body::from(PNG))
+                }
+                _ => response.status(StatusCode::NOT_FOUND).body(Body::empty()),
+            };
+            Ok::<_, Infallible>(response.unwrap())
+        }))
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+    let host = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    host
+}
+
+async fn listed_urls(app: &TestApp, answer_id: i64) -> Vec<String> {
+    let res = app.get(&format!("/api/v1/answers/{}/photos", answer_id)).await;
+    res.json()["results"].as_array().unwrap().iter().map(|photo| photo["url"].as_str().unwrap().to_string()).collect()
+}
+
+#[tokio::test]
+async fn broken_and_non_image_photos_are_flagged_and_hidden() {
+    let host = photo_host().await;
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(5, "Does it fold?").await;
+    let paths = ["/photo.png", "/moved.png", "/notes.txt", "/fake.png", "/missing.png", "/flaky.png", "/loop.png"];
+    let urls: Vec<String> = paths.iter().map(|path| format!("{}{}", host, path)).collect();
+    let answer_id = app.add_answer(question_id, "Flat", &urls.iter().map(String::as_str).collect::<Vec<_>>()).await;
+
+    // Unverified photos are listed until they are found to be broken.
+    assert_eq!(listed_urls(&app, answer_id).await.len(), paths.len());
+
+    assert_eq!(verifier().process_pending(&app.state).await.unwrap(), paths.len());
+    // Only the photo whose server may recover is tried again, until it runs out of attempts.
+    for _ in 1..MAX_ATTEMPTS {
+        assert_eq!(verifier().process_pending(&app.state).await.unwrap(), 1);
+    }
+    assert_eq!(verifier().process_pending(&app.state).await.unwrap(), 0);
+
+    assert_eq!(listed_urls(&app, answer_id).await, urls[..2]);
+    let res = app.get(&format!("/api/v1/questions/{}/answers", question_id)).await;
+    assert_eq!(res.json()["results"][0]["photos"].as_array().unwrap().len(), 2);
+    let res = app.get("/api/v1/products/5/qa-stats").await;
+    assert_eq!(res.json()["photo_count"], 2);
+}
+
+#[tokio::test]
+async fn broken_photos_change_last_modified() {
+    let host = photo_host().await;
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(5, "Does it fold?").await;
+    app.add_answer(question_id, "Flat", &[&format!("{}/missing.png", host)]).await;
+    app.backdate().await;
+
+    let uris = ["/api/v1/questions?product_id=5".to_string(), format!("/api/v1/questions/{}/answers", question_id)];
+    let mut last_modified = Vec::new();
+    for uri in &uris {
+        last_modified.push(app.get(uri).await.header("last-modified").unwrap().to_string());
+    }
+
+    assert_eq!(verifier().process_pending(&app.state).await.unwrap(), 1);
+    for (uri, last_modified) in uris.iter().zip(&last_modified) {
+        let res = app.get_with_headers(uri, &[("if-modified-since", last_modified)]).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn photos_on_private_networks_are_never_fetched() {
+    let host = photo_host().await;
+    let app = TestApp::spawn_with(|state| state.photo_fetcher.allow_private = false).await;
+    let question_id = app.add_question(5, "Is it waterproof?").await;
+    let url = format!("{}/photo.png", host);
+    let answer_id = app.add_answer(question_id, "Yes", &[&url]).await;
+
+    assert_eq!(verifier().process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(verifier().process_pending(&app.state).await.unwrap(), 0);
+    assert!(listed_urls(&app, answer_id).await.is_empty());
+
+    let fetcher = PhotoFetcher::new(Duration::from_secs(5));
+    for url in ["http://127.0.0.1/a.png", "http://localhost/a.png", "http://[::1]/a.png", "http://169.254.169.254/latest/meta-data", "http://[::ffff:10.0.0.1]/a.png", "http://[64:ff9b::7f00:1]/a.png"] {
+        let result = fetcher.fetch(url, 1024).await;
+        assert!(matches!(result, Err(FetchError::Blocked(_))), "{}: {:?}", url, result);
+    }
+    assert!(matches!(fetcher.fetch("file:///etc/passwd", 1024).await, Err(FetchError::InvalidUrl(_))));
+
+    assert!(is_blocked("192.168.1.20".parse().unwrap()));
+    assert!(is_blocked("100.100.0.1".parse().unwrap()));
+    assert!(is_blocked("fd12::1".parse().unwrap()));
+    // IPv6 addresses carrying a private IPv4 address: IPv4-compatible, NAT64, 6to4 and Teredo.
+    for ip in ["::10.0.0.1", "64:ff9b::192.168.1.20", "64:ff9b:1::a9fe:a9fe", "2002:7f00:1::", "2001:0:4136:e378:8000:63bf:80ff:fffe"] {
+        assert!(is_blocked(ip.parse().unwrap()), "{}", ip);
+    }
+    assert!(!is_blocked("93.184.216.34".parse().unwrap()));
+    assert!(!is_blocked("2606:2800:220:1::".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn fetches_are_limited_in_size_time_and_redirects() {
+    let host = photo_host().await;
+    let fetcher = PhotoFetcher { allow_private: true, ..PhotoFetcher::new(Duration::from_millis(500)) };
+
+    let fetched = fetcher.fetch(&format!("{}/moved.png", host), 1024).await.unwrap();
+    assert_eq!((fetched.content_type.as_deref(), fetched.data.as_ref()), (Some("image/png"), PNG));
+
+    let result = fetcher.fetch(&format!("{}/huge.png", host), 1024).await;
+    assert!(matches!(result, Err(FetchError::TooLarge(1024))), "{:?}", result);
+    let result = fetcher.fetch(&format!("{}/chunked.png", host), 1024).await;
+    assert!(matches!(result, Err(FetchError::TooLarge(1024))), "{:?}", result);
+    let result = fetcher.fetch(&format!("{}/slow.png", host), 1024).await;
+    assert!(matches!(result, Err(FetchError::Timeout)), "{:?}", result);
+    let result = fetcher.fetch(&format!("{}/loop.png", host), 1024).await;
+    assert!(matches!(result, Err(FetchError::TooManyRedirects)), "{:?}", result);
+    let result = fetcher.fetch(&format!("{}/missing.png", host), 1024).await;
+    assert!(matches!(result, Err(FetchError::Status(404))), "{:?}", result);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::TestApp;
+use hyper::{Body, Method, StatusCode};
+use std::net::SocketAddr;
+
+async fn question_helpfulness(app: &TestApp, product_id: i32) -> i64 {
+    let res = app.get(&format!("/api/v1/questions?product_id={}", product_id)).await;
+    res.json()["results"][0]["question_helpfulness"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn each_user_token_counts_once() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let uri = format!("/api/v1/questions/{}/helpful", question_id);
+
+    for voter in ["alice", "alice", "bob", "alice"] {
+        let res = app.vote(Method::PUT, &uri, voter).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert!(res.body.is_empty());
+    }
+
+    assert_eq!(question_helpfulness(&app, 1).await, 2);
+}
+
+#[tokio::test]
+async fn votes_without_a_token_count_once_per_ip_address() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let uri = format!("/api/v1/questions/{}/helpful", question_id);
+
+    // The port differs between connections from the same client, so only the IP counts.
+    let first = SocketAddr::from(([192, 0, 2, 1], 40000));
+    let first_again = SocketAddr::from(([192, 0, 2, 1], 40001));
+    let second = SocketAddr::from(([192, 0, 2, 2], 40000));
+    for addr in [first, first_again, second] {
+        let res = app.request_from(addr, Method::PUT, &uri, &[], Body::empty()).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+    }
+    assert_eq!(question_helpfulness(&app, 1).await, 2);
+
+    // A token identifies its user independently of the address it is sent from.
+    let res = app.request_from(first, Method::PUT, &uri, &[("x-user-token", "alice")], Body::empty()).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert_eq!(question_helpfulness(&app, 1).await, 3);
+}
+
+#[tokio::test]
+async fn delete_withdraws_a_question_vote() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let uri = format!("/api/v1/questions/{}/helpful", question_id);
+
+    app.vote(Method::PUT, &uri, "alice").await;
+    app.vote(Method::PUT, &uri, "bob").await;
+    assert_eq!(question_helpfulness(&app, 1).await, 2);
+
+    for _ in 0..2 {
+        let res = app.vote(Method::DELETE, &uri, "alice").await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        assert!(res.body.is_empty());
+    }
+    assert_eq!(question_helpfulness(&app, 1).await, 1);
+
+    // Withdrawing a vote that was never cast changes nothing.
+    assert_eq!(app.vote(Method::DELETE, &uri, "carol").await.status, StatusCode::NO_CONTENT);
+    assert_eq!(question_helpfulness(&app, 1).await, 1);
+
+    // A withdrawn vote can be cast again.
+    app.vote(Method::PUT, &uri, "alice").await;
+    assert_eq!(question_helpfulness(&app, 1).await, 2);
+}
+
+#[tokio::test]
+async fn answer_votes_are_deduplicated_and_can_be_withdrawn() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+    let answer_id = app.add_answer(question_id, "Answer", &[]).await;
+    let uri = format!("/api/v1/answers/{}/helpful", answer_id);
+    let list = format!("/api/v1/questions/{}/answers", question_id);
+
+    for voter in ["alice", "alice", "bob"] {
+        assert_eq!(app.vote(Method::PUT, &uri, voter).await.status, StatusCode::NO_CONTENT);
+    }
+    assert_eq!(app.get(&list).await.json()["results"][0]["helpfulness"], 2);
+
+    assert_eq!(app.vote(Method::DELETE, &uri, "bob").await.status, StatusCode::NO_CONTENT);
+    assert_eq!(app.vote(Method::DELETE, &uri, "bob").await.status, StatusCode::NO_CONTENT);
+    assert_eq!(app.get(&list).await.json()["results"][0]["helpfulness"], 1);
+
+    // The nested answers in the questions list reflect the withdrawn vote too.
+    let res = app.get("/api/v1/questions?product_id=1").await;
+    assert_eq!(res.json()["results"][0]["answers"][answer_id.to_string()]["helpfulness"], 1);
+}
+
+#[tokio::test]
+async fn withdrawing_votes_rejects_bad_and_unknown_ids() {
+    let app = TestApp::spawn().await;
+
+    for (uri, status, message) in [
+        ("/api/v1/questions/abc/helpful", StatusCode::BAD_REQUEST, "Invalid question_id path parameter"),
+        ("/api/v1/questions/424242/helpful", StatusCode::NOT_FOUND, "Question not found"),
+        ("/api/v1/answers/abc/helpful", StatusCode::BAD_REQUEST, "Invalid answer_id path parameter"),
+        ("/api/v1/answers/424242/helpful", StatusCode::NOT_FOUND, "Answer not found"),
+    ] {
+        let res = app.vote(Method::DELETE, uri, "alice").await;
+        assert_eq!(res.status, status, "{}", uri);
+        assert_eq!(res.text(), message, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn oversized_user_tokens_are_rejected() {
+    let app = TestApp::spawn().await;
+    let question_id = app.add_question(1, "Question").await;
+
+    let token = "x".repeat(129);
+    let res = app.vote(Method::PUT, &format!("/api/v1/questions/{}/helpful", question_id), &token).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.text(), "Invalid X-User-Token header");
+    assert_eq!(question_helpfulness(&app, 1).await, 0);
+}
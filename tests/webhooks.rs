@@ -0,0 +1,155 @@
+mod common;
+
+use common::TestApp;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use qa_rs::webhooks::{self, WebhookDispatcher, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn dispatcher(max_attempts: i32, backoff: Duration) -> WebhookDispatcher {
+    WebhookDispatcher::new(Duration::from_secs(1), 10, max_attempts, backoff, Duration::from_secs(5))
+}
+
+struct Received {
+    event: String,
+    delivery: String,
+    signature: String,
+    body: String,
+}
+
+/// A webhook receiver recording what it is sent, and answering 500 while `failing` is set.
+struct Receiver {
+    url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+    failing: Arc<AtomicBool>,
+}
+
+impl Receiver {
+    async fn spawn() -> Receiver {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let failing = Arc::new(AtomicBool::new(false));
+        let (sink, fail) = (received.clone(), failing.clone());
+        let make_svc = make_service_fn(move |_| {
+            let (sink, fail) = (sink.clone(), fail.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let (sink, fail) = (sink.clone(), fail.clone());
+                    async move {
+                        let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+                        let (event, delivery, signature) = (header(EVENT_HEADER), header(DELIVERY_HEADER), header(SIGNATURE_HEADER));
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        sink.lock().unwrap().push(Received { event, delivery, signature, body: String::from_utf8(body.to_vec()).unwrap() });
+                        let status = if fail.load(Ordering::SeqCst) { StatusCode::INTERNAL_SERVER_ERROR } else { StatusCode::NO_CONTENT };
+                        Ok::<_, Infallible>(Response::builder().status(status).body(Body::empty()).unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}/hooks", server.local_addr());
+        tokio::spawn(server);
+        Receiver { url, received, failing }
+    }
+
+    fn events(&self) -> Vec<String> {
+        self.received.lock().unwrap().iter().map(|received| received.event.clone()).collect()
+    }
+}
+
+#[tokio::test]
+async fn writes_are_delivered_as_signed_events() {
+    let receiver = Receiver::spawn().await;
+    let app = TestApp::spawn().await;
+    let (_, secret) = webhooks::create_webhook(&app.state.db, &receiver.url, "").await.unwrap();
+
+    let question_id = app.add_question(5, "Does it fold?").await;
+    let answer_id = app.add_answer(question_id, "Flat", &[]).await;
+    // A repeated vote changes nothing, so it records no event.
+    for _ in 0..2 {
+        app.vote(Method::PUT, &format!("/api/v1/questions/{}/helpful", question_id), "voter").await;
+        app.vote(Method::PUT, &format!("/api/v1/answers/{}/helpful", answer_id), "voter").await;
+    }
+    app.put(&format!("/api/v1/questions/{}/report", question_id)).await;
+    app.put(&format!("/api/v1/answers/{}/report", answer_id)).await;
+
+    assert_eq!(dispatcher(3, Duration::ZERO).process_pending(&app.state).await.unwrap(), 6);
+    assert_eq!(
+        receiver.events(),
+        ["question.created", "answer.created", "question.helpful", "answer.helpful", "question.reported", "answer.reported"]
+    );
+
+    let received = std::mem::take(&mut *receiver.received.lock().unwrap());
+    let created = &received[1];
+    let body: serde_json::Value = serde_json::from_str(&created.body).unwrap();
+    assert_eq!(body["type"], "answer.created");
+    assert_eq!(body["data"], serde_json::json!({ "answer_id": answer_id, "question_id": question_id, "product_id": 5 }));
+    assert!(!created.delivery.is_empty());
+
+    let timestamp = created.signature.strip_prefix("t=").and_then(|rest| rest.split(',').next()).unwrap();
+    assert_eq!(created.signature, webhooks::signature(&secret, timestamp.parse().unwrap(), created.body.as_bytes()));
+    assert_ne!(created.signature, webhooks::signature("whsec_other", timestamp.parse().unwrap(), created.body.as_bytes()));
+
+    // Delivered events are not sent again.
+    assert_eq!(dispatcher(3, Duration::ZERO).process_pending(&app.state).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn webhooks_only_receive_the_events_they_subscribe_to() {
+    let (all, answers) = (Receiver::spawn().await, Receiver::spawn().await);
+    let app = TestApp::spawn().await;
+    webhooks::create_webhook(&app.state.db, &all.url, "").await.unwrap();
+    let events = webhooks::parse_events(&["answer.created".into(), "answer.reported".into()]).unwrap();
+    webhooks::create_webhook(&app.state.db, &answers.url, &events).await.unwrap();
+    assert!(webhooks::parse_events(&["answer.deleted".into()]).is_err());
+
+    let question_id = app.add_question(5, "Does it fold?").await;
+    let answer_id = app.add_answer(question_id, "Flat", &[]).await;
+    app.put(&format!("/api/v1/answers/{}/report", answer_id)).await;
+
+    assert_eq!(dispatcher(3, Duration::ZERO).process_pending(&app.state).await.unwrap(), 5);
+    assert_eq!(all.events(), ["question.created", "answer.created", "answer.reported"]);
+    assert_eq!(answers.events(), ["answer.created", "answer.reported"]);
+}
+
+#[tokio::test]
+async fn failing_deliveries_are_retried_then_dead_lettered_until_redelivered() {
+    let receiver = Receiver::spawn().await;
+    receiver.failing.store(true, Ordering::SeqCst);
+    let app = TestApp::spawn().await;
+    webhooks::create_webhook(&app.state.db, &receiver.url, "question.created").await.unwrap();
+    app.add_question(5, "Does it fold?").await;
+
+    // A failed delivery waits out its backoff before it is tried again.
+    assert_eq!(dispatcher(3, Duration::from_secs(60)).process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(dispatcher(3, Duration::from_secs(60)).process_pending(&app.state).await.unwrap(), 0);
+
+    let app = TestApp::spawn().await;
+    webhooks::create_webhook(&app.state.db, &receiver.url, "question.created").await.unwrap();
+    app.add_question(5, "Does it fold?").await;
+    receiver.received.lock().unwrap().clear();
+    for _ in 0..3 {
+        assert_eq!(dispatcher(3, Duration::ZERO).process_pending(&app.state).await.unwrap(), 1);
+    }
+    assert_eq!(dispatcher(3, Duration::ZERO).process_pending(&app.state).await.unwrap(), 0);
+
+    // Every attempt is the same delivery.
+    let deliveries: Vec<String> = receiver.received.lock().unwrap().iter().map(|received| received.delivery.clone()).collect();
+    assert_eq!(deliveries.len(), 3);
+    assert!(deliveries.iter().all(|delivery| *delivery == deliveries[0]));
+
+    let dead_letters = webhooks::list_dead_letters(&app.state.db).await.unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].event_type, "question.created");
+    assert_eq!(dead_letters[0].attempts, 3);
+    assert_eq!(dead_letters[0].last_error.as_deref(), Some("Webhook answered 500"));
+
+    receiver.failing.store(false, Ordering::SeqCst);
+    assert!(webhooks::redeliver(&app.state.db, dead_letters[0].id).await.unwrap());
+    assert!(!webhooks::redeliver(&app.state.db, dead_letters[0].id).await.unwrap());
+    assert_eq!(dispatcher(3, Duration::ZERO).process_pending(&app.state).await.unwrap(), 1);
+    assert_eq!(receiver.events().len(), 4);
+    assert!(webhooks::list_dead_letters(&app.state.db).await.unwrap().is_empty());
+}